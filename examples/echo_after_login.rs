@@ -0,0 +1,87 @@
+use std::net::SocketAddr;
+
+use axum::Router;
+use fastwebsockets::{Frame, OpCode};
+use tinap::{
+    client::Client,
+    server::{autheticate::AuthConfirm, ws_authenticate_with, AppSocket, Server},
+};
+
+/// echo every frame back over the encrypted channel, prefixed with who sent it, until the
+/// client closes or sends something that doesn't open
+async fn echo(confirm: AuthConfirm, mut ws: AppSocket) {
+    let username = String::from_utf8_lossy(confirm.username()).into_owned();
+    let mut channel = confirm.channel();
+    while let Ok(frame) = ws.read_frame().await {
+        if frame.opcode == OpCode::Close {
+            break;
+        }
+        let message = match channel.open(&frame.payload) {
+            Ok(message) => message,
+            Err(err) => {
+                println!("server refused a frame: {err}");
+                break;
+            }
+        };
+        let mut reply = format!("{username}: ").into_bytes();
+        reply.extend_from_slice(&message);
+        let sealed = channel.seal(&reply);
+        if ws.write_frame(Frame::binary(sealed.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// runs a server with an echo endpoint that only answers after a login, and talks to it
+#[tokio::main]
+async fn main() {
+    let state = Server::initialize();
+    let app = Router::new()
+        .route("/echo", ws_authenticate_with(echo))
+        .with_state(state.clone())
+        .merge(state.router());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:6969")
+        .await
+        .unwrap();
+    tokio::task::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .unwrap()
+    });
+
+    let client = Client::new("127.0.0.1".into(), 6969);
+    client
+        .register_user("echo".into(), "correct horse".into())
+        .await
+        .unwrap();
+
+    let wrong = client
+        .authenticate_then("echo", "echo".into(), "battery staple".into())
+        .await
+        .is_ok_and(|login| login.is_some());
+    println!("wrong password gets a socket: {wrong}");
+
+    let (confirm, mut ws) = client
+        .authenticate_then("echo", "echo".into(), "correct horse".into())
+        .await
+        .unwrap()
+        .expect("login should go through");
+    let mut channel = confirm.channel();
+    ws.write_frame(Frame::binary(channel.seal(b"hello").into()))
+        .await
+        .unwrap();
+    let frame = ws.read_frame().await.unwrap();
+    let reply = channel.open(&frame.payload).unwrap();
+    println!("{}", String::from_utf8_lossy(&reply));
+
+    // a flipped bit anywhere in the ciphertext gets the frame refused
+    let mut tampered = channel.seal(b"hello again");
+    *tampered.last_mut().unwrap() ^= 1;
+    ws.write_frame(Frame::binary(tampered.into()))
+        .await
+        .unwrap();
+    let _ = ws.read_frame().await;
+}
@@ -0,0 +1,31 @@
+use std::net::SocketAddr;
+
+use tinap::server::Server;
+use tokio::sync::broadcast::error::RecvError;
+
+/// runs a server and prints everything that happens on it
+#[tokio::main]
+async fn main() {
+    let state = Server::initialize();
+    let mut events = state.subscribe();
+    tokio::task::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => println!("{event:?}"),
+                Err(RecvError::Lagged(missed)) => println!("missed {missed} events"),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    let app = state.router();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:6969")
+        .await
+        .unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap()
+}
@@ -0,0 +1,197 @@
+use std::{env, process::exit};
+
+use tinap::server::{
+    instance::{instance_path, Instance},
+    setup_file, Server, DB_PATH, SETUP_PATH,
+};
+
+fn usage() -> ! {
+    eprintln!("Usage: tinap-admin identify [db path] [setup path]");
+    eprintln!("       tinap-admin check-setup [setup path]");
+    eprintln!("       tinap-admin migrate-setup [setup path]");
+    eprintln!("       tinap-admin takeout <username> [db path] [setup path]");
+    eprintln!("       tinap-admin reregister <username> <on|off> [db path] [setup path]");
+    eprintln!("       tinap-admin delete <username> [db path] [setup path]");
+    exit(1)
+}
+
+/// print which instances a database and a server setup belong to, so they can be checked
+/// against each other before deploying them together
+fn identify(db_path: &str, setup_path: &str) {
+    let store = match sled::open(db_path) {
+        Ok(store) => store,
+        Err(err) => {
+            println!("Error opening database `{db_path}`: `{err}`");
+            exit(1)
+        }
+    };
+    let stored = Instance::from_store(&store);
+    let setup = Instance::from_file(instance_path(setup_path));
+
+    let mut matching = true;
+    for (name, instance) in [("Database", &stored), ("Setup", &setup)] {
+        match instance {
+            Ok(Some(instance)) => println!("{name}: {instance}"),
+            Ok(None) => {
+                matching = false;
+                println!("{name}: no instance recorded");
+            }
+            Err(err) => {
+                matching = false;
+                println!("{name}: error reading instance `{err}`");
+            }
+        }
+    }
+
+    if let (Ok(Some(stored)), Ok(Some(setup))) = (&stored, &setup) {
+        matching = stored.id == setup.id;
+    }
+    if matching {
+        println!("Database and setup belong to the same instance");
+    } else {
+        println!("Database and setup do not belong to the same instance");
+        exit(1)
+    }
+}
+
+/// check that a server setup file is intact
+fn check_setup(setup_path: &str) {
+    match setup_file::read_setup(setup_path) {
+        Ok(Some(_)) => println!("Setup `{setup_path}` is intact"),
+        Ok(None) => {
+            println!("No setup at `{setup_path}`");
+            exit(1)
+        }
+        Err(err) => {
+            println!("Setup `{setup_path}` can't be used: `{err}`");
+            exit(1)
+        }
+    }
+}
+
+/// rewrite a server setup file written in an older format or without the setup key, so the
+/// server reads it again
+fn migrate_setup(setup_path: &str) {
+    match setup_file::migrate_setup(setup_path) {
+        Ok(true) => println!("Migrated setup `{setup_path}`"),
+        Ok(false) => println!("Setup `{setup_path}` is already up to date"),
+        Err(err) => {
+            println!("Setup `{setup_path}` can't be migrated: `{err}`");
+            exit(1)
+        }
+    }
+}
+
+/// a server on top of an existing setup and database, exits when either can't be opened
+fn open_server(db_path: &str, setup_path: &str) -> Server {
+    let server_setup = match setup_file::read_setup(setup_path) {
+        Ok(Some(server_setup)) => server_setup,
+        Ok(None) => {
+            println!("No setup at `{setup_path}`");
+            exit(1)
+        }
+        Err(err) => {
+            println!("Error reading setup `{setup_path}`: `{err}`");
+            exit(1)
+        }
+    };
+    let store = match sled::open(db_path) {
+        Ok(store) => store,
+        Err(err) => {
+            println!("Error opening database `{db_path}`: `{err}`");
+            exit(1)
+        }
+    };
+    Server::new(server_setup, store)
+}
+
+/// print everything stored about a user as json
+async fn takeout(username: &str, db_path: &str, setup_path: &str) {
+    let server = open_server(db_path, setup_path);
+    match server.takeout(username.as_bytes()).await {
+        Ok(document) => {
+            eprintln!("Exported data of `{username}`");
+            println!("{}", serde_json::to_string_pretty(&document).unwrap());
+        }
+        Err(err) => {
+            println!("Error exporting `{username}`: `{err}`");
+            exit(1)
+        }
+    }
+}
+
+/// force a user to register a new password, or lift that requirement
+async fn reregister(username: &str, required: bool, db_path: &str, setup_path: &str) {
+    let server = open_server(db_path, setup_path);
+    match server
+        .set_must_reregister(username.as_bytes(), required)
+        .await
+    {
+        Ok(()) if required => println!("`{username}` has to register a new password"),
+        Ok(()) => println!("`{username}` can log in with their current password"),
+        Err(err) => {
+            println!("Error updating `{username}`: `{err}`");
+            exit(1)
+        }
+    }
+}
+
+/// remove a user and everything stored about them, whatever the server's deletion policy
+async fn delete(username: &str, db_path: &str, setup_path: &str) {
+    let server = open_server(db_path, setup_path);
+    match server.delete_user(username.as_bytes()).await {
+        Ok(()) => println!("Deleted `{username}`"),
+        Err(err) => {
+            println!("Error deleting `{username}`: `{err}`");
+            exit(1)
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("identify") => identify(
+            args.get(1).map_or(DB_PATH, String::as_str),
+            args.get(2).map_or(SETUP_PATH, String::as_str),
+        ),
+        Some("check-setup") => check_setup(args.get(1).map_or(SETUP_PATH, String::as_str)),
+        Some("migrate-setup") => migrate_setup(args.get(1).map_or(SETUP_PATH, String::as_str)),
+        Some("takeout") => match args.get(1) {
+            Some(username) => {
+                takeout(
+                    username,
+                    args.get(2).map_or(DB_PATH, String::as_str),
+                    args.get(3).map_or(SETUP_PATH, String::as_str),
+                )
+                .await
+            }
+            None => usage(),
+        },
+        Some("reregister") => match (args.get(1), args.get(2).map(String::as_str)) {
+            (Some(username), Some(setting @ ("on" | "off"))) => {
+                reregister(
+                    username,
+                    setting == "on",
+                    args.get(3).map_or(DB_PATH, String::as_str),
+                    args.get(4).map_or(SETUP_PATH, String::as_str),
+                )
+                .await
+            }
+            _ => usage(),
+        },
+        Some("delete") => match args.get(1) {
+            Some(username) => {
+                delete(
+                    username,
+                    args.get(2).map_or(DB_PATH, String::as_str),
+                    args.get(3).map_or(SETUP_PATH, String::as_str),
+                )
+                .await
+            }
+            None => usage(),
+        },
+        _ => usage(),
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// most attributes a single account can hold
+pub const MAX_ATTRIBUTES: usize = 16;
+/// longest allowed attribute key in bytes
+pub const MAX_KEY_LEN: usize = 64;
+/// longest allowed attribute value in bytes
+pub const MAX_VALUE_LEN: usize = 512;
+
+#[derive(Debug, Error)]
+pub enum AttributeError {
+    #[error("Too many attributes `{0}`, at most {MAX_ATTRIBUTES} are allowed")]
+    TooMany(usize),
+    #[error("Attribute keys can't be empty")]
+    EmptyKey,
+    #[error("Attribute key `{0}` is longer than {MAX_KEY_LEN} bytes")]
+    KeyTooLong(String),
+    #[error("Value of attribute `{0}` is longer than {MAX_VALUE_LEN} bytes")]
+    ValueTooLong(String),
+}
+
+/// Small plaintext key-value metadata attached to an account, e.g. a display name or locale
+///
+/// Unlike the password file these are visible to the server, so nothing secret belongs here
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    try_from = "BTreeMap<String, String>",
+    into = "BTreeMap<String, String>"
+)]
+pub struct Attributes(BTreeMap<String, String>);
+
+impl Attributes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// set an attribute, replacing any previous value, as long as the limits still hold
+    pub fn insert(&mut self, key: String, value: String) -> Result<(), AttributeError> {
+        Self::check_entry(&key, &value)?;
+        if !self.0.contains_key(&key) && self.0.len() >= MAX_ATTRIBUTES {
+            return Err(AttributeError::TooMany(self.0.len() + 1));
+        }
+        self.0.insert(key, value);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.0.remove(key)
+    }
+
+    /// check that the whole map is within the limits, used on anything that didn't go through
+    /// `insert`
+    pub fn validate(&self) -> Result<(), AttributeError> {
+        if self.0.len() > MAX_ATTRIBUTES {
+            return Err(AttributeError::TooMany(self.0.len()));
+        }
+        for (key, value) in &self.0 {
+            Self::check_entry(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn check_entry(key: &str, value: &str) -> Result<(), AttributeError> {
+        if key.is_empty() {
+            return Err(AttributeError::EmptyKey);
+        }
+        if key.len() > MAX_KEY_LEN {
+            return Err(AttributeError::KeyTooLong(key.into()));
+        }
+        if value.len() > MAX_VALUE_LEN {
+            return Err(AttributeError::ValueTooLong(key.into()));
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<BTreeMap<String, String>> for Attributes {
+    type Error = AttributeError;
+
+    fn try_from(value: BTreeMap<String, String>) -> Result<Self, Self::Error> {
+        let attributes = Self(value);
+        attributes.validate()?;
+        Ok(attributes)
+    }
+}
+
+impl From<Attributes> for BTreeMap<String, String> {
+    fn from(value: Attributes) -> Self {
+        value.0
+    }
+}
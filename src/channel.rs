@@ -0,0 +1,214 @@
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use thiserror::Error;
+
+use crate::{derive_key, sequence::Side};
+
+/// bytes of the counter every sealed frame starts with
+const COUNTER_LEN: usize = 8;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+/// [`derive_key`] labels for the keys and nonces of each direction
+const CLIENT_KEY_LABEL: &[u8] = b"channel client key";
+const CLIENT_NONCE_LABEL: &[u8] = b"channel client nonce";
+const SERVER_KEY_LABEL: &[u8] = b"channel server key";
+const SERVER_NONCE_LABEL: &[u8] = b"channel server nonce";
+
+#[derive(Debug, Error)]
+pub enum ChannelError {
+    #[error("Sealed frame is too short to hold its counter")]
+    Truncated,
+    #[error("Sealed frame `{0}` was already opened")]
+    Replayed(u64),
+    #[error("Sealed frame `{got}` arrived while expecting `{expected}`")]
+    OutOfOrder { expected: u64, got: u64 },
+    #[error("Sealed frame does not check out")]
+    Tampered,
+}
+
+/// One direction of a [`SecureChannel`]
+struct Direction {
+    cipher: ChaCha20Poly1305,
+    nonce: [u8; NONCE_LEN],
+    /// frames sealed or opened so far
+    counter: u64,
+}
+
+impl Direction {
+    fn derive(session_key: &[u8], key_label: &[u8], nonce_label: &[u8]) -> Self {
+        let key = derive_key(session_key, key_label, KEY_LEN)
+            .expect("key is far below the most HKDF can output");
+        let nonce = derive_key(session_key, nonce_label, NONCE_LEN)
+            .expect("nonce is far below the most HKDF can output");
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            nonce: nonce.try_into().expect("derived exactly a nonce's length"),
+            counter: 0,
+        }
+    }
+
+    /// the nonce for frame `counter`, the base nonce with the counter mixed into its tail
+    fn nonce(&self, counter: u64) -> [u8; NONCE_LEN] {
+        let mut nonce = self.nonce;
+        for (byte, count) in nonce[NONCE_LEN - COUNTER_LEN..]
+            .iter_mut()
+            .zip(counter.to_be_bytes())
+        {
+            *byte ^= count;
+        }
+        nonce
+    }
+}
+
+/// Application traffic after a login, encrypted with keys derived from the session key both
+/// sides ended up with
+///
+/// every frame carries its counter in the clear, which also goes into its nonce and is
+/// authenticated, so frames have to be opened exactly in the order they were sealed. Anything
+/// replayed, dropped, reordered, or changed is refused
+pub struct SecureChannel {
+    send: Direction,
+    receive: Direction,
+}
+
+impl SecureChannel {
+    /// the channel for `side` of a login that agreed on `session_key`, each side sends with the
+    /// keys the other receives with
+    pub fn new(session_key: &[u8], side: Side) -> Self {
+        let client = Direction::derive(session_key, CLIENT_KEY_LABEL, CLIENT_NONCE_LABEL);
+        let server = Direction::derive(session_key, SERVER_KEY_LABEL, SERVER_NONCE_LABEL);
+        match side {
+            Side::Client => Self {
+                send: client,
+                receive: server,
+            },
+            Side::Server => Self {
+                send: server,
+                receive: client,
+            },
+        }
+    }
+
+    /// encrypt `plaintext` into the next frame to send
+    pub fn seal(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let counter = self.send.counter;
+        self.send.counter = counter
+            .checked_add(1)
+            .expect("a channel never sends 2^64 frames");
+        let header = counter.to_be_bytes();
+        let sealed = self
+            .send
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&self.send.nonce(counter)),
+                Payload {
+                    msg: plaintext,
+                    aad: &header,
+                },
+            )
+            .expect("frames are far below the most ChaCha20-Poly1305 can encrypt");
+        let mut frame = Vec::with_capacity(COUNTER_LEN + sealed.len());
+        frame.extend_from_slice(&header);
+        frame.extend_from_slice(&sealed);
+        frame
+    }
+
+    /// decrypt `frame`, which has to be the next one the other side sealed
+    pub fn open(&mut self, frame: &[u8]) -> Result<Vec<u8>, ChannelError> {
+        let (header, sealed) = frame
+            .split_first_chunk::<COUNTER_LEN>()
+            .ok_or(ChannelError::Truncated)?;
+        let counter = u64::from_be_bytes(*header);
+        let expected = self.receive.counter;
+        if counter < expected {
+            return Err(ChannelError::Replayed(counter));
+        }
+        if counter > expected {
+            return Err(ChannelError::OutOfOrder {
+                expected,
+                got: counter,
+            });
+        }
+        let plaintext = self
+            .receive
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&self.receive.nonce(counter)),
+                Payload {
+                    msg: sealed,
+                    aad: header,
+                },
+            )
+            .map_err(|_| ChannelError::Tampered)?;
+        // only a frame that checked out moves the channel along
+        self.receive.counter += 1;
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (SecureChannel, SecureChannel) {
+        let session_key = [7; 64];
+        (
+            SecureChannel::new(&session_key, Side::Client),
+            SecureChannel::new(&session_key, Side::Server),
+        )
+    }
+
+    #[test]
+    fn frames_open_on_the_other_side() {
+        let (mut client, mut server) = pair();
+        let frame = client.seal(b"hello");
+        assert_eq!(server.open(&frame).unwrap(), b"hello");
+        let frame = server.seal(b"hello back");
+        assert_eq!(client.open(&frame).unwrap(), b"hello back");
+        // a side can't open what it sealed itself
+        let frame = client.seal(b"to myself");
+        assert!(matches!(client.open(&frame), Err(ChannelError::Tampered)));
+    }
+
+    #[test]
+    fn flipped_bits_are_refused() {
+        let (mut client, mut server) = pair();
+        let frame = client.seal(b"hello");
+        for bit in 0..frame.len() * 8 {
+            let mut tampered = frame.clone();
+            tampered[bit / 8] ^= 1 << (bit % 8);
+            assert!(server.open(&tampered).is_err(), "bit {bit} went unnoticed");
+        }
+        // refusing frames didn't move the channel along
+        assert_eq!(server.open(&frame).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn replayed_and_reordered_frames_are_refused() {
+        let (mut client, mut server) = pair();
+        let first = client.seal(b"first");
+        let second = client.seal(b"second");
+        let third = client.seal(b"third");
+
+        assert!(matches!(
+            server.open(&second),
+            Err(ChannelError::OutOfOrder {
+                expected: 0,
+                got: 1
+            })
+        ));
+        server.open(&first).unwrap();
+        assert!(matches!(
+            server.open(&first),
+            Err(ChannelError::Replayed(0))
+        ));
+        server.open(&second).unwrap();
+        assert!(matches!(
+            server.open(&third[..COUNTER_LEN - 1]),
+            Err(ChannelError::Truncated)
+        ));
+        assert_eq!(server.open(&third).unwrap(), b"third");
+    }
+}
@@ -1,125 +1,228 @@
-use opaque_ke::{
-    ClientLogin, ClientLoginFinishParameters, ClientLoginFinishResult, ClientLoginStartResult,
-    CredentialResponse,
-};
-use rand::rngs::OsRng;
+use std::fmt::{Debug, Write};
+
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
 
-use crate::{Scheme, WithUsername};
+use crate::{
+    channel::SecureChannel,
+    key_confirmation,
+    sequence::Side,
+    suite::{LoginFinish, SaltedKsf, Suite},
+    username, AuthenticateRequest, Identifiers, KeyLengthError, Scheme,
+};
 
 use super::error::ClientError;
 
-pub struct AuthenticateInitialize<'a> {
+pub struct AuthenticateInitialize<CS: Suite = Scheme> {
     username: String,
-    password: String,
-    client_login_start_result: ClientLoginStartResult<Scheme<'a>>,
+    password: Zeroizing<String>,
+    verify_only: bool,
+    identifiers: Identifiers,
+    /// the costs to stretch the password with, salted for the user unless `legacy_salt`
+    ksf: CS::Ksf,
+    legacy_salt: bool,
+    state: CS::ClientLogin,
+    /// the serialized `CredentialRequest`
+    credential_request: Vec<u8>,
+}
+
+impl AuthenticateInitialize {
+    /// `username` is normalized first, see [`username::normalize`]
+    pub fn new(username: String, password: String) -> Result<Self, ClientError> {
+        Self::for_suite(username, password)
+    }
 }
 
-impl<'a> AuthenticateInitialize<'a> {
+impl<CS: Suite> AuthenticateInitialize<CS> {
     pub fn step(
         self,
         credential_response_bytes: Vec<u8>,
-    ) -> Result<AuthenticateWaiting<'a>, ClientError> {
-        let credential_response = CredentialResponse::deserialize(&credential_response_bytes)?;
-        let client_login_finish_result = self.client_login_start_result.state.finish(
+    ) -> Result<AuthenticateWaiting, ClientError> {
+        let ksf = if self.legacy_salt {
+            self.ksf
+        } else {
+            self.ksf.salted_for(self.username.as_bytes())
+        };
+        let client_login_finish_result = CS::client_login_finish(
+            self.state,
             self.password.as_bytes(),
-            credential_response,
-            ClientLoginFinishParameters::default(),
+            &credential_response_bytes,
+            &self.identifiers,
+            &ksf,
         )?;
 
         Ok(AuthenticateWaiting::new(client_login_finish_result))
     }
 
     pub fn to_data(&self) -> Vec<u8> {
-        let credential_request_bytes = self.client_login_start_result.message.serialize();
-        let with_username = WithUsername {
+        let request = AuthenticateRequest {
             username: self.username.as_bytes(),
-            data: credential_request_bytes.as_slice(),
+            data: &self.credential_request,
+            verify_only: self.verify_only,
         };
-        bincode::serialize(&with_username).unwrap()
+        bincode::serialize(&request).unwrap()
     }
 
-    pub fn new(username: String, password: String) -> Result<Self, ClientError> {
-        let mut client_rng = OsRng;
-        let client_login_start_result =
-            match ClientLogin::<Scheme>::start(&mut client_rng, password.as_bytes()) {
-                Ok(res) => res,
-                Err(err) => {
-                    return Err(ClientError::ProtocolError(err));
-                }
-            };
+    /// ask the server to only check the credentials rather than treat this as a login
+    pub fn with_verify_only(mut self, verify_only: bool) -> Self {
+        self.verify_only = verify_only;
+        self
+    }
+
+    /// log in with `identifiers`, which have to be the ones the user registered with and the
+    /// ones the server expects
+    pub fn with_identifiers(mut self, identifiers: Identifiers) -> Self {
+        self.identifiers = identifiers;
+        self
+    }
+
+    /// stretch the password with the all zero salt from before salts were derived from
+    /// usernames, for logging in to accounts registered back then
+    pub fn with_legacy_salt(mut self, legacy: bool) -> Self {
+        self.legacy_salt = legacy;
+        self
+    }
+
+    /// stretch the password with `ksf`'s costs, which have to be the ones the user registered
+    /// with
+    pub fn with_ksf(mut self, ksf: CS::Ksf) -> Self {
+        self.ksf = ksf;
+        self
+    }
+
+    /// [`AuthenticateInitialize::new`] for logging in with `CS`
+    pub fn for_suite(username: String, password: String) -> Result<Self, ClientError> {
+        let password = Zeroizing::new(password);
+        if password.is_empty() {
+            return Err(ClientError::EmptyPassword);
+        }
+        let username = username::normalize(&username)?.into_string();
+        let (state, credential_request) = match CS::client_login_start(password.as_bytes()) {
+            Ok(res) => res,
+            Err(err) => {
+                return Err(ClientError::ProtocolError(err));
+            }
+        };
         Ok(Self {
             username,
             password,
-            client_login_start_result,
+            verify_only: false,
+            identifiers: Identifiers::default(),
+            ksf: CS::Ksf::default(),
+            legacy_salt: false,
+            state,
+            credential_request,
         })
     }
 }
 
-pub struct AuthenticateWaiting<'a> {
-    client_login_finish_result: ClientLoginFinishResult<Scheme<'a>>,
+impl<CS: Suite> Debug for AuthenticateInitialize<CS> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthenticateInitialize")
+            .field("username", &self.username)
+            .field("password", &REDACTED)
+            .field("verify_only", &self.verify_only)
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct AuthenticateWaiting {
+    client_login_finish_result: LoginFinish,
 }
 
-impl<'a> AuthenticateWaiting<'a> {
-    pub fn new(client_login_finish_result: ClientLoginFinishResult<Scheme<'a>>) -> Self {
+impl AuthenticateWaiting {
+    pub fn new(client_login_finish_result: LoginFinish) -> Self {
         Self {
             client_login_finish_result,
         }
     }
 
     pub fn to_data(&self) -> Vec<u8> {
-        self.client_login_finish_result
-            .message
-            .serialize()
-            .as_slice()
-            .into()
+        self.client_login_finish_result.message.clone()
     }
 
-    pub fn step(self, server_key: Vec<u8>) -> AuthenticateFinish<'a> {
-        AuthenticateFinish::new(server_key, self.client_login_finish_result)
+    /// the static public key of the server the login is with
+    pub fn server_public_key(&self) -> Vec<u8> {
+        self.client_login_finish_result.server_public_key.clone()
+    }
+
+    pub fn step(self, server_confirmation: Vec<u8>) -> AuthenticateFinish {
+        AuthenticateFinish::new(server_confirmation, self.client_login_finish_result)
     }
 }
 
-pub struct AuthenticateFinish<'a> {
-    server_key: Vec<u8>,
-    client_login_finish_result: ClientLoginFinishResult<Scheme<'a>>,
+pub struct AuthenticateFinish {
+    /// the server's proof of its session key, see [`key_confirmation`]
+    server_confirmation: Vec<u8>,
+    client_login_finish_result: LoginFinish,
 }
 
-impl<'a> AuthenticateFinish<'a> {
-    pub fn new(
-        server_key: Vec<u8>,
-        client_login_finish_result: ClientLoginFinishResult<Scheme<'a>>,
-    ) -> Self {
+impl AuthenticateFinish {
+    pub fn new(server_confirmation: Vec<u8>, client_login_finish_result: LoginFinish) -> Self {
         Self {
-            server_key,
+            server_confirmation,
             client_login_finish_result,
         }
     }
 
+    /// whether the server ended up with the same session key, compared in constant time
     pub fn to_data(&self) -> bool {
-        self.client_login_finish_result.session_key.to_vec() == self.server_key
+        confirms(
+            &self.client_login_finish_result.session_key,
+            &self.server_confirmation,
+        )
     }
 
     pub fn step(self) -> AuthenticateConfirm {
         AuthenticateConfirm::new(
-            self.client_login_finish_result.session_key.to_vec(),
-            self.client_login_finish_result.export_key.to_vec(),
+            self.client_login_finish_result.session_key,
+            self.client_login_finish_result.export_key,
         )
+        .with_server_public_key(self.client_login_finish_result.server_public_key)
     }
 }
 
+/// whether `server_confirmation` proves the same session key as `session_key`, compared in
+/// constant time
+fn confirms(session_key: &[u8], server_confirmation: &[u8]) -> bool {
+    key_confirmation(session_key)
+        .ct_eq(server_confirmation)
+        .into()
+}
+
+/// The keys a login ended with, wiped from memory when dropped
 pub struct AuthenticateConfirm {
-    session_key: Vec<u8>,
-    export_key: Vec<u8>,
+    session_key: Zeroizing<Vec<u8>>,
+    export_key: Zeroizing<Vec<u8>>,
+    server_public_key: Vec<u8>,
+    session_token: Option<Zeroizing<String>>,
 }
 
 impl AuthenticateConfirm {
     pub fn new(session_key: Vec<u8>, export_key: Vec<u8>) -> Self {
         Self {
-            session_key,
-            export_key,
+            session_key: Zeroizing::new(session_key),
+            export_key: Zeroizing::new(export_key),
+            server_public_key: Vec::new(),
+            session_token: None,
         }
     }
 
+    pub fn with_server_public_key(mut self, server_public_key: Vec<u8>) -> Self {
+        self.server_public_key = server_public_key;
+        self
+    }
+
+    pub fn with_session_token(mut self, session_token: Option<String>) -> Self {
+        self.session_token = session_token.map(Zeroizing::new);
+        self
+    }
+
+    /// bearer token the server handed out for the login, `None` when it didn't send one
+    pub fn session_token(&self) -> Option<&str> {
+        self.session_token.as_deref().map(String::as_str)
+    }
+
     pub fn session_key(&self) -> &[u8] {
         &self.session_key
     }
@@ -127,4 +230,86 @@ impl AuthenticateConfirm {
     pub fn export_key(&self) -> &[u8] {
         &self.export_key
     }
+
+    /// the static public key of the server the login was with, for pinning it with
+    /// [`Client::with_server_public_key`](super::Client::with_server_public_key)
+    pub fn server_public_key(&self) -> &[u8] {
+        &self.server_public_key
+    }
+
+    /// `len` bytes of key for `label`, the server derives the same key for the same label, see
+    /// [`derive_key`](crate::derive_key)
+    pub fn derive_key(&self, label: &[u8], len: usize) -> Result<Vec<u8>, KeyLengthError> {
+        crate::derive_key(&self.session_key, label, len)
+    }
+
+    /// this side of an encrypted channel for traffic after the login, the server gets the
+    /// other side from its own confirmation
+    pub fn channel(&self) -> SecureChannel {
+        SecureChannel::new(&self.session_key, Side::Client)
+    }
+
+    /// the session key as lowercase hex, for printing
+    pub fn session_key_hex(&self) -> String {
+        hex(&self.session_key)
+    }
+
+    /// the export key as lowercase hex, for printing
+    pub fn export_key_hex(&self) -> String {
+        hex(&self.export_key)
+    }
+}
+
+impl Debug for AuthenticateConfirm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthenticateConfirm")
+            .field("session_key", &REDACTED)
+            .field("export_key", &REDACTED)
+            .field("server_public_key", &self.server_public_key)
+            .field(
+                "session_token",
+                &self.session_token.as_ref().map(|_| REDACTED),
+            )
+            .finish()
+    }
+}
+
+/// stands in for secrets in `Debug` output
+pub(crate) const REDACTED: &str = "<redacted>";
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut out, byte| {
+            let _ = write!(out, "{byte:02x}");
+            out
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_session_key_confirms() {
+        assert!(confirms(b"session key", &key_confirmation(b"session key")));
+    }
+
+    #[test]
+    fn other_session_key_does_not_confirm() {
+        assert!(!confirms(b"session key", &key_confirmation(b"other key")));
+    }
+
+    #[test]
+    fn confirmation_of_another_length_does_not_confirm() {
+        let confirmation = key_confirmation(b"session key");
+        assert!(!confirms(b"session key", &[]));
+        assert!(!confirms(b"session key", &confirmation[1..]));
+        assert!(!confirms(b"session key", &[confirmation, vec![0]].concat()));
+    }
+
+    #[test]
+    fn session_key_itself_does_not_confirm() {
+        assert!(!confirms(b"session key", b"session key"));
+    }
 }
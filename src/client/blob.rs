@@ -0,0 +1,58 @@
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::{rngs::OsRng, RngCore};
+
+use crate::derive_key;
+
+use super::error::ClientError;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+/// [`derive_key`] label for the key blobs are encrypted with
+const BLOB_KEY_LABEL: &[u8] = b"blob key";
+
+/// the cipher for blobs, keyed from the export key so only the user's password can recreate it
+fn cipher(export_key: &[u8]) -> ChaCha20Poly1305 {
+    let key = derive_key(export_key, BLOB_KEY_LABEL, KEY_LEN)
+        .expect("key is far below the most HKDF can output");
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+/// encrypt `blob` to store for `username`, a fresh random nonce goes in front
+///
+/// the username is authenticated along with it, so a blob moved to another user doesn't open
+pub fn seal(export_key: &[u8], username: &[u8], blob: &[u8]) -> Vec<u8> {
+    let mut nonce = [0; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let sealed = cipher(export_key)
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: blob,
+                aad: username,
+            },
+        )
+        .expect("blobs are far below the most ChaCha20-Poly1305 can encrypt");
+    let mut stored = Vec::with_capacity(NONCE_LEN + sealed.len());
+    stored.extend_from_slice(&nonce);
+    stored.extend_from_slice(&sealed);
+    stored
+}
+
+/// decrypt a blob made by [`seal`]
+pub fn open(export_key: &[u8], username: &[u8], stored: &[u8]) -> Result<Vec<u8>, ClientError> {
+    let (nonce, sealed) = stored
+        .split_first_chunk::<NONCE_LEN>()
+        .ok_or(ClientError::BlobUnreadable)?;
+    cipher(export_key)
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: sealed,
+                aad: username,
+            },
+        )
+        .map_err(|_| ClientError::BlobUnreadable)
+}
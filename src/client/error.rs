@@ -1,9 +1,20 @@
+use std::time::Duration;
+
 use boring_derive::From;
 use fastwebsockets::WebSocketError;
 use fastwebsockets::{Frame, OpCode};
+use hyper::StatusCode;
 use opaque_ke::errors::ProtocolError;
 use thiserror::Error;
 
+use super::{timings::Phase, trace::TraceEntry};
+use crate::{
+    padding::PaddingError,
+    sequence::OutOfSequence,
+    storage_key::UsernameError,
+    wire::{kind, CloseReason, ErrorKind, Feature},
+};
+
 #[derive(Debug, Error, From)]
 pub enum ClientError {
     #[from(skip)]
@@ -12,29 +23,276 @@ pub enum ClientError {
     #[error("Protocal error `{0:?}`")]
     ProtocolError(ProtocolError),
     #[from(skip)]
+    #[error("Password is empty")]
+    EmptyPassword,
+    #[from(skip)]
+    #[error("Password is correct but has to be changed before logging in")]
+    PasswordChangeRequired,
+    #[from(skip)]
+    #[error("Server's public key is not the one expected of it")]
+    ServerKeyMismatch,
+    #[from(skip)]
     #[error("Failed to authenticate")]
     NotAuthenticated,
+    #[from(skip)]
+    #[error("Connection ended before the server reported the outcome")]
+    OutcomeUnknown,
     #[error("Websocket connection error `{0}`")]
     Websocket(WebSocketError),
     #[error("Error with io `{0}`")]
     IOError(std::io::Error),
     #[error("Error with http communication `{0}`")]
     HyperError(hyper::http::Error),
+    #[error("Http request failed `{0}`")]
+    Http(hyper::Error),
+    #[error("Malformed json from the server `{0}`")]
+    Json(serde_json::Error),
     #[error("Received unexpected frame `{0:?}` with `{1:?}`")]
     UnexpectedFrame(OpCode, Vec<u8>),
+    #[error("Malformed padded message `{0}`")]
+    Padding(PaddingError),
+    #[error("Exchange went out of sequence `{0}`")]
+    OutOfSequence(OutOfSequence),
+    #[from(skip)]
+    #[error("Server did not agree to pad messages")]
+    PaddingRefused,
+    #[from(skip)]
+    #[error("Server rejected the request with `{0}`: {reason}", reason = describe(.1))]
+    Rejected(u16, String),
+    #[from(skip)]
+    #[error("Server could not make sense of a message `{0}`")]
+    Invalid(CloseReason),
+    #[from(skip)]
+    #[error("Server ran into an internal error `{0}`")]
+    ServerFailed(CloseReason),
+    #[from(skip)]
+    #[error("Server reported a broken connection `{0}`")]
+    ServerTransport(CloseReason),
+    #[from(skip)]
+    #[error("Server closed the connection with `{0}`")]
+    UnknownClose(CloseReason),
+    #[from(skip)]
+    #[error("Refusing to connect to `{0}` without TLS")]
+    InsecureTransport(String),
+    #[from(skip)]
+    #[error("TLS with the server failed `{0}`")]
+    Tls(String),
+    #[from(skip)]
+    #[error("Server is temporarily unable to handle the request `{0}`")]
+    TryAgainLater(String),
+    #[from(skip)]
+    #[error("Account is locked, try again in `{retry_after:?}`")]
+    AccountLocked { retry_after: Option<Duration> },
+    #[from(skip)]
+    #[error("Server does not support `{0}`")]
+    FeatureUnsupported(Feature),
+    #[from(skip)]
+    #[error("Stored blob could not be decrypted")]
+    BlobUnreadable,
+    #[error("Invalid username `{0}`")]
+    Username(UsernameError),
+    #[from(skip)]
+    #[error("Server took too long to answer during `{0}`")]
+    Timeout(Phase),
+    #[from(skip)]
+    #[error("Operation was still waiting on the server when its deadline passed")]
+    DeadlineExceeded,
+    #[from(skip)]
+    #[error("Could not connect to any server `{0:?}`")]
+    AllTargetsFailed(Vec<(String, ClientError)>),
+    #[from(skip)]
+    #[error("Could not connect after `{0}` attempts, the last failed with `{1}`")]
+    RetriesExhausted(u32, Box<ClientError>),
+    #[from(skip)]
+    #[error("{0}")]
+    Traced(Box<ClientError>, Vec<TraceEntry>),
 }
 
 impl ClientError {
+    /// category of the error, which decides the close code
+    pub fn error_kind(&self) -> ErrorKind {
+        match self {
+            Self::ClosedEarly | Self::OutcomeUnknown => ErrorKind::Closed,
+            Self::Websocket(_)
+            | Self::IOError(_)
+            | Self::HyperError(_)
+            | Self::Http(_)
+            | Self::PaddingRefused
+            | Self::InsecureTransport(_)
+            | Self::Tls(_)
+            | Self::ServerTransport(_)
+            | Self::Timeout(_)
+            | Self::DeadlineExceeded
+            | Self::AllTargetsFailed(_)
+            | Self::RetriesExhausted(_, _) => ErrorKind::Transport,
+            Self::ProtocolError(_)
+            | Self::UnexpectedFrame(_, _)
+            | Self::Padding(_)
+            | Self::BlobUnreadable
+            | Self::Json(_)
+            | Self::Invalid(_) => ErrorKind::Invalid,
+            Self::ServerFailed(_) | Self::OutOfSequence(_) => ErrorKind::Internal,
+            Self::EmptyPassword
+            | Self::Username(_)
+            | Self::PasswordChangeRequired
+            | Self::ServerKeyMismatch
+            | Self::NotAuthenticated
+            | Self::FeatureUnsupported(_)
+            | Self::Rejected(_, _)
+            | Self::UnknownClose(_) => ErrorKind::Rejected,
+            Self::TryAgainLater(_) | Self::AccountLocked { .. } => ErrorKind::Unavailable,
+            Self::Traced(err, _) => err.error_kind(),
+        }
+    }
+
     pub fn to_code(&self) -> u16 {
+        self.error_kind().close_code()
+    }
+
+    /// what the server is told when the client gives up on an exchange because of this error
+    pub fn close_reason(&self) -> CloseReason {
+        CloseReason::new(self.error_kind(), self.kind(), None)
+    }
+
+    /// the error for the server closing the connection with `reason` before the exchange was
+    /// done, picked by the category of the close code
+    pub fn from_close(reason: CloseReason) -> Self {
+        if reason.message == kind::ACCOUNT_LOCKED {
+            let retry_after = reason.detail.as_deref().and_then(|secs| secs.parse().ok());
+            return Self::AccountLocked {
+                retry_after: retry_after.map(Duration::from_secs),
+            };
+        }
+        match reason.kind {
+            Some(ErrorKind::Closed) => Self::ClosedEarly,
+            Some(ErrorKind::Transport) => Self::ServerTransport(reason),
+            Some(ErrorKind::Invalid) => Self::Invalid(reason),
+            Some(ErrorKind::Rejected) => Self::Rejected(reason.code, reason.text()),
+            Some(ErrorKind::Unavailable) => Self::TryAgainLater(reason.text()),
+            Some(ErrorKind::Internal) => Self::ServerFailed(reason),
+            None => Self::UnknownClose(reason),
+        }
+    }
+
+    /// stable identifier of the error, sent to the server instead of the message
+    pub fn kind(&self) -> &'static str {
         match self {
-            Self::ClosedEarly => 1000,
-            Self::ProtocolError(_) => 1008,
-            Self::NotAuthenticated => 1008,
-            Self::Websocket(_) => 1002,
-            Self::IOError(_) => 1002,
-            Self::HyperError(_) => 1002,
-            Self::UnexpectedFrame(_, _) => 1008,
+            Self::ClosedEarly => "closed_early",
+            Self::ProtocolError(_) => "protocol",
+            Self::EmptyPassword => "empty_password",
+            Self::PasswordChangeRequired => "password_change_required",
+            Self::ServerKeyMismatch => "server_key_mismatch",
+            Self::NotAuthenticated => "not_authenticated",
+            Self::OutcomeUnknown => "outcome_unknown",
+            Self::Websocket(_) => "websocket",
+            Self::IOError(_) => "io",
+            Self::HyperError(_) | Self::Http(_) => "http",
+            Self::Json(_) => "serialization",
+            Self::UnexpectedFrame(_, _) => "unexpected_frame",
+            Self::Padding(_) => "padding",
+            Self::OutOfSequence(_) => "out_of_sequence",
+            Self::PaddingRefused => "padding_refused",
+            Self::Rejected(_, _) => "rejected",
+            Self::Invalid(_) => "invalid",
+            Self::ServerFailed(_) => "server_failed",
+            Self::ServerTransport(_) => "server_transport",
+            Self::UnknownClose(_) => "unknown_close",
+            Self::InsecureTransport(_) => "insecure_transport",
+            Self::Tls(_) => "tls",
+            Self::TryAgainLater(_) => "try_again_later",
+            Self::FeatureUnsupported(_) => "feature_unsupported",
+            Self::AccountLocked { .. } => "account_locked",
+            Self::BlobUnreadable => "blob_unreadable",
+            Self::Username(_) => "invalid_username",
+            Self::Timeout(_) => "timeout",
+            Self::DeadlineExceeded => "deadline_exceeded",
+            Self::AllTargetsFailed(_) => "all_targets_failed",
+            Self::RetriesExhausted(_, _) => "retries_exhausted",
+            Self::Traced(err, _) => err.kind(),
+        }
+    }
+
+    /// whether the same request is worth trying again later
+    pub fn is_transient(&self) -> bool {
+        match self {
+            // nothing was sent yet when connecting timed out
+            Self::TryAgainLater(_) | Self::AccountLocked { .. } | Self::Timeout(Phase::Connect) => {
+                true
+            }
+            Self::Traced(err, _) => err.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// whether connecting failed in a way that might not last, e.g. the server restarting or
+    /// shedding load, rather than something another attempt would run into again
+    pub fn is_unreachable(&self) -> bool {
+        match self {
+            Self::IOError(_) | Self::Timeout(Phase::Connect) => true,
+            Self::Websocket(WebSocketError::InvalidStatusCode(code)) => {
+                *code == StatusCode::SERVICE_UNAVAILABLE.as_u16()
+            }
+            Self::Websocket(err) => matches!(
+                err,
+                WebSocketError::UnexpectedEOF
+                    | WebSocketError::ConnectionClosed
+                    | WebSocketError::IoError(_)
+            ),
+            Self::AllTargetsFailed(failures) => {
+                failures.iter().all(|(_, err)| err.is_unreachable())
+            }
+            Self::Traced(err, _) => err.is_unreachable(),
+            _ => false,
+        }
+    }
+
+    /// the error without any trace attached
+    pub fn inner(&self) -> &ClientError {
+        match self {
+            Self::Traced(err, _) => err,
+            err => err,
+        }
+    }
+
+    /// the most recent connection events leading up to the error, only recorded when the client
+    /// was built with tracing turned on
+    pub fn context(&self) -> &[TraceEntry] {
+        match self {
+            Self::Traced(_, entries) => entries,
+            _ => &[],
+        }
+    }
+}
+
+/// message for a kind of failure the server reported when closing the connection
+pub fn describe(server_kind: &str) -> &'static str {
+    match server_kind.split(':').next().unwrap_or_default() {
+        kind::CLOSED_EARLY => "The server ended the exchange early",
+        kind::USER_ALREADY_EXISTS => "That username is already taken",
+        kind::USER_DOES_NOT_EXIST => "No such user",
+        kind::CLIENT_UNRESPONSIVE => "The server gave up waiting on this client",
+        kind::TOO_MANY_ATTEMPTS => "Too many attempts in progress for this user",
+        kind::ACCOUNT_LOCKED => "The account is locked",
+        kind::PROTOCOL => "The server could not process the exchange",
+        kind::WEBSOCKET | kind::IO | kind::HTTP => "The connection to the server failed",
+        kind::UNEXPECTED_FRAME | kind::SERIALIZATION => "The server received a malformed message",
+        kind::PAYLOAD_TOO_LARGE => "The server received a message larger than it accepts",
+        kind::DATABASE | kind::SETUP | kind::INSTANCE_MISMATCH | kind::OUT_OF_SEQUENCE => {
+            "The server ran into an internal error"
         }
+        kind::DATABASE_UNAVAILABLE => "The server is temporarily unavailable",
+        kind::ATTRIBUTES => "Invalid attributes",
+        kind::PADDING => "The server could not read a padded message",
+        kind::INVALID_USERNAME => "Invalid username",
+        kind::REREGISTRATION_REQUIRED => "The password has to be changed",
+        kind::NOT_AUTHENTICATED => "The password was not accepted",
+        kind::USERNAME_MISMATCH => "A password can only be changed for the user logged in",
+        kind::BUSY => "The server is busy, try again later",
+        kind::UNSUPPORTED => "The server does not offer that operation",
+        kind::INVALID_INVITE => "The invite is not valid, has expired or was already used",
+        kind::BOOTSTRAP_UNAVAILABLE => "The server can no longer be bootstrapped",
+        kind::BLOB_TOO_LARGE => "The blob is larger than the server accepts",
+        _ => "The server rejected the request",
     }
 }
 
@@ -0,0 +1,62 @@
+use std::{future::Future, pin::Pin};
+
+use tokio::sync::oneshot;
+
+use super::error::ClientError;
+
+/// A unit of key stretching work
+pub type KsfJob = Box<dyn FnOnce() + Send + 'static>;
+
+/// Decides where the client runs the steps that involve key stretching
+///
+/// The key stretching function is deliberately slow, on some platforms blocking the calling
+/// thread for that long isn't acceptable, so those steps are handed to the executor rather than
+/// being run in place.
+pub trait KsfExecutor: Send + Sync {
+    /// run `job` to completion, the returned future resolves once it has finished
+    fn execute(&self, job: KsfJob) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+/// Runs the work in place, blocking whatever called it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Inline;
+
+impl KsfExecutor for Inline {
+    fn execute(&self, job: KsfJob) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        job();
+        Box::pin(std::future::ready(()))
+    }
+}
+
+/// Runs the work on tokio's blocking thread pool, leaving the async workers free. What the
+/// client uses unless told otherwise
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpawnBlocking;
+
+impl KsfExecutor for SpawnBlocking {
+    fn execute(&self, job: KsfJob) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let handle = tokio::task::spawn_blocking(job);
+        Box::pin(async move {
+            // a panic in the job drops its result, which is reported by `run`
+            let _ = handle.await;
+        })
+    }
+}
+
+/// run `work` through `executor` and hand back its result
+pub(crate) async fn run<T, F>(executor: &dyn KsfExecutor, work: F) -> Result<T, ClientError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, ClientError> + Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    executor
+        .execute(Box::new(move || {
+            let _ = tx.send(work());
+        }))
+        .await;
+    match rx.await {
+        Ok(result) => result,
+        Err(_) => Err(std::io::Error::other("key stretching did not finish").into()),
+    }
+}
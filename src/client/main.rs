@@ -1,11 +1,18 @@
-use std::{fmt::Display, process::exit};
+use std::{fmt::Display, path::PathBuf, process::exit};
 
+use clap::{Parser, Subcommand, ValueEnum};
 use pants_gen::password::PasswordSpec;
-use tinap::client::Client;
+use tinap::{
+    client::{error::ClientError, Client},
+    ksf::Argon2Params,
+    outcome::{DeleteOutcome, RegistrationOutcome},
+};
 
 enum Choice {
     Register,
     Login,
+    ChangePassword,
+    Delete,
 }
 
 impl Display for Choice {
@@ -13,14 +20,266 @@ impl Display for Choice {
         match self {
             Self::Register => write!(f, "Register"),
             Self::Login => write!(f, "Login"),
+            Self::ChangePassword => write!(f, "Change password"),
+            Self::Delete => write!(f, "Delete account"),
         }
     }
 }
 
+/// environment variable the password is read from when there's no `--password-file`
+const PASSWORD_ENV: &str = "TINAP_PASSWORD";
+
+/// Register, log in, and manage accounts on a tinap server
+///
+/// Without a subcommand an interactive menu is shown
+#[derive(Debug, Parser)]
+#[command(name = "tinap-client", version)]
+struct Args {
+    /// make the registered account the server's first admin
+    #[arg(long, global = true)]
+    bootstrap_token: Option<String>,
+    /// print how long each phase of the exchange took
+    #[arg(long, global = true)]
+    timings: bool,
+    /// stretch passwords with the much costlier high security preset, an account registered
+    /// with it has to be used with it from then on
+    #[arg(long, global = true)]
+    high_security: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// check the server can be reached and speaks tinap
+    Doctor,
+    /// register a new account
+    Register(Credentials),
+    /// log in, exiting with 1 when the password is wrong
+    Login {
+        #[command(flatten)]
+        credentials: Credentials,
+        /// how to print the session and export keys
+        #[arg(long, value_enum, default_value_t = Output::Hex)]
+        output: Output,
+    },
+    /// delete an account, without asking for confirmation
+    Delete(Credentials),
+    /// log in and write everything the server holds about the account to a file, as json
+    Takeout {
+        #[command(flatten)]
+        credentials: Credentials,
+        #[arg(long)]
+        out: PathBuf,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+struct Credentials {
+    #[arg(long)]
+    username: String,
+    /// file holding the password, otherwise it's taken from `TINAP_PASSWORD` or the first line
+    /// of stdin
+    #[arg(long)]
+    password_file: Option<PathBuf>,
+}
+
+impl Credentials {
+    fn password(&self) -> std::io::Result<String> {
+        let password = match &self.password_file {
+            Some(path) => std::fs::read_to_string(path)?,
+            None => match std::env::var(PASSWORD_ENV) {
+                Ok(password) => password,
+                Err(_) => {
+                    let mut line = String::new();
+                    std::io::stdin().read_line(&mut line)?;
+                    line
+                }
+            },
+        };
+        // a trailing newline from the file or stdin isn't part of the password
+        Ok(password.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Output {
+    Hex,
+    Json,
+}
+
 #[tokio::main]
 async fn main() {
-    let client = Client::new("127.0.0.1".to_string(), 6969);
-    let choices = vec![Choice::Login, Choice::Register];
+    let args = Args::parse();
+    let params = if args.high_security {
+        Argon2Params::HIGH_SECURITY
+    } else {
+        Argon2Params::default()
+    };
+    let ksf = match params.to_ksf() {
+        Ok(ksf) => ksf,
+        Err(err) => {
+            eprintln!("Error occurred: `{err}`");
+            exit(1)
+        }
+    };
+    let client = Client::new("127.0.0.1".to_string(), 6969)
+        .with_bootstrap_token(args.bootstrap_token)
+        .with_ksf(ksf);
+    let success = match args.command {
+        None => {
+            interactive(client, args.timings, params).await;
+            return;
+        }
+        Some(Command::Doctor) => {
+            let report = client.preflight().await;
+            println!("{report}");
+            report.passed()
+        }
+        Some(Command::Register(credentials)) => {
+            let password = read_password(&credentials);
+            let (outcome, timings) = client
+                .register_user_timed(credentials.username, password)
+                .await;
+            if args.timings {
+                eprintln!("{timings}");
+            }
+            match outcome {
+                Ok(RegistrationOutcome::Created) => true,
+                Ok(RegistrationOutcome::AlreadyExists) => {
+                    eprintln!("User already registered");
+                    false
+                }
+                Err(err) => report(err),
+            }
+        }
+        Some(Command::Login {
+            credentials,
+            output,
+        }) => {
+            let password = read_password(&credentials);
+            let (auth, timings) = client
+                .authenticate_timed(credentials.username, password)
+                .await;
+            if args.timings {
+                eprintln!("{timings}");
+            }
+            match auth {
+                Ok(Some(auth)) => {
+                    match output {
+                        Output::Hex => {
+                            println!("session_key: {}", auth.session_key_hex());
+                            println!("export_key: {}", auth.export_key_hex());
+                            if let Some(token) = auth.session_token() {
+                                println!("session_token: {token}");
+                            }
+                        }
+                        Output::Json => println!(
+                            "{}",
+                            serde_json::json!({
+                                "session_key": auth.session_key_hex(),
+                                "export_key": auth.export_key_hex(),
+                                "session_token": auth.session_token(),
+                            })
+                        ),
+                    }
+                    true
+                }
+                Ok(None) => {
+                    eprintln!("Could not authenticate");
+                    false
+                }
+                Err(err) => report(err),
+            }
+        }
+        Some(Command::Delete(credentials)) => {
+            let password = read_password(&credentials);
+            match client.delete_user(credentials.username, password).await {
+                Ok(DeleteOutcome::Deleted) => true,
+                Ok(DeleteOutcome::NotAuthenticated) => {
+                    eprintln!("Could not authenticate");
+                    false
+                }
+                Err(err) => report(err),
+            }
+        }
+        Some(Command::Takeout { credentials, out }) => {
+            let password = read_password(&credentials);
+            let document = async {
+                let auth = client.authenticate(credentials.username, password).await?;
+                let Some(token) = auth.as_ref().and_then(|auth| auth.session_token()) else {
+                    return Ok(None);
+                };
+                client.takeout(token).await.map(Some)
+            };
+            match document.await {
+                Ok(Some(document)) => {
+                    let json = serde_json::to_string_pretty(&document).unwrap();
+                    match std::fs::write(&out, json) {
+                        Ok(()) => true,
+                        Err(err) => {
+                            eprintln!("Error writing `{}`: `{err}`", out.display());
+                            false
+                        }
+                    }
+                }
+                Ok(None) => {
+                    eprintln!("Could not authenticate");
+                    false
+                }
+                Err(err) => report(err),
+            }
+        }
+    };
+    if !success {
+        exit(1)
+    }
+}
+
+fn read_password(credentials: &Credentials) -> String {
+    match credentials.password() {
+        Ok(password) if !password.is_empty() => password,
+        Ok(_) => {
+            eprintln!("Password can't be empty");
+            exit(1)
+        }
+        Err(err) => {
+            eprintln!("Could not read the password: `{err}`");
+            exit(1)
+        }
+    }
+}
+
+/// print a failed operation along with whatever context was recorded for it
+fn report(err: ClientError) -> bool {
+    eprintln!("Error occurred: `{err}`");
+    if let Some(hint) = hint(&err) {
+        eprintln!("{hint}");
+    }
+    for entry in err.context() {
+        eprintln!("  {entry}");
+    }
+    false
+}
+
+/// what to do about `err`, for the errors where that isn't obvious from the message
+fn hint(err: &ClientError) -> Option<&'static str> {
+    match err.inner() {
+        ClientError::Timeout(_) | ClientError::DeadlineExceeded => {
+            Some("The server may be down or overloaded, try again later")
+        }
+        _ => None,
+    }
+}
+
+/// the menu driven client, for people at a terminal
+async fn interactive(client: Client, show_timings: bool, params: Argon2Params) {
+    let choices = vec![
+        Choice::Login,
+        Choice::Register,
+        Choice::ChangePassword,
+        Choice::Delete,
+    ];
     let action = inquire::Select::new("What would you like to do?", choices).prompt();
     let action = match action {
         Ok(choice) => choice,
@@ -32,51 +291,64 @@ async fn main() {
 
     match action {
         Choice::Register => {
-            let username = inquire::Text::new("Username:").prompt().unwrap();
-            let password = PasswordSpec::default().generate().unwrap();
-            println!("Your password is:");
-            println!("{password}");
-            let validator = move |input: &str| {
-                if input != password {
-                    Ok(inquire::validator::Validation::Invalid(
-                        "You must use the provided password".into(),
-                    ))
-                } else {
-                    Ok(inquire::validator::Validation::Valid)
-                }
-            };
-            let password_input = inquire::Password::new("Password:")
-                .with_display_mode(inquire::PasswordDisplayMode::Masked)
-                .with_help_message("Enter the provided password to confirm")
-                .without_confirmation()
-                .with_validator(validator)
+            let username = inquire::Text::new("Username:")
+                .with_validator(inquire::required!("Username can't be empty"))
                 .prompt()
                 .unwrap();
+            let measure = inquire::Confirm::new("Check how long logging in takes on this machine?")
+                .with_default(false)
+                .prompt()
+                .unwrap_or(false);
+            if measure {
+                match params.measure() {
+                    Ok(duration) => println!("Hashing the password takes about {duration:?}"),
+                    Err(err) => println!("Could not measure hashing: `{err}`"),
+                }
+            }
+            let password_input = generated_password("Password:");
 
             println!("Registering `{username}`");
 
-            match client.register(username, password_input).await {
-                Ok(auth) => {
-                    if auth {
-                        println!("User registered");
-                    } else {
-                        println!("User already registered");
-                    }
+            let (outcome, timings) = client
+                .register_confirmed_timed(username, password_input)
+                .await;
+            if show_timings {
+                println!("{timings}");
+            }
+            match outcome {
+                Ok(Some(confirm)) => {
+                    println!("User registered");
+                    println!("export_key: `{:?}`", confirm.export_key());
                 }
+                Ok(None) => println!("User already registered"),
                 Err(err) => {
                     println!("Error occurred: `{err}`");
+                    if let Some(hint) = hint(&err) {
+                        println!("{hint}");
+                    }
+                    for entry in err.context() {
+                        println!("  {entry}");
+                    }
                 }
             }
         }
         Choice::Login => {
-            let username = inquire::Text::new("Username:").prompt().unwrap();
+            let username = inquire::Text::new("Username:")
+                .with_validator(inquire::required!("Username can't be empty"))
+                .prompt()
+                .unwrap();
             let password = inquire::Password::new("Password:")
                 .with_display_mode(inquire::PasswordDisplayMode::Masked)
                 .without_confirmation()
+                .with_validator(inquire::required!("Password can't be empty"))
                 .prompt()
                 .unwrap();
 
-            match client.authenticate(username, password).await {
+            let (auth, timings) = client.authenticate_timed(username, password).await;
+            if show_timings {
+                println!("{timings}");
+            }
+            match auth {
                 Ok(auth) => {
                     if let Some(auth) = auth {
                         println!("User authorized");
@@ -88,16 +360,103 @@ async fn main() {
                 }
                 Err(err) => {
                     println!("Error occurred: `{err}`");
+                    if let Some(hint) = hint(&err) {
+                        println!("{hint}");
+                    }
+                    for entry in err.context() {
+                        println!("  {entry}");
+                    }
+                }
+            }
+        }
+        Choice::ChangePassword => {
+            let username = inquire::Text::new("Username:")
+                .with_validator(inquire::required!("Username can't be empty"))
+                .prompt()
+                .unwrap();
+            let old_password = inquire::Password::new("Current password:")
+                .with_display_mode(inquire::PasswordDisplayMode::Masked)
+                .without_confirmation()
+                .with_validator(inquire::required!("Password can't be empty"))
+                .prompt()
+                .unwrap();
+            let new_password = generated_password("New password:");
+
+            match client
+                .change_password(username, old_password, new_password)
+                .await
+            {
+                Ok(true) => println!("Password changed"),
+                Ok(false) => println!("Could not authenticate"),
+                Err(err) => {
+                    println!("Error occurred: `{err}`");
+                    if let Some(hint) = hint(&err) {
+                        println!("{hint}");
+                    }
+                    for entry in err.context() {
+                        println!("  {entry}");
+                    }
+                }
+            }
+        }
+        Choice::Delete => {
+            let username = inquire::Text::new("Username:")
+                .with_validator(inquire::required!("Username can't be empty"))
+                .prompt()
+                .unwrap();
+            let password = inquire::Password::new("Password:")
+                .with_display_mode(inquire::PasswordDisplayMode::Masked)
+                .without_confirmation()
+                .with_validator(inquire::required!("Password can't be empty"))
+                .prompt()
+                .unwrap();
+            let confirmed = inquire::Confirm::new(&format!("Delete `{username}` for good?"))
+                .with_default(false)
+                .prompt()
+                .unwrap_or(false);
+            if !confirmed {
+                return;
+            }
+
+            match client.delete_user(username, password).await {
+                Ok(DeleteOutcome::Deleted) => println!("Account deleted"),
+                Ok(DeleteOutcome::NotAuthenticated) => println!("Could not authenticate"),
+                Err(ClientError::FeatureUnsupported(_)) => {
+                    println!("This server does not let users delete their own account")
+                }
+                Err(err) => {
+                    println!("Error occurred: `{err}`");
+                    if let Some(hint) = hint(&err) {
+                        println!("{hint}");
+                    }
+                    for entry in err.context() {
+                        println!("  {entry}");
+                    }
                 }
             }
         }
     }
-    //
-    // let (username, password) = ("bobody".to_string(), "something".to_string());
-    // client
-    //     .register_user(username.clone(), password.clone())
-    //     .await
-    //     .unwrap();
-    // let auth = client.authenticate_user(username, password).await.unwrap();
-    // println!("Auth: {auth}");
+}
+
+/// generate a password and have the user type it back in, prompting with `message`
+fn generated_password(message: &str) -> String {
+    let password = PasswordSpec::default().generate().unwrap();
+    println!("Your password is:");
+    println!("{password}");
+    let validator = move |input: &str| {
+        if input != password {
+            Ok(inquire::validator::Validation::Invalid(
+                "You must use the provided password".into(),
+            ))
+        } else {
+            Ok(inquire::validator::Validation::Valid)
+        }
+    };
+    inquire::Password::new(message)
+        .with_display_mode(inquire::PasswordDisplayMode::Masked)
+        .with_help_message("Enter the provided password to confirm")
+        .without_confirmation()
+        .with_validator(validator)
+        .prompt()
+        .unwrap()
 }
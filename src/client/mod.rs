@@ -1,35 +1,502 @@
 pub mod authenticate;
+pub mod blob;
 pub mod error;
+pub mod executor;
+pub mod preflight;
 pub mod registration;
+pub mod retry;
+pub mod session;
+pub mod timings;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod trace;
+pub mod transport;
 
-use std::future::Future;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
 
-use authenticate::{AuthenticateConfirm, AuthenticateInitialize};
+use authenticate::{AuthenticateConfirm, AuthenticateFinish, AuthenticateInitialize, REDACTED};
 use error::ClientError;
-use fastwebsockets::{handshake, FragmentCollector, Frame, OpCode};
-use http_body_util::Empty;
+use executor::{KsfExecutor, SpawnBlocking};
+use fastwebsockets::{handshake, FragmentCollector, Frame, OpCode, WebSocketError};
+use http_body_util::{BodyExt, Empty, Full};
 use hyper::{
-    header::{CONNECTION, UPGRADE},
+    body::Bytes,
+    header::{AUTHORIZATION, CONNECTION, CONTENT_TYPE, SEC_WEBSOCKET_PROTOCOL, UPGRADE},
     upgrade::Upgraded,
-    Request,
+    Method, Request, StatusCode,
 };
 use hyper_util::rt::TokioIo;
+use opaque_ke::errors::ProtocolError;
 use pants_gen::password::PasswordSpec;
-use registration::RegistrationInitialize;
+use preflight::PreflightReport;
+use registration::{RegistrationConfirm, RegistrationInitialize};
+use retry::RetryPolicy;
+use session::Session;
+use subtle::ConstantTimeEq;
+use timings::{Phase, Timings};
+#[cfg(feature = "tls")]
+use tls::Tls;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time::timeout,
+};
+use trace::{Trace, TRACE_ENV};
+use transport::{Message, Transport};
+use zeroize::Zeroizing;
+
+use crate::{
+    attributes::Attributes,
+    clock::{Clock, SystemClock},
+    outcome::{DeleteOutcome, RegistrationOutcome},
+    padding::{pad, unpad, unpad_close, PADDING_PROTOCOL},
+    sequence::{self, MessageKind, Sequence, Side},
+    suite::Suite,
+    takeout::TakeoutDocument,
+    username,
+    wire::{
+        kind, parse_features, session_close, CloseReason, Feature, Operation, ATTRIBUTES_PATH,
+        BOOTSTRAP_HEADER, DONE, FEATURES_HEADER, MAX_MESSAGE_SIZE, NO_BLOB, RETRIEVE_PATH,
+        SESSION_PATH, STORE_PATH, TAKEOUT_PATH,
+    },
+    Identifiers, Scheme,
+};
 
-pub struct Client {
-    domain: String,
-    port: u16,
+/// how long connecting and upgrading to a websocket can take unless configured otherwise
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// how long to wait on any one message from the server unless configured otherwise
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// how long a whole operation can take unless configured otherwise
+pub const DEFAULT_OPERATION_DEADLINE: Duration = Duration::from_secs(120);
+/// how long to spend telling the server the client gave up, it likely isn't listening anyway
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Runs the exchanges against a server with [`Scheme`], or with another suite when made with
+/// [`Client::for_suite`]
+pub struct Client<CS: Suite = Scheme> {
+    targets: Vec<(String, u16)>,
+    last_good: AtomicUsize,
+    padding: bool,
+    /// most bytes a message from the server can take
+    max_message_size: usize,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    operation_deadline: Duration,
+    /// how to keep trying when no server could be reached, `None` to give up right away
+    retry: Option<RetryPolicy>,
+    /// the static public key logins have to end up with, any key when `None`
+    server_public_key: Option<Vec<u8>>,
+    /// identities registrations and logins are bound to
+    identifiers: Identifiers,
+    trace: bool,
+    bootstrap_token: Option<String>,
+    require_tls: bool,
+    ksf_executor: Arc<dyn KsfExecutor>,
+    legacy_salt: bool,
+    /// the costs passwords are stretched with
+    ksf: CS::Ksf,
+    /// what the server last connected to advertised, `None` until a server has said
+    features: Mutex<Option<Vec<Feature>>>,
+    clock: Arc<dyn Clock>,
+    /// when accounts the server reported as locked unlock, by username
+    lockouts: Mutex<HashMap<String, SystemTime>>,
+    /// connect over TLS with these roots, plain connections when `None`
+    #[cfg(feature = "tls")]
+    tls: Option<Tls>,
+    /// serve every connection from these routes in memory instead of connecting anywhere
+    #[cfg(feature = "test-util")]
+    loopback: Option<axum::Router>,
 }
 
 impl Client {
     pub fn new(domain: String, port: u16) -> Self {
-        Self { domain, port }
+        Self::with_targets(vec![(domain, port)])
+    }
+
+    /// a client that fails over between several servers
+    ///
+    /// connections are attempted in order, starting from whichever target last worked, and
+    /// only fail once every target has been tried. A failure partway through an exchange is
+    /// never retried elsewhere since the OPAQUE state can't be carried over to another server
+    pub fn with_targets(targets: Vec<(String, u16)>) -> Self {
+        Self::for_suite(targets)
+    }
+
+    /// a client that connects over TLS, trusting the webpki roots
+    #[cfg(feature = "tls")]
+    pub fn new_tls(domain: String, port: u16) -> Self {
+        Self::new(domain, port).with_tls(true)
+    }
+}
+
+impl<CS: Suite> Client<CS> {
+    /// [`Client::with_targets`] for a client running its exchanges with `CS`, which has to be
+    /// the suite the servers run, see [`Suite`]
+    pub fn for_suite(targets: Vec<(String, u16)>) -> Self {
+        Self {
+            targets,
+            last_good: AtomicUsize::new(0),
+            padding: false,
+            max_message_size: MAX_MESSAGE_SIZE,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            operation_deadline: DEFAULT_OPERATION_DEADLINE,
+            retry: None,
+            server_public_key: None,
+            identifiers: Identifiers::default(),
+            trace: std::env::var(TRACE_ENV).is_ok_and(|value| value == "1"),
+            bootstrap_token: None,
+            require_tls: false,
+            ksf_executor: Arc::new(SpawnBlocking),
+            legacy_salt: false,
+            ksf: CS::Ksf::default(),
+            features: Mutex::new(None),
+            clock: Arc::new(SystemClock),
+            lockouts: Mutex::new(HashMap::new()),
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "test-util")]
+            loopback: None,
+        }
+    }
+
+    /// connect over TLS, the server's certificate has to be for the domain being connected to
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, tls: bool) -> Self {
+        self.tls = tls.then(Tls::new);
+        self
+    }
+
+    /// also trust the certificates in `pem`, e.g. for test servers with a self-signed
+    /// certificate. Turns on TLS if it isn't already
+    #[cfg(feature = "tls")]
+    pub fn with_root_certificates(mut self, pem: &[u8]) -> Result<Self, ClientError> {
+        self.tls.get_or_insert_with(Tls::new).add_pem(pem)?;
+        Ok(self)
+    }
+
+    /// pad every message to a fixed size, the server has to be configured to pad as well
+    pub fn with_padding(mut self, padding: bool) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// refuse messages from the server over `max_message_size` bytes, defaults to
+    /// [`MAX_MESSAGE_SIZE`]. Only needs raising to retrieve blobs larger than that
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// only accept logins with a server whose static public key is `server_public_key`, others
+    /// fail with [`ClientError::ServerKeyMismatch`] before the client confirms anything. The key
+    /// can be pinned on first use from [`RegistrationConfirm::server_public_key`] or
+    /// [`AuthenticateConfirm::server_public_key`]
+    pub fn with_server_public_key(mut self, server_public_key: Vec<u8>) -> Self {
+        self.server_public_key = Some(server_public_key);
+        self
+    }
+
+    /// stretch passwords with `ksf`'s costs, e.g. from
+    /// [`Argon2::with_params`](crate::Argon2::with_params). The salt is
+    /// derived per user regardless. Accounts have to keep being used with the costs they were
+    /// registered with
+    pub fn with_ksf(mut self, ksf: CS::Ksf) -> Self {
+        self.ksf = ksf;
+        self
+    }
+
+    /// bind registrations and logins to `identifiers`, which have to match the server's. A user
+    /// has to log in with the identifiers they registered with
+    pub fn with_identifiers(mut self, identifiers: Identifiers) -> Self {
+        self.identifiers = identifiers;
+        self
+    }
+
+    /// serve every connection from `router` in memory, see
+    /// [`loopback_pair`](crate::loopback::loopback_pair)
+    #[cfg(feature = "test-util")]
+    pub(crate) fn with_loopback(mut self, router: axum::Router) -> Self {
+        self.loopback = Some(router);
+        self
+    }
+
+    /// give up on a server when connecting to it and upgrading to a websocket takes longer than
+    /// `connect_timeout`, defaults to [`DEFAULT_CONNECT_TIMEOUT`]
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// give up when the server takes longer than `read_timeout` to send its next message,
+    /// defaults to [`DEFAULT_READ_TIMEOUT`]
+    pub fn with_read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// give up on an operation that's still waiting on the server `operation_deadline` after it
+    /// started, however steadily the server answers. Defaults to [`DEFAULT_OPERATION_DEADLINE`]
+    pub fn with_operation_deadline(mut self, operation_deadline: Duration) -> Self {
+        self.operation_deadline = operation_deadline;
+        self
+    }
+
+    /// try connecting again following `retry` when no server could be reached, e.g. while one
+    /// restarts. Off unless set
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// run the steps involving key stretching through `executor`, by default they run on
+    /// tokio's blocking threads so a slow hash doesn't stall the other tasks on the runtime.
+    /// [`Inline`](executor::Inline) runs them in place instead
+    pub fn with_ksf_executor(mut self, executor: impl KsfExecutor + 'static) -> Self {
+        self.ksf_executor = Arc::new(executor);
+        self
+    }
+
+    /// log in with the all zero salt accounts registered before salts were derived from
+    /// usernames were stretched with
+    ///
+    /// registrations always use the per user salt, so [`Client::change_password`] with this set
+    /// moves an old account over
+    pub fn with_legacy_salt(mut self, legacy_salt: bool) -> Self {
+        self.legacy_salt = legacy_salt;
+        self
+    }
+
+    /// refuse to talk to servers over plain connections
+    pub fn with_require_tls(mut self, require_tls: bool) -> Self {
+        self.require_tls = require_tls;
+        self
+    }
+
+    /// send a bootstrap token with registrations, so the registered account becomes the
+    /// server's first admin
+    pub fn with_bootstrap_token(mut self, token: Option<String>) -> Self {
+        self.bootstrap_token = token;
+        self
+    }
+
+    /// record what happens on the connection and attach it to any error, see [`ClientError::context`]
+    pub fn with_trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// use `clock` for keeping track of how long accounts stay locked
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// whether the server last connected to advertised `feature`, always `false` before any
+    /// connection was made
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|features| features.contains(&feature))
+    }
+
+    /// check that every server can be reached and is answering, without sending any
+    /// credentials, to tell apart connection problems from login problems
+    pub async fn preflight(&self) -> PreflightReport {
+        let mut targets = Vec::with_capacity(self.targets.len());
+        for (domain, port) in &self.targets {
+            let report = preflight::check_target(
+                domain,
+                *port,
+                self.require_tls,
+                #[cfg(feature = "tls")]
+                self.tls.as_ref(),
+                self.clock.as_ref(),
+            )
+            .await;
+            targets.push(report);
+        }
+        PreflightReport { targets }
+    }
+
+    /// fail early when the server is known not to support `feature`, if it's unknown the server
+    /// gets to decide
+    fn require(&self, feature: Feature) -> Result<(), ClientError> {
+        match &*self.features.lock().unwrap() {
+            Some(features) if !features.contains(&feature) => {
+                Err(ClientError::FeatureUnsupported(feature))
+            }
+            _ => Ok(()),
+        }
     }
 }
 
 struct SpawnExecutor;
 
+/// The connection handed over after a login, see [`Client::authenticate_then`]
+pub type AppSocket = FragmentCollector<TokioIo<Upgraded>>;
+
+/// A websocket to the server
+///
+/// in a session the server ends each operation with a text frame in place of the close frame,
+/// which is read back as the close frame it stands for so operations run the same either way
+struct Connection {
+    ws: AppSocket,
+    session: bool,
+    /// pad every message to a fixed size
+    padding: bool,
+    /// how long to wait on any one frame from the server
+    read_timeout: Duration,
+    /// when the operation running over the connection has to be done by
+    deadline: Option<Instant>,
+}
+
+impl Connection {
+    /// read the next frame, giving up when it takes longer than the read timeout or the
+    /// operation's deadline passes first
+    async fn read_frame(&mut self) -> Result<Frame<'static>, ClientError> {
+        let limit = match self.deadline {
+            Some(deadline) => self
+                .read_timeout
+                .min(deadline.saturating_duration_since(Instant::now())),
+            None => self.read_timeout,
+        };
+        let frame = match timeout(limit, self.ws.read_frame()).await {
+            Ok(frame) => frame?,
+            Err(_) => {
+                let err = match self.deadline {
+                    Some(deadline) if Instant::now() >= deadline => ClientError::DeadlineExceeded,
+                    _ => ClientError::Timeout(Phase::RoundTrip),
+                };
+                // the server is left partway through the exchange, so nothing else can follow
+                // over this connection
+                let _ = timeout(CLOSE_TIMEOUT, self.close(err.close_reason())).await;
+                return Err(err);
+            }
+        };
+        if self.session && frame.opcode == OpCode::Text {
+            return match session_close(&frame.payload) {
+                Some(payload) => Ok(Frame::new(true, OpCode::Close, None, payload.into())),
+                None => Err(frame.into()),
+            };
+        }
+        Ok(frame)
+    }
+
+    async fn write_frame(&mut self, frame: Frame<'_>) -> Result<(), WebSocketError> {
+        self.ws.write_frame(frame).await
+    }
+}
+
+impl Transport for Connection {
+    async fn send(&mut self, data: Vec<u8>) -> Result<(), ClientError> {
+        let data = if self.padding { pad(&data) } else { data };
+        self.write_frame(Frame::binary(data.into())).await?;
+        Ok(())
+    }
+
+    /// protocol data with any padding stripped, anything other than that or the server closing
+    /// gets the connection closed
+    async fn recv(&mut self) -> Result<Message, ClientError> {
+        let frame = self.read_frame().await?;
+        let data = match frame.opcode {
+            OpCode::Close => return Ok(Message::Close(close_reason(&frame, self.padding))),
+            OpCode::Binary if self.padding => unpad(&frame.payload).map_err(ClientError::from),
+            OpCode::Binary => Ok(frame.payload.to_vec()),
+            _ => Err(frame.into()),
+        };
+        if let Err(err) = &data {
+            self.close(err.close_reason()).await?;
+        }
+        Ok(Message::Data(data?))
+    }
+
+    /// close and wait for the server to answer, dropping the connection straight away can leave
+    /// the server failing to answer instead of reading why the client closed
+    async fn close(&mut self, reason: CloseReason) -> Result<(), ClientError> {
+        self.write_frame(Frame::close(reason.code, &reason.to_payload()))
+            .await?;
+        let answered = async {
+            while let Ok(frame) = self.ws.read_frame().await {
+                if frame.opcode == OpCode::Close {
+                    break;
+                }
+            }
+        };
+        let _ = timeout(CLOSE_TIMEOUT, answered).await;
+        Ok(())
+    }
+
+    /// wait out the server ending an operation whose outcome is already known here, so the next
+    /// operation in a session doesn't read it. Outside of a session the connection is simply
+    /// dropped
+    async fn skip_end(&mut self) {
+        if self.session {
+            let _ = self.read_frame().await;
+        }
+    }
+}
+
+/// the reason the server closed with in `frame`, with the padding stripped when `padding`
+fn close_reason(frame: &Frame, padding: bool) -> CloseReason {
+    if padding {
+        CloseReason::from_payload(unpad_close(&frame.payload))
+    } else {
+        CloseReason::from_payload(&frame.payload)
+    }
+}
+
+/// error for `message` arriving where the server should have closed
+fn unexpected(message: Message) -> ClientError {
+    match message {
+        Message::Data(data) => ClientError::UnexpectedFrame(OpCode::Binary, data),
+        Message::Close(reason) => ClientError::UnknownClose(reason),
+    }
+}
+
+/// whether the connection simply went away, as opposed to something going wrong in the exchange
+fn is_disconnect(err: &WebSocketError) -> bool {
+    matches!(
+        err,
+        WebSocketError::UnexpectedEOF
+            | WebSocketError::ConnectionClosed
+            | WebSocketError::IoError(_)
+    )
+}
+
+/// what a registration that handed back `confirm` amounts to
+pub(crate) fn registration_outcome(confirm: Option<RegistrationConfirm>) -> RegistrationOutcome {
+    match confirm {
+        Some(_) => RegistrationOutcome::Created,
+        None => RegistrationOutcome::AlreadyExists,
+    }
+}
+
+/// whether the server never answered, having gone away or gone quiet
+fn went_silent(err: &ClientError) -> bool {
+    match err {
+        ClientError::Websocket(err) => is_disconnect(err),
+        ClientError::IOError(_) | ClientError::Timeout(_) | ClientError::DeadlineExceeded => true,
+        _ => false,
+    }
+}
+
+/// the next frame on a connection handed over after a login, giving up after `limit`
+async fn read_within(ws: &mut AppSocket, limit: Duration) -> Result<Frame<'static>, ClientError> {
+    match timeout(limit, ws.read_frame()).await {
+        Ok(frame) => Ok(frame?),
+        Err(_) => Err(ClientError::Timeout(Phase::RoundTrip)),
+    }
+}
+
 impl<Fut> hyper::rt::Executor<Fut> for SpawnExecutor
 where
     Fut: Future + Send + 'static,
@@ -40,19 +507,59 @@ where
     }
 }
 
+/// send `request` over `stream`, only successful answers give back their body
+async fn send_request<S>(stream: S, request: Request<Full<Bytes>>) -> Result<Bytes, ClientError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::task::spawn(conn);
+    let response = sender.send_request(request).await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+    match status {
+        status if status.is_success() => Ok(body),
+        StatusCode::UNAUTHORIZED => Err(ClientError::NotAuthenticated),
+        status => Err(ClientError::Rejected(
+            status.as_u16(),
+            String::from_utf8_lossy(&body).into_owned(),
+        )),
+    }
+}
+
+/// what the close ending a deletion says happened, anything but a [`DeleteOutcome`] from a normal
+/// close is an [`ClientError::UnexpectedFrame`]
+fn delete_outcome(reason: CloseReason) -> Result<DeleteOutcome, ClientError> {
+    if !reason.is_normal() {
+        return Err(ClientError::from_close(reason));
+    }
+    let text = reason.text();
+    // servers from before the outcome was sent closed with `done`, or a single 1 before that
+    if text == DONE || text == "\u{1}" {
+        return Ok(DeleteOutcome::Deleted);
+    }
+    serde_json::from_str(&text)
+        .map_err(|_| ClientError::UnexpectedFrame(OpCode::Close, text.into_bytes()))
+}
+
 pub struct LoginStart {
     username: String,
-    password: String,
+    password: Zeroizing<String>,
 }
 
 impl LoginStart {
     pub fn new(username: String) -> Self {
         let password = PasswordSpec::default().generate().unwrap();
-        Self { username, password }
+        Self {
+            username,
+            password: Zeroizing::new(password),
+        }
     }
 
+    /// the login when `password` is the generated one, compared in constant time
     pub fn confirm(self, password: String) -> Option<LoginInfo> {
-        if password == self.password {
+        let password = Zeroizing::new(password);
+        if password.as_bytes().ct_eq(self.password.as_bytes()).into() {
             Some(LoginInfo {
                 username: self.username,
                 password: self.password,
@@ -63,30 +570,182 @@ impl LoginStart {
     }
 }
 
+impl Debug for LoginStart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoginStart")
+            .field("username", &self.username)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
 pub struct LoginInfo {
     username: String,
-    password: String,
+    password: Zeroizing<String>,
 }
 
 impl LoginInfo {
-    pub async fn authenticate(
+    pub async fn authenticate<CS: Suite>(
         self,
-        client: Client,
+        client: Client<CS>,
     ) -> Result<Option<AuthenticateConfirm>, ClientError> {
-        client.authenticate(self.username, self.password).await
+        // moved out rather than copied, the emptied original is wiped either way
+        let mut password = self.password;
+        client
+            .authenticate(self.username, std::mem::take(&mut *password))
+            .await
     }
 }
 
-impl Client {
-    async fn connect(
+impl Debug for LoginInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoginInfo")
+            .field("username", &self.username)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
+impl<CS: Suite> Client<CS> {
+    /// open a connection that runs any number of operations one after another, saving a
+    /// handshake for every operation after the first, see [`Session`]
+    pub async fn session(&self) -> Result<Session<'_, CS>, ClientError> {
+        self.require(Feature::Session)?;
+        let mut trace = Trace::new(self.trace);
+        let ws = self.connect(SESSION_PATH, &mut trace).await;
+        let mut ws = trace.finish(ws)?;
+        ws.session = true;
+        Ok(Session { client: self, ws })
+    }
+
+    /// the connection to run `operation` over, `ws` when in a session after telling the server
+    /// which operation follows, otherwise a new connection to the operation's own endpoint
+    async fn open<'w>(
         &self,
+        ws: Option<&'w mut Connection>,
+        connected: &'w mut Option<Connection>,
+        operation: Operation,
+        trace: &mut Trace,
+    ) -> Result<&'w mut Connection, ClientError> {
+        let deadline = self.deadline();
+        let Some(ws) = ws else {
+            let mut ws = self.connect(operation.path(), trace).await?;
+            ws.deadline = deadline;
+            return Ok(connected.insert(ws));
+        };
+        ws.deadline = deadline;
+        let data = bincode::serialize(&operation).expect("operations always serialize");
+        trace.sent("operation", data.len());
+        ws.send(data).await?;
+        Ok(ws)
+    }
+
+    /// when an operation starting now has to be done by, `None` for deadlines too far off to
+    /// represent
+    fn deadline(&self) -> Option<Instant> {
+        Instant::now().checked_add(self.operation_deadline)
+    }
+
+    async fn connect(&self, endpoint: &str, trace: &mut Trace) -> Result<Connection, ClientError> {
+        let Some(retry) = self.retry else {
+            return self.connect_once(endpoint, trace).await;
+        };
+        let mut attempt = 1;
+        loop {
+            let err = match self.connect_once(endpoint, trace).await {
+                Err(err) if err.is_unreachable() => err,
+                result => return result,
+            };
+            if attempt >= retry.max_attempts {
+                return Err(ClientError::RetriesExhausted(attempt, Box::new(err)));
+            }
+            tokio::time::sleep(retry.delay(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    /// go through the targets once, starting from the last one that worked
+    async fn connect_once(
+        &self,
+        endpoint: &str,
+        trace: &mut Trace,
+    ) -> Result<Connection, ClientError> {
+        let start = self.last_good.load(Ordering::Relaxed);
+        let mut failures = Vec::new();
+        for offset in 0..self.targets.len() {
+            let index = (start + offset) % self.targets.len();
+            let (domain, port) = &self.targets[index];
+            let connected = timeout(
+                self.connect_timeout,
+                self.connect_to(domain, *port, endpoint, trace),
+            )
+            .await
+            .unwrap_or(Err(ClientError::Timeout(Phase::Connect)));
+            match connected {
+                Ok(ws) => {
+                    self.last_good.store(index, Ordering::Relaxed);
+                    return Ok(ws);
+                }
+                Err(err) => failures.push((format!("{domain}:{port}"), err)),
+            }
+        }
+
+        // with a single server there's nothing to aggregate
+        if failures.len() == 1 {
+            let (_, err) = failures.remove(0);
+            return Err(err);
+        }
+        Err(ClientError::AllTargetsFailed(failures))
+    }
+
+    async fn connect_to(
+        &self,
+        domain: &str,
+        port: u16,
         endpoint: &str,
-    ) -> Result<FragmentCollector<TokioIo<Upgraded>>, ClientError> {
-        let dest = format!("{}:{}", self.domain, self.port);
+        trace: &mut Trace,
+    ) -> Result<Connection, ClientError> {
+        #[cfg(feature = "test-util")]
+        if let Some(router) = &self.loopback {
+            let stream = crate::loopback::connect(router.clone());
+            return self
+                .handshake(stream, "http", domain, port, endpoint, trace)
+                .await;
+        }
+        let dest = format!("{domain}:{port}");
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            let stream = tokio::net::TcpStream::connect(&dest).await?;
+            let stream = tls.connect(domain, stream).await?;
+            return self
+                .handshake(stream, "https", domain, port, endpoint, trace)
+                .await;
+        }
+        if self.require_tls {
+            return Err(ClientError::InsecureTransport(dest));
+        }
         let stream = tokio::net::TcpStream::connect(&dest).await?;
+        self.handshake(stream, "http", domain, port, endpoint, trace)
+            .await
+    }
+
+    /// upgrade `stream` to a websocket for `endpoint`
+    async fn handshake<S>(
+        &self,
+        stream: S,
+        scheme: &str,
+        domain: &str,
+        port: u16,
+        endpoint: &str,
+        trace: &mut Trace,
+    ) -> Result<Connection, ClientError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let dest = format!("{domain}:{port}");
         let req = Request::builder()
             .method("GET")
-            .uri(format!("http://{dest}/{endpoint}"))
+            .uri(format!("{scheme}://{dest}/{endpoint}"))
             .header("Host", dest)
             .header(UPGRADE, "websocket")
             .header(CONNECTION, "upgrade")
@@ -94,65 +753,270 @@ impl Client {
                 "Sec-WebSocket-Key",
                 fastwebsockets::handshake::generate_key(),
             )
-            .header("Sec-WebSocket-Version", "13")
-            .body(Empty::<hyper::body::Bytes>::new())?;
+            .header("Sec-WebSocket-Version", "13");
+        let req = if self.padding {
+            req.header(SEC_WEBSOCKET_PROTOCOL, PADDING_PROTOCOL)
+        } else {
+            req
+        };
+        let req = match &self.bootstrap_token {
+            Some(token) if endpoint == "registration" => req.header(BOOTSTRAP_HEADER, token),
+            _ => req,
+        };
+        let req = req.body(Empty::<hyper::body::Bytes>::new())?;
 
-        let (ws, _) = handshake::client(&SpawnExecutor, req, stream).await?;
-        Ok(FragmentCollector::new(ws))
+        let (mut ws, response) = handshake::client(&SpawnExecutor, req, stream).await?;
+        ws.set_max_message_size(self.max_message_size);
+        let subprotocol = response
+            .headers()
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|protocol| protocol.to_str().ok());
+        trace.connected(&format!("{domain}:{port}/{endpoint}"), subprotocol);
+        // servers from before features were advertised leave the header out
+        if let Some(features) = response
+            .headers()
+            .get(FEATURES_HEADER)
+            .and_then(|features| features.to_str().ok())
+        {
+            *self.features.lock().unwrap() = Some(parse_features(features));
+        }
+        if self.padding && response.headers().get(SEC_WEBSOCKET_PROTOCOL).is_none() {
+            return Err(ClientError::PaddingRefused);
+        }
+        Ok(Connection {
+            ws: FragmentCollector::new(ws),
+            session: false,
+            padding: self.padding,
+            read_timeout: self.read_timeout,
+            deadline: None,
+        })
     }
 
-    async fn close(
-        mut ws: fastwebsockets::FragmentCollector<TokioIo<Upgraded>>,
-        err: &ClientError,
+    /// send the next message of the exchange
+    async fn send<T: Transport>(
+        &self,
+        ws: &mut T,
+        seq: &mut Sequence,
+        trace: &mut Trace,
+        kind: MessageKind,
+        data: Vec<u8>,
     ) -> Result<(), ClientError> {
-        ws.write_frame(Frame::close(err.to_code(), err.to_string().as_bytes()))
-            .await?;
-        Ok(())
+        seq.sent(kind)?;
+        trace.sent(kind.name(), data.len());
+        ws.send(data).await
     }
 
-    pub async fn register(&self, username: String, password: String) -> Result<bool, ClientError> {
-        let mut ws = self.connect("registration").await?;
-        let state = RegistrationInitialize::new(username, password)?;
-
-        let data = state.to_data();
-        ws.write_frame(Frame::new(true, OpCode::Binary, None, data.into()))
-            .await?;
-        let frame = ws.read_frame().await?;
+    /// the next message of the exchange, which has to be `kind`. The server closing before then
+    /// is reported as the error it closed with
+    async fn receive<T: Transport>(
+        ws: &mut T,
+        seq: &mut Sequence,
+        trace: &mut Trace,
+        kind: MessageKind,
+    ) -> Result<Vec<u8>, ClientError> {
+        let message = ws.recv().await?;
+        trace.received(&message);
+        match message {
+            Message::Data(data) => {
+                seq.received(kind)?;
+                Ok(data)
+            }
+            Message::Close(reason) => Err(ClientError::from_close(reason)),
+        }
+    }
 
-        match frame.opcode {
-            OpCode::Close => return Err(ClientError::ClosedEarly),
-            OpCode::Binary => {}
-            _ => {
-                let err = frame.into();
-                Self::close(ws, &err).await?;
-                return Err(err);
+    /// the server ending the exchange, along with its reason
+    async fn verdict<T: Transport>(
+        ws: &mut T,
+        trace: &mut Trace,
+    ) -> Result<CloseReason, ClientError> {
+        let message = ws.recv().await?;
+        trace.received(&message);
+        match message {
+            Message::Close(reason) => Ok(reason),
+            message => {
+                let err = unexpected(message);
+                ws.close(err.close_reason()).await?;
+                Err(err)
             }
         }
+    }
 
-        let registration_response_bytes = frame.payload.to_vec();
-        let state = match state.step(registration_response_bytes) {
-            Ok(res) => res,
+    /// pass `result` along, closing the connection with the error when it failed
+    async fn or_close<T: Transport, V>(
+        ws: &mut T,
+        result: Result<V, ClientError>,
+    ) -> Result<V, ClientError> {
+        match result {
+            Ok(value) => Ok(value),
             Err(err) => {
-                Self::close(ws, &err).await?;
-                return Err(err);
+                ws.close(err.close_reason()).await?;
+                Err(err)
             }
-        };
+        }
+    }
+
+    #[deprecated(note = "use `register_user`, which tells apart why a registration didn't happen")]
+    pub async fn register(&self, username: String, password: String) -> Result<bool, ClientError> {
+        let outcome = self.register_user(username, password).await?;
+        Ok(outcome == RegistrationOutcome::Created)
+    }
+
+    pub async fn register_user(
+        &self,
+        username: String,
+        password: String,
+    ) -> Result<RegistrationOutcome, ClientError> {
+        self.register_user_timed(username, password).await.0
+    }
+
+    /// [`Client::register_user`], also reporting how long each phase of the exchange took
+    pub async fn register_user_timed(
+        &self,
+        username: String,
+        password: String,
+    ) -> (Result<RegistrationOutcome, ClientError>, Timings) {
+        let (result, timings) = self.register_confirmed_timed(username, password).await;
+        (result.map(registration_outcome), timings)
+    }
+
+    /// [`Client::register_user`], handing back the keys the registration ended with, `None`
+    /// when the username was already taken
+    pub async fn register_confirmed(
+        &self,
+        username: String,
+        password: String,
+    ) -> Result<Option<RegistrationConfirm>, ClientError> {
+        self.register_confirmed_timed(username, password).await.0
+    }
+
+    /// [`Client::register_confirmed`], also reporting how long each phase of the exchange took
+    pub async fn register_confirmed_timed(
+        &self,
+        username: String,
+        password: String,
+    ) -> (Result<Option<RegistrationConfirm>, ClientError>, Timings) {
+        let mut trace = Trace::new(self.trace);
+        let result = self
+            .run_registration(username, password, None, None, &mut trace)
+            .await;
+        trace.finish_timed(result)
+    }
 
-        let data = state.to_data();
-        ws.write_frame(Frame::new(true, OpCode::Binary, None, data.into()))
+    /// [`Client::register_user`] on a server that only lets invited users register, `invite`
+    /// is used up when the account is created
+    pub async fn register_with_invite(
+        &self,
+        username: String,
+        password: String,
+        invite: String,
+    ) -> Result<RegistrationOutcome, ClientError> {
+        let mut trace = Trace::new(self.trace);
+        let result = self
+            .run_registration(username, password, Some(invite), None, &mut trace)
+            .await;
+        trace.finish(result).map(registration_outcome)
+    }
+
+    async fn run_registration(
+        &self,
+        username: String,
+        password: String,
+        invite: Option<String>,
+        ws: Option<&mut Connection>,
+        trace: &mut Trace,
+    ) -> Result<Option<RegistrationConfirm>, ClientError> {
+        let state = RegistrationInitialize::<CS>::for_suite(username, password)?
+            .with_invite(invite)
+            .with_identifiers(self.identifiers.clone())
+            .with_ksf(self.ksf.clone());
+        let mut connected = None;
+        let ws = self
+            .open(ws, &mut connected, Operation::Registration, trace)
             .await?;
-        let frame = ws.read_frame().await?;
+        self.registration_exchange(ws, state, trace).await
+    }
 
-        match frame.opcode {
-            OpCode::Close => {}
-            _ => {
-                let err = frame.into();
-                Self::close(ws, &err).await?;
-                return Err(err);
-            }
+    /// [`Client::register_user`] over `transport`, which has to reach the server's registration
+    /// endpoint
+    pub async fn register_over<T: Transport>(
+        &self,
+        transport: &mut T,
+        username: String,
+        password: String,
+    ) -> Result<RegistrationOutcome, ClientError> {
+        let mut trace = Trace::new(self.trace);
+        let result = async {
+            let state = RegistrationInitialize::<CS>::for_suite(username, password)?
+                .with_identifiers(self.identifiers.clone())
+                .with_ksf(self.ksf.clone());
+            self.registration_exchange(transport, state, &mut trace)
+                .await
         }
+        .await;
+        trace.finish(result).map(registration_outcome)
+    }
 
-        Ok(true)
+    /// run the whole registration over `ws`, which is already set up for it, `None` when the
+    /// username was already taken
+    async fn registration_exchange<T: Transport>(
+        &self,
+        ws: &mut T,
+        state: RegistrationInitialize<CS>,
+        trace: &mut Trace,
+    ) -> Result<Option<RegistrationConfirm>, ClientError> {
+        let mut seq = Sequence::new(sequence::REGISTRATION, Side::Client);
+        let (reason, confirm) = self.upload(ws, state, &mut seq, trace).await?;
+
+        if reason.is_normal() {
+            Ok(Some(confirm))
+        } else if reason.message == kind::USER_ALREADY_EXISTS {
+            Ok(None)
+        } else {
+            Err(ClientError::from_close(reason))
+        }
+    }
+
+    /// run the registration exchange up to the server's verdict, which is the close reason
+    /// handed back along with the keys the registration ends with if the server took it
+    async fn upload<T: Transport>(
+        &self,
+        ws: &mut T,
+        state: RegistrationInitialize<CS>,
+        seq: &mut Sequence,
+        trace: &mut Trace,
+    ) -> Result<(CloseReason, RegistrationConfirm), ClientError> {
+        self.send(
+            ws,
+            seq,
+            trace,
+            MessageKind::RegistrationRequest,
+            state.to_data(),
+        )
+        .await?;
+        let registration_response_bytes =
+            Self::receive(ws, seq, trace, MessageKind::RegistrationResponse).await?;
+
+        let stepped = executor::run(self.ksf_executor.as_ref(), move || {
+            state.step(registration_response_bytes)
+        })
+        .await;
+        let state = Self::or_close(ws, stepped).await?;
+
+        let upload = state.to_data();
+        let confirm = state.step();
+        self.send(ws, seq, trace, MessageKind::RegistrationUpload, upload)
+            .await?;
+        let reason = match Self::verdict(ws, trace).await {
+            Ok(reason) => reason,
+            // the upload went out but the server never confirmed it, so it may or may not be stored
+            Err(err) if went_silent(&err) => return Err(ClientError::OutcomeUnknown),
+            Err(err) => return Err(err),
+        };
+        if reason.is_normal() {
+            seq.received(MessageKind::Done)?;
+        }
+        Ok((reason, confirm))
     }
 
     pub async fn authenticate(
@@ -160,75 +1024,665 @@ impl Client {
         username: String,
         password: String,
     ) -> Result<Option<AuthenticateConfirm>, ClientError> {
-        // setup authentication
-        let mut ws = self.connect("authenticate").await?;
-        let state = AuthenticateInitialize::new(username, password)?;
-        let data = state.to_data();
+        self.authenticate_timed(username, password).await.0
+    }
 
-        // send and receive with server
-        ws.write_frame(Frame::new(true, OpCode::Binary, None, data.into()))
-            .await?;
-        let frame = ws.read_frame().await?;
-        match frame.opcode {
-            OpCode::Binary => {}
-            OpCode::Close => {
-                return Err(ClientError::ClosedEarly);
-            }
+    /// [`Client::authenticate`], also reporting how long each phase of the exchange took
+    pub async fn authenticate_timed(
+        &self,
+        username: String,
+        password: String,
+    ) -> (Result<Option<AuthenticateConfirm>, ClientError>, Timings) {
+        let mut trace = Trace::new(self.trace);
+        let result = self
+            .checked_authenticate(username, password, false, None, &mut trace)
+            .await;
+        trace.finish_timed(result)
+    }
+
+    /// check a username and password without logging in, runs the full exchange but the server
+    /// skips everything that would normally follow a login
+    pub async fn verify(&self, username: String, password: String) -> Result<bool, ClientError> {
+        self.require(Feature::VerifyOnly)?;
+        let mut trace = Trace::new(self.trace);
+        let result = self
+            .checked_authenticate(username, password, true, None, &mut trace)
+            .await;
+        Ok(trace.finish(result)?.is_some())
+    }
+
+    /// [`Client::run_authenticate`], without contacting the server while the account is known
+    /// to be locked
+    async fn checked_authenticate(
+        &self,
+        username: String,
+        password: String,
+        verify_only: bool,
+        ws: Option<&mut Connection>,
+        trace: &mut Trace,
+    ) -> Result<Option<AuthenticateConfirm>, ClientError> {
+        self.check_lockout(&username)?;
+        let state = AuthenticateInitialize::<CS>::for_suite(username.clone(), password)?
+            .with_verify_only(verify_only)
+            .with_legacy_salt(self.legacy_salt)
+            .with_identifiers(self.identifiers.clone())
+            .with_ksf(self.ksf.clone());
+        let result = self.run_authenticate(state, ws, trace).await;
+        self.track_lockout(&username, result.as_ref().map(Option::is_some));
+        result
+    }
+
+    /// fail straight away while `username` is locked
+    fn check_lockout(&self, username: &str) -> Result<(), ClientError> {
+        let mut lockouts = self.lockouts.lock().unwrap();
+        let Some(until) = lockouts.get(username) else {
+            return Ok(());
+        };
+        match until.duration_since(self.clock.now()) {
+            Ok(retry_after) if !retry_after.is_zero() => Err(ClientError::AccountLocked {
+                retry_after: Some(retry_after),
+            }),
             _ => {
-                let err = frame.into();
-                Self::close(ws, &err).await?;
-                return Err(err);
+                lockouts.remove(username);
+                Ok(())
             }
         }
+    }
 
-        // advance state
-        let credential_response_bytes = frame.payload.to_vec();
-        let state = match state.step(credential_response_bytes) {
-            Ok(res) => res,
-            Err(err) => {
-                Self::close(ws, &err).await?;
-                return Err(err);
+    /// remember when the server says `username` is locked, `outcome` is whether a login went
+    /// through, which clears any lockout
+    fn track_lockout(&self, username: &str, outcome: Result<bool, &ClientError>) {
+        let mut lockouts = self.lockouts.lock().unwrap();
+        match outcome.map_err(ClientError::inner) {
+            Ok(true) => {
+                lockouts.remove(username);
             }
+            Err(ClientError::AccountLocked {
+                retry_after: Some(retry_after),
+            }) => {
+                lockouts.insert(username.into(), self.clock.now() + *retry_after);
+            }
+            _ => {}
+        }
+    }
+
+    /// log in over `endpoint`, which the server has to serve with
+    /// [`ws_authenticate_with`](crate::server::ws_authenticate_with), and get back the still
+    /// open connection for whatever the application does next
+    ///
+    /// `None` when the login didn't go through, the connection is closed then
+    pub async fn authenticate_then(
+        &self,
+        endpoint: &str,
+        username: String,
+        password: String,
+    ) -> Result<Option<(AuthenticateConfirm, AppSocket)>, ClientError> {
+        self.check_lockout(&username)?;
+        let state = AuthenticateInitialize::<CS>::for_suite(username.clone(), password)?
+            .with_legacy_salt(self.legacy_salt)
+            .with_identifiers(self.identifiers.clone())
+            .with_ksf(self.ksf.clone());
+        let mut trace = Trace::new(self.trace);
+        let result = async {
+            let deadline = self.deadline();
+            let mut ws = self.connect(endpoint, &mut trace).await?;
+            ws.deadline = deadline;
+            // the login ends with a text frame like in a session, leaving the connection open
+            ws.session = true;
+            let confirm = self
+                .authentication_exchange(&mut ws, state, &mut trace)
+                .await?;
+            Ok::<_, ClientError>(confirm.map(|confirm| (confirm, ws.ws)))
+        }
+        .await;
+        self.track_lockout(&username, result.as_ref().map(Option::is_some));
+        trace.finish(result)
+    }
+
+    /// keep `blob` on the server for `username`, replacing whatever was stored before
+    ///
+    /// the blob is encrypted here with a key derived from the login's export key, which only
+    /// the password can recreate, so the server only ever sees ciphertext
+    pub async fn store_blob(
+        &self,
+        username: String,
+        password: String,
+        blob: &[u8],
+    ) -> Result<(), ClientError> {
+        self.require(Feature::Blobs)?;
+        // the blob is bound to the name the server knows the user by
+        let owner = username::normalize(&username)?;
+        let Some((confirm, mut ws)) = self
+            .authenticate_then(STORE_PATH, username, password)
+            .await?
+        else {
+            return Err(ClientError::NotAuthenticated);
         };
-        let data = state.to_data();
+        let sealed = blob::seal(confirm.export_key(), owner.as_bytes(), blob);
+        ws.write_frame(Frame::binary(sealed.into())).await?;
+        let frame = read_within(&mut ws, self.read_timeout).await?;
+        if frame.opcode != OpCode::Close {
+            return Err(frame.into());
+        }
+        let reason = close_reason(&frame, self.padding);
+        if !reason.is_normal() {
+            return Err(ClientError::from_close(reason));
+        }
+        Ok(())
+    }
 
-        // send and receive with server
-        ws.write_frame(Frame::new(true, OpCode::Binary, None, data.into()))
-            .await?;
-        let frame = ws.read_frame().await?;
+    /// the blob stored for `username` with [`Client::store_blob`], `None` when there isn't one
+    pub async fn retrieve_blob(
+        &self,
+        username: String,
+        password: String,
+    ) -> Result<Option<Vec<u8>>, ClientError> {
+        self.require(Feature::Blobs)?;
+        let owner = username::normalize(&username)?;
+        let Some((confirm, mut ws)) = self
+            .authenticate_then(RETRIEVE_PATH, username, password)
+            .await?
+        else {
+            return Err(ClientError::NotAuthenticated);
+        };
+        let frame = read_within(&mut ws, self.read_timeout).await?;
         match frame.opcode {
-            OpCode::Binary => {}
-            OpCode::Close => return Err(ClientError::ClosedEarly),
-            _ => {
-                let err = frame.into();
-                Self::close(ws, &err).await?;
-                return Err(err);
+            OpCode::Binary => {
+                blob::open(confirm.export_key(), owner.as_bytes(), &frame.payload).map(Some)
+            }
+            OpCode::Close => {
+                let reason = close_reason(&frame, self.padding);
+                if reason.is_normal() && reason.message == NO_BLOB {
+                    return Ok(None);
+                }
+                Err(ClientError::from_close(reason))
+            }
+            _ => Err(frame.into()),
+        }
+    }
+
+    /// the attributes of the user `session_token` was issued to, see
+    /// [`AuthenticateConfirm::session_token`]
+    pub async fn get_attributes(&self, session_token: &str) -> Result<Attributes, ClientError> {
+        self.require(Feature::Attributes)?;
+        let body = self
+            .request(Method::GET, ATTRIBUTES_PATH, session_token, Bytes::new())
+            .await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// replace all of the attributes of the user `session_token` was issued to with
+    /// `attributes`
+    pub async fn set_attributes(
+        &self,
+        session_token: &str,
+        attributes: &Attributes,
+    ) -> Result<(), ClientError> {
+        self.require(Feature::Attributes)?;
+        let body = serde_json::to_vec(attributes)?;
+        self.request(Method::PUT, ATTRIBUTES_PATH, session_token, body.into())
+            .await?;
+        Ok(())
+    }
+
+    /// everything the server holds about the user `session_token` was issued to
+    pub async fn takeout(&self, session_token: &str) -> Result<TakeoutDocument, ClientError> {
+        let body = self
+            .request(Method::GET, TAKEOUT_PATH, session_token, Bytes::new())
+            .await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// a plain http request to `path` on the server that last worked, with `session_token` as
+    /// its bearer token, giving back the body of a successful answer
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        session_token: &str,
+        body: Bytes,
+    ) -> Result<Bytes, ClientError> {
+        let (domain, port) = &self.targets[self.last_good.load(Ordering::Relaxed)];
+        let dest = format!("{domain}:{port}");
+        let request = |scheme: &str| {
+            Request::builder()
+                .method(method.clone())
+                .uri(format!("{scheme}://{dest}/{path}"))
+                .header("Host", &dest)
+                .header(AUTHORIZATION, format!("Bearer {session_token}"))
+                .header(CONTENT_TYPE, "application/json")
+                .body(Full::new(body.clone()))
+        };
+        let answer = async {
+            #[cfg(feature = "test-util")]
+            if let Some(router) = &self.loopback {
+                let stream = crate::loopback::connect(router.clone());
+                return send_request(stream, request("http")?).await;
+            }
+            #[cfg(feature = "tls")]
+            if let Some(tls) = &self.tls {
+                let stream = tokio::net::TcpStream::connect(&dest).await?;
+                let stream = tls.connect(domain, stream).await?;
+                return send_request(stream, request("https")?).await;
             }
+            if self.require_tls {
+                return Err(ClientError::InsecureTransport(dest.clone()));
+            }
+            let stream = tokio::net::TcpStream::connect(&dest).await?;
+            send_request(stream, request("http")?).await
         };
+        timeout(self.operation_deadline, answer)
+            .await
+            .unwrap_or(Err(ClientError::DeadlineExceeded))
+    }
+
+    async fn run_authenticate(
+        &self,
+        state: AuthenticateInitialize<CS>,
+        ws: Option<&mut Connection>,
+        trace: &mut Trace,
+    ) -> Result<Option<AuthenticateConfirm>, ClientError> {
+        // setup authentication
+        let mut connected = None;
+        let ws = self
+            .open(ws, &mut connected, Operation::Authenticate, trace)
+            .await?;
+        self.authentication_exchange(ws, state, trace).await
+    }
+
+    /// [`Client::authenticate`] over `transport`, which has to reach the server's
+    /// authentication endpoint
+    pub async fn authenticate_over<T: Transport>(
+        &self,
+        transport: &mut T,
+        username: String,
+        password: String,
+    ) -> Result<Option<AuthenticateConfirm>, ClientError> {
+        let mut trace = Trace::new(self.trace);
+        let result = async {
+            let state = AuthenticateInitialize::<CS>::for_suite(username, password)?
+                .with_legacy_salt(self.legacy_salt)
+                .with_identifiers(self.identifiers.clone())
+                .with_ksf(self.ksf.clone());
+            self.authentication_exchange(transport, state, &mut trace)
+                .await
+        }
+        .await;
+        trace.finish(result)
+    }
+
+    /// run the whole login over `ws`, which is already set up for it
+    async fn authentication_exchange<T: Transport>(
+        &self,
+        ws: &mut T,
+        state: AuthenticateInitialize<CS>,
+        trace: &mut Trace,
+    ) -> Result<Option<AuthenticateConfirm>, ClientError> {
+        let mut seq = Sequence::new(sequence::AUTHENTICATION, Side::Client);
+        let Some(state) = self.login(ws, state, &mut seq, trace).await? else {
+            return Ok(None);
+        };
+        let auth = state.to_data();
+
+        // let server know state of authentication, the outcome is already settled locally so
+        // the server closing early or dropping the connection from here on isn't an error
+        let data = if auth { vec![1] } else { vec![0] };
+        let mut session_token = None;
+        match self
+            .send(ws, &mut seq, trace, MessageKind::Confirmation, data)
+            .await
+        {
+            Ok(()) => match Self::verdict(ws, trace).await {
+                Ok(reason) => {
+                    // the server only asks for a new password once the old one checked out
+                    if auth && reason.message == kind::REREGISTRATION_REQUIRED {
+                        return Err(ClientError::PasswordChangeRequired);
+                    }
+                    if reason.is_normal() {
+                        seq.received(MessageKind::Done)?;
+                        if reason.message == DONE {
+                            session_token = reason.detail;
+                        }
+                    }
+                }
+                Err(err) if went_silent(&err) => {}
+                Err(err) => return Err(err),
+            },
+            Err(err) if went_silent(&err) => {}
+            Err(err) => return Err(err),
+        }
+
+        let state = state.step().with_session_token(session_token);
+
+        let auth = if auth { Some(state) } else { None };
+
+        Ok(auth)
+    }
+
+    /// run the login exchange up to checking the session key against the server's, the caller
+    /// lets the server know how that went
+    ///
+    /// `None` when the password was refused, which shows up here as the password not opening
+    /// the server's response. The connection is closed telling the server so
+    async fn login<T: Transport>(
+        &self,
+        ws: &mut T,
+        state: AuthenticateInitialize<CS>,
+        seq: &mut Sequence,
+        trace: &mut Trace,
+    ) -> Result<Option<AuthenticateFinish>, ClientError> {
+        // send and receive with server
+        self.send(
+            ws,
+            seq,
+            trace,
+            MessageKind::CredentialRequest,
+            state.to_data(),
+        )
+        .await?;
+        let credential_response_bytes =
+            Self::receive(ws, seq, trace, MessageKind::CredentialResponse).await?;
+
+        // advance state
+        let stepped = executor::run(self.ksf_executor.as_ref(), move || {
+            state.step(credential_response_bytes)
+        })
+        .await;
+        if let Err(err @ ClientError::ProtocolError(ProtocolError::InvalidLoginError)) = &stepped {
+            ws.close(err.close_reason()).await?;
+            return Ok(None);
+        }
+        let state = Self::or_close(ws, stepped).await?;
+
+        // a server other than the pinned one doesn't get to see the finalization
+        if self
+            .server_public_key
+            .as_ref()
+            .is_some_and(|expected| *expected != state.server_public_key())
+        {
+            let err = ClientError::ServerKeyMismatch;
+            ws.close(err.close_reason()).await?;
+            return Err(err);
+        }
+
+        // send and receive with server
+        self.send(
+            ws,
+            seq,
+            trace,
+            MessageKind::CredentialFinalization,
+            state.to_data(),
+        )
+        .await?;
 
         // check if authentication passed
-        let server_key = frame.payload.into();
-        let state = state.step(server_key);
+        let confirmation = Self::receive(ws, seq, trace, MessageKind::KeyConfirmation).await?;
+        Ok(Some(state.step(confirmation)))
+    }
+
+    /// change `username`'s password from `old_password` to `new_password`, logging in and
+    /// registering the new password over a single connection
+    ///
+    /// `Ok(false)` means the old password was wrong, the password is left as it was whenever
+    /// this doesn't return `Ok(true)`
+    pub async fn change_password(
+        &self,
+        username: String,
+        old_password: String,
+        new_password: String,
+    ) -> Result<bool, ClientError> {
+        self.checked_change_password(username, old_password, new_password, None)
+            .await
+    }
+
+    async fn checked_change_password(
+        &self,
+        username: String,
+        old_password: String,
+        new_password: String,
+        ws: Option<&mut Connection>,
+    ) -> Result<bool, ClientError> {
+        self.require(Feature::ChangePassword)?;
+        self.check_lockout(&username)?;
+        let mut trace = Trace::new(self.trace);
+        let result = self
+            .run_change_password(username.clone(), old_password, new_password, ws, &mut trace)
+            .await;
+        self.track_lockout(&username, result.as_ref().copied());
+        trace.finish(result)
+    }
+
+    #[deprecated(note = "use `delete_user`, which says why an account wasn't deleted")]
+    pub async fn delete(&self, username: String, password: String) -> Result<bool, ClientError> {
+        let outcome = self.delete_user(username, password).await?;
+        Ok(outcome == DeleteOutcome::Deleted)
+    }
+
+    /// delete `username`'s account, proving it's theirs by logging in with `password`
+    pub async fn delete_user(
+        &self,
+        username: String,
+        password: String,
+    ) -> Result<DeleteOutcome, ClientError> {
+        self.checked_delete(username, password, None).await
+    }
+
+    async fn checked_delete(
+        &self,
+        username: String,
+        password: String,
+        ws: Option<&mut Connection>,
+    ) -> Result<DeleteOutcome, ClientError> {
+        self.require(Feature::Deletion)?;
+        self.check_lockout(&username)?;
+        let mut trace = Trace::new(self.trace);
+        let result = self
+            .run_delete(username.clone(), password, ws, &mut trace)
+            .await;
+        self.track_lockout(
+            &username,
+            result
+                .as_ref()
+                .map(|outcome| *outcome == DeleteOutcome::Deleted),
+        );
+        trace.finish(result)
+    }
+
+    async fn run_delete(
+        &self,
+        username: String,
+        password: String,
+        ws: Option<&mut Connection>,
+        trace: &mut Trace,
+    ) -> Result<DeleteOutcome, ClientError> {
+        let login = AuthenticateInitialize::<CS>::for_suite(username, password)?
+            .with_legacy_salt(self.legacy_salt)
+            .with_identifiers(self.identifiers.clone())
+            .with_ksf(self.ksf.clone());
+        let mut connected = None;
+        let ws = self
+            .open(ws, &mut connected, Operation::Delete, trace)
+            .await?;
+        self.delete_exchange(ws, login, trace).await
+    }
+
+    /// [`Client::delete_user`] over `transport`, which has to reach the server's deletion
+    /// endpoint
+    pub async fn delete_over<T: Transport>(
+        &self,
+        transport: &mut T,
+        username: String,
+        password: String,
+    ) -> Result<DeleteOutcome, ClientError> {
+        let mut trace = Trace::new(self.trace);
+        let result = async {
+            let login = AuthenticateInitialize::<CS>::for_suite(username, password)?
+                .with_legacy_salt(self.legacy_salt)
+                .with_identifiers(self.identifiers.clone())
+                .with_ksf(self.ksf.clone());
+            self.delete_exchange(transport, login, &mut trace).await
+        }
+        .await;
+        trace.finish(result)
+    }
+
+    /// run the whole deletion over `ws`, which is already set up for it
+    async fn delete_exchange<T: Transport>(
+        &self,
+        ws: &mut T,
+        login: AuthenticateInitialize<CS>,
+        trace: &mut Trace,
+    ) -> Result<DeleteOutcome, ClientError> {
+        let mut seq = Sequence::new(sequence::DELETE, Side::Client);
+        let Some(state) = self.login(ws, login, &mut seq, trace).await? else {
+            return Ok(DeleteOutcome::NotAuthenticated);
+        };
         let auth = state.to_data();
 
-        // let server know state of authentication
         let data = if auth { vec![1] } else { vec![0] };
-        ws.write_frame(Frame::new(true, OpCode::Binary, None, data.into()))
+        self.send(ws, &mut seq, trace, MessageKind::Confirmation, data)
             .await?;
-        let frame = ws.read_frame().await?;
-        match frame.opcode {
-            OpCode::Close => {}
-            _ => {
-                let err = frame.into();
-                Self::close(ws, &err).await?;
-                return Err(err);
-            }
+        if !auth {
+            ws.skip_end().await;
+            return Ok(DeleteOutcome::NotAuthenticated);
+        }
+
+        let reason = match Self::verdict(ws, trace).await {
+            Ok(reason) => reason,
+            // the server may or may not have removed the account before the connection dropped
+            Err(err) if went_silent(&err) => return Err(ClientError::OutcomeUnknown),
+            Err(err) => return Err(err),
         };
+        let outcome = delete_outcome(reason)?;
+        seq.received(MessageKind::Done)?;
+        Ok(outcome)
+    }
 
-        let state = state.step();
+    async fn run_change_password(
+        &self,
+        username: String,
+        old_password: String,
+        new_password: String,
+        ws: Option<&mut Connection>,
+        trace: &mut Trace,
+    ) -> Result<bool, ClientError> {
+        let login = AuthenticateInitialize::<CS>::for_suite(username.clone(), old_password)?
+            .with_legacy_salt(self.legacy_salt)
+            .with_identifiers(self.identifiers.clone())
+            .with_ksf(self.ksf.clone());
+        let registration = RegistrationInitialize::<CS>::for_suite(username, new_password)?
+            .with_identifiers(self.identifiers.clone())
+            .with_ksf(self.ksf.clone());
+        let mut connected = None;
+        let ws = self
+            .open(ws, &mut connected, Operation::ChangePassword, trace)
+            .await?;
+        let mut seq = Sequence::new(sequence::CHANGE_PASSWORD, Side::Client);
+        let Some(state) = self.login(ws, login, &mut seq, trace).await? else {
+            return Ok(false);
+        };
+        let auth = state.to_data();
 
-        let auth = if auth { Some(state) } else { None };
+        let data = if auth { vec![1] } else { vec![0] };
+        self.send(ws, &mut seq, trace, MessageKind::Confirmation, data)
+            .await?;
+        if !auth {
+            ws.skip_end().await;
+            return Ok(false);
+        }
 
-        Ok(auth)
+        let (reason, _) = self.upload(ws, registration, &mut seq, trace).await?;
+        if !reason.is_normal() {
+            return Err(ClientError::from_close(reason));
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::ErrorKind;
+
+    fn normal_close(payload: &[u8]) -> CloseReason {
+        CloseReason::from_payload(&[&1000u16.to_be_bytes()[..], payload].concat())
+    }
+
+    #[test]
+    fn deletion_reports_the_outcome_it_was_closed_with() {
+        let deleted = normal_close(br#""deleted""#);
+        assert_eq!(delete_outcome(deleted).unwrap(), DeleteOutcome::Deleted);
+        let refused = normal_close(br#""not_authenticated""#);
+        assert_eq!(
+            delete_outcome(refused).unwrap(),
+            DeleteOutcome::NotAuthenticated
+        );
+    }
+
+    #[test]
+    fn deletion_takes_the_reasons_older_servers_sent() {
+        assert_eq!(
+            delete_outcome(normal_close(DONE.as_bytes())).unwrap(),
+            DeleteOutcome::Deleted
+        );
+        assert_eq!(
+            delete_outcome(normal_close(&[1])).unwrap(),
+            DeleteOutcome::Deleted
+        );
+    }
+
+    #[test]
+    fn deletion_closed_without_a_payload_is_unexpected() {
+        let err = delete_outcome(normal_close(&[])).unwrap_err();
+        assert!(
+            matches!(err, ClientError::UnexpectedFrame(OpCode::Close, payload) if payload.is_empty())
+        );
+    }
+
+    #[test]
+    fn deletion_closed_with_anything_else_is_unexpected() {
+        let err = delete_outcome(normal_close(b"gone")).unwrap_err();
+        assert!(matches!(
+            err,
+            ClientError::UnexpectedFrame(OpCode::Close, _)
+        ));
+    }
+
+    #[test]
+    fn deletion_closed_with_an_error_is_that_error() {
+        let reason = CloseReason::new(ErrorKind::Internal, kind::DATABASE, None);
+        let err = delete_outcome(reason).unwrap_err();
+        assert!(!matches!(err, ClientError::UnexpectedFrame(_, _)));
+    }
+
+    fn login_start(password: &str) -> LoginStart {
+        LoginStart {
+            username: "user".to_string(),
+            password: Zeroizing::new(password.to_string()),
+        }
+    }
+
+    #[test]
+    fn generated_password_confirms() {
+        let info = login_start("generated").confirm("generated".to_string());
+        assert_eq!(info.map(|info| info.username), Some("user".to_string()));
+    }
+
+    #[test]
+    fn other_password_does_not_confirm() {
+        assert!(login_start("generated")
+            .confirm("generatee".to_string())
+            .is_none());
+    }
+
+    #[test]
+    fn password_of_another_length_does_not_confirm() {
+        assert!(login_start("generated")
+            .confirm("generate".to_string())
+            .is_none());
+        assert!(login_start("generated")
+            .confirm("generated!".to_string())
+            .is_none());
+        assert!(login_start("generated").confirm(String::new()).is_none());
     }
 }
@@ -0,0 +1,314 @@
+use std::{
+    fmt::Display,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use http_body_util::{BodyExt, Empty};
+use hyper::{body::Bytes, Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    time::timeout,
+};
+
+#[cfg(feature = "tls")]
+use super::tls::Tls;
+use crate::{
+    clock::Clock,
+    wire::{ServerInfo, INFO_PATH},
+};
+
+/// longest any single check may take, so an address that drops packets shows up as a failure
+/// rather than hanging
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Part of the connection a preflight check covers, in the order they're checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    Dns,
+    Tcp,
+    Tls,
+    Info,
+}
+
+impl Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Dns => write!(f, "dns"),
+            Self::Tcp => write!(f, "tcp"),
+            Self::Tls => write!(f, "tls"),
+            Self::Info => write!(f, "info"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckStatus {
+    Passed(String),
+    Failed(String),
+    /// the check doesn't apply to this client
+    Skipped(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub layer: Layer,
+    pub status: CheckStatus,
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.status {
+            CheckStatus::Passed(detail) => write!(f, "{}: ok, {detail}", self.layer),
+            CheckStatus::Failed(detail) => write!(f, "{}: failed, {detail}", self.layer),
+            CheckStatus::Skipped(detail) => write!(f, "{}: skipped, {detail}", self.layer),
+        }
+    }
+}
+
+/// What the checks found out about one of the client's servers, the checks stop at the first
+/// layer that fails
+#[derive(Debug, Clone)]
+pub struct TargetReport {
+    pub target: String,
+    pub checks: Vec<Check>,
+    /// how long opening the connection took
+    pub connect_latency: Option<Duration>,
+    /// how long the info request took to come back
+    pub round_trip: Option<Duration>,
+    pub info: Option<ServerInfo>,
+    /// seconds the server's clock is ahead of this one, negative when it's behind. Only
+    /// accurate to within the round trip
+    pub clock_skew: Option<i64>,
+}
+
+impl TargetReport {
+    fn new(target: String) -> Self {
+        Self {
+            target,
+            checks: Vec::new(),
+            connect_latency: None,
+            round_trip: None,
+            info: None,
+            clock_skew: None,
+        }
+    }
+
+    fn push(&mut self, layer: Layer, status: CheckStatus) {
+        self.checks.push(Check { layer, status });
+    }
+
+    /// the layer that failed, `None` when everything passed
+    pub fn failed_layer(&self) -> Option<Layer> {
+        self.checks
+            .iter()
+            .find(|check| matches!(check.status, CheckStatus::Failed(_)))
+            .map(|check| check.layer)
+    }
+
+    pub fn passed(&self) -> bool {
+        self.failed_layer().is_none()
+    }
+}
+
+impl Display for TargetReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.target)?;
+        for check in &self.checks {
+            write!(f, "\n  {check}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of [`Client::preflight`](super::Client::preflight), one report per server the client
+/// knows about
+#[derive(Debug, Clone)]
+pub struct PreflightReport {
+    pub targets: Vec<TargetReport>,
+}
+
+impl PreflightReport {
+    /// whether every server passed every check
+    pub fn passed(&self) -> bool {
+        self.targets.iter().all(TargetReport::passed)
+    }
+}
+
+impl Display for PreflightReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, target) in self.targets.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{target}")?;
+        }
+        Ok(())
+    }
+}
+
+/// run every check against `domain:port`, without sending any credentials
+pub(crate) async fn check_target(
+    domain: &str,
+    port: u16,
+    require_tls: bool,
+    #[cfg(feature = "tls")] tls: Option<&Tls>,
+    clock: &dyn Clock,
+) -> TargetReport {
+    let dest = format!("{domain}:{port}");
+    let mut report = TargetReport::new(dest.clone());
+
+    let addrs = match timeout(CHECK_TIMEOUT, tokio::net::lookup_host(&dest)).await {
+        Ok(Ok(addrs)) => addrs.collect::<Vec<SocketAddr>>(),
+        Ok(Err(err)) => {
+            report.push(Layer::Dns, CheckStatus::Failed(err.to_string()));
+            return report;
+        }
+        Err(_) => {
+            report.push(Layer::Dns, CheckStatus::Failed("timed out".into()));
+            return report;
+        }
+    };
+    if addrs.is_empty() {
+        report.push(Layer::Dns, CheckStatus::Failed("no addresses".into()));
+        return report;
+    }
+    let resolved = addrs
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    report.push(
+        Layer::Dns,
+        CheckStatus::Passed(format!("resolved to {resolved}")),
+    );
+
+    let started = Instant::now();
+    let stream = match timeout(CHECK_TIMEOUT, TcpStream::connect(&addrs[..])).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(err)) => {
+            report.push(Layer::Tcp, CheckStatus::Failed(err.to_string()));
+            return report;
+        }
+        Err(_) => {
+            report.push(Layer::Tcp, CheckStatus::Failed("timed out".into()));
+            return report;
+        }
+    };
+    let latency = started.elapsed();
+    report.connect_latency = Some(latency);
+    report.push(
+        Layer::Tcp,
+        CheckStatus::Passed(format!("connected in {latency:?}")),
+    );
+
+    #[cfg(feature = "tls")]
+    if let Some(tls) = tls {
+        let stream = match timeout(CHECK_TIMEOUT, tls.connect(domain, stream)).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(err)) => {
+                report.push(Layer::Tls, CheckStatus::Failed(err.to_string()));
+                return report;
+            }
+            Err(_) => {
+                report.push(Layer::Tls, CheckStatus::Failed("timed out".into()));
+                return report;
+            }
+        };
+        report.push(
+            Layer::Tls,
+            CheckStatus::Passed(format!("certificate is valid for {domain}")),
+        );
+        return check_info(report, stream, "https", &dest, clock).await;
+    }
+    if require_tls {
+        report.push(
+            Layer::Tls,
+            CheckStatus::Failed("TLS is required but this client isn't set up for it".into()),
+        );
+        return report;
+    }
+    report.push(Layer::Tls, CheckStatus::Skipped("not required".into()));
+    check_info(report, stream, "http", &dest, clock).await
+}
+
+/// the info layer of [`check_target`] over an established `stream`
+async fn check_info<S>(
+    mut report: TargetReport,
+    stream: S,
+    scheme: &str,
+    dest: &str,
+    clock: &dyn Clock,
+) -> TargetReport
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let started = Instant::now();
+    let info = match timeout(CHECK_TIMEOUT, fetch_info(stream, scheme, dest)).await {
+        Ok(Ok(info)) => info,
+        Ok(Err(reason)) => {
+            report.push(Layer::Info, CheckStatus::Failed(reason));
+            return report;
+        }
+        Err(_) => {
+            report.push(Layer::Info, CheckStatus::Failed("timed out".into()));
+            return report;
+        }
+    };
+    let round_trip = started.elapsed();
+    let skew = info.server_time as i64 - clock.unix_secs() as i64;
+    let features = info
+        .features
+        .iter()
+        .map(|feature| feature.name())
+        .collect::<Vec<_>>();
+    report.push(
+        Layer::Info,
+        CheckStatus::Passed(format!(
+            "tinap {} with features [{}], answered in {round_trip:?}, clock skew {skew}s",
+            info.version,
+            features.join(", ")
+        )),
+    );
+    report.round_trip = Some(round_trip);
+    report.clock_skew = Some(skew);
+    report.info = Some(info);
+    report
+}
+
+/// ask the server about itself over `stream`, failures are described for the report
+async fn fetch_info<S>(stream: S, scheme: &str, dest: &str) -> Result<ServerInfo, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .map_err(|err| format!("http handshake failed `{err}`"))?;
+    tokio::task::spawn(conn);
+    let req = Request::builder()
+        .method("GET")
+        .uri(format!("{scheme}://{dest}/{INFO_PATH}"))
+        .header("Host", dest)
+        .body(Empty::<Bytes>::new())
+        .map_err(|err| err.to_string())?;
+    let response = sender
+        .send_request(req)
+        .await
+        .map_err(|err| format!("request failed `{err}`"))?;
+    match response.status() {
+        StatusCode::OK => {}
+        StatusCode::NOT_FOUND => {
+            return Err("server has no info endpoint, it may be an older version".into())
+        }
+        status => return Err(format!("server answered `{status}`")),
+    }
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .map_err(|err| format!("reading the answer failed `{err}`"))?
+        .to_bytes();
+    serde_json::from_slice(&body).map_err(|err| format!("unexpected answer `{err}`"))
+}
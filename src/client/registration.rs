@@ -1,100 +1,170 @@
-use opaque_ke::{
-    ClientRegistration, ClientRegistrationFinishParameters, ClientRegistrationFinishResult,
-    ClientRegistrationStartResult, RegistrationResponse,
-};
-use rand::rngs::OsRng;
+use std::fmt::Debug;
 
-use crate::{Scheme, WithUsername};
+use zeroize::Zeroizing;
 
-use super::error::ClientError;
+use crate::{
+    suite::{RegistrationFinish, SaltedKsf, Suite},
+    username, Identifiers, Scheme, WithUsername,
+};
 
-pub struct RegistrationInitialize<'a> {
+use super::{
+    authenticate::{hex, REDACTED},
+    error::ClientError,
+};
+
+pub struct RegistrationInitialize<CS: Suite = Scheme> {
     username: String,
-    password: String,
-    client_rng: OsRng,
-    client_registration_start_result: ClientRegistrationStartResult<Scheme<'a>>,
+    password: Zeroizing<String>,
+    ksf: CS::Ksf,
+    state: CS::ClientRegistration,
+    /// the serialized `RegistrationRequest`
+    registration_request: Vec<u8>,
+    invite: Option<String>,
+    identifiers: Identifiers,
 }
 
-impl<'a> RegistrationInitialize<'a> {
+impl RegistrationInitialize {
+    /// `username` is normalized first, see [`username::normalize`]
+    pub fn new(username: String, password: String) -> Result<Self, ClientError> {
+        Self::for_suite(username, password)
+    }
+}
+
+impl<CS: Suite> RegistrationInitialize<CS> {
+    /// register with `invite`, for servers that only let invited users register
+    pub fn with_invite(mut self, invite: Option<String>) -> Self {
+        self.invite = invite;
+        self
+    }
+
+    /// stretch the password with `ksf`'s costs, salted for the user. Logins have to use the same
+    /// costs
+    pub fn with_ksf(mut self, ksf: CS::Ksf) -> Self {
+        self.ksf = ksf.salted_for(self.username.as_bytes());
+        self
+    }
+
+    /// bind the registration to `identifiers`, logins have to use the same ones
+    pub fn with_identifiers(mut self, identifiers: Identifiers) -> Self {
+        self.identifiers = identifiers;
+        self
+    }
+
     pub fn step(
         self,
         registration_response_bytes: Vec<u8>,
-    ) -> Result<RegistrationWaiting<'a>, ClientError> {
-        let registration_response =
-            match RegistrationResponse::deserialize(&registration_response_bytes) {
-                Ok(res) => res,
-                Err(err) => {
-                    return Err(ClientError::ProtocolError(err));
-                }
-            };
-
-        let client_finish_registration_result =
-            match self.client_registration_start_result.state.finish(
-                &mut self.client_rng.clone(),
-                self.password.as_bytes(),
-                registration_response,
-                ClientRegistrationFinishParameters::default(),
-            ) {
-                Ok(res) => res,
-                Err(err) => {
-                    return Err(ClientError::ProtocolError(err));
-                }
-            };
+    ) -> Result<RegistrationWaiting, ClientError> {
+        let client_finish_registration_result = match CS::client_registration_finish(
+            self.state,
+            self.password.as_bytes(),
+            &registration_response_bytes,
+            &self.identifiers,
+            &self.ksf,
+        ) {
+            Ok(res) => res,
+            Err(err) => {
+                return Err(ClientError::ProtocolError(err));
+            }
+        };
 
         Ok(RegistrationWaiting::new(client_finish_registration_result))
     }
 
     pub fn to_data(&self) -> Vec<u8> {
-        let registration_request_bytes = self.client_registration_start_result.message.serialize();
         let with_username = WithUsername {
             username: self.username.as_bytes(),
-            data: registration_request_bytes.as_slice(),
+            data: &self.registration_request,
+            invite: self.invite.as_ref().map(String::as_bytes),
         };
         bincode::serialize(&with_username).unwrap()
     }
 
-    pub fn new(username: String, password: String) -> Result<Self, ClientError> {
-        let mut client_rng = OsRng;
-        let client_registration_start_result =
-            match ClientRegistration::<Scheme>::start(&mut client_rng, password.as_bytes()) {
-                Ok(res) => res,
-                Err(err) => {
-                    return Err(ClientError::ProtocolError(err));
-                }
-            };
+    /// [`RegistrationInitialize::new`] for registering with `CS`
+    pub fn for_suite(username: String, password: String) -> Result<Self, ClientError> {
+        let password = Zeroizing::new(password);
+        if password.is_empty() {
+            return Err(ClientError::EmptyPassword);
+        }
+        let username = username::normalize(&username)?.into_string();
+        let (state, registration_request) = match CS::client_registration_start(password.as_bytes())
+        {
+            Ok(res) => res,
+            Err(err) => {
+                return Err(ClientError::ProtocolError(err));
+            }
+        };
+        let ksf = CS::Ksf::default().salted_for(username.as_bytes());
         Ok(Self {
             username,
             password,
-            client_rng,
-            client_registration_start_result,
+            ksf,
+            state,
+            registration_request,
+            invite: None,
+            identifiers: Identifiers::default(),
         })
     }
 }
 
-pub struct RegistrationWaiting<'a> {
-    client_finish_registration_result: ClientRegistrationFinishResult<Scheme<'a>>,
+pub struct RegistrationWaiting {
+    client_finish_registration_result: RegistrationFinish,
 }
 
-impl<'a> RegistrationWaiting<'a> {
-    pub fn new(
-        client_finish_registration_result: ClientRegistrationFinishResult<Scheme<'a>>,
-    ) -> Self {
+impl RegistrationWaiting {
+    pub fn new(client_finish_registration_result: RegistrationFinish) -> Self {
         Self {
             client_finish_registration_result,
         }
     }
 
     pub fn to_data(&self) -> Vec<u8> {
-        self.client_finish_registration_result
-            .message
-            .serialize()
-            .as_slice()
-            .into()
+        self.client_finish_registration_result.message.clone()
     }
 
     pub fn step(self) -> RegistrationConfirm {
-        RegistrationConfirm
+        RegistrationConfirm::new(
+            self.client_finish_registration_result.export_key,
+            self.client_finish_registration_result.server_public_key,
+        )
+    }
+}
+
+/// The keys a registration ended with, the export key is wiped from memory when dropped
+pub struct RegistrationConfirm {
+    export_key: Zeroizing<Vec<u8>>,
+    server_public_key: Vec<u8>,
+}
+
+impl RegistrationConfirm {
+    pub fn new(export_key: Vec<u8>, server_public_key: Vec<u8>) -> Self {
+        Self {
+            export_key: Zeroizing::new(export_key),
+            server_public_key,
+        }
+    }
+
+    /// the export key every login with the same password ends with, so data can be encrypted
+    /// under it without logging in first
+    pub fn export_key(&self) -> &[u8] {
+        &self.export_key
+    }
+
+    /// the public key of the server the account was registered with
+    pub fn server_public_key(&self) -> &[u8] {
+        &self.server_public_key
+    }
+
+    /// the export key as lowercase hex, for printing
+    pub fn export_key_hex(&self) -> String {
+        hex(&self.export_key)
     }
 }
 
-pub struct RegistrationConfirm;
+impl Debug for RegistrationConfirm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RegistrationConfirm")
+            .field("export_key", &REDACTED)
+            .field("server_public_key", &self.server_public_key)
+            .finish()
+    }
+}
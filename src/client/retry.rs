@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use rand::{rngs::OsRng, Rng};
+
+/// How the client keeps trying to connect when no server could be reached, see
+/// [`Client::with_retry`](super::Client::with_retry)
+///
+/// Only connecting and upgrading to a websocket is ever retried. Once an exchange has started its
+/// state can't be replayed, so anything failing after that is reported as it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// attempts in total, including the first
+    pub max_attempts: u32,
+    /// wait before the second attempt, doubled for every attempt after that
+    pub base_delay: Duration,
+    /// most extra time added to each wait at random, so clients that failed together don't all
+    /// come back at once
+    pub jitter: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(250),
+            jitter: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// how long to wait after attempt `attempt` failed, counting from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let jitter = self.jitter.mul_f64(OsRng.gen::<f64>());
+        backoff.saturating_add(jitter)
+    }
+}
@@ -0,0 +1,90 @@
+use fastwebsockets::Frame;
+
+use super::{
+    authenticate::AuthenticateConfirm, error::ClientError, registration_outcome, trace::Trace,
+    Client, Connection,
+};
+use crate::{
+    outcome::{DeleteOutcome, RegistrationOutcome},
+    suite::Suite,
+    Scheme,
+};
+
+/// A connection to the server that runs any number of operations one after another, see
+/// [`Client::session`]
+///
+/// the operations behave as they do on [`Client`]. One that fails because of something on this
+/// side, or the connection itself, closes the connection and every operation after it fails too
+pub struct Session<'c, CS: Suite = Scheme> {
+    pub(super) client: &'c Client<CS>,
+    pub(super) ws: Connection,
+}
+
+impl<CS: Suite> Session<'_, CS> {
+    /// [`Client::register_user`] over the session
+    pub async fn register_user(
+        &mut self,
+        username: String,
+        password: String,
+    ) -> Result<RegistrationOutcome, ClientError> {
+        let mut trace = Trace::new(self.client.trace);
+        let result = self
+            .client
+            .run_registration(username, password, None, Some(&mut self.ws), &mut trace)
+            .await;
+        trace.finish(result).map(registration_outcome)
+    }
+
+    /// [`Client::authenticate`] over the session
+    pub async fn authenticate(
+        &mut self,
+        username: String,
+        password: String,
+    ) -> Result<Option<AuthenticateConfirm>, ClientError> {
+        let mut trace = Trace::new(self.client.trace);
+        let result = self
+            .client
+            .checked_authenticate(username, password, false, Some(&mut self.ws), &mut trace)
+            .await;
+        trace.finish(result)
+    }
+
+    /// [`Client::change_password`] over the session
+    pub async fn change_password(
+        &mut self,
+        username: String,
+        old_password: String,
+        new_password: String,
+    ) -> Result<bool, ClientError> {
+        self.client
+            .checked_change_password(username, old_password, new_password, Some(&mut self.ws))
+            .await
+    }
+
+    #[deprecated(note = "use `delete_user`, which says why an account wasn't deleted")]
+    pub async fn delete(
+        &mut self,
+        username: String,
+        password: String,
+    ) -> Result<bool, ClientError> {
+        let outcome = self.delete_user(username, password).await?;
+        Ok(outcome == DeleteOutcome::Deleted)
+    }
+
+    /// [`Client::delete_user`] over the session
+    pub async fn delete_user(
+        &mut self,
+        username: String,
+        password: String,
+    ) -> Result<DeleteOutcome, ClientError> {
+        self.client
+            .checked_delete(username, password, Some(&mut self.ws))
+            .await
+    }
+
+    /// let the server know there's nothing more to do
+    pub async fn close(mut self) -> Result<(), ClientError> {
+        self.ws.write_frame(Frame::close(1000, &[])).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+use std::{fmt::Display, time::Duration};
+
+/// The parts an exchange with the server is made up of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// opening the connection and upgrading it to a websocket
+    Connect,
+    /// work done on the client, including the key stretching
+    Local,
+    /// waiting on the server to answer a message
+    RoundTrip,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connect => write!(f, "connect"),
+            Self::Local => write!(f, "local"),
+            Self::RoundTrip => write!(f, "round trip"),
+        }
+    }
+}
+
+/// How long each phase of an exchange took, in the order they happened
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timings {
+    phases: Vec<(Phase, Duration)>,
+}
+
+impl Timings {
+    pub(crate) fn push(&mut self, phase: Phase, duration: Duration) {
+        self.phases.push((phase, duration));
+    }
+
+    pub fn phases(&self) -> &[(Phase, Duration)] {
+        &self.phases
+    }
+
+    /// time spent in `phase` over the whole exchange
+    pub fn total_of(&self, phase: Phase) -> Duration {
+        self.phases
+            .iter()
+            .filter(|(kind, _)| *kind == phase)
+            .map(|(_, duration)| *duration)
+            .sum()
+    }
+
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, duration)| *duration).sum()
+    }
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (phase, duration) in &self.phases {
+            writeln!(f, "{phase}: {duration:?}")?;
+        }
+        write!(f, "total: {:?}", self.total())
+    }
+}
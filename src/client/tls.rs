@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+    TlsConnector,
+};
+
+use super::error::ClientError;
+
+/// Roots a client trusts when connecting over TLS, the webpki roots plus any added by hand
+#[derive(Clone)]
+pub(crate) struct Tls {
+    roots: RootCertStore,
+    config: Arc<ClientConfig>,
+}
+
+impl Tls {
+    pub(crate) fn new() -> Self {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        Self::with_roots(roots)
+    }
+
+    fn with_roots(roots: RootCertStore) -> Self {
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots.clone())
+            .with_no_client_auth();
+        Self {
+            roots,
+            config: Arc::new(config),
+        }
+    }
+
+    /// also trust the certificates in `pem`, e.g. for a server with a self-signed certificate
+    pub(crate) fn add_pem(&mut self, pem: &[u8]) -> Result<(), ClientError> {
+        let mut roots = self.roots.clone();
+        for cert in rustls_pemfile::certs(&mut &pem[..]) {
+            let cert =
+                cert.map_err(|err| ClientError::Tls(format!("unreadable certificate {err}")))?;
+            roots
+                .add(cert)
+                .map_err(|err| ClientError::Tls(err.to_string()))?;
+        }
+        *self = Self::with_roots(roots);
+        Ok(())
+    }
+
+    /// run the handshake over `stream`, checking the certificate is for `domain`
+    pub(crate) async fn connect(
+        &self,
+        domain: &str,
+        stream: TcpStream,
+    ) -> Result<TlsStream<TcpStream>, ClientError> {
+        let name = ServerName::try_from(domain.to_string())
+            .map_err(|err| ClientError::Tls(format!("invalid server name `{domain}` {err}")))?;
+        TlsConnector::from(self.config.clone())
+            .connect(name, stream)
+            .await
+            .map_err(|err| ClientError::Tls(err.to_string()))
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use fastwebsockets::OpCode;
+
+use super::{
+    error::ClientError,
+    timings::{Phase, Timings},
+    transport::Message,
+};
+
+/// environment variable that turns tracing on for every [`Client`](super::Client)
+pub const TRACE_ENV: &str = "TINAP_CLIENT_TRACE";
+/// how many of the most recent events are kept for error reports
+const CONTEXT_LEN: usize = 16;
+
+/// Something that happened on the connection, never includes payloads so it's safe to share
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent {
+    Connected {
+        target: String,
+        subprotocol: Option<String>,
+    },
+    Sent {
+        message: &'static str,
+        len: usize,
+    },
+    Received {
+        opcode: OpCode,
+        len: usize,
+    },
+    Closed {
+        code: u16,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// time since the operation started
+    pub elapsed: Duration,
+    pub event: TraceEvent,
+}
+
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{:?}] ", self.elapsed)?;
+        match &self.event {
+            TraceEvent::Connected {
+                target,
+                subprotocol,
+            } => write!(f, "connected to {target} (subprotocol {subprotocol:?})"),
+            TraceEvent::Sent { message, len } => write!(f, "sent {message}, {len} bytes"),
+            TraceEvent::Received { opcode, len } => write!(f, "received {opcode:?}, {len} bytes"),
+            TraceEvent::Closed { code } => write!(f, "received close with code {code}"),
+        }
+    }
+}
+
+/// Timings and, when tracing is turned on, recent events of a single operation
+pub(crate) struct Trace {
+    recording: Option<(Instant, VecDeque<TraceEntry>)>,
+    timings: Timings,
+    /// when the current phase started
+    mark: Instant,
+}
+
+impl Trace {
+    pub(crate) fn new(enabled: bool) -> Self {
+        Self {
+            recording: enabled.then(|| (Instant::now(), VecDeque::with_capacity(CONTEXT_LEN))),
+            timings: Timings::default(),
+            mark: Instant::now(),
+        }
+    }
+
+    /// end the current phase as `phase` and start the next one
+    fn lap(&mut self, phase: Phase) {
+        let now = Instant::now();
+        self.timings.push(phase, now - self.mark);
+        self.mark = now;
+    }
+
+    fn record(&mut self, event: TraceEvent) {
+        if let Some((started, entries)) = &mut self.recording {
+            let entry = TraceEntry {
+                elapsed: started.elapsed(),
+                event,
+            };
+            tracing::debug!("{entry}");
+            if entries.len() == CONTEXT_LEN {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+    }
+
+    pub(crate) fn connected(&mut self, target: &str, subprotocol: Option<&str>) {
+        self.lap(Phase::Connect);
+        if self.recording.is_some() {
+            self.record(TraceEvent::Connected {
+                target: target.into(),
+                subprotocol: subprotocol.map(Into::into),
+            });
+        }
+    }
+
+    pub(crate) fn sent(&mut self, message: &'static str, len: usize) {
+        self.lap(Phase::Local);
+        self.record(TraceEvent::Sent { message, len });
+    }
+
+    pub(crate) fn received(&mut self, message: &Message) {
+        self.lap(Phase::RoundTrip);
+        match message {
+            Message::Close(reason) => self.record(TraceEvent::Closed { code: reason.code }),
+            Message::Data(data) => self.record(TraceEvent::Received {
+                opcode: OpCode::Binary,
+                len: data.len(),
+            }),
+        }
+    }
+
+    /// attach what was recorded to a failed operation
+    pub(crate) fn finish<T>(self, result: Result<T, ClientError>) -> Result<T, ClientError> {
+        self.finish_timed(result).0
+    }
+
+    /// [`Trace::finish`], also handing back how long each phase took
+    pub(crate) fn finish_timed<T>(
+        mut self,
+        result: Result<T, ClientError>,
+    ) -> (Result<T, ClientError>, Timings) {
+        // whatever happened after the last message was local work
+        self.lap(Phase::Local);
+        let result = match (result, self.recording) {
+            (Err(err), Some((_, entries))) => {
+                Err(ClientError::Traced(Box::new(err), entries.into()))
+            }
+            (result, _) => result,
+        };
+        (result, self.timings)
+    }
+}
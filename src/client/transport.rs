@@ -0,0 +1,91 @@
+use std::future::Future;
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use super::error::ClientError;
+use crate::wire::CloseReason;
+
+/// What came in from the other side of a [`Transport`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// the next message of the exchange
+    Data(Vec<u8>),
+    /// the other side ended the exchange, with its reason
+    Close(CloseReason),
+}
+
+/// Carries the messages of an exchange between the client and the server
+///
+/// The exchanges only need whole messages delivered in order, and a way to end them with a
+/// reason. [`Client`](super::Client) runs them over websockets on its own, implementing this runs
+/// them over anything else, see [`Client::register_over`](super::Client::register_over)
+pub trait Transport: Send {
+    /// send the next message of the exchange
+    fn send(&mut self, data: Vec<u8>) -> impl Future<Output = Result<(), ClientError>> + Send;
+
+    /// the next message from the other side
+    fn recv(&mut self) -> impl Future<Output = Result<Message, ClientError>> + Send;
+
+    /// end the exchange, telling the other side why
+    fn close(
+        &mut self,
+        reason: CloseReason,
+    ) -> impl Future<Output = Result<(), ClientError>> + Send;
+
+    /// wait out the other side ending an exchange whose outcome is already known, for
+    /// transports that carry several exchanges one after another. Does nothing by default
+    fn skip_end(&mut self) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+}
+
+/// One end of an in memory [`Transport`], see [`pipe`]
+#[derive(Debug)]
+pub struct MemoryTransport {
+    sender: UnboundedSender<Message>,
+    receiver: UnboundedReceiver<Message>,
+}
+
+/// two ends of a transport that only exists in memory, whatever one sends the other receives
+///
+/// meant for driving an exchange against a server running in the same process, e.g. in tests.
+/// Either end being dropped shows up on the other as a broken pipe
+pub fn pipe() -> (MemoryTransport, MemoryTransport) {
+    let (left_sender, right_receiver) = unbounded_channel();
+    let (right_sender, left_receiver) = unbounded_channel();
+    (
+        MemoryTransport {
+            sender: left_sender,
+            receiver: left_receiver,
+        },
+        MemoryTransport {
+            sender: right_sender,
+            receiver: right_receiver,
+        },
+    )
+}
+
+/// error for the other end of a [`pipe`] being gone
+fn broken_pipe() -> ClientError {
+    std::io::Error::from(std::io::ErrorKind::BrokenPipe).into()
+}
+
+impl MemoryTransport {
+    fn deliver(&self, message: Message) -> Result<(), ClientError> {
+        self.sender.send(message).map_err(|_| broken_pipe())
+    }
+}
+
+impl Transport for MemoryTransport {
+    async fn send(&mut self, data: Vec<u8>) -> Result<(), ClientError> {
+        self.deliver(Message::Data(data))
+    }
+
+    async fn recv(&mut self) -> Result<Message, ClientError> {
+        self.receiver.recv().await.ok_or_else(broken_pipe)
+    }
+
+    async fn close(&mut self, reason: CloseReason) -> Result<(), ClientError> {
+        self.deliver(Message::Close(reason))
+    }
+}
@@ -0,0 +1,170 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::Argon2;
+
+/// memory below this, in KiB, leaves passwords cheap to brute force
+pub const MIN_MEMORY_KIB: u32 = 8 * 1024;
+/// memory above this, in KiB, won't fit on low end clients
+pub const MAX_MEMORY_KIB: u32 = 1024 * 1024;
+/// passes below this leave passwords cheap to brute force
+pub const MIN_ITERATIONS: u32 = 1;
+/// passes above this make logins take far too long on low end clients
+pub const MAX_ITERATIONS: u32 = 64;
+/// lanes above this don't help anyone
+pub const MAX_PARALLELISM: u32 = 16;
+
+#[derive(Debug, Error)]
+pub enum ParamsError {
+    #[error("Memory cost of `{0}` KiB is below the floor of `{MIN_MEMORY_KIB}` KiB")]
+    MemoryTooLow(u32),
+    #[error("Memory cost of `{0}` KiB is above the ceiling of `{MAX_MEMORY_KIB}` KiB")]
+    MemoryTooHigh(u32),
+    #[error("`{0}` iterations is below the floor of `{MIN_ITERATIONS}`")]
+    IterationsTooLow(u32),
+    #[error("`{0}` iterations is above the ceiling of `{MAX_ITERATIONS}`")]
+    IterationsTooHigh(u32),
+    #[error("Parallelism of `{0}` is outside of 1 to `{MAX_PARALLELISM}`")]
+    Parallelism(u32),
+    #[error("Argon2 rejected the parameters `{0}`")]
+    Argon2(argon2::Error),
+}
+
+/// What to do with parameters outside of the recommended range
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Strictness {
+    /// hand the problem back as a warning and carry on
+    #[default]
+    Warn,
+    /// fail
+    Refuse,
+}
+
+/// Costs of the Argon2 key stretching the client runs on every registration and login
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Argon2Params {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+/// Costs of whichever key stretching a [`Suite`](crate::suite::Suite) runs, for reporting them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum KsfParams {
+    Argon2(Argon2Params),
+    Scrypt { log_n: u8, r: u32, p: u32 },
+}
+
+impl std::fmt::Display for KsfParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Argon2(params) => write!(
+                f,
+                "argon2: {} KiB, {} iterations, {} lanes",
+                params.memory_kib, params.iterations, params.parallelism
+            ),
+            Self::Scrypt { log_n, r, p } => {
+                write!(f, "scrypt: 2^{log_n} rounds, {r} blocks, {p} lanes")
+            }
+        }
+    }
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Self {
+            memory_kib: argon2::Params::DEFAULT_M_COST,
+            iterations: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl Argon2Params {
+    /// far costlier than the defaults, for accounts worth the slower logins. Around a quarter
+    /// GiB of memory, which rules out the lowest end clients
+    pub const HIGH_SECURITY: Self = Self {
+        memory_kib: 256 * 1024,
+        iterations: 4,
+        parallelism: 4,
+    };
+
+    /// the key stretching these parameters describe, see [`Argon2::with_params`]
+    pub fn to_ksf(&self) -> Result<Argon2, ParamsError> {
+        Argon2::with_params(self.memory_kib, self.iterations, self.parallelism)
+    }
+
+    /// check the parameters against the recommended floors and ceilings
+    pub fn validate(&self) -> Result<(), ParamsError> {
+        if self.memory_kib < MIN_MEMORY_KIB {
+            return Err(ParamsError::MemoryTooLow(self.memory_kib));
+        }
+        if self.memory_kib > MAX_MEMORY_KIB {
+            return Err(ParamsError::MemoryTooHigh(self.memory_kib));
+        }
+        if self.iterations < MIN_ITERATIONS {
+            return Err(ParamsError::IterationsTooLow(self.iterations));
+        }
+        if self.iterations > MAX_ITERATIONS {
+            return Err(ParamsError::IterationsTooHigh(self.iterations));
+        }
+        if self.parallelism == 0 || self.parallelism > MAX_PARALLELISM {
+            return Err(ParamsError::Parallelism(self.parallelism));
+        }
+        self.to_argon2()?;
+        Ok(())
+    }
+
+    /// [`Argon2Params::validate`], only failing when `strictness` says so. Otherwise any
+    /// problem comes back as a warning for the caller to report however suits it
+    pub fn check(&self, strictness: Strictness) -> Result<Option<ParamsError>, ParamsError> {
+        match (self.validate(), strictness) {
+            (Ok(()), _) => Ok(None),
+            (Err(err), Strictness::Warn) => Ok(Some(err)),
+            (Err(err), Strictness::Refuse) => Err(err),
+        }
+    }
+
+    pub fn to_argon2(&self) -> Result<argon2::Params, ParamsError> {
+        argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(ParamsError::Argon2)
+    }
+
+    /// how long hashing a password takes with these parameters on this machine
+    pub fn measure(&self) -> Result<Duration, ParamsError> {
+        let argon2 = argon2::Argon2::new(
+            argon2::Algorithm::default(),
+            argon2::Version::default(),
+            self.to_argon2()?,
+        );
+        let mut output = [0; 64];
+        let started = Instant::now();
+        argon2
+            .hash_password_into(b"calibration", &[0; 16], &mut output)
+            .map_err(ParamsError::Argon2)?;
+        Ok(started.elapsed())
+    }
+
+    /// the cheapest parameters, starting from the defaults, that take at least `target` to hash
+    /// on this machine
+    ///
+    /// only iterations are raised, so a longer target never gives cheaper parameters. Stops at
+    /// [`MAX_ITERATIONS`] if the machine is fast enough that the target can't be reached
+    pub fn calibrate(target: Duration) -> Result<Self, ParamsError> {
+        let mut params = Self {
+            iterations: MIN_ITERATIONS,
+            ..Self::default()
+        };
+        let per_iteration = params.measure()?;
+        if per_iteration.is_zero() {
+            params.iterations = MAX_ITERATIONS;
+            return Ok(params);
+        }
+        let needed = target.as_nanos().div_ceil(per_iteration.as_nanos());
+        params.iterations = needed.clamp(MIN_ITERATIONS.into(), MAX_ITERATIONS.into()) as u32;
+        Ok(params)
+    }
+}
@@ -1,23 +1,106 @@
-use std::marker::PhantomData;
-
 use generic_array::{ArrayLength, GenericArray};
+use hkdf::Hkdf;
 use opaque_ke::{errors::InternalError, ksf::Ksf, CipherSuite};
 use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Sha512};
+use thiserror::Error;
+
+use ksf::{Argon2Params, ParamsError};
 
+pub mod attributes;
+pub mod channel;
+#[cfg(feature = "client")]
 pub mod client;
+pub mod clock;
+pub mod ksf;
+#[cfg(feature = "test-util")]
+pub mod loopback;
+pub mod outcome;
+pub mod padding;
+pub mod sequence;
+#[cfg(feature = "server")]
 pub mod server;
+pub mod storage_key;
+#[cfg(any(feature = "client", feature = "server"))]
+pub mod suite;
+pub mod takeout;
+pub mod username;
+#[cfg(all(feature = "client", feature = "server"))]
+pub mod verify;
+pub mod wire;
 
 /// The Scheme being used for the OPAQUE protocol
 #[derive(Debug, Clone, Copy)]
-pub struct Scheme<'a> {
-    _lifetime: PhantomData<&'a ()>,
+pub struct Scheme;
+
+impl CipherSuite for Scheme {
+    type OprfCs = opaque_ke::Ristretto255;
+    type KeGroup = opaque_ke::Ristretto255;
+    type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDh;
+    type Ksf = Argon2;
+}
+
+impl Scheme {
+    pub const ID: SuiteId = SuiteId::Ristretto255;
+}
+
+/// [`Scheme`] over NIST P-256 instead of Ristretto255, for deployments that can only use
+/// NIST curves
+#[cfg(feature = "p256")]
+#[derive(Debug, Clone, Copy)]
+pub struct P256Scheme;
+
+#[cfg(feature = "p256")]
+impl CipherSuite for P256Scheme {
+    type OprfCs = p256::NistP256;
+    type KeGroup = p256::NistP256;
+    type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDh;
+    type Ksf = Argon2;
+}
+
+#[cfg(feature = "p256")]
+impl P256Scheme {
+    pub const ID: SuiteId = SuiteId::P256;
 }
 
-impl<'a> CipherSuite for Scheme<'a> {
+/// [`Scheme`] stretching passwords with scrypt instead of Argon2, for matching deployments
+/// that already use it
+#[cfg(feature = "scrypt")]
+#[derive(Debug, Clone, Copy)]
+pub struct SchemeScrypt;
+
+#[cfg(feature = "scrypt")]
+impl CipherSuite for SchemeScrypt {
     type OprfCs = opaque_ke::Ristretto255;
     type KeGroup = opaque_ke::Ristretto255;
     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDh;
-    type Ksf = Argon2<'a>;
+    type Ksf = Scrypt;
+}
+
+#[cfg(feature = "scrypt")]
+impl SchemeScrypt {
+    pub const ID: SuiteId = SuiteId::Ristretto255Scrypt;
+}
+
+/// Which cipher suite produced a password file, files from one suite are useless to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SuiteId {
+    /// [`Scheme`]
+    Ristretto255,
+    /// `P256Scheme`
+    P256,
+    /// `SchemeScrypt`
+    Ristretto255Scrypt,
+}
+
+impl std::fmt::Display for SuiteId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ristretto255 => write!(f, "ristretto255"),
+            Self::P256 => write!(f, "p256"),
+            Self::Ristretto255Scrypt => write!(f, "ristretto255-scrypt"),
+        }
+    }
 }
 
 /// Small wrapper for serializing and deserializing data sent from the client to the server
@@ -25,21 +108,255 @@ impl<'a> CipherSuite for Scheme<'a> {
 pub struct WithUsername<'a> {
     pub username: &'a [u8],
     pub data: &'a [u8],
+    /// invite a registration is made with, for servers that only let invited users register
+    #[serde(borrow)]
+    pub invite: Option<&'a [u8]>,
+}
+
+/// The first message of an authentication, [`WithUsername`] plus options for the exchange
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthenticateRequest<'a> {
+    pub username: &'a [u8],
+    pub data: &'a [u8],
+    /// only check the credentials, the server skips anything else that normally follows a login
+    pub verify_only: bool,
+}
+
+/// Identities of the client and the server bound into the OPAQUE exchanges
+///
+/// both sides have to use the same ones, and a client has to log in with the ones it registered
+/// with, otherwise logins fail. `None` stands for the respective public key, as in the OPAQUE spec
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identifiers {
+    pub client: Option<Vec<u8>>,
+    pub server: Option<Vec<u8>>,
+}
+
+impl Identifiers {
+    #[cfg(any(feature = "client", feature = "server"))]
+    pub(crate) fn as_opaque(&self) -> opaque_ke::Identifiers<'_> {
+        opaque_ke::Identifiers {
+            client: self.client.as_deref(),
+            server: self.server.as_deref(),
+        }
+    }
 }
 
 /// Newtype for Argon2 key stretching, wasn't able to get the `opaque_ke` feature working
-#[derive(Default)]
-pub struct Argon2<'a>(argon2::Argon2<'a>);
+///
+/// The default has an all zero salt, which is what every password registered before salts were
+/// derived from usernames was stretched with. Those users can still log in by asking for the
+/// legacy salt and move over by changing their password, which always uses
+/// [`Argon2::for_user`]
+///
+/// the costs default to the `argon2` crate's, [`Argon2::with_params`] sets others. Whatever
+/// costs a password was registered with have to be used for every login with it
+#[derive(Default, Clone)]
+pub struct Argon2 {
+    argon2: argon2::Argon2<'static>,
+    salt: [u8; ARGON2_RECOMMENDED_SALT_LEN],
+}
 const ARGON2_RECOMMENDED_SALT_LEN: usize = 16;
-impl Ksf for Argon2<'_> {
+/// domain separation for deriving salts from usernames
+const SALT_INFO: &[u8] = b"tinap argon2 salt";
+/// domain separation for keys derived from session keys, the label follows it
+const DERIVE_INFO_PREFIX: &[u8] = b"tinap derived key ";
+/// the most bytes [`derive_key`] can produce, 255 blocks of HKDF-SHA512
+pub const MAX_DERIVED_KEY_LEN: usize = 255 * 64;
+
+/// More key was asked of [`derive_key`] than it can produce
+#[derive(Debug, Error)]
+#[error("Can't derive `{0}` bytes of key, at most {MAX_DERIVED_KEY_LEN} are possible")]
+pub struct KeyLengthError(pub usize);
+
+/// `len` bytes of key for `label`, derived from a login's session key with HKDF-SHA512
+///
+/// both sides of a login get the same key for the same label and unrelated keys for different
+/// labels, e.g. `b"mac"` and `b"storage"`, so the session key itself never has to be used
+/// directly. The labels starting with `channel ` or `blob ` and `key confirmation` are what
+/// the crate derives its own keys with, applications should stick to others
+pub fn derive_key(session_key: &[u8], label: &[u8], len: usize) -> Result<Vec<u8>, KeyLengthError> {
+    let info = [DERIVE_INFO_PREFIX, label].concat();
+    let mut key = vec![0; len];
+    Hkdf::<Sha512>::new(None, session_key)
+        .expand(&info, &mut key)
+        .map_err(|_| KeyLengthError(len))?;
+    Ok(key)
+}
+
+/// [`derive_key`] label for the value the server proves it has the session key with
+#[cfg(any(feature = "client", feature = "server"))]
+const KEY_CONFIRMATION_LABEL: &[u8] = b"key confirmation";
+#[cfg(any(feature = "client", feature = "server"))]
+const KEY_CONFIRMATION_LEN: usize = 32;
+
+/// what the server sends for the client to check it ended up with the same session key, the
+/// session key itself never leaves either side
+#[cfg(any(feature = "client", feature = "server"))]
+pub(crate) fn key_confirmation(session_key: &[u8]) -> Vec<u8> {
+    derive_key(session_key, KEY_CONFIRMATION_LABEL, KEY_CONFIRMATION_LEN)
+        .expect("key confirmation is far below the most HKDF can output")
+}
+
+impl Argon2 {
+    /// key stretching salted with a value derived from `username`, so a precomputed table only
+    /// ever works against a single user
+    pub fn for_user(username: &[u8]) -> Self {
+        Self::default().salted_for(username)
+    }
+
+    /// key stretching costing `m_cost` KiB of memory, `t_cost` passes, and `p_cost` lanes,
+    /// with the all zero salt until [`Argon2::salted_for`] a user
+    pub fn with_params(m_cost: u32, t_cost: u32, p_cost: u32) -> Result<Self, ParamsError> {
+        let params = Argon2Params {
+            memory_kib: m_cost,
+            iterations: t_cost,
+            parallelism: p_cost,
+        }
+        .to_argon2()?;
+        Ok(Self {
+            argon2: argon2::Argon2::new(
+                argon2::Algorithm::default(),
+                argon2::Version::default(),
+                params,
+            ),
+            salt: [0; ARGON2_RECOMMENDED_SALT_LEN],
+        })
+    }
+
+    /// the same costs, salted with a value derived from `username`
+    pub fn salted_for(mut self, username: &[u8]) -> Self {
+        Hkdf::<Sha256>::new(None, username)
+            .expand(SALT_INFO, &mut self.salt)
+            .expect("salt is far below the most HKDF can output");
+        self
+    }
+
+    /// the costs this stretches passwords with
+    pub fn params(&self) -> Argon2Params {
+        let params = self.argon2.params();
+        Argon2Params {
+            memory_kib: params.m_cost(),
+            iterations: params.t_cost(),
+            parallelism: params.p_cost(),
+        }
+    }
+}
+
+impl Ksf for Argon2 {
+    fn hash<L: ArrayLength<u8>>(
+        &self,
+        input: GenericArray<u8, L>,
+    ) -> Result<GenericArray<u8, L>, InternalError> {
+        let mut output = GenericArray::default();
+        self.argon2
+            .hash_password_into(&input, &self.salt, &mut output)
+            .map_err(|_| InternalError::KsfError)?;
+        Ok(output)
+    }
+}
+
+/// Scrypt key stretching, see [`SchemeScrypt`]
+///
+/// like [`Argon2`] the default has the all zero salt, [`Scrypt::salted_for`] salts it per user.
+/// The costs default to the `scrypt` crate's recommended ones
+#[cfg(feature = "scrypt")]
+#[derive(Debug, Clone)]
+pub struct Scrypt {
+    params: scrypt::Params,
+    salt: [u8; ARGON2_RECOMMENDED_SALT_LEN],
+}
+
+#[cfg(feature = "scrypt")]
+impl Default for Scrypt {
+    fn default() -> Self {
+        Self {
+            params: scrypt::Params::recommended(),
+            salt: [0; ARGON2_RECOMMENDED_SALT_LEN],
+        }
+    }
+}
+
+#[cfg(feature = "scrypt")]
+impl Scrypt {
+    /// key stretching costing `2^log_n` iterations of blocks `r` long, run `p` times
+    pub fn with_params(log_n: u8, r: u32, p: u32) -> Result<Self, scrypt::errors::InvalidParams> {
+        Ok(Self {
+            params: scrypt::Params::new(log_n, r, p, scrypt::Params::RECOMMENDED_LEN)?,
+            ..Self::default()
+        })
+    }
+
+    /// the same costs, salted with a value derived from `username`
+    pub fn salted_for(mut self, username: &[u8]) -> Self {
+        Hkdf::<Sha256>::new(None, username)
+            .expand(SALT_INFO, &mut self.salt)
+            .expect("salt is far below the most HKDF can output");
+        self
+    }
+
+    /// the costs this stretches passwords with
+    pub fn params(&self) -> ksf::KsfParams {
+        ksf::KsfParams::Scrypt {
+            log_n: self.params.log_n(),
+            r: self.params.r(),
+            p: self.params.p(),
+        }
+    }
+}
+
+#[cfg(feature = "scrypt")]
+impl Ksf for Scrypt {
     fn hash<L: ArrayLength<u8>>(
         &self,
         input: GenericArray<u8, L>,
     ) -> Result<GenericArray<u8, L>, InternalError> {
         let mut output = GenericArray::default();
-        self.0
-            .hash_password_into(&input, &[0; ARGON2_RECOMMENDED_SALT_LEN], &mut output)
+        scrypt::scrypt(&input, &self.salt, &self.params, &mut output)
             .map_err(|_| InternalError::KsfError)?;
         Ok(output)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_label_derives_the_same_key() {
+        let first = derive_key(b"session key", b"mac", 32).unwrap();
+        let second = derive_key(b"session key", b"mac", 32).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first.len(), 32);
+    }
+
+    #[test]
+    fn different_labels_derive_different_keys() {
+        let mac = derive_key(b"session key", b"mac", 32).unwrap();
+        let storage = derive_key(b"session key", b"storage", 32).unwrap();
+        assert_ne!(mac, storage);
+    }
+
+    #[test]
+    fn different_session_keys_derive_different_keys() {
+        let first = derive_key(b"session key", b"mac", 32).unwrap();
+        let second = derive_key(b"other session key", b"mac", 32).unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[cfg(any(feature = "client", feature = "server"))]
+    #[test]
+    fn key_confirmation_does_not_give_away_the_session_key() {
+        let confirmation = key_confirmation(b"session key");
+        assert_ne!(confirmation, b"session key");
+        assert_eq!(confirmation, key_confirmation(b"session key"));
+        assert_ne!(confirmation, key_confirmation(b"other session key"));
+    }
+
+    #[test]
+    fn too_long_a_key_is_an_error() {
+        assert!(derive_key(b"session key", b"mac", MAX_DERIVED_KEY_LEN).is_ok());
+        let err = derive_key(b"session key", b"mac", MAX_DERIVED_KEY_LEN + 1).unwrap_err();
+        assert_eq!(err.0, MAX_DERIVED_KEY_LEN + 1);
+    }
+}
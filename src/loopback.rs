@@ -0,0 +1,88 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::Router;
+use hyper::server::conn::http1;
+use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+
+use crate::{client::Client, server::Server, suite::Suite};
+
+/// bytes buffered in each direction of a loopback connection
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// A client wired straight to `server` in memory, for end to end tests without binding a port
+///
+/// every connection the client opens gets its own in memory stream with `server`'s routes served
+/// on the other end, so the whole protocol runs exactly as it does over TCP
+pub fn loopback_pair<CS: Suite>(server: &Server<CS>) -> Client<CS> {
+    Client::for_suite(vec![("loopback".into(), 0)]).with_loopback(server.router())
+}
+
+/// The client's end of a loopback connection
+///
+/// writes after the server dropped its end are taken and discarded, the way a TCP socket takes
+/// them into its send buffer, so e.g. answering the server's close frame works the same as over
+/// TCP
+#[derive(Debug)]
+pub(crate) struct Stream(DuplexStream);
+
+/// `result` of a write, treating the other end being gone as the write going through
+fn sent<T>(result: io::Result<T>, sent: T) -> io::Result<T> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(sent),
+        result => result,
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0)
+            .poll_write(cx, buf)
+            .map(|result| sent(result, buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0)
+            .poll_flush(cx)
+            .map(|result| sent(result, ()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0)
+            .poll_shutdown(cx)
+            .map(|result| sent(result, ()))
+    }
+}
+
+/// a stream to `router` served in the background, standing in for a TCP connection to it
+pub(crate) fn connect(router: Router) -> Stream {
+    let (client, server) = tokio::io::duplex(BUFFER_SIZE);
+    tokio::task::spawn(async move {
+        let served = http1::Builder::new()
+            .serve_connection(TokioIo::new(server), TowerToHyperService::new(router))
+            .with_upgrades()
+            .await;
+        if let Err(err) = served {
+            tracing::debug!("Loopback connection ended with `{err}`");
+        }
+    });
+    Stream(client)
+}
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// How a registration that ran to completion turned out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrationOutcome {
+    /// a new account was stored
+    Created,
+    /// the username was already taken, nothing was stored
+    AlreadyExists,
+}
+
+/// How a deletion that ran to completion turned out
+///
+/// the server closes a deletion with the outcome as JSON, e.g. `"deleted"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteOutcome {
+    /// the account is gone
+    Deleted,
+    /// the password was wrong, the account is still there
+    NotAuthenticated,
+}
@@ -0,0 +1,123 @@
+use thiserror::Error;
+
+use crate::wire::MAX_CLOSE_REASON;
+
+/// websocket subprotocol a client offers to ask for padded messages
+pub const PADDING_PROTOCOL: &str = "tinap-padded";
+/// padded messages are always a multiple of this many bytes, large enough that every message of
+/// the default [`Scheme`](crate::Scheme) fits in a single bucket
+pub const BUCKET_SIZE: usize = 512;
+/// every close reason is filled up to this many bytes when padding, whatever the outcome, so
+/// exchanges can't be told apart by how they ended
+pub const PADDED_REASON_LEN: usize = MAX_CLOSE_REASON;
+
+const LEN_PREFIX: usize = 4;
+
+#[derive(Debug, Error)]
+pub enum PaddingError {
+    #[error("Padded message is too short to hold its length")]
+    Truncated,
+    #[error("Padded message claims `{0}` bytes but only holds `{1}`")]
+    BadLength(usize, usize),
+}
+
+/// prefix `data` with its length and fill it with zeroes up to the next multiple of
+/// [`BUCKET_SIZE`]
+pub fn pad(data: &[u8]) -> Vec<u8> {
+    let padded_len = (LEN_PREFIX + data.len()).div_ceil(BUCKET_SIZE) * BUCKET_SIZE;
+    let mut padded = Vec::with_capacity(padded_len);
+    padded.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    padded.extend_from_slice(data);
+    padded.resize(padded_len, 0);
+    padded
+}
+
+/// fill the close `reason` with trailing spaces up to [`PADDED_REASON_LEN`]
+pub fn pad_reason(reason: &[u8]) -> Vec<u8> {
+    let mut padded = reason[..reason.len().min(PADDED_REASON_LEN)].to_vec();
+    padded.resize(PADDED_REASON_LEN, b' ');
+    padded
+}
+
+/// the payload of a close frame whose reason went through [`pad_reason`], with the spaces it
+/// added stripped again. The status code in front is left alone
+pub fn unpad_close(payload: &[u8]) -> &[u8] {
+    let end = payload
+        .iter()
+        .rposition(|byte| *byte != b' ')
+        .map_or(0, |last| last + 1);
+    &payload[..end.max(payload.len().min(2))]
+}
+
+/// recover the original data from a message produced by [`pad`]
+pub fn unpad(padded: &[u8]) -> Result<Vec<u8>, PaddingError> {
+    let (len, rest) = padded
+        .split_first_chunk::<LEN_PREFIX>()
+        .ok_or(PaddingError::Truncated)?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if len > rest.len() {
+        return Err(PaddingError::BadLength(len, rest.len()));
+    }
+    Ok(rest[..len].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::{close_reason, CloseReason, DONE};
+
+    fn close_payload(code: u16, reason: &[u8]) -> Vec<u8> {
+        [&code.to_be_bytes()[..], &pad_reason(reason)].concat()
+    }
+
+    #[test]
+    fn every_close_has_the_same_size() {
+        let token = "t".repeat(64);
+        let closes = [
+            close_payload(1000, &[1]),
+            close_payload(1000, DONE.as_bytes()),
+            close_payload(1000, &close_reason(DONE, Some(&token))),
+            close_payload(1000, br#""deleted""#),
+            close_payload(4001, b"user_already_exists"),
+            close_payload(4001, b"not_authenticated"),
+            close_payload(4000, &"x".repeat(200).into_bytes()),
+        ];
+        for close in &closes {
+            assert_eq!(close.len(), 2 + PADDED_REASON_LEN);
+        }
+    }
+
+    #[test]
+    fn padded_reasons_read_back_as_sent() {
+        let payload = close_payload(4001, b"user_already_exists");
+        let reason = CloseReason::from_payload(unpad_close(&payload));
+        assert_eq!(reason.code, 4001);
+        assert_eq!(reason.message, "user_already_exists");
+        assert_eq!(reason.detail, None);
+
+        let payload = close_payload(1000, &close_reason(DONE, Some("token")));
+        let reason = CloseReason::from_payload(unpad_close(&payload));
+        assert_eq!(reason.message, DONE);
+        assert_eq!(reason.detail.as_deref(), Some("token"));
+    }
+
+    #[test]
+    fn empty_reason_keeps_its_status_code() {
+        // 0x2020 would otherwise be taken for padding
+        let payload = close_payload(0x2020, &[]);
+        assert_eq!(
+            CloseReason::from_payload(unpad_close(&payload)).code,
+            0x2020
+        );
+    }
+
+    #[test]
+    fn padded_messages_fill_whole_buckets() {
+        for len in [0, 1, BUCKET_SIZE - LEN_PREFIX, BUCKET_SIZE, 3 * BUCKET_SIZE] {
+            let data = vec![7; len];
+            let padded = pad(&data);
+            assert_eq!(padded.len() % BUCKET_SIZE, 0);
+            assert_eq!(unpad(&padded).unwrap(), data);
+        }
+    }
+}
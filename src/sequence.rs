@@ -0,0 +1,235 @@
+use thiserror::Error;
+
+/// Which way a message travels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ToServer,
+    ToClient,
+}
+
+/// Which end of an exchange is walking a [`Sequence`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+impl Side {
+    /// which way messages this side sends travel
+    pub fn outgoing(self) -> Direction {
+        match self {
+            Self::Client => Direction::ToServer,
+            Self::Server => Direction::ToClient,
+        }
+    }
+
+    /// which way messages this side receives travel
+    pub fn incoming(self) -> Direction {
+        match self {
+            Self::Client => Direction::ToClient,
+            Self::Server => Direction::ToServer,
+        }
+    }
+}
+
+/// What a message carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    RegistrationRequest,
+    RegistrationResponse,
+    RegistrationUpload,
+    CredentialRequest,
+    CredentialResponse,
+    CredentialFinalization,
+    /// a value derived from the server's session key, for the client to compare against its own
+    KeyConfirmation,
+    /// whether the client derived the same session key
+    Confirmation,
+    /// the server closing normally once the operation went through
+    Done,
+}
+
+impl MessageKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::RegistrationRequest => "registration request",
+            Self::RegistrationResponse => "registration response",
+            Self::RegistrationUpload => "registration upload",
+            Self::CredentialRequest => "credential request",
+            Self::CredentialResponse => "credential response",
+            Self::CredentialFinalization => "credential finalization",
+            Self::KeyConfirmation => "key confirmation",
+            Self::Confirmation => "confirmation",
+            Self::Done => "done",
+        }
+    }
+}
+
+/// One message of an exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpectedStep {
+    pub direction: Direction,
+    pub message_kind: MessageKind,
+}
+
+const fn to_server(message_kind: MessageKind) -> ExpectedStep {
+    ExpectedStep {
+        direction: Direction::ToServer,
+        message_kind,
+    }
+}
+
+const fn to_client(message_kind: MessageKind) -> ExpectedStep {
+    ExpectedStep {
+        direction: Direction::ToClient,
+        message_kind,
+    }
+}
+
+/// the frames of each operation, in order
+pub const REGISTRATION: &[ExpectedStep] = &[
+    to_server(MessageKind::RegistrationRequest),
+    to_client(MessageKind::RegistrationResponse),
+    to_server(MessageKind::RegistrationUpload),
+    to_client(MessageKind::Done),
+];
+
+pub const AUTHENTICATION: &[ExpectedStep] = &[
+    to_server(MessageKind::CredentialRequest),
+    to_client(MessageKind::CredentialResponse),
+    to_server(MessageKind::CredentialFinalization),
+    to_client(MessageKind::KeyConfirmation),
+    to_server(MessageKind::Confirmation),
+    to_client(MessageKind::Done),
+];
+
+/// a login followed by a registration of the new password
+pub const CHANGE_PASSWORD: &[ExpectedStep] = &[
+    to_server(MessageKind::CredentialRequest),
+    to_client(MessageKind::CredentialResponse),
+    to_server(MessageKind::CredentialFinalization),
+    to_client(MessageKind::KeyConfirmation),
+    to_server(MessageKind::Confirmation),
+    to_server(MessageKind::RegistrationRequest),
+    to_client(MessageKind::RegistrationResponse),
+    to_server(MessageKind::RegistrationUpload),
+    to_client(MessageKind::Done),
+];
+
+pub const DELETE: &[ExpectedStep] = AUTHENTICATION;
+
+/// A side sending or receiving something other than the next step of its exchange
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error(
+    "{side:?} strayed from the sequence at step {at}, expected `{expected:?}` but got `{got:?}`"
+)]
+pub struct OutOfSequence {
+    pub side: Side,
+    pub at: usize,
+    /// `None` when the exchange should have been over
+    pub expected: Option<ExpectedStep>,
+    pub got: ExpectedStep,
+}
+
+/// Where one side is in an exchange
+///
+/// The client and the server both walk the same table as they run an exchange, so a step added
+/// on one side but not the other ends the exchange with an [`OutOfSequence`] instead of hanging
+/// the peer. Errors can end an exchange at any step and aren't part of the tables, only messages
+/// of an exchange that's going well are checked against them.
+#[derive(Debug)]
+pub struct Sequence {
+    steps: &'static [ExpectedStep],
+    side: Side,
+    next: usize,
+}
+
+impl Sequence {
+    pub fn new(steps: &'static [ExpectedStep], side: Side) -> Self {
+        Self {
+            steps,
+            side,
+            next: 0,
+        }
+    }
+
+    /// move past a message this side sent, which has to be the next step
+    pub fn sent(&mut self, message_kind: MessageKind) -> Result<(), OutOfSequence> {
+        self.advance(self.side.outgoing(), message_kind)
+    }
+
+    /// move past a message this side received, which has to be the next step
+    pub fn received(&mut self, message_kind: MessageKind) -> Result<(), OutOfSequence> {
+        self.advance(self.side.incoming(), message_kind)
+    }
+
+    fn advance(
+        &mut self,
+        direction: Direction,
+        message_kind: MessageKind,
+    ) -> Result<(), OutOfSequence> {
+        let step = ExpectedStep {
+            direction,
+            message_kind,
+        };
+        let expected = self.steps.get(self.next).copied();
+        if expected != Some(step) {
+            return Err(OutOfSequence {
+                side: self.side,
+                at: self.next,
+                expected,
+                got: step,
+            });
+        }
+        self.next += 1;
+        Ok(())
+    }
+
+    /// whether every step has happened
+    pub fn is_finished(&self) -> bool {
+        self.next == self.steps.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// every step one side sends is one the other side expects to receive next
+    #[test]
+    fn both_sides_walk_the_tables_in_lockstep() {
+        for steps in [REGISTRATION, AUTHENTICATION, CHANGE_PASSWORD, DELETE] {
+            let mut client = Sequence::new(steps, Side::Client);
+            let mut server = Sequence::new(steps, Side::Server);
+            for step in steps {
+                let (sender, receiver) = match step.direction {
+                    Direction::ToServer => (&mut client, &mut server),
+                    Direction::ToClient => (&mut server, &mut client),
+                };
+                sender.sent(step.message_kind).unwrap();
+                receiver.received(step.message_kind).unwrap();
+            }
+            assert!(client.is_finished() && server.is_finished());
+        }
+    }
+
+    #[test]
+    fn straying_from_the_table_is_an_error() {
+        let mut server = Sequence::new(AUTHENTICATION, Side::Server);
+        let err = server.sent(MessageKind::CredentialResponse).unwrap_err();
+        assert_eq!(err.at, 0);
+        assert_eq!(err.expected, Some(AUTHENTICATION[0]));
+        // nothing moved, the right step still goes through
+        server.received(MessageKind::CredentialRequest).unwrap();
+
+        let mut client = Sequence::new(REGISTRATION, Side::Client);
+        for step in REGISTRATION {
+            match step.direction {
+                Direction::ToServer => client.sent(step.message_kind).unwrap(),
+                Direction::ToClient => client.received(step.message_kind).unwrap(),
+            }
+        }
+        let err = client.received(MessageKind::Done).unwrap_err();
+        assert_eq!(err.expected, None);
+    }
+}
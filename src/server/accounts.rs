@@ -0,0 +1,352 @@
+use std::sync::atomic::Ordering;
+
+use sled::{transaction::ConflictableTransactionError, Transactional};
+
+use super::{
+    attributes::Attributes,
+    blobs::BLOBS_TREE,
+    bootstrap::{ADMIN_ATTRIBUTE, BOOTSTRAPPED_KEY},
+    error::ServerError,
+    failures::FAILURES_TREE,
+    instance::METADATA_TREE,
+    invites::{self, INVITES_TREE},
+    lockout::LOCKOUT_TREE,
+    record::{self, UserRecord},
+    store::UserStore,
+    Server, ATTRIBUTES_TREE, QUARANTINE_TREE, REREGISTER_TREE,
+};
+use crate::{storage_key::StorageKey, suite::Suite};
+
+impl<CS: Suite> Server<CS> {
+    /// store the password file of a new user, when `bootstrap` is set also mark the account as
+    /// admin and make sure bootstrapping can't happen again, when `invite` is given use it up
+    ///
+    /// the check for an existing user and the writes happen atomically, so of two concurrent
+    /// registrations for the same user exactly one gets [`ServerError::UserAlreadyExists`].
+    /// With the password files in sled bootstrapping goes through a single transaction, so a
+    /// crash can't leave an admin without a password file or a bootstrap that can be used twice.
+    /// Bootstrapping fails with [`ServerError::BootstrapUnavailable`] once it happened before or
+    /// any user exists
+    pub(super) async fn create_user(
+        &self,
+        key: &StorageKey,
+        password_file: &[u8],
+        bootstrap: bool,
+        invite: Option<&[u8]>,
+    ) -> Result<(), ServerError> {
+        let users = self.users()?;
+        let record =
+            UserRecord::new(password_file.to_vec(), self.clock.unix_secs()).with_suite(CS::ID);
+        let password_file = record::seal(&record);
+        let password_file = password_file.as_slice();
+        if let Some(invite) = invite {
+            return self
+                .create_invited(users.as_ref(), key, password_file, invite)
+                .await;
+        }
+        if !bootstrap {
+            return match users.insert_if_absent(key, password_file).await? {
+                true => Ok(()),
+                false => Err(ServerError::UserAlreadyExists),
+            };
+        }
+        let mut attributes = Attributes::new();
+        attributes.insert(ADMIN_ATTRIBUTE.into(), "true".into())?;
+        let attributes = bincode::serialize(&attributes)?;
+        let bootstrapped_key = self.tree_name(BOOTSTRAPPED_KEY);
+        let bootstrapped_at = self.clock.unix_secs().to_be_bytes();
+        let attributes_tree = self.store.open_tree(self.tree_name(ATTRIBUTES_TREE))?;
+        let metadata = self.store.open_tree(METADATA_TREE)?;
+        // sled transactions can't scan a tree, so the store being empty is checked up front and
+        // the flag, which only a bootstrap ever sets, is what concurrent bootstraps race on
+        if users.count().await? > 0 {
+            return Err(ServerError::BootstrapUnavailable);
+        }
+        match users.as_sled() {
+            Some(users) => {
+                (users, &attributes_tree, &metadata).transaction(
+                    |(users, attributes_tree, metadata)| {
+                        if metadata.get(bootstrapped_key.as_bytes())?.is_some() {
+                            return Err(ConflictableTransactionError::Abort(
+                                ServerError::BootstrapUnavailable,
+                            ));
+                        }
+                        if users.get(key.as_bytes())?.is_some() {
+                            return Err(ConflictableTransactionError::Abort(
+                                ServerError::UserAlreadyExists,
+                            ));
+                        }
+                        users.insert(key.as_bytes(), password_file)?;
+                        self.crash_point()
+                            .map_err(ConflictableTransactionError::Abort)?;
+                        attributes_tree.insert(key.as_bytes(), attributes.as_slice())?;
+                        metadata.insert(bootstrapped_key.as_bytes(), &bootstrapped_at[..])?;
+                        Ok::<_, ConflictableTransactionError<ServerError>>(())
+                    },
+                )?;
+            }
+            None => {
+                // the flag is taken before the user is written, a bootstrap losing the race
+                // to it leaves nothing behind
+                metadata
+                    .compare_and_swap(
+                        bootstrapped_key.as_bytes(),
+                        None::<&[u8]>,
+                        Some(&bootstrapped_at[..]),
+                    )?
+                    .map_err(|_| ServerError::BootstrapUnavailable)?;
+                if !users.insert_if_absent(key, password_file).await? {
+                    metadata.remove(bootstrapped_key.as_bytes())?;
+                    return Err(ServerError::UserAlreadyExists);
+                }
+                self.crash_point()?;
+                (&attributes_tree, &metadata).transaction(|(attributes_tree, metadata)| {
+                    attributes_tree.insert(key.as_bytes(), attributes.as_slice())?;
+                    metadata.insert(bootstrapped_key.as_bytes(), &bootstrapped_at[..])?;
+                    Ok::<_, ConflictableTransactionError<ServerError>>(())
+                })?;
+            }
+        }
+        tracing::info!(user = %self.loggable(key), "Bootstrapped admin account");
+        Ok(())
+    }
+
+    /// store the sealed password file of a new user and use up `invite`, which fails with
+    /// [`ServerError::InvalidInvite`] when it's unknown, expired or already used
+    ///
+    /// with the password files in sled both happen in one transaction, otherwise the invite is
+    /// taken first and handed back when the user turns out to exist already
+    async fn create_invited(
+        &self,
+        users: &dyn UserStore,
+        key: &StorageKey,
+        password_file: &[u8],
+        invite: &[u8],
+    ) -> Result<(), ServerError> {
+        let invites = self.store.open_tree(self.tree_name(INVITES_TREE))?;
+        let now = self.clock.unix_secs();
+        let usable = |created_at: &[u8]| !invites::expired(created_at, now, self.invite_ttl);
+        let Some(users) = users.as_sled() else {
+            let Some(created_at) = invites
+                .remove(invite)?
+                .filter(|created_at| usable(created_at))
+            else {
+                return Err(ServerError::InvalidInvite);
+            };
+            self.crash_point()?;
+            if !users.insert_if_absent(key, password_file).await? {
+                invites.insert(invite, created_at)?;
+                return Err(ServerError::UserAlreadyExists);
+            }
+            return Ok(());
+        };
+        (users, &invites).transaction(|(users, invites)| {
+            if users.get(key.as_bytes())?.is_some() {
+                return Err(ConflictableTransactionError::Abort(
+                    ServerError::UserAlreadyExists,
+                ));
+            }
+            if !invites
+                .remove(invite)?
+                .is_some_and(|created_at| usable(&created_at))
+            {
+                return Err(ConflictableTransactionError::Abort(
+                    ServerError::InvalidInvite,
+                ));
+            }
+            self.crash_point()
+                .map_err(ConflictableTransactionError::Abort)?;
+            users.insert(key.as_bytes(), password_file)?;
+            Ok::<_, ConflictableTransactionError<ServerError>>(())
+        })?;
+        Ok(())
+    }
+
+    /// remove everything stored under `key`, session tokens included, in a single transaction
+    /// when the password files are in sled so a crash can't leave part of a removed user behind
+    pub(super) async fn remove_user(&self, key: &StorageKey) -> Result<(), ServerError> {
+        let users = self.users()?;
+        let attributes = self.store.open_tree(self.tree_name(ATTRIBUTES_TREE))?;
+        let failures = self.store.open_tree(self.tree_name(FAILURES_TREE))?;
+        let reregister = self.store.open_tree(self.tree_name(REREGISTER_TREE))?;
+        let lockouts = self.store.open_tree(self.tree_name(LOCKOUT_TREE))?;
+        let blobs = self.store.open_tree(self.tree_name(BLOBS_TREE))?;
+        let sessions = self.sessions()?;
+        let (tokens, by_user) = sessions.trees();
+        let user_tokens = sessions.of_user(key)?;
+        let Some(users) = users.as_sled() else {
+            // the password file goes first, without it nothing else about the user matters
+            users.remove(key).await?;
+            self.crash_point()?;
+            (
+                &attributes,
+                &failures,
+                &reregister,
+                &lockouts,
+                &blobs,
+                tokens,
+                by_user,
+            )
+                .transaction(
+                    |(attributes, failures, reregister, lockouts, blobs, tokens, by_user)| {
+                        attributes.remove(key.as_bytes())?;
+                        failures.remove(key.as_bytes())?;
+                        reregister.remove(key.as_bytes())?;
+                        lockouts.remove(key.as_bytes())?;
+                        blobs.remove(key.as_bytes())?;
+                        user_tokens.revoke(tokens, by_user)?;
+                        Ok::<_, ConflictableTransactionError<ServerError>>(())
+                    },
+                )?;
+            return Ok(());
+        };
+        (
+            users,
+            &attributes,
+            &failures,
+            &reregister,
+            &lockouts,
+            &blobs,
+            tokens,
+            by_user,
+        )
+            .transaction(
+                |(users, attributes, failures, reregister, lockouts, blobs, tokens, by_user)| {
+                    users.remove(key.as_bytes())?;
+                    self.crash_point()
+                        .map_err(ConflictableTransactionError::Abort)?;
+                    attributes.remove(key.as_bytes())?;
+                    failures.remove(key.as_bytes())?;
+                    reregister.remove(key.as_bytes())?;
+                    lockouts.remove(key.as_bytes())?;
+                    blobs.remove(key.as_bytes())?;
+                    user_tokens.revoke(tokens, by_user)?;
+                    Ok::<_, ConflictableTransactionError<ServerError>>(())
+                },
+            )?;
+        Ok(())
+    }
+
+    /// the password file in `key`'s stored record
+    ///
+    /// a record that fails its checksum is counted and, when quarantining, moved out of the
+    /// users store
+    pub(super) async fn open_record(
+        &self,
+        key: &StorageKey,
+        record: &[u8],
+    ) -> Result<Vec<u8>, ServerError> {
+        let err = match record::unseal(record) {
+            // a record from another suite is intact, it just can't be used here
+            Ok(record) => {
+                return record
+                    .password_file_for(CS::ID)
+                    .map_err(ServerError::CorruptRecord)
+            }
+            Err(err) => err,
+        };
+        let corrupt = self.corrupt_records.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::error!(
+            user = %self.loggable(key),
+            corrupt,
+            "Record failed its check: `{err}`"
+        );
+        if self.quarantine {
+            let quarantine = self.store.open_tree(self.tree_name(QUARANTINE_TREE))?;
+            quarantine.insert(key, record)?;
+            self.users()?.remove(key).await?;
+            tracing::warn!(user = %self.loggable(key), "Moved record to quarantine");
+        }
+        Err(ServerError::CorruptRecord(err))
+    }
+
+    /// swap in a new password file for `key`, which also takes care of any request for the user
+    /// to register a new password
+    ///
+    /// when and last logged in are carried over from the record being replaced
+    pub(super) async fn replace_password(
+        &self,
+        key: &StorageKey,
+        password_file: &[u8],
+    ) -> Result<(), ServerError> {
+        let users = self.users()?;
+        let now = self.clock.unix_secs();
+        let replacing = |previous: Option<&[u8]>| {
+            let previous = previous.and_then(|previous| record::unseal(previous).ok());
+            let record = UserRecord {
+                created_at: previous
+                    .as_ref()
+                    .map_or(now, |previous| previous.created_at),
+                last_login: previous.and_then(|previous| previous.last_login),
+                ..UserRecord::new(password_file.to_vec(), now).with_suite(CS::ID)
+            };
+            record::seal(&record)
+        };
+        let reregister = self.store.open_tree(self.tree_name(REREGISTER_TREE))?;
+        // tokens from logins with the old password don't outlive it
+        let sessions = self.sessions()?;
+        let (tokens, by_user) = sessions.trees();
+        let user_tokens = sessions.of_user(key)?;
+        let Some(users) = users.as_sled() else {
+            let previous = users.get(key).await?;
+            users.insert(key, &replacing(previous.as_deref())).await?;
+            self.crash_point()?;
+            (&reregister, tokens, by_user).transaction(|(reregister, tokens, by_user)| {
+                reregister.remove(key.as_bytes())?;
+                user_tokens.revoke(tokens, by_user)?;
+                Ok::<_, ConflictableTransactionError<ServerError>>(())
+            })?;
+            return Ok(());
+        };
+        (users, &reregister, tokens, by_user).transaction(
+            |(users, reregister, tokens, by_user)| {
+                let previous = users.get(key.as_bytes())?;
+                users.insert(key.as_bytes(), replacing(previous.as_deref()))?;
+                self.crash_point()
+                    .map_err(ConflictableTransactionError::Abort)?;
+                reregister.remove(key.as_bytes())?;
+                user_tokens.revoke(tokens, by_user)?;
+                Ok::<_, ConflictableTransactionError<ServerError>>(())
+            },
+        )?;
+        Ok(())
+    }
+
+    /// stands in for a crash partway through a write touching several trees, see
+    /// `Server::with_crash_before_commit`
+    fn crash_point(&self) -> Result<(), ServerError> {
+        #[cfg(feature = "test-util")]
+        if self.crash_before_commit {
+            let crashed = super::store::StoreError::Backend("crashed before commit".into());
+            return Err(crashed.into());
+        }
+        Ok(())
+    }
+
+    /// note that `key` just logged in
+    ///
+    /// with the password files in sled this can't undo a password change or deletion racing it,
+    /// other stores only get a best effort
+    pub(super) async fn touch_last_login(&self, key: &StorageKey) -> Result<(), ServerError> {
+        let users = self.users()?;
+        let now = self.clock.unix_secs();
+        let touched = |stored: &[u8]| {
+            let mut record = record::unseal(stored).ok()?;
+            record.last_login = Some(now);
+            Some(record::seal(&record))
+        };
+        let Some(tree) = users.as_sled() else {
+            if let Some(stored) = users.get(key).await? {
+                if let Some(record) = touched(&stored) {
+                    users.insert(key, &record).await?;
+                }
+            }
+            return Ok(());
+        };
+        // a record that doesn't open is left for the next login to report
+        tree.update_and_fetch(key.as_bytes(), |stored| {
+            stored.map(|stored| touched(stored).unwrap_or_else(|| stored.to_vec()))
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use super::{attributes::Attributes, deletion::DeletionPolicy, error::ServerError, Server};
+use crate::suite::Suite;
+
+/// users listed when a request doesn't say how many it wants
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+/// most users listed in one response
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Bearer token that has to come with every request to the `/admin` routes
+#[derive(Clone)]
+pub struct AdminToken {
+    digest: Arc<[u8]>,
+}
+
+impl AdminToken {
+    pub fn new(token: &str) -> Self {
+        Self {
+            digest: Sha256::digest(token.as_bytes()).as_slice().into(),
+        }
+    }
+
+    /// whether `presented` is the token, the digests are compared in constant time so how long
+    /// that takes says nothing about the token itself
+    pub fn matches(&self, presented: &[u8]) -> bool {
+        Sha256::digest(presented)
+            .as_slice()
+            .ct_eq(&self.digest)
+            .into()
+    }
+}
+
+/// Which part of the users to list, as query parameters
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct Page {
+    #[serde(default)]
+    pub offset: usize,
+    /// [`DEFAULT_PAGE_SIZE`] when left out, never more than [`MAX_PAGE_SIZE`]
+    pub limit: Option<usize>,
+}
+
+/// One page of registered users
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserPage {
+    /// every registered user, not just the ones on this page
+    pub total: usize,
+    pub offset: usize,
+    pub users: Vec<String>,
+}
+
+/// Why an admin request didn't go through
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminError {
+    /// short machine readable name of the error
+    pub error: String,
+    pub message: String,
+}
+
+fn error_response(status: StatusCode, error: &str, message: String) -> Response {
+    let error = AdminError {
+        error: error.into(),
+        message,
+    };
+    (status, Json(error)).into_response()
+}
+
+/// turn away any request without the admin token as its bearer token
+pub async fn require_token<CS: Suite>(
+    State(state): State<Server<CS>>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Response {
+    let presented = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.as_bytes().strip_prefix(b"Bearer "));
+    match (&state.admin_token, presented) {
+        (Some(token), Some(presented)) if token.matches(presented) => next.run(request).await,
+        _ => error_response(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "Missing or wrong admin token".into(),
+        ),
+    }
+}
+
+fn failed(err: ServerError) -> Response {
+    let status = match err {
+        ServerError::UserDoesNotExist => StatusCode::NOT_FOUND,
+        ServerError::Username(_) | ServerError::Attributes(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    error_response(status, err.kind(), err.to_string())
+}
+
+/// list registered users, a page at a time
+pub async fn list_users<CS: Suite>(
+    State(state): State<Server<CS>>,
+    Query(page): Query<Page>,
+) -> Response {
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    let listed = async {
+        Ok::<_, ServerError>(UserPage {
+            total: state.user_count().await?,
+            offset: page.offset,
+            users: state.list_users(page.offset, limit).await?,
+        })
+    };
+    match listed.await {
+        Ok(users) => Json(users).into_response(),
+        Err(err) => failed(err),
+    }
+}
+
+/// A freshly made invite
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewInvite {
+    pub invite: String,
+}
+
+/// make a single use invite to register with
+pub async fn create_invite<CS: Suite>(State(state): State<Server<CS>>) -> Response {
+    match state.create_invite() {
+        Ok(invite) => (StatusCode::CREATED, Json(NewInvite { invite })).into_response(),
+        Err(err) => failed(err),
+    }
+}
+
+/// remove a user and everything stored about them, unless the
+/// [`DeletionPolicy`](super::deletion::DeletionPolicy) leaves that to `tinap-admin`
+pub async fn delete_user<CS: Suite>(
+    State(state): State<Server<CS>>,
+    Path(username): Path<String>,
+) -> Response {
+    if state.deletion_policy == DeletionPolicy::Disabled {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "deletion_disabled",
+            "Accounts can only be removed offline".into(),
+        );
+    }
+    match state.delete_user(username.as_bytes()).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => failed(err),
+    }
+}
+
+/// the attributes stored for a user
+pub async fn user_attributes<CS: Suite>(
+    State(state): State<Server<CS>>,
+    Path(username): Path<String>,
+) -> Response {
+    let found = async {
+        let key = state.storage_key(username.as_bytes())?;
+        if !state.users()?.contains(&key).await? {
+            return Err(ServerError::UserDoesNotExist);
+        }
+        state.attributes(username.as_bytes())
+    };
+    match found.await {
+        Ok(attributes) => Json(attributes).into_response(),
+        Err(err) => failed(err),
+    }
+}
+
+/// replace all of a user's attributes with the json map in the body
+pub async fn set_user_attributes<CS: Suite>(
+    State(state): State<Server<CS>>,
+    Path(username): Path<String>,
+    Json(attributes): Json<BTreeMap<String, String>>,
+) -> Response {
+    let replaced = async {
+        let attributes = Attributes::try_from(attributes)?;
+        state.set_attributes(username.as_bytes(), &attributes).await
+    };
+    match replaced.await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => failed(err),
+    }
+}
+
+/// everything stored about a user, for support cases
+pub async fn user_takeout<CS: Suite>(
+    State(state): State<Server<CS>>,
+    Path(username): Path<String>,
+) -> Response {
+    match state.export(username.as_bytes(), true).await {
+        Ok(document) => Json(document).into_response(),
+        Err(err) => failed(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_token_matches() {
+        assert!(AdminToken::new("secret").matches(b"secret"));
+    }
+
+    #[test]
+    fn other_token_does_not_match() {
+        assert!(!AdminToken::new("secret").matches(b"secret!"));
+        assert!(!AdminToken::new("secret").matches(b"sekret"));
+        assert!(!AdminToken::new("secret").matches(b""));
+    }
+}
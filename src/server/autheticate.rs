@@ -1,138 +1,291 @@
-use opaque_ke::{
-    CredentialFinalization, CredentialRequest, ServerLogin, ServerLoginFinishResult,
-    ServerLoginStartParameters, ServerLoginStartResult, ServerRegistration, ServerSetup,
-};
-use rand::rngs::OsRng;
+use opaque_ke::ServerSetup;
+use subtle::ConstantTimeEq;
 
-use crate::{Scheme, WithUsername};
+use crate::{
+    channel::SecureChannel,
+    key_confirmation,
+    sequence::Side,
+    suite::Suite,
+    username::{self, DEFAULT_MAX_USERNAME_LEN},
+    wire, AuthenticateRequest, Identifiers, KeyLengthError, Scheme,
+};
 
 use super::error::ServerError;
 
-pub struct AuthWaiting<'a> {
-    server_setup: ServerSetup<Scheme<'a>>,
+pub struct AuthWaiting<CS: Suite = Scheme> {
+    server_setup: CS::Setup,
+    max_username_len: usize,
+    identifiers: Identifiers,
+}
+
+impl AuthWaiting {
+    pub fn new(server_setup: ServerSetup<Scheme>) -> Self {
+        Self::for_suite(server_setup)
+    }
 }
 
-impl<'a> AuthWaiting<'a> {
-    pub fn new(server_setup: ServerSetup<Scheme<'a>>) -> Self {
-        Self { server_setup }
+impl<CS: Suite> AuthWaiting<CS> {
+    /// [`AuthWaiting::new`] for servers running `CS`
+    pub fn for_suite(server_setup: CS::Setup) -> Self {
+        Self {
+            server_setup,
+            max_username_len: DEFAULT_MAX_USERNAME_LEN,
+            identifiers: Identifiers::default(),
+        }
+    }
+
+    pub fn with_max_username_len(mut self, max_username_len: usize) -> Self {
+        self.max_username_len = max_username_len;
+        self
+    }
+
+    /// expect clients to log in with `identifiers`, see [`Identifiers`]
+    pub fn with_identifiers(mut self, identifiers: Identifiers) -> Self {
+        self.identifiers = identifiers;
+        self
     }
 
-    pub fn step(self, initial_data: Vec<u8>) -> Result<AuthInitial<'a>, ServerError> {
-        let data: WithUsername = bincode::deserialize(&initial_data)?;
-        let username = data.username;
-        let credential_request_bytes = data.data;
-        let credential_request = CredentialRequest::deserialize(credential_request_bytes)?;
+    /// the username is normalized the same way the client did it, see [`username::normalize`]
+    pub fn step(self, initial_data: Vec<u8>) -> Result<AuthInitial<CS>, ServerError> {
+        let data: AuthenticateRequest = wire::decode(&initial_data)?;
+        let username = username::normalize_bytes(data.username, self.max_username_len)?;
+        let credential_request = CS::credential_request(data.data)?;
         Ok(AuthInitial::new(
-            username.into(),
+            username.into_string().into_bytes(),
             credential_request,
             self.server_setup,
-        ))
+            data.verify_only,
+        )
+        .with_identifiers(self.identifiers))
     }
 }
 
-pub struct AuthInitial<'a> {
+pub struct AuthInitial<CS: Suite = Scheme> {
     username: Vec<u8>,
-    credential_request: CredentialRequest<Scheme<'a>>,
-    server_setup: ServerSetup<Scheme<'a>>,
+    credential_request: CS::CredentialRequest,
+    server_setup: CS::Setup,
+    verify_only: bool,
+    identifiers: Identifiers,
 }
 
-impl<'a> AuthInitial<'a> {
+impl<CS: Suite> AuthInitial<CS> {
     pub fn new(
         username: Vec<u8>,
-        credential_request: CredentialRequest<Scheme<'a>>,
-        server_setup: ServerSetup<Scheme<'a>>,
+        credential_request: CS::CredentialRequest,
+        server_setup: CS::Setup,
+        verify_only: bool,
     ) -> Self {
         Self {
             username,
             credential_request,
             server_setup,
+            verify_only,
+            identifiers: Identifiers::default(),
         }
     }
 
+    pub fn with_identifiers(mut self, identifiers: Identifiers) -> Self {
+        self.identifiers = identifiers;
+        self
+    }
+
     pub fn username(&self) -> &[u8] {
         &self.username
     }
 
-    pub fn step(self, password_file_bytes: Vec<u8>) -> Result<AuthWithCreds<'a>, ServerError> {
-        let password_file = ServerRegistration::<Scheme>::deserialize(&password_file_bytes)?;
-        let server_login_start_result = ServerLogin::start(
-            &mut OsRng,
+    /// whether the client only wants its credentials checked
+    pub fn verify_only(&self) -> bool {
+        self.verify_only
+    }
+
+    /// `None` for users that aren't registered, the exchange then runs against a dummy record
+    /// and fails the same way a wrong password would
+    pub fn step(
+        self,
+        password_file_bytes: Option<Vec<u8>>,
+    ) -> Result<AuthWithCreds<CS>, ServerError> {
+        let (state, credential_response) = CS::server_login_start(
             &self.server_setup,
-            Some(password_file),
+            password_file_bytes.as_deref(),
             self.credential_request,
             &self.username,
-            ServerLoginStartParameters::default(),
+            &self.identifiers,
         )?;
-        Ok(AuthWithCreds::new(server_login_start_result))
+        Ok(AuthWithCreds::new(
+            self.username,
+            state,
+            credential_response,
+            self.verify_only,
+        ))
     }
 }
 
-pub struct AuthWithCreds<'a> {
-    server_login_start_result: ServerLoginStartResult<Scheme<'a>>,
+pub struct AuthWithCreds<CS: Suite = Scheme> {
+    username: Vec<u8>,
+    state: CS::ServerLogin,
+    /// the serialized `CredentialResponse`
+    credential_response: Vec<u8>,
+    verify_only: bool,
 }
 
-impl<'a> AuthWithCreds<'a> {
-    pub fn new(server_login_start_result: ServerLoginStartResult<Scheme<'a>>) -> Self {
+impl<CS: Suite> AuthWithCreds<CS> {
+    pub fn new(
+        username: Vec<u8>,
+        state: CS::ServerLogin,
+        credential_response: Vec<u8>,
+        verify_only: bool,
+    ) -> Self {
         Self {
-            server_login_start_result,
+            username,
+            state,
+            credential_response,
+            verify_only,
         }
     }
 
     pub fn to_data(&self) -> Vec<u8> {
-        self.server_login_start_result
-            .message
-            .serialize()
-            .as_slice()
-            .into()
+        self.credential_response.clone()
     }
 
-    pub fn step(
-        self,
-        credential_finalization_bytes: Vec<u8>,
-    ) -> Result<AuthFinal<'a>, ServerError> {
-        let credential_finalization =
-            CredentialFinalization::deserialize(&credential_finalization_bytes)?;
-        let server_login_finish_result = self
-            .server_login_start_result
-            .state
-            .finish(credential_finalization)?;
-        Ok(AuthFinal::new(server_login_finish_result))
+    pub fn step(self, credential_finalization_bytes: Vec<u8>) -> Result<AuthFinal, ServerError> {
+        let session_key = CS::server_login_finish(self.state, &credential_finalization_bytes)?;
+        Ok(AuthFinal::new(self.username, session_key, self.verify_only))
     }
 }
 
-pub struct AuthFinal<'a> {
-    server_login_finish_result: ServerLoginFinishResult<Scheme<'a>>,
+pub struct AuthFinal {
+    username: Vec<u8>,
+    session_key: Vec<u8>,
+    verify_only: bool,
 }
 
-impl<'a> AuthFinal<'a> {
-    pub fn new(server_login_finish_result: ServerLoginFinishResult<Scheme<'a>>) -> Self {
+impl AuthFinal {
+    pub fn new(username: Vec<u8>, session_key: Vec<u8>, verify_only: bool) -> Self {
         Self {
-            server_login_finish_result,
+            username,
+            session_key,
+            verify_only,
         }
     }
 
+    /// proof of the session key for the client, derived from it so the key itself is never sent
     pub fn to_data(&self) -> Vec<u8> {
-        self.server_login_finish_result
-            .session_key
-            .as_slice()
-            .into()
+        key_confirmation(&self.session_key)
     }
 
+    /// take the client's report of whether it derived the same session key
+    ///
+    /// getting here means `ServerLogin::finish` already checked the client's key confirmation,
+    /// so the report can only turn a login down, never make a failed one count
     pub fn step(self, state: Vec<u8>) -> AuthConfirm {
-        AuthConfirm::new(state == vec![1])
+        let confirmed = reports_confirmed(&state);
+        AuthConfirm::new(true, Some(confirmed), self.verify_only)
+            .with_username(self.username)
+            .with_session_key(self.session_key)
+    }
+
+    /// the client went away without reporting back, only to be used when the server's check is
+    /// trusted on its own, see [`ConfirmationPolicy`](super::confirmation::ConfirmationPolicy)
+    pub fn unconfirmed(self) -> AuthConfirm {
+        AuthConfirm::new(true, None, self.verify_only)
+            .with_username(self.username)
+            .with_session_key(self.session_key)
     }
 }
 
+/// whether the client's report says it derived the same session key, compared in constant time
+fn reports_confirmed(state: &[u8]) -> bool {
+    state.ct_eq(&[1u8]).into()
+}
+
 pub struct AuthConfirm {
-    authenticated: bool,
+    /// whether the server's own check of the client's key confirmation passed
+    verified: bool,
+    /// whether the client reported deriving the same session key, `None` when it never reported
+    client_confirmed: Option<bool>,
+    verify_only: bool,
+    /// who logged in, as the client gave it
+    username: Vec<u8>,
+    session_key: Vec<u8>,
 }
 
 impl AuthConfirm {
-    pub fn new(authenticated: bool) -> Self {
-        Self { authenticated }
+    pub fn new(verified: bool, client_confirmed: Option<bool>, verify_only: bool) -> Self {
+        Self {
+            verified,
+            client_confirmed,
+            verify_only,
+            username: Vec::new(),
+            session_key: Vec::new(),
+        }
+    }
+
+    pub fn with_username(mut self, username: Vec<u8>) -> Self {
+        self.username = username;
+        self
+    }
+
+    pub fn username(&self) -> &[u8] {
+        &self.username
+    }
+
+    pub fn with_session_key(mut self, session_key: Vec<u8>) -> Self {
+        self.session_key = session_key;
+        self
+    }
+
+    pub fn session_key(&self) -> &[u8] {
+        &self.session_key
     }
 
+    /// `len` bytes of key for `label`, the client derives the same key for the same label, see
+    /// [`derive_key`](crate::derive_key)
+    pub fn derive_key(&self, label: &[u8], len: usize) -> Result<Vec<u8>, KeyLengthError> {
+        crate::derive_key(&self.session_key, label, len)
+    }
+
+    /// this side of an encrypted channel for traffic after the login, the client gets the
+    /// other side from its own confirmation
+    pub fn channel(&self) -> SecureChannel {
+        SecureChannel::new(&self.session_key, Side::Server)
+    }
+
+    /// whether the login succeeded, which needs the server's check to have passed no matter
+    /// what the client says
     pub fn authenticated(&self) -> bool {
-        self.authenticated
+        self.verified && self.client_confirmed != Some(false)
+    }
+
+    /// whether the client went away before reporting back, there's nobody left to answer then
+    pub fn unconfirmed(&self) -> bool {
+        self.client_confirmed.is_none()
+    }
+
+    /// whether this was only a credential check, in which case nothing that normally follows a
+    /// login should happen
+    pub fn verify_only(&self) -> bool {
+        self.verify_only
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confirmed_report_is_taken() {
+        assert!(reports_confirmed(&[1]));
+    }
+
+    #[test]
+    fn other_reports_are_not() {
+        assert!(!reports_confirmed(&[0]));
+        assert!(!reports_confirmed(&[2]));
+    }
+
+    #[test]
+    fn reports_of_other_lengths_are_not() {
+        assert!(!reports_confirmed(&[]));
+        assert!(!reports_confirmed(&[1, 1]));
     }
 }
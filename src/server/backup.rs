@@ -0,0 +1,146 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use boring_derive::From;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{
+    attributes::Attributes,
+    record::{self, RecordError},
+};
+
+/// layout of the backups written now, bumped whenever it changes in a way older readers can't
+/// cope with
+pub const BACKUP_FORMAT: u32 = 1;
+
+#[derive(Debug, Error, From)]
+pub enum BackupError {
+    #[error("Error interacting with database `{0}`")]
+    Database(sled::Error),
+    #[from(skip)]
+    #[error("Backup has unsupported format `{0}`")]
+    UnsupportedFormat(u32),
+    #[from(skip)]
+    #[error("Entry `{0}` is not valid base64 `{1}`")]
+    Encoding(usize, base64::DecodeError),
+    #[from(skip)]
+    #[error("Entry `{0}` holds a record that can't be used `{1}`")]
+    Record(usize, RecordError),
+    #[from(skip)]
+    #[error("Entry `{0}` has stored attributes that can't be read `{1}`")]
+    Attributes(usize, bincode::Error),
+    #[from(skip)]
+    #[error("`{0}` users in the backup are already registered")]
+    Conflicts(usize),
+}
+
+/// Every registered user, in a form that can be kept outside the database and restored into
+/// another one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backup {
+    pub format: u32,
+    pub users: Vec<BackupEntry>,
+}
+
+/// One user in a [`Backup`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupEntry {
+    /// the key the user is stored under, base64
+    pub username: String,
+    /// the stored record exactly as it is, base64
+    pub record: String,
+    /// seconds since the unix epoch, `None` when the record couldn't be opened
+    pub created_at: Option<u64>,
+    /// seconds since the unix epoch
+    pub last_login: Option<u64>,
+    /// left out of backups from before attributes were exported
+    #[serde(default, skip_serializing_if = "Attributes::is_empty")]
+    pub attributes: Attributes,
+}
+
+/// What a [`import_users`] run wrote
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// users that weren't registered before
+    pub added: usize,
+    /// users whose record replaced the one already there
+    pub replaced: usize,
+}
+
+/// copy out every user in `users`, the tree of password files, along with their attributes from
+/// `attributes`
+///
+/// the tree is walked with an iterator, so this can run while the server is using it. Records
+/// are copied byte for byte, including any that fail their checksum, the metadata is only there
+/// for people reading the backup
+pub fn export_users(users: &sled::Tree, attributes: &sled::Tree) -> Result<Backup, BackupError> {
+    let mut entries = Vec::new();
+    for entry in users.iter() {
+        let (key, stored) = entry?;
+        let opened = record::unseal(&stored).ok();
+        let user_attributes = match attributes.get(&key)? {
+            Some(data) => bincode::deserialize(&data)
+                .map_err(|err| BackupError::Attributes(entries.len(), err))?,
+            None => Attributes::new(),
+        };
+        entries.push(BackupEntry {
+            username: STANDARD.encode(&key),
+            record: STANDARD.encode(&stored),
+            created_at: opened.as_ref().map(|opened| opened.created_at),
+            last_login: opened.and_then(|opened| opened.last_login),
+            attributes: user_attributes,
+        });
+    }
+    Ok(Backup {
+        format: BACKUP_FORMAT,
+        users: entries,
+    })
+}
+
+/// restore `backup` into `users`, the tree of password files, and `attributes`
+///
+/// every entry is checked before anything is written, and then all of them are written in one
+/// batch per tree, so a bad backup leaves the trees as they were. Users that are already
+/// registered make the whole import fail, unless `force` is set in which case their records and
+/// attributes are replaced
+pub fn import_users(
+    users: &sled::Tree,
+    attributes: &sled::Tree,
+    backup: &Backup,
+    force: bool,
+) -> Result<ImportReport, BackupError> {
+    if backup.format != BACKUP_FORMAT {
+        return Err(BackupError::UnsupportedFormat(backup.format));
+    }
+    let mut batch = sled::Batch::default();
+    let mut attribute_batch = sled::Batch::default();
+    let mut report = ImportReport::default();
+    for (index, entry) in backup.users.iter().enumerate() {
+        let key = STANDARD
+            .decode(&entry.username)
+            .map_err(|err| BackupError::Encoding(index, err))?;
+        let stored = STANDARD
+            .decode(&entry.record)
+            .map_err(|err| BackupError::Encoding(index, err))?;
+        record::unseal(&stored).map_err(|err| BackupError::Record(index, err))?;
+        match users.contains_key(&key)? {
+            true => report.replaced += 1,
+            false => report.added += 1,
+        }
+        match entry.attributes.is_empty() {
+            true => attribute_batch.remove(key.as_slice()),
+            false => attribute_batch.insert(
+                key.as_slice(),
+                bincode::serialize(&entry.attributes).expect("attributes always serialize"),
+            ),
+        }
+        batch.insert(key, stored);
+    }
+    if report.replaced > 0 && !force {
+        return Err(BackupError::Conflicts(report.replaced));
+    }
+    users.apply_batch(batch)?;
+    attributes.apply_batch(attribute_batch)?;
+    users.flush()?;
+    attributes.flush()?;
+    Ok(report)
+}
@@ -0,0 +1,4 @@
+/// sled tree holding the blob each user stored, under the same key as their password file
+pub(crate) const BLOBS_TREE: &str = "blobs";
+/// largest blob a user can store unless configured otherwise
+pub const DEFAULT_MAX_BLOB_SIZE: usize = 64 * 1024;
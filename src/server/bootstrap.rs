@@ -0,0 +1,78 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use subtle::ConstantTimeEq;
+
+pub use crate::wire::BOOTSTRAP_HEADER;
+
+/// attribute marking an account as an administrator
+pub const ADMIN_ATTRIBUTE: &str = "admin";
+/// key in the metadata tree recording that bootstrapping already happened
+pub(crate) const BOOTSTRAPPED_KEY: &str = "bootstrapped";
+
+/// A single use token that lets the first registration on an empty store create an admin
+#[derive(Clone)]
+pub struct Bootstrap {
+    token: Arc<str>,
+    claimed: Arc<AtomicBool>,
+}
+
+impl Bootstrap {
+    pub fn new(token: String) -> Self {
+        Self {
+            token: token.into(),
+            claimed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// take the bootstrap for one registration, fails when the token is wrong or another
+    /// registration already holds it
+    pub fn claim(&self, token: &[u8]) -> bool {
+        bool::from(token.ct_eq(self.token.as_bytes()))
+            && self
+                .claimed
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+    }
+
+    /// give the bootstrap back after a registration holding it didn't go through
+    pub fn release(&self) {
+        self.claimed.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claims_with_the_same_token() {
+        let bootstrap = Bootstrap::new("secret".into());
+        assert!(bootstrap.claim(b"secret"));
+    }
+
+    #[test]
+    fn refuses_a_different_token() {
+        let bootstrap = Bootstrap::new("secret".into());
+        assert!(!bootstrap.claim(b"secreT"));
+    }
+
+    #[test]
+    fn refuses_a_token_of_another_length() {
+        let bootstrap = Bootstrap::new("secret".into());
+        assert!(!bootstrap.claim(b"secret "));
+        assert!(!bootstrap.claim(b"secre"));
+        assert!(!bootstrap.claim(b""));
+    }
+
+    #[test]
+    fn only_one_claim_until_released() {
+        let bootstrap = Bootstrap::new("secret".into());
+        assert!(bootstrap.claim(b"secret"));
+        assert!(!bootstrap.claim(b"secret"));
+        bootstrap.release();
+        assert!(bootstrap.claim(b"secret"));
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+pub use crate::wire::Operation;
+
+/// default number of registrations that can run at once
+pub const DEFAULT_REGISTRATION_BUDGET: usize = 256;
+/// default number of authentications that can run at once
+pub const DEFAULT_AUTHENTICATE_BUDGET: usize = 1024;
+/// default number of password changes that can run at once
+pub const DEFAULT_CHANGE_PASSWORD_BUDGET: usize = 256;
+/// default number of account deletions that can run at once
+pub const DEFAULT_DELETE_BUDGET: usize = 256;
+/// default number of exchanges of any kind that can run at once
+pub const DEFAULT_HANDSHAKE_BUDGET: usize = 1024;
+
+struct BudgetInner {
+    semaphore: Arc<Semaphore>,
+    limit: AtomicUsize,
+    /// permits that should go away once they're handed back, from shrinking the limit while
+    /// they were in use
+    owed: AtomicUsize,
+}
+
+/// How many exchanges of one kind can be in flight at once
+///
+/// Each operation has its own budget so a flood of one kind, e.g. expensive registrations,
+/// can't starve the others. Limits can be changed while the server is running.
+#[derive(Clone)]
+pub struct Budget {
+    inner: Arc<BudgetInner>,
+}
+
+impl Budget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            inner: Arc::new(BudgetInner {
+                semaphore: Arc::new(Semaphore::new(limit)),
+                limit: AtomicUsize::new(limit),
+                owed: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.inner.limit.load(Ordering::Relaxed)
+    }
+
+    /// how many exchanges are currently running
+    pub fn in_use(&self) -> usize {
+        let unavailable = self.limit() + self.inner.owed.load(Ordering::Relaxed);
+        unavailable.saturating_sub(self.inner.semaphore.available_permits())
+    }
+
+    /// change the limit, exchanges already running are unaffected
+    pub fn set_limit(&self, limit: usize) {
+        let previous = self.inner.limit.swap(limit, Ordering::Relaxed);
+        if limit > previous {
+            let extra = limit - previous;
+            // cancel out anything still owed before handing out new permits
+            let owed = self
+                .inner
+                .owed
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |owed| {
+                    Some(owed.saturating_sub(extra))
+                })
+                .unwrap_or(0);
+            self.inner.semaphore.add_permits(extra - owed.min(extra));
+        } else {
+            let excess = previous - limit;
+            let forgotten = self.inner.semaphore.forget_permits(excess);
+            self.inner
+                .owed
+                .fetch_add(excess - forgotten, Ordering::Relaxed);
+        }
+    }
+
+    /// wait up to `wait` for room in the budget
+    pub async fn acquire(&self, wait: Duration) -> Option<BudgetPermit> {
+        let semaphore = self.inner.semaphore.clone();
+        let permit = tokio::time::timeout(wait, semaphore.acquire_owned())
+            .await
+            .ok()?
+            .ok()?;
+        Some(BudgetPermit {
+            permit: Some(permit),
+            budget: self.inner.clone(),
+        })
+    }
+}
+
+/// Room for one exchange, handed back to the budget when dropped
+pub struct BudgetPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    budget: Arc<BudgetInner>,
+}
+
+impl Drop for BudgetPermit {
+    fn drop(&mut self) {
+        let owed = self
+            .budget
+            .owed
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |owed| {
+                owed.checked_sub(1)
+            });
+        if let (Ok(_), Some(permit)) = (owed, self.permit.take()) {
+            permit.forget();
+        }
+    }
+}
+
+/// Room for one exchange in both its own budget and the one shared by all exchanges, handed
+/// back when dropped
+pub struct ExchangePermit {
+    _own: BudgetPermit,
+    _shared: BudgetPermit,
+}
+
+/// Budgets for every [`Operation`], and one they all share that bounds the total memory and CPU
+/// handshakes can take up
+#[derive(Clone)]
+pub struct Budgets {
+    registration: Budget,
+    authenticate: Budget,
+    change_password: Budget,
+    delete: Budget,
+    handshakes: Budget,
+}
+
+impl Budgets {
+    pub fn get(&self, operation: Operation) -> &Budget {
+        match operation {
+            Operation::Registration => &self.registration,
+            Operation::Authenticate => &self.authenticate,
+            Operation::ChangePassword => &self.change_password,
+            Operation::Delete => &self.delete,
+        }
+    }
+
+    /// the budget every operation draws from on top of its own
+    pub fn handshakes(&self) -> &Budget {
+        &self.handshakes
+    }
+
+    /// wait up to `wait` for room for an `operation` exchange, in its own budget and the shared
+    /// one
+    pub async fn acquire(&self, operation: Operation, wait: Duration) -> Option<ExchangePermit> {
+        let shared = self.handshakes.acquire(wait).await?;
+        let own = self.get(operation).acquire(wait).await?;
+        Some(ExchangePermit {
+            _own: own,
+            _shared: shared,
+        })
+    }
+
+    /// limit and current usage of every budget
+    pub fn usage(&self) -> Vec<(Operation, usize, usize)> {
+        [
+            Operation::Registration,
+            Operation::Authenticate,
+            Operation::ChangePassword,
+            Operation::Delete,
+        ]
+        .into_iter()
+        .map(|operation| {
+            let budget = self.get(operation);
+            (operation, budget.limit(), budget.in_use())
+        })
+        .collect()
+    }
+}
+
+impl Default for Budgets {
+    fn default() -> Self {
+        Self {
+            registration: Budget::new(DEFAULT_REGISTRATION_BUDGET),
+            authenticate: Budget::new(DEFAULT_AUTHENTICATE_BUDGET),
+            change_password: Budget::new(DEFAULT_CHANGE_PASSWORD_BUDGET),
+            delete: Budget::new(DEFAULT_DELETE_BUDGET),
+            handshakes: Budget::new(DEFAULT_HANDSHAKE_BUDGET),
+        }
+    }
+}
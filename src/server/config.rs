@@ -0,0 +1,79 @@
+use std::{path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    blobs::DEFAULT_MAX_BLOB_SIZE, concurrency::DEFAULT_HANDSHAKE_BUDGET,
+    invites::DEFAULT_INVITE_TTL, lockout::LockoutPolicy, tokens::DEFAULT_SESSION_TTL, DB_PATH,
+    SETUP_PATH,
+};
+use crate::Identifiers;
+
+/// how long a client gets to deliver a complete frame before the connection is dropped
+pub const DEFAULT_FRAME_TIMEOUT: Duration = Duration::from_secs(30);
+/// how long a frame may keep arriving once its first bytes are in
+pub const DEFAULT_FRAME_ARRIVAL_LIMIT: Duration = Duration::from_secs(10);
+/// how long running exchanges get to finish when the server shuts down
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// Settings for a [`Server`](super::Server), applied with
+/// [`Server::with_config`](super::Server::with_config)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// upper bound on each step of an exchange, a client that doesn't send its next frame in
+    /// time is sent a close and dropped, so a stalled client can't hold on to a task forever
+    pub frame_timeout: Duration,
+    /// upper bound on how long a frame may keep arriving once its first bytes are in, so a
+    /// client trickling a frame in can't hold on to the connection for the whole `frame_timeout`
+    pub frame_arrival_limit: Duration,
+    /// where the database lives, only used by
+    /// [`Server::initialize_from`](super::Server::initialize_from)
+    pub db_path: PathBuf,
+    /// where the server setup lives, only used by
+    /// [`Server::initialize_from`](super::Server::initialize_from)
+    pub setup_path: PathBuf,
+    /// write usernames into logs, otherwise they're redacted
+    pub log_usernames: bool,
+    /// how long running exchanges get to finish on shutdown before the database is flushed
+    /// without them
+    pub shutdown_grace: Duration,
+    /// lock accounts after repeated failed logins, off when `None`
+    pub lockout: Option<LockoutPolicy>,
+    /// how many exchanges of any kind can run at once, further ones are turned away with a 503
+    pub max_handshakes: usize,
+    /// bearer token for the `/admin` routes, which aren't served at all without one
+    pub admin_token: Option<String>,
+    /// only let users register with an invite, see
+    /// [`Server::with_invite_only`](super::Server::with_invite_only)
+    pub invite_only: bool,
+    /// how long invites can be used for once they're made
+    pub invite_ttl: Duration,
+    /// how long the session tokens handed out after logins last
+    pub session_ttl: Duration,
+    /// largest blob a user can store, in bytes
+    pub max_blob_size: usize,
+    /// identities logins are bound to, clients have to use the same ones
+    pub identifiers: Identifiers,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            frame_timeout: DEFAULT_FRAME_TIMEOUT,
+            frame_arrival_limit: DEFAULT_FRAME_ARRIVAL_LIMIT,
+            db_path: DB_PATH.into(),
+            setup_path: SETUP_PATH.into(),
+            log_usernames: false,
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+            lockout: None,
+            max_handshakes: DEFAULT_HANDSHAKE_BUDGET,
+            admin_token: None,
+            invite_only: false,
+            invite_ttl: DEFAULT_INVITE_TTL,
+            session_ttl: DEFAULT_SESSION_TTL,
+            max_blob_size: DEFAULT_MAX_BLOB_SIZE,
+            identifiers: Identifiers::default(),
+        }
+    }
+}
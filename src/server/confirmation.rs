@@ -0,0 +1,35 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// What a login counts as when the client goes away before sending its final confirmation
+///
+/// By then the server has already checked the client's key confirmation, so it knows the
+/// password was right, but the client never learned the login went through. Trusting the
+/// server's check means the login is recorded and acted on, e.g. a deletion goes ahead, even
+/// though the client may believe it failed and try again. Requiring the client means a client
+/// that drops at the wrong moment has its login thrown away even though it proved the password.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfirmationPolicy {
+    /// the login fails unless the client confirms
+    #[default]
+    RequireClient,
+    /// the server's check is enough, the login is reported as unconfirmed
+    TrustServer,
+}
+
+impl ConfirmationPolicy {
+    /// whether a verified login stands without the client's confirmation
+    pub fn trust_server(self) -> bool {
+        self == Self::TrustServer
+    }
+}
+
+impl Display for ConfirmationPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RequireClient => write!(f, "require client"),
+            Self::TrustServer => write!(f, "trust server"),
+        }
+    }
+}
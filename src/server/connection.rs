@@ -0,0 +1,375 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use fastwebsockets::{upgrade, FragmentCollector, Frame, OpCode, Payload, Role, WebSocketError};
+use hyper::upgrade::Upgraded;
+use hyper_util::rt::TokioIo;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::watch,
+    time::timeout,
+};
+
+use super::{error::ServerError, Server, CLOSE_TIMEOUT, PADDED_END_TIME};
+use crate::{
+    padding::{pad, pad_reason, unpad},
+    sequence::{MessageKind, Sequence},
+    suite::Suite,
+    wire::{session_end, CloseReason, MAX_MESSAGE_SIZE, MAX_PAYLOAD_SIZE},
+};
+
+/// The connection handed over after a login, see [`Server::authenticate_then`]
+pub type AppSocket = FragmentCollector<MeteredIo<TokioIo<Upgraded>>>;
+
+/// When the bytes of the frame being read started coming in, `None` until they do
+#[derive(Clone)]
+struct Arrival(Arc<watch::Sender<Option<Instant>>>);
+
+impl Arrival {
+    fn new() -> Self {
+        Self(Arc::new(watch::channel(None).0))
+    }
+
+    /// start over for the next frame
+    fn reset(&self) {
+        self.0.send_replace(None);
+    }
+
+    /// note that bytes came in, only the first ones of a frame count
+    fn mark(&self) {
+        self.0.send_if_modified(|started| {
+            if started.is_some() {
+                return false;
+            }
+            *started = Some(Instant::now());
+            true
+        });
+    }
+
+    /// wait until the frame has been arriving for longer than `limit`
+    async fn overdue(&self, limit: Duration) {
+        let mut arrival = self.0.subscribe();
+        // copied out right away, the borrow can't be held across an await
+        let started = arrival
+            .wait_for(Option::is_some)
+            .await
+            .map(|started| *started);
+        match started {
+            Ok(Some(started)) => tokio::time::sleep_until((started + limit).into()).await,
+            // the sender lives as long as `self`, so this never gives up
+            _ => std::future::pending().await,
+        }
+    }
+}
+
+/// A stream that notes when bytes start coming in, so a frame trickling in can be told apart from
+/// a client that's still working out what to send, see [`Server::with_frame_arrival_limit`]
+pub struct MeteredIo<S> {
+    inner: S,
+    arrival: Arrival,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for MeteredIo<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let read = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if buf.filled().len() > before {
+            self.arrival.mark();
+        }
+        read
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for MeteredIo<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// A client's websocket
+///
+/// in a session operations end with a text frame carrying what would otherwise be the close
+/// frame's payload, so the connection stays open for the next one, see
+/// [`SESSION_PATH`](crate::wire::SESSION_PATH)
+pub(super) struct WebSocket {
+    pub(super) inner: AppSocket,
+    pub(super) session: bool,
+    arrival: Arrival,
+}
+
+impl WebSocket {
+    pub(super) async fn read_frame<'f>(&mut self) -> Result<Frame<'f>, WebSocketError> {
+        self.inner.read_frame().await
+    }
+
+    pub(super) async fn write_frame(&mut self, frame: Frame<'_>) -> Result<(), WebSocketError> {
+        self.inner.write_frame(frame).await
+    }
+
+    /// the frame ending the current operation with `code` and `reason`
+    pub(super) fn end_frame(&self, code: u16, reason: &[u8]) -> Frame<'static> {
+        if !self.session {
+            return Frame::close(code, reason);
+        }
+        Frame::text(Payload::Owned(session_end(code, reason)))
+    }
+}
+
+impl<CS: Suite> Server<CS> {
+    /// wrapper to send a `Close` message in case there is an error
+    pub(super) async fn close(
+        &self,
+        ws: &mut WebSocket,
+        err: &ServerError,
+        started: Instant,
+    ) -> Result<(), WebSocketError> {
+        self.padded_wait(started).await;
+        let frame = self.close_frame(ws, err);
+        ws.write_frame(frame).await?;
+        Ok(())
+    }
+
+    fn close_frame(&self, ws: &WebSocket, err: &ServerError) -> Frame<'static> {
+        let reason = err.close_reason();
+        self.end_frame(ws, reason.code, &reason.to_payload())
+    }
+
+    /// when padding, hold off ending an exchange that `started` until [`PADDED_END_TIME`] after
+    pub(super) async fn padded_wait(&self, started: Instant) {
+        if self.padding {
+            tokio::time::sleep_until((started + PADDED_END_TIME).into()).await;
+        }
+    }
+
+    /// `reason` for a frame ending an exchange, filled up to a fixed size when padding
+    fn end_reason(&self, reason: &[u8]) -> Vec<u8> {
+        if self.padding {
+            pad_reason(reason)
+        } else {
+            reason.to_vec()
+        }
+    }
+
+    /// the frame ending the current operation over `ws`, see [`WebSocket::end_frame`]
+    fn end_frame(&self, ws: &WebSocket, code: u16, reason: &[u8]) -> Frame<'static> {
+        ws.end_frame(code, &self.end_reason(reason))
+    }
+
+    /// the close frame ending a connection outside of an exchange, e.g. once a login was handed
+    /// over
+    pub(super) fn close_with(&self, code: u16, reason: &[u8]) -> Frame<'static> {
+        Frame::close(code, &self.end_reason(reason))
+    }
+
+    /// frame carrying protocol data, padded when requested
+    fn data_frame(&self, data: Vec<u8>) -> Frame<'static> {
+        let data = if self.padding { pad(&data) } else { data };
+        Frame::new(true, OpCode::Binary, None, data.into())
+    }
+
+    /// protocol data carried by a frame, stripping any padding
+    pub(super) fn frame_data(&self, frame: Frame) -> Result<Vec<u8>, ServerError> {
+        if frame.payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(ServerError::PayloadTooLarge(
+                frame.payload.len(),
+                MAX_PAYLOAD_SIZE,
+            ));
+        }
+        if self.padding {
+            Ok(unpad(&frame.payload)?)
+        } else {
+            Ok(frame.payload.to_vec())
+        }
+    }
+
+    /// finish upgrading the connection to a websocket, giving up when it takes longer than
+    /// `frame_timeout`
+    ///
+    /// the exchange's permits are held from the moment the upgrade is accepted, so without this
+    /// a client that never completes the handshake would hold them forever
+    pub(super) async fn upgrade(&self, fut: upgrade::UpgradeFut) -> Result<WebSocket, ServerError> {
+        match timeout(self.frame_timeout, fut).await {
+            Ok(ws) => {
+                // nothing has been read yet, so the stream can be rewrapped as it is
+                let arrival = Arrival::new();
+                let io = MeteredIo {
+                    inner: ws?.into_inner(),
+                    arrival: arrival.clone(),
+                };
+                let mut ws = fastwebsockets::WebSocket::after_handshake(io, Role::Server);
+                // blobs are the only messages that can get big, nothing needs more than that
+                ws.set_max_message_size(MAX_MESSAGE_SIZE.max(self.max_blob_size));
+                Ok(WebSocket {
+                    inner: FragmentCollector::new(ws),
+                    session: false,
+                    arrival,
+                })
+            }
+            Err(_) => Err(ServerError::ClientUnresponsive),
+        }
+    }
+
+    /// the next frame, giving up when it takes longer than `frame_timeout` to arrive or keeps
+    /// arriving for longer than `frame_arrival_limit` once its first bytes are in
+    ///
+    /// the deadlines cover the whole frame, including any fragments and control frames received
+    /// along the way, so a client dribbling bytes can't keep the connection alive indefinitely
+    pub(super) async fn next_frame<'f>(
+        &self,
+        ws: &mut WebSocket,
+    ) -> Result<Frame<'f>, ServerError> {
+        ws.arrival.reset();
+        let arrival = ws.arrival.clone();
+        let read = async {
+            tokio::select! {
+                frame = ws.read_frame() => Some(frame),
+                () = arrival.overdue(self.frame_arrival_limit) => None,
+            }
+        };
+        match timeout(self.frame_timeout, read).await {
+            Ok(Some(frame)) => Ok(frame?),
+            Ok(None) | Err(_) => Err(ServerError::ClientUnresponsive),
+        }
+    }
+
+    /// read the next frame like [`Server::next_frame`], telling the client when it took too long
+    pub(super) async fn read_frame<'f>(
+        &self,
+        ws: &mut WebSocket,
+    ) -> Result<Frame<'f>, ServerError> {
+        match self.next_frame(ws).await {
+            Err(err @ ServerError::ClientUnresponsive) => {
+                // the client likely isn't reading either, so only make a token effort to tell it
+                let frame = self.close_frame(ws, &err);
+                let _ = timeout(CLOSE_TIMEOUT, ws.write_frame(frame)).await;
+                Err(err)
+            }
+            frame => frame,
+        }
+    }
+
+    /// read the next frame, which has to carry protocol data
+    ///
+    /// the client closing is reported with [`ServerError::from_close`] without answering, anything
+    /// else that isn't protocol data gets the connection closed with the error
+    pub(super) async fn expect_binary(
+        &self,
+        ws: &mut WebSocket,
+        started: Instant,
+        seq: &mut Sequence,
+        kind: MessageKind,
+    ) -> Result<Vec<u8>, ServerError> {
+        let frame = self.read_frame(ws).await?;
+        match frame.opcode {
+            OpCode::Binary => {
+                tracing::debug!(step = kind.name(), len = frame.payload.len(), "received");
+                let step = seq.received(kind).map_err(ServerError::from);
+                self.or_close(ws, started, step).await?;
+            }
+            OpCode::Close => {
+                let reason = CloseReason::from_payload(&frame.payload);
+                return Err(ServerError::from_close(reason));
+            }
+            _ => {
+                let err = frame.into();
+                self.close(ws, &err, started).await?;
+                return Err(err);
+            }
+        }
+        let data = self.frame_data(frame);
+        self.or_close(ws, started, data).await
+    }
+
+    /// send the next message of the exchange
+    pub(super) async fn send(
+        &self,
+        ws: &mut WebSocket,
+        seq: &mut Sequence,
+        kind: MessageKind,
+        data: Vec<u8>,
+    ) -> Result<(), ServerError> {
+        seq.sent(kind)?;
+        tracing::debug!(step = kind.name(), len = data.len(), "sent");
+        ws.write_frame(self.data_frame(data)).await?;
+        Ok(())
+    }
+
+    /// let the client know the operation went through, which ends the exchange
+    pub(super) async fn done(
+        &self,
+        ws: &mut WebSocket,
+        started: Instant,
+        seq: &mut Sequence,
+        reason: &[u8],
+    ) -> Result<(), ServerError> {
+        let step = seq.sent(MessageKind::Done).map_err(ServerError::from);
+        self.or_close(ws, started, step).await?;
+        tracing::debug!(step = MessageKind::Done.name(), "sent");
+        debug_assert!(seq.is_finished(), "exchange ended early");
+        self.padded_wait(started).await;
+        let frame = self.end_frame(ws, 1000, reason);
+        ws.write_frame(frame).await?;
+        Ok(())
+    }
+
+    /// pass `result` along, closing the connection with the error when it failed
+    pub(super) async fn or_close<T>(
+        &self,
+        ws: &mut WebSocket,
+        started: Instant,
+        result: Result<T, ServerError>,
+    ) -> Result<T, ServerError> {
+        if let Err(err) = &result {
+            self.close(ws, err, started).await?;
+        }
+        result
+    }
+
+    /// wait for whatever was just written to be durable, when writes are coalesced
+    pub(super) async fn durable(
+        &self,
+        ws: &mut WebSocket,
+        started: Instant,
+    ) -> Result<(), ServerError> {
+        match &self.flusher {
+            Some(flusher) => {
+                let durable = flusher.durable().await;
+                self.or_close(ws, started, durable).await
+            }
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// Who gets to remove accounts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeletionPolicy {
+    /// users can delete their own account by logging in to the delete endpoint
+    #[default]
+    SelfService,
+    /// only administrators can remove accounts, the delete endpoint refuses every request
+    AdminOnly,
+    /// accounts are only ever removed offline through `tinap-admin`
+    Disabled,
+}
+
+impl DeletionPolicy {
+    /// whether users can delete their own account
+    pub fn self_service(self) -> bool {
+        self == Self::SelfService
+    }
+}
+
+impl Display for DeletionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SelfService => write!(f, "self service"),
+            Self::AdminOnly => write!(f, "admin only"),
+            Self::Disabled => write!(f, "disabled"),
+        }
+    }
+}
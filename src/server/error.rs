@@ -1,19 +1,58 @@
 use boring_derive::From;
 use fastwebsockets::{Frame, OpCode, WebSocketError};
 use opaque_ke::errors::ProtocolError;
+use sled::transaction::TransactionError;
 use thiserror::Error;
 
+pub use crate::wire::TRY_AGAIN_LATER;
+use crate::wire::{kind, CloseReason, ErrorKind};
+use crate::{padding::PaddingError, storage_key::UsernameError};
+
+use crate::sequence::OutOfSequence;
+
+use super::{
+    attributes::AttributeError, concurrency::Operation, listeners::ListenAddr, record::RecordError,
+    setup_file::SetupError, store::StoreError,
+};
+
 #[derive(Debug, Error, From)]
 pub enum ServerError {
     #[from(skip)]
     #[error("Communication terminated early")]
     ClosedEarly,
     #[from(skip)]
+    #[error("Too many `{0}` exchanges in progress")]
+    Busy(Operation),
+    #[from(skip)]
+    #[error("`{0}` is not available on this server")]
+    Unsupported(Operation),
+    #[from(skip)]
+    #[error("Client closed the connection with `{0}`")]
+    ClientClosed(CloseReason),
+    #[from(skip)]
     #[error("User already exists")]
     UserAlreadyExists,
     #[from(skip)]
     #[error("User does not exist")]
     UserDoesNotExist,
+    #[from(skip)]
+    #[error("Client took too long to send a frame")]
+    ClientUnresponsive,
+    #[from(skip)]
+    #[error("Too many attempts in progress for this user")]
+    TooManyAttempts,
+    #[from(skip)]
+    #[error("User has to register a new password before logging in")]
+    ReRegistrationRequired,
+    #[from(skip)]
+    #[error("Client could not prove it knows the password")]
+    NotAuthenticated,
+    #[from(skip)]
+    #[error("Account is locked for another `{0}` seconds")]
+    AccountLocked(u64),
+    #[from(skip)]
+    #[error("New password was registered for a different user than the one logged in")]
+    UsernameMismatch,
     #[error("Protocol error `{0:?}`")]
     ProtocolError(ProtocolError),
     #[error("Websocket connection error `{0}`")]
@@ -28,6 +67,51 @@ pub enum ServerError {
     Serialization(bincode::Error),
     #[error("Error interacting with database `{0}`")]
     Database(sled::Error),
+    #[error("Error interacting with the user store `{0}`")]
+    Store(StoreError),
+    #[error("Invalid attributes `{0}`")]
+    Attributes(AttributeError),
+    #[error("Malformed padded message `{0}`")]
+    Padding(PaddingError),
+    #[error("Invalid username `{0}`")]
+    Username(UsernameError),
+    #[error("Invalid server setup `{0}`")]
+    Setup(SetupError),
+    #[error("Exchange went out of sequence `{0}`")]
+    OutOfSequence(OutOfSequence),
+    #[from(skip)]
+    #[error("Stored record is corrupt `{0}`")]
+    CorruptRecord(RecordError),
+    #[from(skip)]
+    #[error("Could not listen on `{0}` `{1}`")]
+    Bind(ListenAddr, std::io::Error),
+    #[from(skip)]
+    #[error("Could not set up TLS `{0}`")]
+    Tls(String),
+    #[from(skip)]
+    #[error("Invite is missing, unknown, or already used")]
+    InvalidInvite,
+    #[from(skip)]
+    #[error("Server was already bootstrapped or already has users")]
+    BootstrapUnavailable,
+    #[from(skip)]
+    #[error("Blob of `{0}` bytes is over the limit of `{1}`")]
+    BlobTooLarge(usize, usize),
+    #[from(skip)]
+    #[error("Message of `{0}` bytes is over the limit of `{1}`")]
+    PayloadTooLarge(usize, usize),
+    #[from(skip)]
+    #[error("Database belongs to instance `{0}` but the server setup belongs to `{1}`")]
+    InstanceMismatch(String, String),
+}
+
+impl From<TransactionError<ServerError>> for ServerError {
+    fn from(value: TransactionError<ServerError>) -> Self {
+        match value {
+            TransactionError::Abort(err) => err,
+            TransactionError::Storage(err) => err.into(),
+        }
+    }
 }
 
 impl<'a> From<Frame<'a>> for ServerError {
@@ -37,19 +121,214 @@ impl<'a> From<Frame<'a>> for ServerError {
 }
 
 impl ServerError {
-    // not sure how appropriate these are
+    /// whether the error means the client went away, rather than sent something wrong
+    pub fn client_gone(&self) -> bool {
+        matches!(
+            self,
+            Self::ClosedEarly
+                | Self::ClientClosed(_)
+                | Self::ClientUnresponsive
+                | Self::Websocket(_)
+                | Self::IOError(_)
+        )
+    }
+
+    /// category of the error, which decides the close code
+    pub fn error_kind(&self) -> ErrorKind {
+        match self {
+            Self::ClosedEarly | Self::ClientClosed(_) => ErrorKind::Closed,
+            Self::Websocket(_) | Self::IOError(_) | Self::HyperError(_) => ErrorKind::Transport,
+            Self::ProtocolError(_)
+            | Self::UnexpectedFrame(_, _)
+            | Self::Serialization(_)
+            | Self::Attributes(_)
+            | Self::Padding(_)
+            | Self::PayloadTooLarge(_, _) => ErrorKind::Invalid,
+            Self::UserAlreadyExists
+            | Self::UserDoesNotExist
+            | Self::ClientUnresponsive
+            | Self::TooManyAttempts
+            | Self::ReRegistrationRequired
+            | Self::NotAuthenticated
+            | Self::UsernameMismatch
+            | Self::Unsupported(_)
+            | Self::InvalidInvite
+            | Self::BootstrapUnavailable
+            | Self::BlobTooLarge(_, _)
+            | Self::Username(_) => ErrorKind::Rejected,
+            // the client can log in once the lockout is over
+            Self::Busy(_) | Self::AccountLocked(_) => ErrorKind::Unavailable,
+            Self::Database(_) | Self::Store(_) if self.is_transient() => ErrorKind::Unavailable,
+            Self::Database(_)
+            | Self::Store(_)
+            | Self::CorruptRecord(_)
+            | Self::Setup(_)
+            | Self::OutOfSequence(_)
+            | Self::Bind(_, _)
+            | Self::Tls(_)
+            | Self::InstanceMismatch(_, _) => ErrorKind::Internal,
+        }
+    }
+
     pub fn to_code(&self) -> u16 {
+        self.error_kind().close_code()
+    }
+
+    /// what the client is told when the exchange fails because of this error
+    pub fn close_reason(&self) -> CloseReason {
+        let detail = match self {
+            Self::AccountLocked(remaining) => Some(remaining.to_string()),
+            _ => None,
+        };
+        CloseReason::new(self.error_kind(), self.kind(), detail.as_deref())
+    }
+
+    /// the error for the client closing the connection before the exchange was done, keeping
+    /// what it said when it's a reason this side understands
+    pub fn from_close(reason: CloseReason) -> Self {
+        match reason.kind {
+            Some(kind) if kind != ErrorKind::Closed => Self::ClientClosed(reason),
+            _ => Self::ClosedEarly,
+        }
+    }
+
+    /// stable identifier of the error, sent to the client instead of the message
+    pub fn kind(&self) -> &'static str {
         match self {
-            Self::ClosedEarly => 1000,
-            Self::ProtocolError(_) => 1008,
-            Self::Websocket(_) => 1002,
-            Self::IOError(_) => 1002,
-            Self::HyperError(_) => 1002,
-            Self::UnexpectedFrame(_, _) => 1008,
-            Self::Serialization(_) => 1008,
-            Self::Database(_) => 1008,
-            Self::UserAlreadyExists => 1008,
-            Self::UserDoesNotExist => 1008,
+            Self::ClosedEarly | Self::ClientClosed(_) => kind::CLOSED_EARLY,
+            Self::UserAlreadyExists => kind::USER_ALREADY_EXISTS,
+            Self::UserDoesNotExist => kind::USER_DOES_NOT_EXIST,
+            Self::ClientUnresponsive => kind::CLIENT_UNRESPONSIVE,
+            Self::TooManyAttempts => kind::TOO_MANY_ATTEMPTS,
+            Self::ReRegistrationRequired => kind::REREGISTRATION_REQUIRED,
+            Self::NotAuthenticated => kind::NOT_AUTHENTICATED,
+            Self::AccountLocked(_) => kind::ACCOUNT_LOCKED,
+            Self::UsernameMismatch => kind::USERNAME_MISMATCH,
+            Self::Busy(_) => kind::BUSY,
+            Self::Unsupported(_) => kind::UNSUPPORTED,
+            Self::ProtocolError(_) => kind::PROTOCOL,
+            Self::Websocket(_) => kind::WEBSOCKET,
+            Self::IOError(_) => kind::IO,
+            Self::HyperError(_) => kind::HTTP,
+            Self::UnexpectedFrame(_, _) => kind::UNEXPECTED_FRAME,
+            Self::Serialization(_) => kind::SERIALIZATION,
+            Self::Database(_) | Self::Store(_) if self.is_transient() => kind::DATABASE_UNAVAILABLE,
+            Self::Database(_) | Self::Store(_) | Self::CorruptRecord(_) => kind::DATABASE,
+            Self::Attributes(_) => kind::ATTRIBUTES,
+            Self::Padding(_) => kind::PADDING,
+            Self::Username(_) => kind::INVALID_USERNAME,
+            Self::Setup(_) => kind::SETUP,
+            Self::OutOfSequence(_) => kind::OUT_OF_SEQUENCE,
+            Self::Bind(_, _) => kind::IO,
+            Self::Tls(_) => kind::TLS,
+            Self::InvalidInvite => kind::INVALID_INVITE,
+            Self::BootstrapUnavailable => kind::BOOTSTRAP_UNAVAILABLE,
+            Self::BlobTooLarge(_, _) => kind::BLOB_TOO_LARGE,
+            Self::PayloadTooLarge(_, _) => kind::PAYLOAD_TOO_LARGE,
+            Self::InstanceMismatch(_, _) => kind::INSTANCE_MISMATCH,
+        }
+    }
+
+    /// whether the same request could succeed if tried again, as opposed to something being
+    /// wrong with the request or the store itself
+    pub fn is_transient(&self) -> bool {
+        match self {
+            // io errors cover failed flushes and files being held by a backup, everything else
+            // sled reports means the store is broken or being misused
+            Self::Database(err) => matches!(err, sled::Error::Io(_)),
+            Self::Store(err) => err.is_transient(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "client")]
+    use crate::client::error::ClientError;
+    use crate::{
+        sequence::{MessageKind, Sequence, Side, AUTHENTICATION},
+        wire::MAX_CLOSE_REASON,
+    };
+
+    /// one of every variant, with the longest contents that come up
+    fn every_error() -> Vec<ServerError> {
+        let long = "x".repeat(4 * MAX_CLOSE_REASON);
+        let http = hyper::http::Request::builder()
+            .method("not a method")
+            .body(())
+            .unwrap_err();
+        let out_of_sequence = Sequence::new(AUTHENTICATION, Side::Server)
+            .sent(MessageKind::Done)
+            .unwrap_err();
+        vec![
+            ServerError::ClosedEarly,
+            ServerError::Busy(Operation::Registration),
+            ServerError::Unsupported(Operation::Delete),
+            ServerError::ClientClosed(CloseReason::new(ErrorKind::Invalid, &long, Some(&long))),
+            ServerError::UserAlreadyExists,
+            ServerError::UserDoesNotExist,
+            ServerError::ClientUnresponsive,
+            ServerError::TooManyAttempts,
+            ServerError::ReRegistrationRequired,
+            ServerError::NotAuthenticated,
+            ServerError::AccountLocked(u64::MAX),
+            ServerError::UsernameMismatch,
+            ServerError::ProtocolError(ProtocolError::InvalidLoginError),
+            ServerError::Websocket(WebSocketError::ConnectionClosed),
+            ServerError::IOError(std::io::Error::other(long.clone())),
+            ServerError::HyperError(http),
+            ServerError::UnexpectedFrame(OpCode::Text, long.clone().into_bytes()),
+            ServerError::Serialization(bincode::deserialize::<u64>(&[]).unwrap_err()),
+            ServerError::Database(sled::Error::Unsupported(long.clone())),
+            ServerError::Database(sled::Error::Io(std::io::Error::other(long.clone()))),
+            ServerError::Store(StoreError::Unavailable(long.clone())),
+            ServerError::Store(StoreError::Backend(long.clone())),
+            ServerError::Attributes(AttributeError::KeyTooLong(long.clone())),
+            ServerError::Padding(PaddingError::BadLength(usize::MAX, 0)),
+            ServerError::Username(UsernameError::TooLong(usize::MAX, 0)),
+            ServerError::Setup(SetupError::Tampered),
+            ServerError::OutOfSequence(out_of_sequence),
+            ServerError::CorruptRecord(RecordError::Checksum),
+            ServerError::Bind(
+                ListenAddr::Tcp(([127, 0, 0, 1], 0).into()),
+                std::io::Error::other(long.clone()),
+            ),
+            ServerError::Tls(long.clone()),
+            ServerError::InvalidInvite,
+            ServerError::BootstrapUnavailable,
+            ServerError::BlobTooLarge(usize::MAX, 0),
+            ServerError::PayloadTooLarge(usize::MAX, 0),
+            ServerError::InstanceMismatch(long.clone(), long),
+        ]
+    }
+
+    #[test]
+    fn close_frames_are_valid_and_read_back_the_same() {
+        for err in every_error() {
+            let reason = err.close_reason();
+            let frame = Frame::close(err.to_code(), &reason.to_payload());
+            assert!(
+                frame.payload.len() <= 125,
+                "{err:?} closes with too long a frame"
+            );
+            let text = std::str::from_utf8(&frame.payload[2..]);
+            assert!(
+                text.is_ok(),
+                "{err:?} closes with a reason that isn't UTF-8"
+            );
+
+            let read = CloseReason::from_payload(&frame.payload);
+            assert_eq!(read, reason, "{err:?}");
+            assert_eq!(read.kind, Some(err.error_kind()), "{err:?}");
+            assert_eq!(read.message, err.kind(), "{err:?}");
+            #[cfg(feature = "client")]
+            assert_eq!(
+                ClientError::from_close(read).error_kind(),
+                err.error_kind(),
+                "client reads {err:?} as another kind"
+            );
         }
     }
 }
@@ -0,0 +1,61 @@
+use super::concurrency::Operation;
+use crate::storage_key::StorageKey;
+
+/// how many events a subscriber can fall behind by before it starts missing them
+pub const DEFAULT_EVENT_CAPACITY: usize = 1024;
+
+/// Something that happened on the server
+///
+/// Published to [`Server::subscribe`](super::Server::subscribe) receivers and passed on to any
+/// [`Hook`](super::hooks::Hook)s. More kinds of events will be added over time.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ServerEvent {
+    Registered {
+        user: StorageKey,
+    },
+    Authenticated {
+        user: StorageKey,
+        /// the client went away before confirming, see
+        /// [`ConfirmationPolicy`](super::confirmation::ConfirmationPolicy)
+        unconfirmed: bool,
+    },
+    AuthenticationFailed {
+        user: StorageKey,
+        reason: String,
+    },
+    /// a login that only checked the credentials went through
+    Verified {
+        user: StorageKey,
+    },
+    PasswordChanged {
+        user: StorageKey,
+    },
+    Deleted {
+        user: StorageKey,
+        /// the client went away before confirming the login that asked for the deletion
+        unconfirmed: bool,
+    },
+    /// the user's attributes were replaced, by the user or an administrator
+    AttributesChanged {
+        user: StorageKey,
+    },
+    /// everything stored about the user was handed out, to the user or an administrator, see
+    /// [`TakeoutDocument`](crate::takeout::TakeoutDocument)
+    Exported {
+        user: StorageKey,
+        admin: bool,
+    },
+    /// an administrator changed whether the user has to register a new password
+    ReRegistrationFlagged {
+        user: StorageKey,
+        required: bool,
+    },
+    /// an exchange ended in an error, which may have happened before the user was known
+    ExchangeFailed {
+        operation: Operation,
+        /// stable identifier of the error, see [`wire::kind`](crate::wire::kind)
+        kind: &'static str,
+        error: String,
+    },
+}
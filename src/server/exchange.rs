@@ -0,0 +1,772 @@
+use std::{future::Future, time::Instant};
+
+use fastwebsockets::{upgrade, Frame, OpCode, Payload};
+use tokio::time::timeout;
+use tracing::Instrument;
+
+use super::{
+    autheticate::{AuthConfirm, AuthWaiting},
+    blobs::BLOBS_TREE,
+    concurrency::Operation,
+    connection::WebSocket,
+    error::ServerError,
+    events::ServerEvent,
+    failures::{self, AuthFailure, FAILURES_TREE},
+    invites::{self, INVITES_TREE},
+    lockout::{self, LOCKOUT_TREE},
+    registration::{RegUpload, RegWaiting},
+    AppSocket, Server, BUDGET_WAIT, CLOSE_TIMEOUT, REREGISTER_TREE,
+};
+use crate::{
+    outcome::{DeleteOutcome, RegistrationOutcome},
+    sequence::{self, MessageKind, Sequence, Side},
+    storage_key::StorageKey,
+    suite::Suite,
+    wire::{self, close_reason, kind, ErrorKind, DONE, NO_BLOB},
+};
+
+impl<CS: Suite> Server<CS> {
+    /// upgrade the connection and run a single `operation` over it, the route's handler has
+    /// already admitted it. Returns whether the operation went through
+    pub(super) async fn serve_operation(
+        &self,
+        fut: upgrade::UpgradeFut,
+        operation: Operation,
+        bootstrap: bool,
+    ) -> bool {
+        match self.upgrade(fut).await {
+            Ok(mut ws) => self
+                .run_operation(&mut ws, operation, bootstrap)
+                .await
+                .unwrap_or(false),
+            Err(err) => {
+                self.exchange_failed(operation, &err);
+                false
+            }
+        }
+    }
+
+    /// run `operation` over `ws` and report how it went, returns whether it went through
+    async fn run_operation(
+        &self,
+        ws: &mut WebSocket,
+        operation: Operation,
+        bootstrap: bool,
+    ) -> Result<bool, ServerError> {
+        let span = tracing::info_span!("operation", %operation, user = tracing::field::Empty);
+        #[cfg(feature = "metrics")]
+        let started = Instant::now();
+        async {
+            let mut attempt = Attempt::default();
+            let result = match operation {
+                Operation::Registration => match self.registration(ws, bootstrap).await {
+                    Ok(RegistrationOutcome::Created) => Ok(true),
+                    Ok(RegistrationOutcome::AlreadyExists) => {
+                        tracing::info!(outcome = "refused", "User already exists");
+                        Ok(false)
+                    }
+                    Err(err) => Err(err),
+                },
+                Operation::Authenticate => self
+                    .authenticate(ws, &mut attempt)
+                    .await
+                    .map(|state| state.authenticated()),
+                Operation::ChangePassword => {
+                    let changed = self.change_password(ws).await;
+                    if let Ok(false) = changed {
+                        tracing::info!(outcome = "refused", "Old password did not check out");
+                    }
+                    changed
+                }
+                Operation::Delete => {
+                    let deleted = self.delete(ws).await;
+                    if let Ok(false) = deleted {
+                        tracing::info!(outcome = "refused", "Password did not check out");
+                    }
+                    deleted
+                }
+            };
+            self.report_exchange(operation, attempt.verify_only, result.as_ref().copied());
+            #[cfg(feature = "metrics")]
+            self.metrics.exchange(
+                operation,
+                attempt.verify_only,
+                result.as_ref().copied(),
+                started.elapsed(),
+            );
+            result
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// log how an exchange of `operation` ended, `Ok` with whether it went through. Credential
+    /// checks are logged apart from logins
+    fn report_exchange(
+        &self,
+        operation: Operation,
+        verify_only: bool,
+        result: Result<bool, &ServerError>,
+    ) {
+        match result {
+            Ok(true) if verify_only => tracing::info!(outcome = "verified"),
+            Ok(true) => tracing::info!(outcome = "success"),
+            Ok(false) if operation == Operation::Authenticate => {
+                tracing::info!(outcome = "refused", "Login was not confirmed")
+            }
+            Ok(false) => {}
+            Err(err) => self.exchange_failed(operation, err),
+        }
+    }
+
+    /// log in over `fut` and, when that worked, hand the still open connection to `handler`
+    /// for whatever the application does next
+    ///
+    /// the login ends with a text frame carrying what would otherwise be the close code and
+    /// reason, as in a session, and from then on the connection belongs to `handler`. Failed
+    /// logins and credential checks get the connection closed as usual without calling it
+    pub async fn authenticate_then<F, Fut>(&self, fut: upgrade::UpgradeFut, handler: F)
+    where
+        F: FnOnce(AuthConfirm, AppSocket) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        self.login_then(fut, |confirm, ws| handler(confirm, ws.inner))
+            .await
+    }
+
+    /// [`Server::authenticate_then`], handing over the connection with the deadlines for reading
+    /// from it still attached
+    pub(super) async fn login_then<F, Fut>(&self, fut: upgrade::UpgradeFut, handler: F)
+    where
+        F: FnOnce(AuthConfirm, WebSocket) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let operation = Operation::Authenticate;
+        let mut ws = match self.upgrade(fut).await {
+            Ok(ws) => ws,
+            Err(err) => {
+                self.exchange_failed(operation, &err);
+                return;
+            }
+        };
+        ws.session = true;
+        let span = tracing::info_span!("operation", %operation, user = tracing::field::Empty);
+        let started = Instant::now();
+        let login = async {
+            let mut attempt = Attempt::default();
+            let login = self.authenticate(&mut ws, &mut attempt).await;
+            let result = login.as_ref().map(AuthConfirm::authenticated);
+            self.report_exchange(operation, attempt.verify_only, result);
+            #[cfg(feature = "metrics")]
+            self.metrics
+                .exchange(operation, attempt.verify_only, result, started.elapsed());
+            login
+        }
+        .instrument(span.clone())
+        .await;
+        match login {
+            Ok(confirm) if confirm.authenticated() && !confirm.verify_only() => {
+                handler(confirm, ws).instrument(span).await
+            }
+            Err(err) if err.client_gone() => {}
+            _ => {
+                self.padded_wait(started).await;
+                let frame = self.close_with(1000, &[]);
+                let _ = timeout(CLOSE_TIMEOUT, ws.write_frame(frame)).await;
+            }
+        }
+    }
+
+    /// keep the blob sent after `confirm`'s login, see [`STORE_PATH`](crate::wire::STORE_PATH)
+    pub(super) async fn serve_store(&self, confirm: AuthConfirm, mut ws: WebSocket) {
+        let started = Instant::now();
+        let frame = match self.receive_blob(&confirm, &mut ws).await {
+            Ok(()) => self.close_with(1000, DONE.as_bytes()),
+            Err(err) => self.blob_failed(&err),
+        };
+        self.padded_wait(started).await;
+        let _ = timeout(CLOSE_TIMEOUT, ws.write_frame(frame)).await;
+    }
+
+    /// store the next frame on `ws` as the user's blob, the last one stored wins
+    async fn receive_blob(
+        &self,
+        confirm: &AuthConfirm,
+        ws: &mut WebSocket,
+    ) -> Result<(), ServerError> {
+        let key = self.storage_key(confirm.username())?;
+        let frame = self.next_frame(ws).await?;
+        if frame.opcode != OpCode::Binary {
+            return Err(frame.into());
+        }
+        if frame.payload.len() > self.max_blob_size {
+            return Err(ServerError::BlobTooLarge(
+                frame.payload.len(),
+                self.max_blob_size,
+            ));
+        }
+        let blobs = self.store.open_tree(self.tree_name(BLOBS_TREE))?;
+        blobs.insert(key, &*frame.payload)?;
+        if let Some(flusher) = &self.flusher {
+            flusher.durable().await?;
+        }
+        Ok(())
+    }
+
+    /// send back the blob of `confirm`'s user, see [`RETRIEVE_PATH`](crate::wire::RETRIEVE_PATH)
+    pub(super) async fn serve_retrieve(&self, confirm: AuthConfirm, mut ws: WebSocket) {
+        let started = Instant::now();
+        let sent = async {
+            let key = self.storage_key(confirm.username())?;
+            let blobs = self.store.open_tree(self.tree_name(BLOBS_TREE))?;
+            let Some(blob) = blobs.get(key)? else {
+                return Ok(NO_BLOB);
+            };
+            ws.write_frame(Frame::binary(Payload::Owned(blob.to_vec())))
+                .await?;
+            Ok::<_, ServerError>(DONE)
+        }
+        .await;
+        let frame = match sent {
+            Ok(reason) => self.close_with(1000, reason.as_bytes()),
+            Err(err) => self.blob_failed(&err),
+        };
+        self.padded_wait(started).await;
+        let _ = timeout(CLOSE_TIMEOUT, ws.write_frame(frame)).await;
+    }
+
+    /// log why a blob couldn't be stored or sent, and the close telling the client
+    fn blob_failed(&self, err: &ServerError) -> Frame<'static> {
+        tracing::warn!(kind = err.kind(), "Blob exchange failed: `{err}`");
+        self.close_with(err.to_code(), &err.close_reason().to_payload())
+    }
+
+    /// run operations over one connection until the client closes it, each one is admitted
+    /// against its own budget as it starts
+    ///
+    /// registrations in a session can't bootstrap, that's left to the registration endpoint
+    pub(super) async fn session(&self, fut: upgrade::UpgradeFut) -> Result<(), ServerError> {
+        let mut ws = self.upgrade(fut).await?;
+        ws.session = true;
+        loop {
+            // waiting for the next operation, a client that stays idle for too long is let go
+            let frame = match timeout(self.frame_timeout, ws.read_frame()).await {
+                Ok(frame) => frame?,
+                Err(_) => {
+                    let frame = self.close_with(1000, b"idle");
+                    let _ = timeout(CLOSE_TIMEOUT, ws.write_frame(frame)).await;
+                    return Ok(());
+                }
+            };
+            let data = match frame.opcode {
+                OpCode::Binary => self.frame_data(frame),
+                OpCode::Close => return Ok(()),
+                _ => Err(frame.into()),
+            };
+            let operation = data.and_then(|data| Ok(wire::decode::<Operation>(&data)?));
+            let operation = match operation {
+                Ok(operation) => operation,
+                Err(err) => {
+                    // without knowing the operation there's no telling where the next one
+                    // starts, so the whole session ends
+                    ws.session = false;
+                    self.close(&mut ws, &err, Instant::now()).await?;
+                    return Err(err);
+                }
+            };
+
+            if operation == Operation::Delete && !self.deletion_policy.self_service() {
+                let err = ServerError::Unsupported(operation);
+                self.close(&mut ws, &err, Instant::now()).await?;
+                continue;
+            }
+            let Some(_permit) = self.budgets.acquire(operation, BUDGET_WAIT).await else {
+                let err = ServerError::Busy(operation);
+                self.close(&mut ws, &err, Instant::now()).await?;
+                continue;
+            };
+            let result = self.run_operation(&mut ws, operation, false).await;
+            if result.is_err_and(|err| err.client_gone()) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// handle a registration request, `bootstrap` makes the new account an admin
+    async fn registration(
+        &self,
+        ws: &mut WebSocket,
+        bootstrap: bool,
+    ) -> Result<RegistrationOutcome, ServerError> {
+        let started = Instant::now();
+        let mut seq = Sequence::new(sequence::REGISTRATION, Side::Server);
+        let invite_required = self.invite_only && !bootstrap;
+        let (key, state) = self
+            .register_exchange(ws, started, &mut seq, None, invite_required)
+            .await?;
+
+        // a missing invite fails the same way an unknown one does
+        let invite = invite_required.then(|| state.invite().unwrap_or_default());
+        let (_, password_serialized) = state.to_data();
+        match self
+            .create_user(&key, password_serialized, bootstrap, invite)
+            .await
+        {
+            Ok(()) => {}
+            Err(ServerError::UserAlreadyExists) => {
+                self.close(ws, &ServerError::UserAlreadyExists, started)
+                    .await?;
+                return Ok(RegistrationOutcome::AlreadyExists);
+            }
+            Err(err) => {
+                self.close(ws, &err, started).await?;
+                return Err(err);
+            }
+        }
+        self.durable(ws, started).await?;
+
+        // let client know registration is complete
+        self.done(ws, started, &mut seq, &[1]).await?;
+
+        self.emit(ServerEvent::Registered { user: key }).await;
+
+        Ok(RegistrationOutcome::Created)
+    }
+
+    /// run the registration exchange up to the client's upload, leaving storing it to the caller
+    ///
+    /// when `expected` is given the registration has to be for that user, and with
+    /// `invite_required` it has to come with an unused invite, both are checked before the server
+    /// answers
+    async fn register_exchange(
+        &self,
+        ws: &mut WebSocket,
+        started: Instant,
+        seq: &mut Sequence,
+        expected: Option<&StorageKey>,
+        invite_required: bool,
+    ) -> Result<(StorageKey, RegUpload), ServerError> {
+        let state = RegWaiting::<CS>::for_suite(self.server_setup.clone())
+            .with_max_username_len(self.key_policy.max_len);
+        let data = self
+            .expect_binary(ws, started, seq, MessageKind::RegistrationRequest)
+            .await?;
+        let state = self.or_close(ws, started, state.step(data)).await?;
+        let key = match self.storage_key(state.username()) {
+            Ok(key) if expected.is_some_and(|expected| *expected != key) => {
+                Err(ServerError::UsernameMismatch)
+            }
+            res => res,
+        };
+        let key = self.or_close(ws, started, key).await?;
+        self.record_user(&key);
+        if invite_required {
+            let invited = self.check_invite(state.invite());
+            self.or_close(ws, started, invited).await?;
+        }
+
+        self.send(ws, seq, MessageKind::RegistrationResponse, state.to_data())
+            .await?;
+        let data = self
+            .expect_binary(ws, started, seq, MessageKind::RegistrationUpload)
+            .await?;
+        let state = self.or_close(ws, started, state.step(data)).await?;
+        Ok((key, state))
+    }
+
+    /// whether `invite` can still be used
+    fn check_invite(&self, invite: Option<&[u8]>) -> Result<(), ServerError> {
+        let tree = self.store.open_tree(self.tree_name(INVITES_TREE))?;
+        let created_at = match invite {
+            Some(invite) => tree.get(invite)?,
+            None => None,
+        };
+        match created_at {
+            Some(created_at)
+                if !invites::expired(&created_at, self.clock.unix_secs(), self.invite_ttl) =>
+            {
+                Ok(())
+            }
+            _ => Err(ServerError::InvalidInvite),
+        }
+    }
+
+    /// report an exchange that ended in an error, to subscribers and in the logs
+    fn exchange_failed(&self, operation: Operation, err: &ServerError) {
+        match err.error_kind() {
+            ErrorKind::Internal => tracing::error!(
+                %operation,
+                outcome = "failure",
+                kind = err.kind(),
+                "Error in exchange: `{err}`"
+            ),
+            _ => tracing::warn!(
+                %operation,
+                outcome = "failure",
+                kind = err.kind(),
+                "Error in exchange: `{err}`"
+            ),
+        }
+        self.publish(ServerEvent::ExchangeFailed {
+            operation,
+            kind: err.kind(),
+            error: err.to_string(),
+        });
+    }
+
+    /// note down why an authentication of an existing user failed
+    fn record_failure(&self, key: &StorageKey, reason: String) -> Result<(), ServerError> {
+        let tree = self.store.open_tree(self.tree_name(FAILURES_TREE))?;
+        let failure = AuthFailure {
+            at: self.clock.unix_secs(),
+            reason,
+        };
+        failures::record(&tree, key, failure)
+    }
+
+    /// handle an authentication request
+    ///
+    /// every way an authentication of an existing user can end passes through here, so the
+    /// reason of any failure gets recorded no matter where in the exchange it happened
+    async fn authenticate(
+        &self,
+        ws: &mut WebSocket,
+        attempt: &mut Attempt,
+    ) -> Result<AuthConfirm, ServerError> {
+        let result = self.authenticate_exchange(ws, attempt).await;
+        self.login_finished(attempt, result.as_ref()).await;
+        result
+    }
+
+    /// record how a login of an existing user ended and let the hooks know
+    ///
+    /// a credential check only ever lets the hooks know it went through, nothing else that
+    /// follows a login happens for it
+    async fn login_finished(&self, attempt: &Attempt, result: Result<&AuthConfirm, &ServerError>) {
+        let Some(key) = attempt.user.clone() else {
+            return;
+        };
+        let unconfirmed = result.is_ok_and(AuthConfirm::unconfirmed);
+        if let Err(err) = self.update_lockout(&key, attempt, result) {
+            tracing::error!("Error updating account lockout: `{err}`");
+        }
+        // the password checked out, the user only has to change it before logging in
+        if let Err(ServerError::ReRegistrationRequired) = result {
+            return;
+        }
+        let reason = if attempt.locked {
+            Some("Account is locked".to_string())
+        } else {
+            failure_reason(result)
+        };
+        match reason {
+            Some(reason) => {
+                if let Err(err) = self.record_failure(&key, reason.clone()) {
+                    tracing::error!("Error recording authentication failure: `{err}`");
+                }
+                self.emit(ServerEvent::AuthenticationFailed { user: key, reason })
+                    .await;
+            }
+            None if attempt.verify_only => self.emit(ServerEvent::Verified { user: key }).await,
+            None => {
+                if let Err(err) = self.touch_last_login(&key).await {
+                    tracing::error!("Error recording last login: `{err}`");
+                }
+                self.emit(ServerEvent::Authenticated {
+                    user: key,
+                    unconfirmed,
+                })
+                .await
+            }
+        }
+    }
+
+    /// seconds until `key` unlocks, `None` when it isn't locked
+    pub(super) fn locked_for(&self, key: &StorageKey) -> Result<Option<u64>, ServerError> {
+        if self.lockout.is_none() {
+            return Ok(None);
+        }
+        let tree = self.store.open_tree(self.tree_name(LOCKOUT_TREE))?;
+        lockout::locked_for(&tree, key, self.clock.unix_secs())
+    }
+
+    /// count a failed login of `key` towards locking it, or start over after a successful one
+    ///
+    /// only a password that didn't check out counts, a client going away or the server failing
+    /// isn't a guess. Attempts while the account is locked don't make the lock any longer, and a
+    /// user who has to register a new password still proved they know the old one
+    fn update_lockout(
+        &self,
+        key: &StorageKey,
+        attempt: &Attempt,
+        result: Result<&AuthConfirm, &ServerError>,
+    ) -> Result<(), ServerError> {
+        let Some(policy) = &self.lockout else {
+            return Ok(());
+        };
+        let tree = self.store.open_tree(self.tree_name(LOCKOUT_TREE))?;
+        match result {
+            Ok(state) if state.authenticated() => lockout::reset(&tree, key),
+            Err(ServerError::ReRegistrationRequired) => lockout::reset(&tree, key),
+            _ if !attempt.wrong_password || attempt.locked => Ok(()),
+            _ => {
+                let now = self.clock.unix_secs();
+                if let Some(window) = lockout::failed(&tree, key, policy, now)? {
+                    tracing::warn!(
+                        user = %self.loggable(key),
+                        ?window,
+                        "Locked account after repeated failed logins"
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// run the authentication exchange, filling in `attempt` as it goes
+    async fn authenticate_exchange(
+        &self,
+        ws: &mut WebSocket,
+        attempt: &mut Attempt,
+    ) -> Result<AuthConfirm, ServerError> {
+        let started = Instant::now();
+        let mut seq = Sequence::new(sequence::AUTHENTICATION, Side::Server);
+        let (key, state) = self.login(ws, started, &mut seq, attempt).await?;
+
+        // only tell the user after they proved they know the old password
+        if state.authenticated() && !state.verify_only() {
+            let flagged = self
+                .store
+                .open_tree(self.tree_name(REREGISTER_TREE))
+                .and_then(|tree| tree.contains_key(&key));
+            let err = match flagged {
+                Ok(false) => None,
+                Ok(true) => Some(ServerError::ReRegistrationRequired),
+                Err(err) => Some(err.into()),
+            };
+            if let Some(err) = err {
+                if !state.unconfirmed() {
+                    self.close(ws, &err, started).await?;
+                }
+                return Err(err);
+            }
+        }
+
+        if state.unconfirmed() {
+            return Ok(state);
+        }
+        if state.authenticated() && !state.verify_only() {
+            let token = self.or_close(ws, started, self.issue_token(&key)).await?;
+            self.done(ws, started, &mut seq, &close_reason(DONE, Some(&token)))
+                .await?;
+        } else {
+            self.done(ws, started, &mut seq, DONE.as_bytes()).await?;
+        }
+
+        Ok(state)
+    }
+
+    /// a session token for `key`, who just logged in
+    fn issue_token(&self, key: &StorageKey) -> Result<String, ServerError> {
+        self.sessions()?
+            .issue(key, self.clock.unix_secs(), self.session_ttl)
+    }
+
+    /// run the login exchange up to the client's confirmation, filling in `attempt` as it goes
+    async fn login(
+        &self,
+        ws: &mut WebSocket,
+        started: Instant,
+        seq: &mut Sequence,
+        attempt: &mut Attempt,
+    ) -> Result<(StorageKey, AuthConfirm), ServerError> {
+        let state = AuthWaiting::<CS>::for_suite(self.server_setup.clone())
+            .with_max_username_len(self.key_policy.max_len)
+            .with_identifiers(self.identifiers.clone());
+        let data = self
+            .expect_binary(ws, started, seq, MessageKind::CredentialRequest)
+            .await?;
+        let state = self.or_close(ws, started, state.step(data)).await?;
+        attempt.verify_only = state.verify_only();
+
+        let key = self.storage_key(state.username());
+        let key = self.or_close(ws, started, key).await?;
+        self.record_user(&key);
+        // held until the exchange ends, however it ends
+        let _in_flight = match &self.user_in_flight {
+            Some(user_in_flight) => {
+                let guard = user_in_flight
+                    .enter(&key)
+                    .ok_or(ServerError::TooManyAttempts);
+                Some(self.or_close(ws, started, guard).await?)
+            }
+            None => None,
+        };
+        // an unknown user carries on with a dummy record, so the exchange fails the same way a
+        // wrong password does and can't be used to find out who is registered. A locked account
+        // goes the same way without its record being looked at, unless lockouts are disclosed
+        let locked = self.locked_for(&key);
+        let password_file = match self.or_close(ws, started, locked).await? {
+            Some(remaining) if self.disclose_lockouts => {
+                attempt.user = Some(key);
+                attempt.locked = true;
+                let locked = Err(ServerError::AccountLocked(remaining));
+                return self.or_close(ws, started, locked).await;
+            }
+            Some(remaining) => {
+                tracing::debug!(remaining, "Account is locked");
+                attempt.user = Some(key.clone());
+                attempt.locked = true;
+                Ok(None)
+            }
+            None => {
+                self.read_with_retry(|| async { Ok(self.users()?.get(&key).await?) })
+                    .await
+            }
+        };
+        let password_file = self.or_close(ws, started, password_file).await?;
+        let password_file = match password_file {
+            // the user exists even when their record can't be read, which they need to hear of
+            Some(record) => {
+                attempt.user = Some(key.clone());
+                let password_file = self.open_record(&key, &record).await;
+                Some(self.or_close(ws, started, password_file).await?)
+            }
+            None => None,
+        };
+
+        let state = state.step(password_file);
+        let state = self.or_close(ws, started, state).await?;
+
+        self.send(ws, seq, MessageKind::CredentialResponse, state.to_data())
+            .await?;
+        let data = self
+            .expect_binary(ws, started, seq, MessageKind::CredentialFinalization)
+            .await;
+        // a client whose password doesn't open its envelope gives up here, the way any failed
+        // step is reported
+        attempt.wrong_password = matches!(
+            &data,
+            Err(ServerError::ClientClosed(reason)) if reason.message == kind::PROTOCOL
+        );
+        let finish = state.step(data?);
+        attempt.wrong_password |= finish.is_err();
+        let state = self.or_close(ws, started, finish).await?;
+
+        self.send(ws, seq, MessageKind::KeyConfirmation, state.to_data())
+            .await?;
+        match self
+            .expect_binary(ws, started, seq, MessageKind::Confirmation)
+            .await
+        {
+            Ok(data) => Ok((key, state.step(data))),
+            // the server already checked the client's key confirmation at this point
+            Err(err) if err.client_gone() && self.confirmation_policy.trust_server() => {
+                Ok((key, state.unconfirmed()))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// log the client in and report how it went, for exchanges that act on the account once the
+    /// client proved who it is
+    ///
+    /// `None` when the client couldn't prove it, in which case it has already been told
+    async fn run_auth(
+        &self,
+        ws: &mut WebSocket,
+        started: Instant,
+        seq: &mut Sequence,
+    ) -> Result<Option<(StorageKey, AuthConfirm)>, ServerError> {
+        let mut attempt = Attempt::default();
+        let login = self.login(ws, started, seq, &mut attempt).await;
+        self.login_finished(&attempt, login.as_ref().map(|(_, state)| state))
+            .await;
+        let (key, state) = login?;
+        if !state.authenticated() {
+            self.close(ws, &ServerError::NotAuthenticated, started)
+                .await?;
+            return Ok(None);
+        }
+        Ok(Some((key, state)))
+    }
+
+    /// handle a password change, the client logs in with the old password and then registers
+    /// the new one over the same connection
+    ///
+    /// the password file is only replaced once the login succeeded, anything going wrong before
+    /// then leaves it as it was. Returns whether the password was changed
+    async fn change_password(&self, ws: &mut WebSocket) -> Result<bool, ServerError> {
+        let started = Instant::now();
+        let mut seq = Sequence::new(sequence::CHANGE_PASSWORD, Side::Server);
+        let Some((key, _)) = self.run_auth(ws, started, &mut seq).await? else {
+            return Ok(false);
+        };
+
+        // the new password has to be for the account that just logged in
+        let (key, state) = self
+            .register_exchange(ws, started, &mut seq, Some(&key), false)
+            .await?;
+        let (_, password_serialized) = state.to_data();
+        let replaced = self.replace_password(&key, password_serialized).await;
+        self.or_close(ws, started, replaced).await?;
+        self.durable(ws, started).await?;
+
+        self.done(ws, started, &mut seq, DONE.as_bytes()).await?;
+
+        self.emit(ServerEvent::PasswordChanged { user: key }).await;
+
+        Ok(true)
+    }
+
+    /// handle a user deleting their own account, the client logs in and the account is removed
+    /// once that succeeds. Returns whether the account was removed
+    async fn delete(&self, ws: &mut WebSocket) -> Result<bool, ServerError> {
+        let started = Instant::now();
+        let mut seq = Sequence::new(sequence::DELETE, Side::Server);
+        let Some((key, state)) = self.run_auth(ws, started, &mut seq).await? else {
+            return Ok(false);
+        };
+
+        let removed = self.remove_user(&key).await;
+        self.or_close(ws, started, removed).await?;
+        self.durable(ws, started).await?;
+
+        if !state.unconfirmed() {
+            let outcome = serde_json::to_vec(&DeleteOutcome::Deleted)
+                .expect("a unit variant always serializes");
+            self.done(ws, started, &mut seq, &outcome).await?;
+        }
+
+        self.emit(ServerEvent::Deleted {
+            user: key,
+            unconfirmed: state.unconfirmed(),
+        })
+        .await;
+
+        Ok(true)
+    }
+}
+
+/// What's known about a login while it runs, for recording how it ended
+#[derive(Debug, Default)]
+struct Attempt {
+    /// set once the user is known to exist
+    user: Option<StorageKey>,
+    /// the client only asked for its credentials to be checked
+    verify_only: bool,
+    /// the account was locked, so its record wasn't looked at
+    locked: bool,
+    /// the client failed to prove it knows the password, rather than going away or running
+    /// into an error
+    wrong_password: bool,
+}
+
+/// why a login failed, `None` when it succeeded
+fn failure_reason(result: Result<&AuthConfirm, &ServerError>) -> Option<String> {
+    match result {
+        Ok(state) if state.authenticated() => None,
+        Ok(_) => Some("Client could not confirm the session key".to_string()),
+        Err(err) => Some(err.to_string()),
+    }
+}
@@ -0,0 +1,32 @@
+use super::error::ServerError;
+use crate::storage_key::StorageKey;
+pub use crate::takeout::AuthFailure;
+
+/// how many failures are kept for each user, older ones are dropped
+pub const MAX_FAILURES: usize = 10;
+/// sled tree holding each user's recent authentication failures
+pub(crate) const FAILURES_TREE: &str = "auth_failures";
+
+pub(crate) fn record(
+    tree: &sled::Tree,
+    key: &StorageKey,
+    failure: AuthFailure,
+) -> Result<(), ServerError> {
+    tree.update_and_fetch(key, |data| {
+        let mut failures: Vec<AuthFailure> = data
+            .and_then(|data| bincode::deserialize(data).ok())
+            .unwrap_or_default();
+        failures.push(failure.clone());
+        let excess = failures.len().saturating_sub(MAX_FAILURES);
+        failures.drain(..excess);
+        bincode::serialize(&failures).ok()
+    })?;
+    Ok(())
+}
+
+pub(crate) fn load(tree: &sled::Tree, key: &StorageKey) -> Result<Vec<AuthFailure>, ServerError> {
+    match tree.get(key)? {
+        Some(data) => Ok(bincode::deserialize(&data)?),
+        None => Ok(Vec::new()),
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+
+use super::error::ServerError;
+
+/// Shares `fsync`s between concurrent writers
+///
+/// Writes are applied to the store immediately, then each writer asks to be told when its write
+/// is durable. A background task waits up to `interval` for other writers to pile up and flushes
+/// once for all of them, so a burst of registrations pays for a handful of flushes rather than
+/// one each, while nobody is told their write succeeded before it actually hit the disk.
+#[derive(Clone)]
+pub struct WriteCoalescer {
+    store: sled::Db,
+    interval: Duration,
+    requests: mpsc::UnboundedSender<oneshot::Sender<bool>>,
+}
+
+impl WriteCoalescer {
+    /// start the flushing task, needs to be called from within a tokio runtime
+    pub fn spawn(store: sled::Db, interval: Duration) -> Self {
+        let (requests, mut waiting) = mpsc::unbounded_channel::<oneshot::Sender<bool>>();
+        let db = store.clone();
+        tokio::task::spawn(async move {
+            while let Some(first) = waiting.recv().await {
+                tokio::time::sleep(interval).await;
+                // everything queued so far was written before this flush starts, so it's covered
+                let mut batch = vec![first];
+                while let Ok(next) = waiting.try_recv() {
+                    batch.push(next);
+                }
+                let flushed = db.flush_async().await.is_ok();
+                for waiter in batch {
+                    let _ = waiter.send(flushed);
+                }
+            }
+        });
+        Self {
+            store,
+            interval,
+            requests,
+        }
+    }
+
+    /// how long writers wait for each other before flushing
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// wait until everything written to the store before this call is durable
+    pub async fn durable(&self) -> Result<(), ServerError> {
+        let (tx, rx) = oneshot::channel();
+        let flushed = match self.requests.send(tx) {
+            Ok(()) => rx.await.unwrap_or(false),
+            Err(_) => false,
+        };
+        if !flushed {
+            // the shared flush didn't work out, flush directly to get hold of the actual error
+            self.store.flush_async().await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,528 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use axum::{
+    extract::State,
+    http::{
+        header::{RETRY_AFTER, SEC_WEBSOCKET_PROTOCOL},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::{get, MethodRouter},
+    Json,
+};
+use fastwebsockets::{upgrade, WebSocketError};
+use tracing::{Instrument, Span};
+
+use super::{
+    autheticate::AuthConfirm, bootstrap::BOOTSTRAP_HEADER, concurrency::ExchangePermit,
+    connection::WebSocket, error::ServerError, listeners::Origin, shedding::ShedGuard,
+    tokens::AuthenticatedUser, AppSocket, Operation, Server, BUDGET_WAIT, FORWARDED_PROTO,
+};
+use crate::{
+    attributes::Attributes,
+    padding::PADDING_PROTOCOL,
+    suite::Suite,
+    wire::{Feature, ServerInfo, FEATURES_HEADER, RETRIEVE_PATH, SESSION_PATH, STORE_PATH},
+};
+
+/// id of the next connection, to tell connections apart in the logs
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+
+/// a connection let in by [`Server::admit_connection`], holding on to its share of the load and
+/// of its operation's budget until dropped
+struct Admission {
+    _shed: Option<ShedGuard>,
+    _permit: Option<ExchangePermit>,
+}
+
+/// a connection that was let in, with the answer accepting its upgrade
+struct Admitted {
+    response: Response,
+    fut: upgrade::UpgradeFut,
+    admission: Admission,
+}
+
+impl<CS: Suite> Server<CS> {
+    /// run the checks every websocket endpoint shares and upgrade the connection: the
+    /// transport, padding, load and, when `operation` is given, room in its budget
+    ///
+    /// sessions take their budgets per operation as they start, so don't pass one
+    #[allow(clippy::result_large_err)]
+    async fn admit_connection(
+        &self,
+        headers: &HeaderMap,
+        origin: Origin,
+        ws: upgrade::IncomingUpgrade,
+        operation: Option<Operation>,
+    ) -> Result<Admitted, Response> {
+        self.check_transport(headers, origin)?;
+        self.negotiate(headers)?;
+        let shed = self.admit()?;
+        let permit = match operation {
+            Some(operation) => match self.budgets.acquire(operation, BUDGET_WAIT).await {
+                Some(permit) => Some(permit),
+                None => {
+                    let busy = ServerError::Busy(operation).to_string();
+                    return Err((StatusCode::SERVICE_UNAVAILABLE, busy).into_response());
+                }
+            },
+            None => None,
+        };
+        let (response, fut) = ws.upgrade().map_err(upgrade_failed)?;
+        Ok(Admitted {
+            response: response.into_response(),
+            fut,
+            admission: Admission {
+                _shed: shed,
+                _permit: permit,
+            },
+        })
+    }
+
+    /// accept the upgrade answered with `response` and run `connection` in its own task,
+    /// under a span for `endpoint`
+    fn spawn_connection<F, Fut>(
+        self,
+        endpoint: &'static str,
+        response: Response,
+        connection: F,
+    ) -> Response
+    where
+        F: FnOnce(Self) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let response = self.accept(response);
+        let exchanges = self.exchanges.clone();
+        exchanges.spawn(connection(self).instrument(connection_span(endpoint)));
+        response
+    }
+
+    /// check that the client and server agree on padding, the client asks for it by offering
+    /// the padding subprotocol
+    #[allow(clippy::result_large_err)]
+    fn negotiate(&self, headers: &HeaderMap) -> Result<(), Response> {
+        let requested = headers
+            .get_all(SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|protocol| protocol.trim() == PADDING_PROTOCOL);
+        match (self.padding, requested) {
+            (true, false) => {
+                Err((StatusCode::BAD_REQUEST, "Server requires padding").into_response())
+            }
+            (false, true) => Err((StatusCode::BAD_REQUEST, "Server does not pad").into_response()),
+            _ => Ok(()),
+        }
+    }
+
+    /// when TLS is required, check the server terminated it or the connection came through a
+    /// trusted proxy over https
+    #[allow(clippy::result_large_err)]
+    fn check_transport(&self, headers: &HeaderMap, origin: Origin) -> Result<(), Response> {
+        let Some(trusted_proxies) = &self.trusted_proxies else {
+            return Ok(());
+        };
+        if origin.tls {
+            return Ok(());
+        }
+        let trusted = origin
+            .peer
+            .is_some_and(|peer| trusted_proxies.contains(&peer.ip()));
+        let https = headers
+            .get(FORWARDED_PROTO)
+            .and_then(|proto| proto.to_str().ok())
+            .is_some_and(|proto| proto.eq_ignore_ascii_case("https"));
+        if trusted && https {
+            Ok(())
+        } else {
+            Err((StatusCode::FORBIDDEN, "Server requires TLS").into_response())
+        }
+    }
+
+    /// let a new connection in unless load is being shed
+    #[allow(clippy::result_large_err)]
+    fn admit(&self) -> Result<Option<ShedGuard>, Response> {
+        let Some(shedder) = &self.shedder else {
+            return Ok(None);
+        };
+        match shedder.admit() {
+            Some(guard) => Ok(Some(guard)),
+            None => Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(RETRY_AFTER, "1")],
+                "Server is overloaded",
+            )
+                .into_response()),
+        }
+    }
+
+    /// let the client know padding was accepted and what else the server supports
+    fn accept(&self, response: impl IntoResponse) -> Response {
+        let mut response = response.into_response();
+        let features = self
+            .features()
+            .into_iter()
+            .map(Feature::name)
+            .collect::<Vec<_>>()
+            .join(",");
+        if let Ok(features) = HeaderValue::from_str(&features) {
+            response.headers_mut().insert(FEATURES_HEADER, features);
+        }
+        if self.padding {
+            response.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(PADDING_PROTOCOL),
+            );
+        }
+        response
+    }
+}
+
+/// span covering everything done over one upgraded connection, `endpoint` is the path it was
+/// upgraded on
+fn connection_span(endpoint: &'static str) -> Span {
+    let id = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+    tracing::info_span!("connection", id, endpoint)
+}
+
+/// answer to a request that was meant to be upgraded to a websocket but can't be
+fn upgrade_failed(err: WebSocketError) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        format!("Expected a websocket upgrade: {err}"),
+    )
+        .into_response()
+}
+
+/// hook for calling the registration endpoint
+pub async fn ws_registration<CS: Suite>(
+    headers: HeaderMap,
+    origin: Origin,
+    ws: upgrade::IncomingUpgrade,
+    State(state): State<Server<CS>>,
+) -> Response {
+    let operation = Operation::Registration;
+    let admitted = match state
+        .admit_connection(&headers, origin, ws, Some(operation))
+        .await
+    {
+        Ok(admitted) => admitted,
+        Err(response) => return response,
+    };
+    // upgraded before claiming the bootstrap, a request that can't be upgraded shouldn't use it up
+    let bootstrap = match headers.get(BOOTSTRAP_HEADER) {
+        None => None,
+        Some(token) => match &state.bootstrap {
+            Some(bootstrap) if bootstrap.claim(token.as_bytes()) => Some(bootstrap.clone()),
+            _ => return (StatusCode::FORBIDDEN, "Bootstrap is not available").into_response(),
+        },
+    };
+    let Admitted {
+        response,
+        fut,
+        admission,
+    } = admitted;
+    state.spawn_connection(operation.path(), response, move |state| async move {
+        let _admission = admission;
+        let created = state
+            .serve_operation(fut, operation, bootstrap.is_some())
+            .await;
+        if let Some(bootstrap) = bootstrap {
+            if !created {
+                bootstrap.release();
+            }
+        }
+    })
+}
+
+/// hook for calling the authentication endpoint
+pub async fn ws_authenticate<CS: Suite>(
+    headers: HeaderMap,
+    origin: Origin,
+    ws: upgrade::IncomingUpgrade,
+    State(state): State<Server<CS>>,
+) -> Response {
+    ws_operation(headers, origin, ws, state, Operation::Authenticate).await
+}
+
+/// an authentication endpoint that hands the connection to `handler` after each successful
+/// login, see [`Server::authenticate_then`]
+///
+/// the connection is admitted the same way as by [`ws_authenticate`], but its authentication
+/// budget and load are given back once the login is done, so long lived connections don't keep
+/// others out. Shutting down still waits on `handler` for the grace period
+pub fn ws_authenticate_with<CS: Suite, F, Fut>(handler: F) -> MethodRouter<Server<CS>>
+where
+    F: FnOnce(AuthConfirm, AppSocket) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    get(
+        move |headers: HeaderMap,
+              origin: Origin,
+              ws: upgrade::IncomingUpgrade,
+              State(state): State<Server<CS>>| {
+            let handler = move |confirm, ws: WebSocket| handler(confirm, ws.inner);
+            authenticate_with(
+                headers,
+                origin,
+                ws,
+                state,
+                Operation::Authenticate.path(),
+                handler,
+            )
+        },
+    )
+}
+
+/// hook for calling the blob storing endpoint, see [`STORE_PATH`]
+pub async fn ws_store<CS: Suite>(
+    headers: HeaderMap,
+    origin: Origin,
+    ws: upgrade::IncomingUpgrade,
+    State(state): State<Server<CS>>,
+) -> Response {
+    let server = state.clone();
+    let handler = move |confirm, socket| async move { server.serve_store(confirm, socket).await };
+    authenticate_with(headers, origin, ws, state, STORE_PATH, handler).await
+}
+
+/// hook for calling the blob retrieval endpoint, see [`RETRIEVE_PATH`]
+pub async fn ws_retrieve<CS: Suite>(
+    headers: HeaderMap,
+    origin: Origin,
+    ws: upgrade::IncomingUpgrade,
+    State(state): State<Server<CS>>,
+) -> Response {
+    let server = state.clone();
+    let handler =
+        move |confirm, socket| async move { server.serve_retrieve(confirm, socket).await };
+    authenticate_with(headers, origin, ws, state, RETRIEVE_PATH, handler).await
+}
+
+/// admit a connection to `path` like [`ws_authenticate`] and run [`Server::authenticate_then`]
+/// over it, giving back the budget and load once the login is done
+async fn authenticate_with<CS: Suite, F, Fut>(
+    headers: HeaderMap,
+    origin: Origin,
+    ws: upgrade::IncomingUpgrade,
+    state: Server<CS>,
+    path: &'static str,
+    handler: F,
+) -> Response
+where
+    F: FnOnce(AuthConfirm, WebSocket) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let Admitted {
+        response,
+        fut,
+        admission,
+    } = match state
+        .admit_connection(&headers, origin, ws, Some(Operation::Authenticate))
+        .await
+    {
+        Ok(admitted) => admitted,
+        Err(response) => return response,
+    };
+    state.spawn_connection(path, response, move |state| async move {
+        let hand_over = move |confirm: AuthConfirm, socket: WebSocket| {
+            drop(admission);
+            handler(confirm, socket)
+        };
+        state.login_then(fut, hand_over).await;
+    })
+}
+
+/// hook for calling the password change endpoint
+pub async fn ws_change_password<CS: Suite>(
+    headers: HeaderMap,
+    origin: Origin,
+    ws: upgrade::IncomingUpgrade,
+    State(state): State<Server<CS>>,
+) -> Response {
+    ws_operation(headers, origin, ws, state, Operation::ChangePassword).await
+}
+
+/// hook for calling the account deletion endpoint
+///
+/// mounted whatever the [`DeletionPolicy`](super::deletion::DeletionPolicy), when users can't
+/// delete their own account every request is refused before any work is done for it
+pub async fn ws_delete<CS: Suite>(
+    headers: HeaderMap,
+    origin: Origin,
+    ws: upgrade::IncomingUpgrade,
+    State(state): State<Server<CS>>,
+) -> Response {
+    if !state.deletion_policy.self_service() {
+        return (StatusCode::FORBIDDEN, "Account deletion is not available").into_response();
+    }
+    ws_operation(headers, origin, ws, state, Operation::Delete).await
+}
+
+/// admit a connection for `operation` and run that one operation over it
+async fn ws_operation<CS: Suite>(
+    headers: HeaderMap,
+    origin: Origin,
+    ws: upgrade::IncomingUpgrade,
+    state: Server<CS>,
+    operation: Operation,
+) -> Response {
+    let Admitted {
+        response,
+        fut,
+        admission,
+    } = match state
+        .admit_connection(&headers, origin, ws, Some(operation))
+        .await
+    {
+        Ok(admitted) => admitted,
+        Err(response) => return response,
+    };
+    state.spawn_connection(operation.path(), response, move |state| async move {
+        let _admission = admission;
+        state.serve_operation(fut, operation, false).await;
+    })
+}
+
+/// hook for calling the session endpoint, see [`SESSION_PATH`]
+///
+/// only the transport and load are checked here, budgets are taken per operation as they start
+pub async fn ws_session<CS: Suite>(
+    headers: HeaderMap,
+    origin: Origin,
+    ws: upgrade::IncomingUpgrade,
+    State(state): State<Server<CS>>,
+) -> Response {
+    let Admitted {
+        response,
+        fut,
+        admission,
+    } = match state.admit_connection(&headers, origin, ws, None).await {
+        Ok(admitted) => admitted,
+        Err(response) => return response,
+    };
+    state.spawn_connection(SESSION_PATH, response, move |state| async move {
+        let _admission = admission;
+        if let Err(err) = state.session(fut).await {
+            tracing::warn!(kind = err.kind(), "Error in session: `{err}`");
+        }
+    })
+}
+
+/// counters and timings of the exchanges, in the Prometheus text format
+#[cfg(feature = "metrics")]
+pub async fn metrics<CS: Suite>(State(state): State<Server<CS>>) -> Response {
+    state
+        .metrics
+        .in_flight(&state.budgets.usage(), state.budgets.handshakes().in_use());
+    if let Some(status) = state.load_status() {
+        state.metrics.load(status);
+    }
+    (
+        [(axum::http::header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        state.metrics.render(),
+    )
+        .into_response()
+}
+
+/// revoke the session token the request carries
+pub async fn logout<CS: Suite>(
+    State(state): State<Server<CS>>,
+    user: AuthenticatedUser<CS>,
+) -> Response {
+    match state.revoke_token(&user.token) {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// the attributes of the user the session token belongs to, as json
+pub async fn get_attributes<CS: Suite>(
+    State(state): State<Server<CS>>,
+    user: AuthenticatedUser<CS>,
+) -> Response {
+    match state.attributes(user.username.as_bytes()) {
+        Ok(attributes) => Json(attributes).into_response(),
+        Err(err) => attributes_failed(err),
+    }
+}
+
+/// replace all of the attributes of the user the session token belongs to with the json map in
+/// the body
+pub async fn put_attributes<CS: Suite>(
+    State(state): State<Server<CS>>,
+    user: AuthenticatedUser<CS>,
+    Json(attributes): Json<BTreeMap<String, String>>,
+) -> Response {
+    let replaced = async {
+        let attributes = Attributes::try_from(attributes)?;
+        state
+            .set_attributes(user.username.as_bytes(), &attributes)
+            .await
+    };
+    match replaced.await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => attributes_failed(err),
+    }
+}
+
+/// answer a failed attribute request with the kind of the error, for the client to tell apart
+fn attributes_failed(err: ServerError) -> Response {
+    let status = match err {
+        ServerError::Attributes(_) => StatusCode::BAD_REQUEST,
+        ServerError::UserDoesNotExist => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, err.kind()).into_response()
+}
+
+/// everything stored about the user the session token belongs to, as json
+pub async fn takeout<CS: Suite>(
+    State(state): State<Server<CS>>,
+    user: AuthenticatedUser<CS>,
+) -> Response {
+    match state.export(user.username.as_bytes(), false).await {
+        Ok(document) => Json(document).into_response(),
+        Err(err) => {
+            let status = match err {
+                ServerError::UserDoesNotExist => StatusCode::NOT_FOUND,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (status, err.kind()).into_response()
+        }
+    }
+}
+
+/// describe the server to clients before they log in, as json
+pub async fn info<CS: Suite>(State(state): State<Server<CS>>) -> Json<ServerInfo> {
+    Json(state.server_info())
+}
+
+/// report how the server is set up, as json
+pub async fn runtime<CS: Suite>(State(state): State<Server<CS>>) -> Response {
+    match state.runtime_info().await {
+        Ok(info) => Json(info).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// report how much space the store takes up and what's in it, as json
+pub async fn store_stats<CS: Suite>(State(state): State<Server<CS>>) -> Response {
+    match state.store_stats() {
+        Ok(stats) => Json(stats).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// reclaim space in the store, answering with the sizes before and after as json
+pub async fn maintain<CS: Suite>(State(state): State<Server<CS>>) -> Response {
+    match state.maintain().await {
+        Ok(report) => Json(report).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
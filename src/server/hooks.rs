@@ -0,0 +1,147 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::sync::Notify;
+
+use super::events::ServerEvent;
+
+/// Reacts to [`ServerEvent`]s, e.g. by calling a webhook
+///
+/// Hooks run on their own task, away from the exchanges, so a slow or failing hook can't hold up
+/// logins.
+pub trait Hook: Send + Sync + 'static {
+    fn name(&self) -> &str;
+
+    fn call(&self, event: ServerEvent) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+/// What to do with a new event when the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// make room by dropping the oldest queued event
+    DropOldest,
+    /// drop the new event
+    DropNewest,
+    /// wait up to the given time for room, then drop the new event
+    Block(Duration),
+}
+
+struct QueueInner {
+    events: Mutex<VecDeque<ServerEvent>>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    /// wakes the dispatcher when events are queued
+    queued: Notify,
+    /// wakes blocked producers when the dispatcher takes an event
+    taken: Notify,
+    dropped: AtomicU64,
+}
+
+/// Bounded queue between the exchanges and the hooks
+#[derive(Clone)]
+pub struct HookQueue {
+    inner: Arc<QueueInner>,
+}
+
+impl HookQueue {
+    /// start dispatching to `hooks`, each call gets at most `hook_timeout` to finish. Needs to be
+    /// called from within a tokio runtime
+    pub fn spawn(
+        hooks: Vec<Arc<dyn Hook>>,
+        capacity: usize,
+        policy: OverflowPolicy,
+        hook_timeout: Duration,
+    ) -> Self {
+        let inner = Arc::new(QueueInner {
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            policy,
+            queued: Notify::new(),
+            taken: Notify::new(),
+            dropped: AtomicU64::new(0),
+        });
+        let queue = inner.clone();
+        tokio::task::spawn(async move {
+            loop {
+                let next = queue.events.lock().unwrap().pop_front();
+                let Some(event) = next else {
+                    queue.queued.notified().await;
+                    continue;
+                };
+                queue.taken.notify_one();
+                for hook in &hooks {
+                    let call = hook.call(event.clone());
+                    // run on its own task so a panicking hook doesn't take the dispatcher down
+                    let handle = tokio::task::spawn(tokio::time::timeout(hook_timeout, call));
+                    match handle.await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(_)) => tracing::warn!(hook = hook.name(), "Hook timed out"),
+                        Err(err) => tracing::error!(hook = hook.name(), "Hook failed: `{err}`"),
+                    }
+                }
+            }
+        });
+        Self { inner }
+    }
+
+    /// hand `event` to the hooks without waiting on them
+    pub async fn enqueue(&self, event: ServerEvent) {
+        let mut event = Some(event);
+        let deadline = match self.inner.policy {
+            OverflowPolicy::Block(wait) => Some(tokio::time::Instant::now() + wait),
+            _ => None,
+        };
+        loop {
+            let taken = self.inner.taken.notified();
+            {
+                let mut events = self.inner.events.lock().unwrap();
+                if events.len() < self.inner.capacity {
+                    events.extend(event.take());
+                    break;
+                }
+                match self.inner.policy {
+                    OverflowPolicy::DropOldest => {
+                        events.pop_front();
+                        events.extend(event.take());
+                        self.dropped();
+                        break;
+                    }
+                    OverflowPolicy::DropNewest => {
+                        self.dropped();
+                        return;
+                    }
+                    OverflowPolicy::Block(_) => {}
+                }
+            }
+            let deadline = deadline.expect("only blocking waits for room");
+            if tokio::time::timeout_at(deadline, taken).await.is_err() {
+                self.dropped();
+                return;
+            }
+        }
+        self.inner.queued.notify_one();
+    }
+
+    fn dropped(&self) {
+        let dropped = self.inner.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::warn!(dropped, "Hook queue is full, dropped an event");
+    }
+
+    /// how many events are waiting to be dispatched
+    pub fn depth(&self) -> usize {
+        self.inner.events.lock().unwrap().len()
+    }
+
+    /// how many events were dropped because the queue was full
+    pub fn dropped_count(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+}
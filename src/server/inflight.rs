@@ -0,0 +1,71 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::storage_key::StorageKey;
+
+/// Caps how many exchanges can be running for any one user at once
+///
+/// Every exchange costs the server work before it's known whether the password was right, so
+/// without this one client can tie the server up by opening many logins for the same user.
+#[derive(Clone)]
+pub struct UserInFlight {
+    limit: usize,
+    running: Arc<Mutex<HashMap<StorageKey, usize>>>,
+}
+
+impl UserInFlight {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            running: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// start an exchange for `key`, `None` when the user already has as many running as allowed
+    pub fn enter(&self, key: &StorageKey) -> Option<InFlightGuard> {
+        let mut running = self.running.lock().unwrap();
+        if running.get(key).copied().unwrap_or(0) >= self.limit {
+            return None;
+        }
+        *running.entry(key.clone()).or_default() += 1;
+        Some(InFlightGuard {
+            key: key.clone(),
+            running: self.running.clone(),
+        })
+    }
+
+    /// how many users have exchanges running, entries go away with the last guard for a user
+    pub fn tracked_users(&self) -> usize {
+        self.running.lock().unwrap().len()
+    }
+
+    /// the most exchanges any single user currently has running
+    pub fn max_running(&self) -> usize {
+        let running = self.running.lock().unwrap();
+        running.values().copied().max().unwrap_or(0)
+    }
+}
+
+/// An exchange for a user that is running, counted until dropped
+pub struct InFlightGuard {
+    key: StorageKey,
+    running: Arc<Mutex<HashMap<StorageKey, usize>>>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut running = self.running.lock().unwrap();
+        if let Some(count) = running.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                running.remove(&self.key);
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    fmt::Display,
+    fs::{read, write},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::error::ServerError;
+
+/// sled tree holding server wide metadata
+pub const METADATA_TREE: &str = "metadata";
+const INSTANCE_KEY: &[u8] = b"instance";
+
+/// where the instance belonging to a server setup file is recorded
+pub fn instance_path(setup_path: impl AsRef<Path>) -> PathBuf {
+    let mut path = setup_path.as_ref().as_os_str().to_owned();
+    path.push(".instance");
+    path.into()
+}
+
+/// What to do when the database and the server setup come from different instances
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MismatchPolicy {
+    /// print a warning and carry on
+    #[default]
+    Warn,
+    /// refuse to start
+    Refuse,
+}
+
+/// Identifies a deployment, recorded both in the database and beside the server setup file so a
+/// database copied between environments can be caught before users start failing to log in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Instance {
+    pub id: String,
+    pub environment: String,
+}
+
+impl Instance {
+    /// a new instance with a random id
+    pub fn generate(environment: String) -> Self {
+        Self {
+            id: format!("{:032x}", rand::random::<u128>()),
+            environment,
+        }
+    }
+
+    /// the instance recorded in the database, if any
+    pub fn from_store(store: &sled::Db) -> Result<Option<Self>, ServerError> {
+        let tree = store.open_tree(METADATA_TREE)?;
+        match tree.get(INSTANCE_KEY)? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn write_store(&self, store: &sled::Db) -> Result<(), ServerError> {
+        let tree = store.open_tree(METADATA_TREE)?;
+        tree.insert(INSTANCE_KEY, bincode::serialize(self)?)?;
+        Ok(())
+    }
+
+    /// the instance recorded in a file, `None` when the file doesn't exist
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Option<Self>, ServerError> {
+        match read(path) {
+            Ok(data) => Ok(Some(bincode::deserialize(&data)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn write_file(&self, path: impl AsRef<Path>) -> Result<(), ServerError> {
+        write(path, bincode::serialize(self)?)?;
+        Ok(())
+    }
+}
+
+impl Display for Instance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.id, self.environment)
+    }
+}
@@ -0,0 +1,21 @@
+use std::time::Duration;
+
+use super::tokens::random_token;
+
+/// sled tree holding the invites that haven't been used yet, each with when it was made
+pub(crate) const INVITES_TREE: &str = "invites";
+/// how long an invite can be used for unless configured otherwise
+pub const DEFAULT_INVITE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// random bytes in an invite
+const INVITE_LEN: usize = 16;
+
+/// a fresh invite, hex so it can be handed around as text
+pub(crate) fn new_invite() -> String {
+    random_token(INVITE_LEN)
+}
+
+/// whether an invite made at `created_at`, as it's stored, has run out by `now`
+pub(crate) fn expired(created_at: &[u8], now: u64, ttl: Duration) -> bool {
+    let created_at = created_at.try_into().map_or(0, u64::from_be_bytes);
+    created_at.saturating_add(ttl.as_secs()) <= now
+}
@@ -0,0 +1,208 @@
+use std::{convert::Infallible, fmt::Display, net::SocketAddr};
+#[cfg(unix)]
+use std::{future::Future, path::PathBuf};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+#[cfg(unix)]
+use axum::{http::StatusCode, Extension, Router};
+#[cfg(unix)]
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+#[cfg(feature = "server-tls")]
+use super::tls::TlsConfig;
+
+/// Marks requests on connections the server terminated TLS for itself, so they pass
+/// [`Server::with_require_tls`](super::Server::with_require_tls) without a proxy. Added by the
+/// server's own TLS listeners, routers served some other way can add it as an [`Extension`](axum::Extension)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlsTerminated;
+
+/// Where a request reached the server from, for telling whether it came over TLS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Origin {
+    /// the address of the peer, only known when the router is served with connect info
+    pub peer: Option<SocketAddr>,
+    /// whether the connection carries [`TlsTerminated`]
+    pub tls: bool,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Origin {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            peer: parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(peer)| *peer),
+            tls: parts.extensions.get::<TlsTerminated>().is_some(),
+        })
+    }
+}
+
+/// Which routes a listener serves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ListenerRole {
+    /// the exchanges clients run, see [`Server::router`](super::Server::router)
+    Public,
+    /// operator facing routes, see [`Server::admin_router`](super::Server::admin_router). Should
+    /// only be reachable from inside the deployment
+    Admin,
+}
+
+impl Display for ListenerRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Public => write!(f, "public"),
+            Self::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Where a listener accepts connections
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    /// a Unix domain socket, for tooling running on the same machine
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl From<SocketAddr> for ListenAddr {
+    fn from(value: SocketAddr) -> Self {
+        Self::Tcp(value)
+    }
+}
+
+impl Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// An address to listen on and what to serve there
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListenerConfig {
+    pub addr: ListenAddr,
+    pub role: ListenerRole,
+    /// only serve peers running as one of these uids, as reported by the kernel through
+    /// `SO_PEERCRED`. Only applies to Unix sockets, where it spares local tooling from having to
+    /// authenticate some other way
+    pub allowed_uids: Option<Vec<u32>>,
+    /// terminate TLS with this certificate instead of serving plain connections. Only applies to
+    /// TCP listeners
+    #[cfg(feature = "server-tls")]
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+impl ListenerConfig {
+    pub fn public(addr: impl Into<ListenAddr>) -> Self {
+        Self {
+            addr: addr.into(),
+            role: ListenerRole::Public,
+            allowed_uids: None,
+            #[cfg(feature = "server-tls")]
+            tls: None,
+        }
+    }
+
+    pub fn admin(addr: impl Into<ListenAddr>) -> Self {
+        Self {
+            addr: addr.into(),
+            role: ListenerRole::Admin,
+            allowed_uids: None,
+            #[cfg(feature = "server-tls")]
+            tls: None,
+        }
+    }
+
+    pub fn with_allowed_uids(mut self, uids: Vec<u32>) -> Self {
+        self.allowed_uids = Some(uids);
+        self
+    }
+
+    #[cfg(feature = "server-tls")]
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+}
+
+/// Who is on the other end of a Unix socket, added to every request arriving over one so
+/// handlers can tell who made it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+}
+
+/// serve `router` on a Unix socket until `shutdown` completes, refusing peers whose uid isn't in
+/// `allowed_uids` when it's set
+#[cfg(unix)]
+pub(super) async fn serve_unix(
+    listener: UnixListener,
+    router: Router,
+    allowed_uids: Option<Vec<u32>>,
+    shutdown: impl Future<Output = ()>,
+) -> std::io::Result<()> {
+    let mut shutdown = std::pin::pin!(shutdown);
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    tracing::warn!("Error accepting unix socket connection: `{err}`");
+                    continue;
+                }
+            },
+            () = &mut shutdown => return Ok(()),
+        };
+        let peer = match stream.peer_cred() {
+            Ok(cred) => PeerCredentials {
+                uid: cred.uid(),
+                gid: cred.gid(),
+                pid: cred.pid(),
+            },
+            Err(err) => {
+                tracing::warn!("Error reading unix socket peer credentials: `{err}`");
+                continue;
+            }
+        };
+        let allowed = allowed_uids
+            .as_ref()
+            .is_none_or(|uids| uids.contains(&peer.uid));
+        let app = if allowed {
+            router.clone()
+        } else {
+            tracing::warn!(uid = peer.uid, "Refusing unix socket connection");
+            Router::new().fallback(|| async { (StatusCode::FORBIDDEN, "Peer is not allowed") })
+        };
+        let service = TowerToHyperService::new(app.layer(Extension(peer)));
+        tokio::task::spawn(async move {
+            let served = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await;
+            if let Err(err) = served {
+                tracing::warn!("Error serving unix socket connection: `{err}`");
+            }
+        });
+    }
+}
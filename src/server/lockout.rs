@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::error::ServerError;
+use crate::storage_key::StorageKey;
+
+/// sled tree holding each user's run of failed logins
+pub(crate) const LOCKOUT_TREE: &str = "lockouts";
+
+/// When repeated failed logins lock an account
+///
+/// While an account is locked its password file isn't even looked at, the login carries on the
+/// way it would for an unknown user, so a locked account fails exactly like a wrong password and
+/// can't be told apart from one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockoutPolicy {
+    /// failed logins in a row before the account is locked
+    pub threshold: u32,
+    /// how long the first lock lasts, every further failure doubles it
+    pub window: Duration,
+    /// upper bound on how long a lock lasts
+    pub max_window: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            window: Duration::from_secs(30),
+            max_window: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl LockoutPolicy {
+    /// how long the account gets locked for after `failures` in a row, `None` when that's not
+    /// enough to lock it
+    pub fn window_after(&self, failures: u32) -> Option<Duration> {
+        let over = failures.checked_sub(self.threshold)?;
+        let factor = 2u32.checked_pow(over).unwrap_or(u32::MAX);
+        Some(self.window.saturating_mul(factor).min(self.max_window))
+    }
+}
+
+/// A user's failed logins since the last successful one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Streak {
+    failures: u32,
+    /// seconds since the unix epoch
+    locked_until: u64,
+}
+
+/// seconds until `key` unlocks, `None` when it isn't locked
+pub(crate) fn locked_for(
+    tree: &sled::Tree,
+    key: &StorageKey,
+    now: u64,
+) -> Result<Option<u64>, ServerError> {
+    let Some(data) = tree.get(key)? else {
+        return Ok(None);
+    };
+    let streak: Streak = bincode::deserialize(&data)?;
+    Ok(streak
+        .locked_until
+        .checked_sub(now)
+        .filter(|remaining| *remaining > 0))
+}
+
+/// count a failed login, returns how long the account is now locked for
+pub(crate) fn failed(
+    tree: &sled::Tree,
+    key: &StorageKey,
+    policy: &LockoutPolicy,
+    now: u64,
+) -> Result<Option<Duration>, ServerError> {
+    let updated = tree.update_and_fetch(key, |data| {
+        let mut streak: Streak = data
+            .and_then(|data| bincode::deserialize(data).ok())
+            .unwrap_or_default();
+        streak.failures = streak.failures.saturating_add(1);
+        if let Some(window) = policy.window_after(streak.failures) {
+            streak.locked_until = now.saturating_add(window.as_secs());
+        }
+        bincode::serialize(&streak).ok()
+    })?;
+    let Some(data) = updated else {
+        return Ok(None);
+    };
+    let streak: Streak = bincode::deserialize(&data)?;
+    Ok(policy.window_after(streak.failures))
+}
+
+/// forget `key`'s failed logins, after it logged in successfully
+pub(crate) fn reset(tree: &sled::Tree, key: &StorageKey) -> Result<(), ServerError> {
+    tree.remove(key)?;
+    Ok(())
+}
@@ -1,17 +1,348 @@
-use axum::{routing::get, Router};
-use tinap::server::{ws_authenticate, ws_registration, Server};
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+#[cfg(feature = "server-tls")]
+use tinap::server::tls::TlsConfig;
+use tinap::server::{
+    backup::{export_users, import_users, Backup, BackupError},
+    config::ServerConfig,
+    error::ServerError,
+    instance::MismatchPolicy,
+    listeners::{ListenAddr, ListenerConfig},
+    migrate::migrate_store,
+    Server, ATTRIBUTES_TREE,
+};
+use tracing_subscriber::EnvFilter;
+
+/// where clients are served when neither the flags nor the config file say otherwise
+const DEFAULT_BIND: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const DEFAULT_PORT: u16 = 6969;
+/// how often expired session tokens are cleared out
+const SESSION_SWEEP: Duration = Duration::from_secs(10 * 60);
+
+/// Serve OPAQUE registrations and logins over websockets
+#[derive(Debug, Parser)]
+#[command(name = "tinap-server", version)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML file with settings, flags take precedence over it
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// address to serve clients on [default: 127.0.0.1]
+    #[arg(long)]
+    bind: Option<IpAddr>,
+    /// port to serve clients on [default: 6969]
+    #[arg(long)]
+    port: Option<u16>,
+    /// where the database lives [default: tinap_db]
+    #[arg(long, global = true)]
+    db_path: Option<PathBuf>,
+    /// where the server setup lives [default: server_setup]
+    #[arg(long)]
+    setup_path: Option<PathBuf>,
+    /// the first account registered with this token becomes an admin
+    #[arg(long)]
+    bootstrap_token: Option<String>,
+    /// serve the admin routes on this address, can be repeated
+    #[arg(long)]
+    admin_bind: Vec<SocketAddr>,
+    /// serve the admin routes on this Unix socket, can be repeated
+    #[arg(long)]
+    admin_socket: Vec<PathBuf>,
+    /// only let these uids use the admin sockets, can be repeated
+    #[arg(long)]
+    admin_uid: Vec<u32>,
+    /// PEM certificate chain to serve clients over TLS with
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key belonging to `--tls-cert`
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Rewrite records stored in older layouts in the current one and exit, safe to run again
+    /// or to interrupt
+    Migrate,
+    /// Write every user to a JSON file, for backups
+    Export {
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Restore users from a JSON file written by `export`
+    Import {
+        #[arg(long = "in")]
+        input: PathBuf,
+        /// replace users that are already registered instead of giving up
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Settings read from `--config`, anything left out falls back to the defaults
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct FileConfig {
+    bind: Option<IpAddr>,
+    port: Option<u16>,
+    #[serde(flatten)]
+    server: ServerConfig,
+}
+
+impl Args {
+    /// the address clients are served on and the server's settings, taking each from the flags
+    /// if given, then the config file, then the defaults
+    fn resolve(&self, file: FileConfig) -> (SocketAddr, ServerConfig) {
+        let addr = SocketAddr::new(
+            self.bind.or(file.bind).unwrap_or(DEFAULT_BIND),
+            self.port.or(file.port).unwrap_or(DEFAULT_PORT),
+        );
+        let mut config = file.server;
+        if let Some(db_path) = &self.db_path {
+            config.db_path = db_path.clone();
+        }
+        if let Some(setup_path) = &self.setup_path {
+            config.setup_path = setup_path.clone();
+        }
+        (addr, config)
+    }
+}
+
+fn read_config(path: &Path) -> Result<FileConfig, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| format!("Could not read config `{}` `{err}`", path.display()))?;
+    toml::from_str(&text).map_err(|err| format!("Invalid config `{}` `{err}`", path.display()))
+}
+
+/// completes on ctrl-c, or on SIGTERM where there is such a thing
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(err) => {
+                tracing::warn!("Could not listen for SIGTERM: `{err}`");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+fn open_db(db_path: &Path) -> sled::Db {
+    sled::open(db_path).unwrap_or_else(|err| {
+        fail(format!(
+            "Could not open database `{}` `{err}`",
+            db_path.display()
+        ))
+    })
+}
+
+fn open_tree(db: &sled::Db, name: &str) -> sled::Tree {
+    db.open_tree(name)
+        .unwrap_or_else(|err| fail(format!("Could not open tree `{name}` `{err}`")))
+}
+
+fn migrate(db_path: &Path) {
+    let db = open_db(db_path);
+    let report =
+        migrate_store(&db).unwrap_or_else(|err| fail(format!("Migration failed: `{err}`")));
+    println!(
+        "Migrated {} records, skipped {}, {} could not be read",
+        report.migrated, report.skipped, report.failed
+    );
+    if report.failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn export(db_path: &Path, out: &Path) {
+    let db = open_db(db_path);
+    let attributes = open_tree(&db, ATTRIBUTES_TREE);
+    let backup = export_users(&db, &attributes)
+        .unwrap_or_else(|err| fail(format!("Export failed: `{err}`")));
+    let json = serde_json::to_string_pretty(&backup).expect("a backup is plain data");
+    if let Err(err) = std::fs::write(out, json) {
+        fail(format!("Could not write `{}` `{err}`", out.display()));
+    }
+    println!(
+        "Exported {} users to `{}`",
+        backup.users.len(),
+        out.display()
+    );
+}
+
+fn import(db_path: &Path, input: &Path, force: bool) {
+    let text = std::fs::read_to_string(input)
+        .unwrap_or_else(|err| fail(format!("Could not read `{}` `{err}`", input.display())));
+    let backup: Backup = serde_json::from_str(&text)
+        .unwrap_or_else(|err| fail(format!("Invalid backup `{}` `{err}`", input.display())));
+    let db = open_db(db_path);
+    let attributes = open_tree(&db, ATTRIBUTES_TREE);
+    match import_users(&db, &attributes, &backup, force) {
+        Ok(report) => println!(
+            "Imported {} new users, replaced {}",
+            report.added, report.replaced
+        ),
+        Err(err @ BackupError::Conflicts(_)) => fail(format!(
+            "Import failed: `{err}`, pass --force to replace them"
+        )),
+        Err(err) => fail(format!("Import failed: `{err}`")),
+    }
+}
+
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
+}
 
 #[tokio::main]
 async fn main() {
-    let state = Server::initialize();
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+    let args = Args::parse();
+    let file = match &args.config {
+        Some(path) => match read_config(path) {
+            Ok(file) => file,
+            Err(err) => fail(err),
+        },
+        None => FileConfig::default(),
+    };
+    let (addr, config) = args.resolve(file);
+    if let Some(command) = &args.command {
+        match command {
+            Command::Migrate => migrate(&config.db_path),
+            Command::Export { out } => export(&config.db_path, out),
+            Command::Import { input, force } => import(&config.db_path, input, *force),
+        }
+        return;
+    }
+
+    let mut state = Server::initialize_from(config, "default", MismatchPolicy::Warn)
+        .unwrap_or_else(|err| fail(format!("Failed to initialize server: `{err}`")))
+        .with_session_sweep(SESSION_SWEEP);
+    if let Some(token) = args.bootstrap_token.clone() {
+        state = state
+            .with_bootstrap_token(token)
+            .await
+            .unwrap_or_else(|err| fail(format!("Failed to set up bootstrapping: `{err}`")));
+    }
+
+    let public = match (&args.tls_cert, &args.tls_key) {
+        #[cfg(feature = "server-tls")]
+        (Some(cert), Some(key)) => ListenerConfig::public(addr).with_tls(TlsConfig::new(cert, key)),
+        #[cfg(not(feature = "server-tls"))]
+        (Some(_), Some(_)) => {
+            fail("--tls-cert and --tls-key need the server to be built with `server-tls`")
+        }
+        _ => ListenerConfig::public(addr),
+    };
+    let mut listeners = vec![public];
+    listeners.extend(args.admin_bind.iter().copied().map(ListenerConfig::admin));
+    for path in &args.admin_socket {
+        let mut listener = ListenerConfig::admin(ListenAddr::Unix(path.clone()));
+        if !args.admin_uid.is_empty() {
+            listener = listener.with_allowed_uids(args.admin_uid.clone());
+        }
+        listeners.push(listener);
+    }
+
+    match state.runtime_info().await {
+        Ok(info) => println!("{info}"),
+        Err(err) => eprintln!("Error gathering runtime info: `{err}`"),
+    }
+
+    match state.serve_all(&listeners, shutdown_signal()).await {
+        Ok(()) => {}
+        Err(ServerError::Bind(addr, err)) if err.kind() == ErrorKind::AddrInUse => fail(format!(
+            "Could not listen on {addr}, something else is already using it. Pick another \
+             address with --bind and --port"
+        )),
+        Err(err) => fail(format!("Server stopped: `{err}`")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+        bind = "0.0.0.0"
+        port = 7000
+        db_path = "file_db"
+        setup_path = "file_setup"
+        log_usernames = true
+    "#;
+
+    fn resolve(flags: &[&str], file: &str) -> (SocketAddr, ServerConfig) {
+        let args = Args::try_parse_from(["tinap-server"].iter().chain(flags)).unwrap();
+        args.resolve(toml::from_str(file).unwrap())
+    }
+
+    #[test]
+    fn defaults_fill_in_for_everything_left_out() {
+        let (addr, config) = resolve(&[], "");
+        assert_eq!(addr, SocketAddr::new(DEFAULT_BIND, DEFAULT_PORT));
+        assert_eq!(config, ServerConfig::default());
+    }
+
+    #[test]
+    fn the_file_overrides_the_defaults() {
+        let (addr, config) = resolve(&[], FILE);
+        assert_eq!(addr, "0.0.0.0:7000".parse().unwrap());
+        assert_eq!(config.db_path, PathBuf::from("file_db"));
+        assert_eq!(config.setup_path, PathBuf::from("file_setup"));
+        assert!(config.log_usernames);
+    }
+
+    #[test]
+    fn flags_override_the_file() {
+        let (addr, config) = resolve(
+            &[
+                "--bind",
+                "::1",
+                "--port",
+                "8000",
+                "--db-path",
+                "flag_db",
+                "--setup-path",
+                "flag_setup",
+            ],
+            FILE,
+        );
+        assert_eq!(addr, "[::1]:8000".parse().unwrap());
+        assert_eq!(config.db_path, PathBuf::from("flag_db"));
+        assert_eq!(config.setup_path, PathBuf::from("flag_setup"));
+        // settings without a flag still come from the file
+        assert!(config.log_usernames);
+    }
 
-    let app = Router::new()
-        .route("/registration", get(ws_registration))
-        .route("/authenticate", get(ws_authenticate))
-        .with_state(state);
+    #[test]
+    fn flags_only_override_what_they_name() {
+        let (addr, config) = resolve(&["--port", "8000"], FILE);
+        assert_eq!(addr, "0.0.0.0:8000".parse().unwrap());
+        assert_eq!(config.db_path, PathBuf::from("file_db"));
+    }
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:6969")
-        .await
-        .unwrap();
-    axum::serve(listener, app).await.unwrap()
+    #[test]
+    fn bad_files_are_refused_naming_the_file() {
+        assert!(toml::from_str::<FileConfig>("port = 70000").is_err());
+        let err = read_config(Path::new("missing.toml")).unwrap_err();
+        assert!(err.contains("missing.toml"), "{err}");
+    }
 }
@@ -0,0 +1,96 @@
+use std::{sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use super::{clock::Clock, error::ServerError, instance::METADATA_TREE};
+
+const LAST_MAINTENANCE_KEY: &[u8] = b"last_maintenance";
+
+/// Overview of how much space the store takes up and what's in it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoreStats {
+    /// bytes used by the database files
+    pub size_on_disk: u64,
+    /// name and number of entries of every tree
+    pub trees: Vec<(String, usize)>,
+    /// when maintenance last finished, in seconds since the unix epoch
+    pub last_maintenance: Option<u64>,
+}
+
+/// Sizes of the store on either side of a maintenance run
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MaintenanceReport {
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+pub fn store_stats(store: &sled::Db) -> Result<StoreStats, ServerError> {
+    let mut trees = Vec::new();
+    for name in store.tree_names() {
+        let tree = store.open_tree(&name)?;
+        trees.push((String::from_utf8_lossy(&name).into_owned(), tree.len()));
+    }
+    let last_maintenance = store
+        .open_tree(METADATA_TREE)?
+        .get(LAST_MAINTENANCE_KEY)?
+        .and_then(|data| data.as_ref().try_into().ok())
+        .map(u64::from_be_bytes);
+
+    Ok(StoreStats {
+        size_on_disk: store.size_on_disk()?,
+        trees,
+        last_maintenance,
+    })
+}
+
+/// flush everything out so sled can rewrite and drop its stale segments, this blocks for as long
+/// as that takes so it shouldn't be run on an async worker
+pub fn run_maintenance(
+    store: &sled::Db,
+    clock: &dyn Clock,
+) -> Result<MaintenanceReport, ServerError> {
+    let size_before = store.size_on_disk()?;
+    store.flush()?;
+    let size_after = store.size_on_disk()?;
+
+    let now = clock.unix_secs();
+    store
+        .open_tree(METADATA_TREE)?
+        .insert(LAST_MAINTENANCE_KEY, now.to_be_bytes().to_vec())?;
+
+    Ok(MaintenanceReport {
+        size_before,
+        size_after,
+    })
+}
+
+/// run maintenance on a blocking thread, leaving the async workers free to serve requests
+pub async fn maintain(
+    store: sled::Db,
+    clock: Arc<dyn Clock>,
+) -> Result<MaintenanceReport, ServerError> {
+    let report = tokio::task::spawn_blocking(move || run_maintenance(&store, clock.as_ref()))
+        .await
+        .map_err(std::io::Error::from)??;
+    tracing::info!(
+        size_before = report.size_before,
+        size_after = report.size_after,
+        "Store maintenance finished"
+    );
+    Ok(report)
+}
+
+/// run maintenance every `interval` for as long as the runtime is alive
+pub fn schedule_maintenance(store: sled::Db, clock: Arc<dyn Clock>, interval: Duration) {
+    tokio::task::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        // the first tick completes straight away, nothing to clean up at startup
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            if let Err(err) = maintain(store.clone(), clock.clone()).await {
+                tracing::error!("Error during store maintenance: `{err}`");
+            }
+        }
+    });
+}
@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+
+use super::{concurrency::Operation, error::ServerError, shedding::LoadStatus};
+
+/// Counters and timings of the exchanges a [`Server`](super::Server) runs, scraped from
+/// `/metrics` in the Prometheus text format
+///
+/// every server has its own registry, so several servers in one process don't trip over each
+/// other
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    registrations: IntCounter,
+    auth_success: IntCounter,
+    auth_failure: IntCounterVec,
+    verify_success: IntCounter,
+    verify_failure: IntCounterVec,
+    deletes: IntCounter,
+    handshake_duration: HistogramVec,
+    in_flight: IntGaugeVec,
+    load_shedding: IntGauge,
+    average_latency: Gauge,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let registrations =
+            IntCounter::new("registrations_total", "Accounts registered").expect("valid metric");
+        let auth_success = IntCounter::new("auth_success_total", "Logins that went through")
+            .expect("valid metric");
+        let auth_failure = IntCounterVec::new(
+            Opts::new("auth_failure_total", "Logins that failed, by reason"),
+            &["reason"],
+        )
+        .expect("valid metric");
+        let verify_success = IntCounter::new(
+            "verify_success_total",
+            "Credential checks that went through",
+        )
+        .expect("valid metric");
+        let verify_failure = IntCounterVec::new(
+            Opts::new(
+                "verify_failure_total",
+                "Credential checks that failed, by reason",
+            ),
+            &["reason"],
+        )
+        .expect("valid metric");
+        let deletes = IntCounter::new("delete_total", "Accounts deleted").expect("valid metric");
+        let handshake_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "handshake_duration_seconds",
+                "How long exchanges took from start to finish, by operation",
+            ),
+            &["operation"],
+        )
+        .expect("valid metric");
+        let in_flight = IntGaugeVec::new(
+            Opts::new(
+                "handshakes_in_flight",
+                "Exchanges running right now, by operation and across all of them",
+            ),
+            &["operation"],
+        )
+        .expect("valid metric");
+        let load_shedding = IntGauge::new(
+            "load_shedding",
+            "1 while new connections are turned away to shed load",
+        )
+        .expect("valid metric");
+        let average_latency = Gauge::new(
+            "load_shedding_average_latency_seconds",
+            "Moving average of how long exchanges take, as load shedding sees it",
+        )
+        .expect("valid metric");
+        // names are fixed and the registry is fresh, so registering can't fail
+        registry
+            .register(Box::new(registrations.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(auth_success.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(auth_failure.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(verify_success.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(verify_failure.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(deletes.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(handshake_duration.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(in_flight.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(load_shedding.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(average_latency.clone()))
+            .expect("unique metric");
+        Self {
+            registry,
+            registrations,
+            auth_success,
+            auth_failure,
+            verify_success,
+            verify_failure,
+            deletes,
+            handshake_duration,
+            in_flight,
+            load_shedding,
+            average_latency,
+        }
+    }
+
+    /// note the load as load shedding sees it
+    pub(super) fn load(&self, status: LoadStatus) {
+        self.load_shedding.set(status.shedding.into());
+        self.average_latency
+            .set(status.average_latency.as_secs_f64());
+    }
+
+    /// note how many exchanges are running, from the budgets' `usage` and the shared budget's
+    /// `total`
+    pub(super) fn in_flight(&self, usage: &[(Operation, usize, usize)], total: usize) {
+        for (operation, _, in_use) in usage {
+            self.in_flight
+                .with_label_values(&[operation.path()])
+                .set(*in_use as i64);
+        }
+        self.in_flight.with_label_values(&["all"]).set(total as i64);
+    }
+
+    /// count an exchange that ran for `elapsed` and ended with `result`, which holds whether the
+    /// operation went through
+    ///
+    /// logins that only checked the credentials are counted under `verify` rather than with the
+    /// logins
+    pub(super) fn exchange(
+        &self,
+        operation: Operation,
+        verify_only: bool,
+        result: Result<bool, &ServerError>,
+        elapsed: Duration,
+    ) {
+        let label = if verify_only {
+            "verify"
+        } else {
+            operation.path()
+        };
+        self.handshake_duration
+            .with_label_values(&[label])
+            .observe(elapsed.as_secs_f64());
+        if verify_only {
+            match result {
+                Ok(true) => self.verify_success.inc(),
+                Ok(false) => self
+                    .verify_failure
+                    .with_label_values(&["unconfirmed"])
+                    .inc(),
+                Err(err) => self.verify_failure.with_label_values(&[err.kind()]).inc(),
+            }
+            return;
+        }
+        match (operation, result) {
+            (Operation::Registration, Ok(true)) => self.registrations.inc(),
+            (Operation::Authenticate, Ok(true)) => self.auth_success.inc(),
+            (Operation::Authenticate, Ok(false)) => {
+                self.auth_failure.with_label_values(&["unconfirmed"]).inc()
+            }
+            (Operation::Authenticate, Err(err)) => {
+                self.auth_failure.with_label_values(&[err.kind()]).inc()
+            }
+            (Operation::Delete, Ok(true)) => self.deletes.inc(),
+            _ => {}
+        }
+    }
+
+    /// everything gathered so far, in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("encoding to memory can't fail");
+        String::from_utf8(buffer).expect("the text format is utf-8")
+    }
+}
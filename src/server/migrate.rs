@@ -0,0 +1,141 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    error::ServerError,
+    record::{self, UserRecord},
+    DEFAULT_TREE, USERS_TREE,
+};
+
+/// What a [`migrate_store`] run did with the records it came across
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationReport {
+    /// records rewritten in the current layout
+    pub migrated: usize,
+    /// records already in the current layout, or changed by someone else while being migrated
+    pub skipped: usize,
+    /// records that couldn't be read, they're left as they are
+    pub failed: usize,
+}
+
+/// whether `name` is a tree holding password files, for the default environment or any other
+fn is_users_tree(name: &[u8]) -> bool {
+    name == DEFAULT_TREE.as_bytes()
+        || name == USERS_TREE.as_bytes()
+        || name.ends_with(format!("_{USERS_TREE}").as_bytes())
+}
+
+/// rewrite every record stored in an older layout in the current one, across the password
+/// files of every environment in `db`
+///
+/// each record is swapped in on its own and only if it didn't change in the meantime, so the
+/// migration can be interrupted and run again at any point. This blocks for as long as it takes
+/// so it shouldn't be run on an async worker
+pub fn migrate_store(db: &sled::Db) -> Result<MigrationReport, ServerError> {
+    let mut report = MigrationReport::default();
+    for name in db.tree_names() {
+        if !is_users_tree(&name) {
+            continue;
+        }
+        let tree = db.open_tree(&name)?;
+        for entry in tree.iter() {
+            let (key, stored) = entry?;
+            let record = match record::unseal(&stored) {
+                Ok(record) if record.version != UserRecord::CURRENT => record,
+                Ok(_) => {
+                    report.skipped += 1;
+                    continue;
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        tree = %String::from_utf8_lossy(&name),
+                        "Record could not be migrated: `{err}`"
+                    );
+                    report.failed += 1;
+                    continue;
+                }
+            };
+            let migrated = record::seal(&record);
+            match tree.compare_and_swap(&key, Some(&stored), Some(migrated))? {
+                Ok(()) => report.migrated += 1,
+                Err(_) => report.skipped += 1,
+            }
+        }
+    }
+    db.flush()?;
+    tracing::info!(
+        migrated = report.migrated,
+        skipped = report.skipped,
+        failed = report.failed,
+        "Store migration finished"
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::Scheme;
+
+    /// a record in the checked envelope, as whichever layout wrote it
+    fn envelope(version: u8, payload: &[u8]) -> Vec<u8> {
+        let mut sealed = b"TREC".to_vec();
+        sealed.push(version);
+        sealed.extend_from_slice(&Sha256::digest(payload)[..8]);
+        sealed.extend_from_slice(payload);
+        sealed
+    }
+
+    #[test]
+    fn migrates_every_older_layout() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree(USERS_TREE).unwrap();
+        let current = UserRecord::new(b"current".to_vec(), 7);
+        tree.insert("current", record::seal(&current)).unwrap();
+        let without_suite = bincode::serialize(&(1u8, b"v1".to_vec(), 5u64, Some(6u64))).unwrap();
+        tree.insert("without_suite", envelope(2, &without_suite))
+            .unwrap();
+        tree.insert("password_file", envelope(1, b"enveloped"))
+            .unwrap();
+        tree.insert("bare", b"bare".to_vec()).unwrap();
+        let mut corrupt = envelope(2, &without_suite);
+        *corrupt.last_mut().unwrap() ^= 1;
+        tree.insert("corrupt", corrupt.clone()).unwrap();
+
+        let report = migrate_store(&db).unwrap();
+        assert_eq!(
+            report,
+            MigrationReport {
+                migrated: 3,
+                skipped: 1,
+                failed: 1,
+            }
+        );
+
+        let read = |key: &str| record::unseal(&tree.get(key).unwrap().unwrap()).unwrap();
+        assert_eq!(read("current"), current);
+        let migrated = read("without_suite");
+        assert_eq!(migrated.version, UserRecord::CURRENT);
+        assert_eq!(migrated.password_file, b"v1");
+        assert_eq!(migrated.created_at, 5);
+        assert_eq!(migrated.last_login, Some(6));
+        assert_eq!(migrated.suite, Scheme::ID);
+        for (key, file) in [("password_file", &b"enveloped"[..]), ("bare", b"bare")] {
+            let migrated = read(key);
+            assert_eq!(migrated.version, UserRecord::CURRENT);
+            assert_eq!(migrated.password_file, file);
+        }
+        assert_eq!(tree.get("corrupt").unwrap().unwrap(), corrupt);
+
+        let again = migrate_store(&db).unwrap();
+        assert_eq!(
+            again,
+            MigrationReport {
+                migrated: 0,
+                skipped: 4,
+                failed: 1,
+            }
+        );
+    }
+}
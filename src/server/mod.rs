@@ -1,266 +1,1341 @@
+mod accounts;
+pub mod admin;
 pub mod autheticate;
+pub mod backup;
+pub mod blobs;
+pub mod bootstrap;
+pub mod concurrency;
+pub mod config;
+pub mod confirmation;
+mod connection;
+pub mod deletion;
 pub mod error;
+pub mod events;
+mod exchange;
+pub mod failures;
+pub mod flush;
+mod handlers;
+pub mod hooks;
+pub mod inflight;
+pub mod instance;
+pub mod invites;
+pub mod listeners;
+pub mod lockout;
+pub mod maintenance;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod migrate;
+pub mod record;
 pub mod registration;
+pub mod runtime;
+pub mod setup_file;
+pub mod shedding;
+pub mod store;
+#[cfg(feature = "server-tls")]
+pub mod tls;
+pub mod tokens;
 
-use std::fs::{read, write};
+pub use crate::{attributes, clock};
+pub use connection::{AppSocket, MeteredIo};
+#[cfg(feature = "metrics")]
+pub use handlers::metrics;
+pub use handlers::{
+    get_attributes, info, logout, maintain, put_attributes, runtime, store_stats, takeout,
+    ws_authenticate, ws_authenticate_with, ws_change_password, ws_delete, ws_registration,
+    ws_retrieve, ws_session, ws_store,
+};
 
-use autheticate::{AuthConfirm, AuthWaiting};
-use axum::{extract::State, response::IntoResponse};
+use std::{
+    borrow::Cow,
+    convert::Infallible,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use admin::AdminToken;
+use attributes::Attributes;
+use axum::{
+    extract::Request,
+    middleware,
+    response::IntoResponse,
+    routing::{delete, get, post, Route},
+    Router,
+};
+use blobs::{BLOBS_TREE, DEFAULT_MAX_BLOB_SIZE};
+use bootstrap::{Bootstrap, BOOTSTRAPPED_KEY};
+use clock::{Clock, SystemClock};
+use concurrency::{Budget, Budgets, Operation};
+use config::{
+    ServerConfig, DEFAULT_FRAME_ARRIVAL_LIMIT, DEFAULT_FRAME_TIMEOUT, DEFAULT_SHUTDOWN_GRACE,
+};
+use confirmation::ConfirmationPolicy;
+use deletion::DeletionPolicy;
 use error::ServerError;
-use fastwebsockets::{upgrade, Frame, OpCode, WebSocketError};
-use hyper::upgrade::Upgraded;
-use hyper_util::rt::TokioIo;
+use events::{ServerEvent, DEFAULT_EVENT_CAPACITY};
+use failures::{AuthFailure, FAILURES_TREE};
+use flush::WriteCoalescer;
+use hooks::{Hook, HookQueue, OverflowPolicy};
+use inflight::UserInFlight;
+use instance::{instance_path, Instance, MismatchPolicy, METADATA_TREE};
+use invites::{DEFAULT_INVITE_TTL, INVITES_TREE};
+use listeners::{ListenAddr, ListenerConfig, ListenerRole};
+use lockout::{LockoutPolicy, LOCKOUT_TREE};
+use maintenance::{MaintenanceReport, StoreStats};
+#[cfg(feature = "metrics")]
+use metrics::Metrics;
 use opaque_ke::ServerSetup;
-use rand::rngs::OsRng;
-use registration::RegWaiting;
+use rand::{rngs::OsRng, Rng};
+use runtime::RuntimeInfo;
+use shedding::{LoadShedder, LoadShedding, LoadStatus};
+use store::{SledStore, UserStore};
+use tokens::{Sessions, DEFAULT_SESSION_TTL, SESSIONS_TREE, USER_SESSIONS_TREE};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, watch},
+    task::JoinSet,
+    time::timeout,
+};
+use tokio_util::task::TaskTracker;
+use tower::{Layer, Service};
+use tracing::Span;
+
+use crate::{
+    storage_key::{self, KeyPolicy, StorageKey},
+    suite::{SaltedKsf, Suite},
+    takeout::{SessionSummary, TakeoutDocument},
+    wire::{
+        Feature, ServerInfo, ATTRIBUTES_PATH, INFO_PATH, RETRIEVE_PATH, SESSION_PATH, STORE_PATH,
+        TAKEOUT_PATH,
+    },
+    Identifiers, Scheme,
+};
+
+/// where [`Server::initialize`] keeps the server setup
+pub const SETUP_PATH: &str = "server_setup";
+/// where [`Server::initialize`] keeps the database
+pub const DB_PATH: &str = "tinap_db";
+
+/// how long to wait on the client when sending a close after it stopped responding
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+/// how long a new connection waits for room in its operation's budget before being turned away
+const BUDGET_WAIT: Duration = Duration::from_millis(500);
+/// when padding, no exchange is ended before this long after it started, whichever way it went
+const PADDED_END_TIME: Duration = Duration::from_secs(2);
+/// how many times a read from the store is attempted before giving up
+const READ_ATTEMPTS: u32 = 3;
+/// base wait between attempts at reading from the store, doubled each time and jittered
+const READ_BACKOFF: Duration = Duration::from_millis(20);
+/// sled tree holding each user's password file when the server has a tree prefix, otherwise
+/// they're kept in the default tree
+const USERS_TREE: &str = "users";
+/// sled tree holding each user's [`Attributes`]
+pub const ATTRIBUTES_TREE: &str = "attributes";
+/// header a TLS terminating proxy uses to say which scheme the client connected with
+const FORWARDED_PROTO: &str = "x-forwarded-proto";
+/// sled tree marking users that have to register a new password before they can log in
+const REREGISTER_TREE: &str = "must_reregister";
+/// name sled gives its default tree
+const DEFAULT_TREE: &str = "__sled__default";
+/// sled tree records that failed their checksum are moved to, when quarantining
+const QUARANTINE_TREE: &str = "quarantine";
 
-use crate::Scheme;
+/// stands in for usernames in logs, unless [`Server::with_log_usernames`] is set
+const REDACTED: &str = "<redacted>";
+
+/// a listener that's been bound and is waiting to be served
+enum Bound {
+    Tcp(TcpListener),
+    #[cfg(feature = "server-tls")]
+    Tls(TcpListener, Arc<tokio_rustls::rustls::ServerConfig>),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
 
 /// [`Server`] maintains the server side setup for OPAQUE protocol, maintains the connection to the
 /// underlying `sled` database, and responds to the websocket connections
+///
+/// the exchanges run with [`Scheme`] unless the server was made for another suite with
+/// [`Server::for_suite`], records made with any other suite are refused
 #[derive(Clone)]
-pub struct Server<'a> {
-    server_setup: ServerSetup<Scheme<'a>>,
+pub struct Server<CS: Suite = Scheme> {
+    server_setup: CS::Setup,
     store: sled::Db,
+    frame_timeout: Duration,
+    frame_arrival_limit: Duration,
+    flusher: Option<WriteCoalescer>,
+    padding: bool,
+    budgets: Budgets,
+    key_policy: KeyPolicy,
+    tenant: Option<String>,
+    tree_prefix: Option<String>,
+    clock: Arc<dyn Clock>,
+    bootstrap: Option<Bootstrap>,
+    trusted_proxies: Option<Vec<IpAddr>>,
+    user_in_flight: Option<UserInFlight>,
+    /// lock accounts after repeated failed logins
+    lockout: Option<LockoutPolicy>,
+    /// tell clients their account is locked instead of failing like a wrong password
+    disclose_lockouts: bool,
+    shedder: Option<LoadShedder>,
+    hooks: Option<HookQueue>,
+    events: broadcast::Sender<ServerEvent>,
+    deletion_policy: DeletionPolicy,
+    confirmation_policy: ConfirmationPolicy,
+    /// where password files are kept, `None` for the server's own sled tree
+    user_store: Option<Arc<dyn UserStore>>,
+    /// move records that fail their checksum out of the way
+    quarantine: bool,
+    /// how many records failed their checksum
+    corrupt_records: Arc<AtomicU64>,
+    /// write usernames into logs instead of redacting them
+    log_usernames: bool,
+    /// fail compound writes partway, see [`Server::with_crash_before_commit`]
+    #[cfg(feature = "test-util")]
+    crash_before_commit: bool,
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+    /// every exchange that's been handed off to its own task
+    exchanges: TaskTracker,
+    /// how long [`Server::shutdown`] waits on running exchanges
+    shutdown_grace: Duration,
+    /// guards the `/admin` routes, which aren't served without it
+    admin_token: Option<AdminToken>,
+    /// only let registrations with an unused invite through
+    invite_only: bool,
+    /// how long invites can be used for
+    invite_ttl: Duration,
+    /// how long the session tokens handed out after logins last
+    session_ttl: Duration,
+    /// largest blob a user can store
+    max_blob_size: usize,
+    /// identities logins are bound to
+    identifiers: Identifiers,
 }
 
-impl<'a> Server<'a> {
-    pub fn new(server_setup: ServerSetup<Scheme<'a>>, store: sled::Db) -> Self {
-        Self {
-            server_setup,
-            store,
-        }
+impl Server {
+    pub fn new(server_setup: ServerSetup<Scheme>, store: sled::Db) -> Self {
+        Self::for_suite(server_setup, store)
+    }
+
+    /// a server that lives alongside other data in a database opened by the caller
+    ///
+    /// everything the server stores goes into trees named `"{prefix}_users"` and
+    /// `"{prefix}_attributes"`, the default tree is never touched, so servers with different
+    /// prefixes can share one database. See [`Server::trees`] for the full list
+    pub fn with_trees(server_setup: ServerSetup<Scheme>, store: sled::Db, prefix: &str) -> Self {
+        let mut server = Self::new(server_setup, store);
+        server.tree_prefix = Some(prefix.into());
+        server
     }
 
     /// ensures that the server makes use of previously established keys and connects to the
     /// database. Opens or creates files as needed
     pub fn initialize() -> Self {
-        let server_setup = match read("server_setup") {
-            Ok(data) => bincode::deserialize(&data).expect("Failed to deserialize server_setup"),
-            Err(err) => {
-                println!("Error reading server_setup: `{err}`");
-                println!("Creating server_setup");
-                let server_setup = ServerSetup::<Scheme>::new(&mut OsRng);
-                let encode =
-                    bincode::serialize(&server_setup).expect("Failed to serialize server_setup");
-                write("server_setup", encode).expect("Failed to write file");
-                server_setup
-            }
+        Self::initialize_with("default", MismatchPolicy::Warn).expect("Failed to initialize server")
+    }
+
+    /// same as [`Server::initialize`], labelling a newly created deployment with `environment`
+    /// and applying `policy` when the database and the server setup belong to different
+    /// instances, e.g. when a database was copied over from another environment
+    pub fn initialize_with(environment: &str, policy: MismatchPolicy) -> Result<Self, ServerError> {
+        Self::open(
+            Path::new(SETUP_PATH),
+            Path::new(DB_PATH),
+            environment,
+            policy,
+        )
+    }
+
+    /// same as [`Server::initialize_with`], keeping the server setup and database where `config`
+    /// says and applying the rest of it
+    pub fn initialize_from(
+        config: ServerConfig,
+        environment: &str,
+        policy: MismatchPolicy,
+    ) -> Result<Self, ServerError> {
+        let server = Self::open(&config.setup_path, &config.db_path, environment, policy)?;
+        Ok(server.with_config(config))
+    }
+
+    fn open(
+        setup_path: &Path,
+        db_path: &Path,
+        environment: &str,
+        policy: MismatchPolicy,
+    ) -> Result<Self, ServerError> {
+        // only a missing setup gets replaced, one that can't be read is reported instead so
+        // existing users aren't locked out by a silently regenerated setup
+        let (server_setup, created) = match setup_file::read_setup(setup_path)? {
+            Some(server_setup) => (server_setup, false),
+            None => (ServerSetup::<Scheme>::new(&mut OsRng), true),
+        };
+        let store = sled::open(db_path)?;
+
+        let setup_instance = if created {
+            None
+        } else {
+            Instance::from_file(instance_path(setup_path))?
         };
-        Server {
+        let store_instance = Instance::from_store(&store)?;
+        let instance = match (&setup_instance, &store_instance) {
+            (Some(instance), _) => instance.clone(),
+            // a freshly made setup can't match a database that already belongs somewhere
+            (None, Some(instance)) if !created => instance.clone(),
+            _ => Instance::generate(environment.into()),
+        };
+
+        match &store_instance {
+            Some(stored) if stored.id != instance.id => {
+                let err = ServerError::InstanceMismatch(stored.to_string(), instance.to_string());
+                match policy {
+                    MismatchPolicy::Warn => tracing::warn!("{err}"),
+                    MismatchPolicy::Refuse => return Err(err),
+                }
+            }
+            Some(_) => {}
+            None => instance.write_store(&store)?,
+        }
+        // a new setup is only written once it's known to belong with the database, otherwise
+        // the next start would find it without an instance file and take the database's
+        if created {
+            tracing::info!(path = %setup_path.display(), "Creating server_setup");
+            setup_file::write_setup(setup_path, &server_setup)?;
+        }
+        if setup_instance.is_none() {
+            instance.write_file(instance_path(setup_path))?;
+        }
+
+        Ok(Server::new(server_setup, store))
+    }
+}
+
+impl<CS: Suite> Server<CS> {
+    /// [`Server::new`] for a server running its exchanges with `CS`, see [`Suite`]
+    pub fn for_suite(server_setup: CS::Setup, store: sled::Db) -> Self {
+        Self {
             server_setup,
-            store: sled::open("tinap_db").unwrap(),
+            store,
+            frame_timeout: DEFAULT_FRAME_TIMEOUT,
+            frame_arrival_limit: DEFAULT_FRAME_ARRIVAL_LIMIT,
+            flusher: None,
+            padding: false,
+            budgets: Budgets::default(),
+            key_policy: KeyPolicy::default(),
+            tenant: None,
+            tree_prefix: None,
+            clock: Arc::new(SystemClock),
+            bootstrap: None,
+            trusted_proxies: None,
+            user_in_flight: None,
+            lockout: None,
+            disclose_lockouts: false,
+            shedder: None,
+            hooks: None,
+            events: broadcast::channel(DEFAULT_EVENT_CAPACITY).0,
+            deletion_policy: DeletionPolicy::default(),
+            confirmation_policy: ConfirmationPolicy::default(),
+            user_store: None,
+            quarantine: false,
+            corrupt_records: Arc::new(AtomicU64::new(0)),
+            log_usernames: false,
+            #[cfg(feature = "test-util")]
+            crash_before_commit: false,
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::new(),
+            exchanges: TaskTracker::new(),
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+            admin_token: None,
+            invite_only: false,
+            invite_ttl: DEFAULT_INVITE_TTL,
+            session_ttl: DEFAULT_SESSION_TTL,
+            max_blob_size: DEFAULT_MAX_BLOB_SIZE,
+            identifiers: Identifiers::default(),
+        }
+    }
+
+    /// apply everything in `config`, see [`ServerConfig`]
+    pub fn with_config(mut self, config: ServerConfig) -> Self {
+        // the paths have already been used by the time there's a server to apply them to
+        let ServerConfig {
+            frame_timeout,
+            frame_arrival_limit,
+            db_path: _,
+            setup_path: _,
+            log_usernames,
+            shutdown_grace,
+            lockout,
+            max_handshakes,
+            admin_token,
+            invite_only,
+            invite_ttl,
+            session_ttl,
+            max_blob_size,
+            identifiers,
+        } = config;
+        self.lockout = lockout;
+        self.identifiers = identifiers;
+        self.invite_only = invite_only;
+        self.invite_ttl = invite_ttl;
+        self.session_ttl = session_ttl;
+        self.max_blob_size = max_blob_size;
+        self.admin_token = admin_token.as_deref().map(AdminToken::new);
+        self.with_frame_timeout(frame_timeout)
+            .with_frame_arrival_limit(frame_arrival_limit)
+            .with_handshake_limit(max_handshakes)
+            .with_log_usernames(log_usernames)
+            .with_shutdown_grace(shutdown_grace)
+    }
+
+    /// counters and timings of the exchanges run so far
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// serve the `/admin` routes alongside the public ones, to requests carrying `token` as their
+    /// bearer token
+    pub fn with_admin_token(mut self, token: String) -> Self {
+        self.admin_token = Some(AdminToken::new(&token));
+        self
+    }
+
+    /// only let users register with an invite from [`Server::create_invite`], each of which
+    /// works once. Bootstrapping the first admin doesn't need one
+    pub fn with_invite_only(mut self, invite_only: bool) -> Self {
+        self.invite_only = invite_only;
+        self
+    }
+
+    /// set how long invites from [`Server::create_invite`] can be used for
+    pub fn with_invite_ttl(mut self, invite_ttl: Duration) -> Self {
+        self.invite_ttl = invite_ttl;
+        self
+    }
+
+    /// set how long the session tokens handed out after logins last
+    pub fn with_session_ttl(mut self, session_ttl: Duration) -> Self {
+        self.session_ttl = session_ttl;
+        self
+    }
+
+    /// set the largest blob a user can store, larger ones are refused and leave whatever was
+    /// stored before in place
+    pub fn with_max_blob_size(mut self, max_blob_size: usize) -> Self {
+        self.max_blob_size = max_blob_size;
+        self
+    }
+
+    /// bind logins to `identifiers`, clients have to register and log in with the same ones or
+    /// their logins fail
+    pub fn with_identifiers(mut self, identifiers: Identifiers) -> Self {
+        self.identifiers = identifiers;
+        self
+    }
+
+    /// drop expired session tokens every `interval`, otherwise they're only dropped when
+    /// someone tries to use them
+    pub fn with_session_sweep(self, interval: Duration) -> Self {
+        match self.sessions() {
+            Ok(sessions) => sessions.schedule_sweep(self.clock.clone(), interval),
+            Err(err) => tracing::error!("Could not open the session tokens: `{err}`"),
         }
+        self
+    }
+
+    /// set how long [`Server::shutdown`] waits for running exchanges to finish
+    pub fn with_shutdown_grace(mut self, shutdown_grace: Duration) -> Self {
+        self.shutdown_grace = shutdown_grace;
+        self
+    }
+
+    /// write usernames into logs, they're redacted otherwise
+    pub fn with_log_usernames(mut self, log_usernames: bool) -> Self {
+        self.log_usernames = log_usernames;
+        self
+    }
+
+    /// fail every write touching several trees after its first step, the way a crash before it
+    /// was committed would, for checking what's left behind
+    #[cfg(feature = "test-util")]
+    pub fn with_crash_before_commit(mut self) -> Self {
+        self.crash_before_commit = true;
+        self
+    }
+
+    /// set the upper bound on how long receiving a single frame may take
+    pub fn with_frame_timeout(mut self, frame_timeout: Duration) -> Self {
+        self.frame_timeout = frame_timeout;
+        self
+    }
+
+    /// set the upper bound on how long a frame may keep arriving once its first bytes are in,
+    /// whatever is left of the frame timeout
+    pub fn with_frame_arrival_limit(mut self, frame_arrival_limit: Duration) -> Self {
+        self.frame_arrival_limit = frame_arrival_limit;
+        self
+    }
+
+    /// only report a registration as complete once it has been flushed to disk, sharing the
+    /// flushes between registrations that happen within `interval` of each other
+    ///
+    /// spawns the flushing task, so needs to be called from within a tokio runtime
+    pub fn with_write_coalescing(mut self, interval: Duration) -> Self {
+        self.flusher = Some(WriteCoalescer::spawn(self.store.clone(), interval));
+        self
+    }
+
+    /// set how usernames are checked before being used as keys
+    pub fn with_key_policy(mut self, key_policy: KeyPolicy) -> Self {
+        self.key_policy = key_policy;
+        self
+    }
+
+    /// keep users under a tenant prefix, so several servers can share one database
+    pub fn with_tenant(mut self, tenant: String) -> Self {
+        self.tenant = Some(tenant);
+        self
+    }
+
+    /// limit how many exchanges of `operation` can run at once
+    pub fn with_budget(self, operation: Operation, limit: usize) -> Self {
+        self.budgets.get(operation).set_limit(limit);
+        self
+    }
+
+    /// limit how many exchanges of any kind can run at once, on top of each operation's own
+    /// budget
+    pub fn with_handshake_limit(self, limit: usize) -> Self {
+        self.budgets.handshakes().set_limit(limit);
+        self
+    }
+
+    /// limit how many authentications can run at once for any single user, turning away the rest
+    /// before any work is done for them
+    pub fn with_user_in_flight_limit(mut self, limit: usize) -> Self {
+        self.user_in_flight = Some(UserInFlight::new(limit));
+        self
+    }
+
+    /// lock accounts for a while after repeated failed logins, see [`LockoutPolicy`]
+    pub fn with_lockout(mut self, policy: LockoutPolicy) -> Self {
+        self.lockout = Some(policy);
+        self
+    }
+
+    /// turn away logins of a locked account with how long it stays locked for, rather than
+    /// letting them fail like a wrong password
+    ///
+    /// clients then stop trying until the lock is up instead of extending it, at the cost of
+    /// anyone being able to tell that an account exists and is locked
+    pub fn with_lockout_disclosure(mut self) -> Self {
+        self.disclose_lockouts = true;
+        self
+    }
+
+    /// keep password files in `store` rather than the server's sled database, see
+    /// [`UserStore`]
+    pub fn with_user_store(mut self, store: impl UserStore) -> Self {
+        self.user_store = Some(Arc::new(store));
+        self
+    }
+
+    /// decide what a login counts as when the client goes away before confirming it, see
+    /// [`ConfirmationPolicy`]
+    pub fn with_confirmation_policy(mut self, policy: ConfirmationPolicy) -> Self {
+        self.confirmation_policy = policy;
+        self
+    }
+
+    /// move records that fail their checksum into a separate tree, so the user can register
+    /// again instead of every login failing on the same corrupt record
+    pub fn with_quarantine(mut self, quarantine: bool) -> Self {
+        self.quarantine = quarantine;
+        self
+    }
+
+    /// decide who can remove accounts, see [`DeletionPolicy`]
+    pub fn with_deletion_policy(mut self, policy: DeletionPolicy) -> Self {
+        self.deletion_policy = policy;
+        self
+    }
+
+    /// receive every [`ServerEvent`] from now on
+    ///
+    /// events are kept for slow receivers up to a bound, a receiver that falls further behind
+    /// gets [`RecvError::Lagged`](broadcast::error::RecvError::Lagged) with how many it missed
+    /// and carries on from the oldest event still kept. Administrative changes are only
+    /// published here, hooks are only told about exchanges
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.events.subscribe()
+    }
+
+    /// the most authentications any single user currently has running
+    pub fn max_user_in_flight(&self) -> usize {
+        self.user_in_flight
+            .as_ref()
+            .map_or(0, UserInFlight::max_running)
+    }
+
+    /// how many users currently have authentications running, should drop back to zero once the
+    /// server is idle
+    pub fn in_flight_users(&self) -> usize {
+        self.user_in_flight
+            .as_ref()
+            .map_or(0, UserInFlight::tracked_users)
+    }
+
+    /// how many exchanges are running, should drop back to zero once the server is idle
+    pub fn running_exchanges(&self) -> usize {
+        self.exchanges.len()
+    }
+
+    /// turn new connections away with a 503 while the server is overloaded
+    pub fn with_load_shedding(mut self, policy: LoadShedding) -> Self {
+        self.shedder = Some(LoadShedder::new(policy));
+        self
+    }
+
+    /// tell `hooks` about registrations and authentications, through a queue holding at most
+    /// `capacity` events that never holds up an exchange
+    ///
+    /// spawns the dispatching task, so needs to be called from within a tokio runtime
+    pub fn with_hooks(
+        mut self,
+        hooks: Vec<Arc<dyn Hook>>,
+        capacity: usize,
+        policy: OverflowPolicy,
+        hook_timeout: Duration,
+    ) -> Self {
+        self.hooks = Some(HookQueue::spawn(hooks, capacity, policy, hook_timeout));
+        self
+    }
+
+    /// number of queued and dropped hook events, `None` without hooks
+    pub fn hook_queue_stats(&self) -> Option<(usize, u64)> {
+        self.hooks
+            .as_ref()
+            .map(|hooks| (hooks.depth(), hooks.dropped_count()))
+    }
+
+    /// how many stored records failed their checksum since the server started
+    pub fn corrupt_record_count(&self) -> u64 {
+        self.corrupt_records.load(Ordering::Relaxed)
+    }
+
+    /// current load as seen by load shedding, `None` when it's off
+    pub fn load_status(&self) -> Option<LoadStatus> {
+        self.shedder.as_ref().map(LoadShedder::status)
+    }
+
+    /// concurrency budget of `operation`, can be adjusted while the server is running
+    pub fn budget(&self, operation: Operation) -> &Budget {
+        self.budgets.get(operation)
+    }
+
+    /// limit and number of running exchanges for every operation
+    pub fn budget_usage(&self) -> Vec<(Operation, usize, usize)> {
+        self.budgets.usage()
+    }
+
+    /// the budget shared by every operation, can be adjusted while the server is running
+    pub fn handshake_budget(&self) -> &Budget {
+        self.budgets.handshakes()
+    }
+
+    /// take the current time from `clock` instead of the system, mostly for testing anything that
+    /// expires. Needs to come before any builder that spawns a background task
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// refuse connections that didn't reach the server over TLS
+    ///
+    /// connections on listeners that terminate TLS themselves are accepted. Others have to come
+    /// from one of `trusted_proxies` and report `X-Forwarded-Proto: https`, the router has to be
+    /// served with `into_make_service_with_connect_info::<SocketAddr>` for the proxy's address to
+    /// be known
+    pub fn with_require_tls(mut self, trusted_proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = Some(trusted_proxies);
+        self
+    }
+
+    /// let one registration carrying `token` create an account marked as admin
+    ///
+    /// only takes effect on a server without any users that was never bootstrapped before,
+    /// otherwise the token is ignored. Once the admin account exists bootstrapping is disabled
+    /// for good
+    pub async fn with_bootstrap_token(mut self, token: String) -> Result<Self, ServerError> {
+        let bootstrapped = self
+            .store
+            .open_tree(METADATA_TREE)?
+            .contains_key(self.tree_name(BOOTSTRAPPED_KEY))?;
+        if bootstrapped {
+            tracing::warn!("Server was already bootstrapped, ignoring the bootstrap token");
+        } else if self.users()?.count().await? > 0 {
+            tracing::warn!("Users already exist, ignoring the bootstrap token");
+        } else {
+            self.bootstrap = Some(Bootstrap::new(token));
+        }
+        Ok(self)
+    }
+
+    /// the clock used for everything time dependent
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    /// reclaim space in the store every `interval`
+    ///
+    /// spawns the maintenance task, so needs to be called from within a tokio runtime
+    pub fn with_scheduled_maintenance(self, interval: Duration) -> Self {
+        maintenance::schedule_maintenance(self.store.clone(), self.clock.clone(), interval);
+        self
+    }
+
+    /// pad every message to a fixed size and end every exchange with a close of the same size at
+    /// the same time, so a passive observer can't tell outcomes apart. Clients need to ask for
+    /// padding as well
+    pub fn with_padding(mut self, padding: bool) -> Self {
+        self.padding = padding;
+        self
     }
 }
 
-impl<'a> Server<'a> {
-    /// wrapper to send a `Close` message in case there is an error
-    async fn close(
-        mut ws: fastwebsockets::FragmentCollector<TokioIo<Upgraded>>,
-        err: &ServerError,
-    ) -> Result<(), WebSocketError> {
-        ws.write_frame(Frame::close(err.to_code(), err.to_string().as_bytes()))
-            .await?;
-        Ok(())
+impl<CS: Suite> Server<CS> {
+    /// report on how the server is set up, printed at startup and useful for debugging
+    pub async fn runtime_info(&self) -> Result<RuntimeInfo, ServerError> {
+        // destructured so any new setting has to be considered here
+        let Self {
+            server_setup: _,
+            store,
+            frame_timeout,
+            frame_arrival_limit,
+            flusher,
+            padding,
+            budgets,
+            key_policy,
+            tenant,
+            tree_prefix: _,
+            clock: _,
+            bootstrap,
+            trusted_proxies,
+            user_in_flight,
+            lockout,
+            disclose_lockouts,
+            shedder,
+            hooks: _,
+            events: _,
+            deletion_policy,
+            confirmation_policy,
+            user_store: _,
+            quarantine,
+            corrupt_records: _,
+            log_usernames,
+            #[cfg(feature = "test-util")]
+                crash_before_commit: _,
+            #[cfg(feature = "metrics")]
+                metrics: _,
+            exchanges: _,
+            shutdown_grace,
+            admin_token,
+            invite_only,
+            invite_ttl,
+            session_ttl,
+            max_blob_size,
+            identifiers,
+        } = self;
+        Ok(RuntimeInfo {
+            version: env!("CARGO_PKG_VERSION").into(),
+            frame_timeout: *frame_timeout,
+            frame_arrival_limit: *frame_arrival_limit,
+            write_coalescing: flusher.as_ref().map(WriteCoalescer::interval),
+            padding: *padding,
+            budgets: budgets
+                .usage()
+                .into_iter()
+                .map(|(operation, limit, _)| (operation, limit))
+                .collect(),
+            max_handshakes: budgets.handshakes().limit(),
+            max_username_len: key_policy.max_len,
+            ksf: CS::Ksf::default().params(),
+            tenant: tenant.clone(),
+            trees: store
+                .tree_names()
+                .iter()
+                .map(|name| String::from_utf8_lossy(name).into_owned())
+                .collect(),
+            user_count: self.users()?.count().await?,
+            bootstrap: bootstrap.is_some(),
+            require_tls: trusted_proxies.is_some(),
+            user_in_flight_limit: user_in_flight.as_ref().map(UserInFlight::limit),
+            lockout: *lockout,
+            disclose_lockouts: *disclose_lockouts,
+            load_shedding: shedder.as_ref().map(LoadShedder::policy),
+            features: self.features(),
+            deletion_policy: *deletion_policy,
+            confirmation_policy: *confirmation_policy,
+            quarantine: *quarantine,
+            log_usernames: *log_usernames,
+            shutdown_grace: *shutdown_grace,
+            admin_api: admin_token.is_some(),
+            invite_only: *invite_only,
+            invite_ttl: *invite_ttl,
+            session_ttl: *session_ttl,
+            max_blob_size: *max_blob_size,
+            identifiers: identifiers.clone(),
+        })
     }
 
-    /// handle a registration request
-    async fn registration(&self, fut: upgrade::UpgradeFut) -> Result<(), ServerError> {
-        let mut ws = fastwebsockets::FragmentCollector::new(fut.await?);
-        let state = RegWaiting::new(self.server_setup.clone());
-        let frame = ws.read_frame().await?;
-        match frame.opcode {
-            OpCode::Binary => {}
-            OpCode::Close => {
-                let err = ServerError::ClosedEarly;
-                return Err(err);
-            }
-            _ => {
-                let err = frame.into();
-                Self::close(ws, &err).await?;
-                return Err(err);
-            }
+    /// optional protocol behaviour this server supports, advertised on every connection it
+    /// accepts
+    ///
+    /// the match is exhaustive so a new [`Feature`] can't be added without deciding when it's
+    /// advertised
+    pub fn features(&self) -> Vec<Feature> {
+        Feature::ALL
+            .into_iter()
+            .filter(|feature| match feature {
+                Feature::Padding => self.padding,
+                Feature::VerifyOnly | Feature::ChangePassword => true,
+                Feature::Deletion => self.deletion_policy.self_service(),
+                Feature::Session | Feature::Blobs | Feature::Attributes => true,
+            })
+            .collect()
+    }
+
+    /// what clients are told about the server before they log in, see [`ServerInfo`]
+    pub fn server_info(&self) -> ServerInfo {
+        ServerInfo {
+            version: env!("CARGO_PKG_VERSION").into(),
+            features: self.features(),
+            server_time: self.clock.unix_secs(),
+            require_tls: self.trusted_proxies.is_some(),
         }
+    }
 
-        let data = frame.payload.to_vec();
-        let state = match state.step(data) {
-            Ok(res) => res,
-            Err(err) => {
-                Self::close(ws, &err).await?;
-                return Err(err);
-            }
-        };
-        let data = state.to_data();
-
-        ws.write_frame(Frame::new(true, OpCode::Binary, None, data.into()))
-            .await?;
-        let frame = ws.read_frame().await?;
-        match frame.opcode {
-            OpCode::Binary => {}
-            OpCode::Close => {
-                return Err(ServerError::ClosedEarly);
-            }
-            _ => {
-                let err = frame.into();
-                Self::close(ws, &err).await?;
-                return Err(err);
-            }
+    /// names of the trees holding this server's data, for backing up exactly what belongs to it
+    pub fn trees(&self) -> Vec<String> {
+        match &self.tree_prefix {
+            Some(_) => vec![
+                self.tree_name(USERS_TREE),
+                self.tree_name(ATTRIBUTES_TREE),
+                self.tree_name(FAILURES_TREE),
+                self.tree_name(REREGISTER_TREE),
+                self.tree_name(QUARANTINE_TREE),
+                self.tree_name(LOCKOUT_TREE),
+                self.tree_name(INVITES_TREE),
+                self.tree_name(SESSIONS_TREE),
+                self.tree_name(USER_SESSIONS_TREE),
+                self.tree_name(BLOBS_TREE),
+            ],
+            None => vec![
+                DEFAULT_TREE.into(),
+                ATTRIBUTES_TREE.into(),
+                FAILURES_TREE.into(),
+                REREGISTER_TREE.into(),
+                QUARANTINE_TREE.into(),
+                LOCKOUT_TREE.into(),
+                INVITES_TREE.into(),
+                SESSIONS_TREE.into(),
+                USER_SESSIONS_TREE.into(),
+                BLOBS_TREE.into(),
+            ],
         }
+    }
 
-        let data = frame.payload.to_vec();
-        let state = match state.step(data) {
-            Ok(res) => res,
-            Err(err) => {
-                Self::close(ws, &err).await?;
-                return Err(err);
-            }
+    /// size and contents of the underlying store
+    pub fn store_stats(&self) -> Result<StoreStats, ServerError> {
+        maintenance::store_stats(&self.store)
+    }
+
+    /// reclaim space in the store, runs on a blocking thread so it can happen alongside live
+    /// traffic
+    pub async fn maintain(&self) -> Result<MaintenanceReport, ServerError> {
+        maintenance::maintain(self.store.clone(), self.clock.clone()).await
+    }
+
+    /// the attributes stored for `username`, empty when none have been set
+    pub fn attributes(&self, username: &[u8]) -> Result<Attributes, ServerError> {
+        let key = self.storage_key(username)?;
+        let tree = self.store.open_tree(self.tree_name(ATTRIBUTES_TREE))?;
+        match tree.get(key)? {
+            Some(data) => Ok(bincode::deserialize(&data)?),
+            None => Ok(Attributes::new()),
+        }
+    }
+
+    /// replace all of the attributes stored for `username`, which must already be registered
+    pub async fn set_attributes(
+        &self,
+        username: &[u8],
+        attributes: &Attributes,
+    ) -> Result<(), ServerError> {
+        attributes.validate()?;
+        let key = self.storage_key(username)?;
+        if !self.users()?.contains(&key).await? {
+            return Err(ServerError::UserDoesNotExist);
+        }
+        let tree = self.store.open_tree(self.tree_name(ATTRIBUTES_TREE))?;
+        tree.insert(&key, bincode::serialize(attributes)?)?;
+        self.publish(ServerEvent::AttributesChanged { user: key });
+        Ok(())
+    }
+
+    /// everything stored about `username` except the password file, for handing over to the
+    /// account owner
+    pub async fn takeout(&self, username: &[u8]) -> Result<TakeoutDocument, ServerError> {
+        let key = self.storage_key(username)?;
+        let Some(stored) = self.users()?.get(&key).await? else {
+            return Err(ServerError::UserDoesNotExist);
         };
+        let record = record::unseal(&stored).map_err(ServerError::CorruptRecord)?;
+        let sessions = self
+            .sessions()?
+            .expiries_of(&key, self.clock.unix_secs())?
+            .into_iter()
+            .map(|expires_at| SessionSummary { expires_at })
+            .collect();
+        let blobs = self.store.open_tree(self.tree_name(BLOBS_TREE))?;
+        Ok(TakeoutDocument {
+            username: String::from_utf8_lossy(username).into_owned(),
+            created_at: record.created_at,
+            last_login: record.last_login,
+            attributes: self.attributes(username)?,
+            auth_failures: self.auth_failures(username)?,
+            locked_for: self.locked_for(&key)?,
+            must_reregister: self.must_reregister(username)?,
+            sessions,
+            blob: blobs.get(&key)?.map(|blob| blob.to_vec()),
+        })
+    }
 
-        let (username, password_serialized) = state.to_data();
-        let contains_key = match self.store.contains_key(username) {
-            Ok(res) => res,
-            Err(err) => {
-                let err = err.into();
-                Server::close(ws, &err).await?;
-                return Err(err);
-            }
+    /// [`Server::takeout`] asked for over http, which subscribers hear about
+    async fn export(&self, username: &[u8], admin: bool) -> Result<TakeoutDocument, ServerError> {
+        let document = self.takeout(username).await?;
+        let user = self.storage_key(username)?;
+        tracing::info!(user = %self.loggable(&user), admin, "Exported user data");
+        self.publish(ServerEvent::Exported { user, admin });
+        Ok(document)
+    }
+
+    /// remove `username` and everything stored about them
+    ///
+    /// meant for administrators working on the store directly, so it isn't subject to the
+    /// [`DeletionPolicy`]
+    pub async fn delete_user(&self, username: &[u8]) -> Result<(), ServerError> {
+        let key = self.storage_key(username)?;
+        if !self.users()?.contains(&key).await? {
+            return Err(ServerError::UserDoesNotExist);
+        }
+        self.remove_user(&key).await?;
+        self.publish(ServerEvent::Deleted {
+            user: key,
+            unconfirmed: false,
+        });
+        Ok(())
+    }
+
+    /// the user a session token handed out after a login belongs to, `None` once it's expired,
+    /// been revoked, or the user is gone. Deleting a user or changing their password revokes
+    /// every token they had
+    pub async fn validate_token(&self, token: &str) -> Result<Option<String>, ServerError> {
+        let sessions = self.sessions()?;
+        let Some(user) = sessions.lookup(token, self.clock.unix_secs())? else {
+            return Ok(None);
+        };
+        let Some(username) = storage_key::username_of(&user, self.tenant.as_deref()) else {
+            return Ok(None);
         };
-        if contains_key {
-            let err = ServerError::UserAlreadyExists;
-            Self::close(ws, &err).await?;
-            return Err(err);
+        let key = self.storage_key(username)?;
+        if !self.users()?.contains(&key).await? {
+            sessions.revoke(token)?;
+            return Ok(None);
         }
+        Ok(Some(String::from_utf8_lossy(username).into_owned()))
+    }
+
+    /// make a session token unusable, `false` when it wasn't valid to begin with
+    pub fn revoke_token(&self, token: &str) -> Result<bool, ServerError> {
+        self.sessions()?.revoke(token)
+    }
 
-        if let Err(err) = self.store.insert(username, password_serialized) {
-            let err = err.into();
-            Self::close(ws, &err).await?;
-            return Err(err);
+    /// a new single use invite for [`Server::with_invite_only`]
+    pub fn create_invite(&self) -> Result<String, ServerError> {
+        let invite = invites::new_invite();
+        let tree = self.store.open_tree(self.tree_name(INVITES_TREE))?;
+        tree.insert(invite.as_bytes(), &self.clock.unix_secs().to_be_bytes()[..])?;
+        tracing::info!("Created an invite");
+        Ok(invite)
+    }
+
+    /// how many users are registered, only counting the server's tenant when it has one
+    pub async fn user_count(&self) -> Result<usize, ServerError> {
+        match self.tenant {
+            Some(_) => Ok(self.usernames().await?.len()),
+            None => Ok(self.users()?.count().await?),
         }
+    }
 
-        // let client know registration is complete
-        ws.write_frame(Frame::close(1000, vec![1].as_slice()))
-            .await?;
+    /// usernames of registered users in byte order, skipping the first `offset` and handing back
+    /// at most `limit`
+    ///
+    /// usernames that aren't utf-8 have the offending bytes replaced
+    pub async fn list_users(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<String>, ServerError> {
+        Ok(self
+            .usernames()
+            .await?
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|username| String::from_utf8_lossy(&username).into_owned())
+            .collect())
+    }
+
+    /// every registered username belonging to the server's tenant
+    async fn usernames(&self) -> Result<Vec<Vec<u8>>, ServerError> {
+        let tenant = self.tenant.as_deref();
+        Ok(self
+            .users()?
+            .keys()
+            .await?
+            .into_iter()
+            .filter_map(|key| storage_key::username_of(&key, tenant).map(<[u8]>::to_vec))
+            .collect())
+    }
 
+    /// require `username` to register a new password, e.g. after their credentials may have
+    /// leaked. Logins with the old password still prove who they are but don't succeed
+    pub async fn set_must_reregister(
+        &self,
+        username: &[u8],
+        required: bool,
+    ) -> Result<(), ServerError> {
+        let key = self.storage_key(username)?;
+        if !self.users()?.contains(&key).await? {
+            return Err(ServerError::UserDoesNotExist);
+        }
+        let tree = self.store.open_tree(self.tree_name(REREGISTER_TREE))?;
+        if required {
+            tree.insert(&key, b"".as_slice())?;
+        } else {
+            tree.remove(&key)?;
+        }
+        self.publish(ServerEvent::ReRegistrationFlagged {
+            user: key,
+            required,
+        });
         Ok(())
     }
 
-    /// handle an authentication request
-    async fn authenticate(&self, fut: upgrade::UpgradeFut) -> Result<AuthConfirm, ServerError> {
-        let mut ws = fastwebsockets::FragmentCollector::new(fut.await?);
-        let state = AuthWaiting::new(self.server_setup.clone());
-        let frame = ws.read_frame().await?;
-        let data = frame.payload.to_vec();
-        let state = match state.step(data) {
-            Ok(res) => res,
-            Err(err) => {
-                Self::close(ws, &err).await?;
-                return Err(err);
-            }
+    /// whether `username` has to register a new password before logging in
+    pub fn must_reregister(&self, username: &[u8]) -> Result<bool, ServerError> {
+        let key = self.storage_key(username)?;
+        let tree = self.store.open_tree(self.tree_name(REREGISTER_TREE))?;
+        Ok(tree.contains_key(key)?)
+    }
+
+    /// why the most recent failed authentications of `username` failed, oldest first
+    ///
+    /// this is never sent over the unauthenticated protocol, it's meant for the account owner
+    /// once they've logged in and for administrators
+    pub fn auth_failures(&self, username: &[u8]) -> Result<Vec<AuthFailure>, ServerError> {
+        let key = self.storage_key(username)?;
+        let tree = self.store.open_tree(self.tree_name(FAILURES_TREE))?;
+        failures::load(&tree, &key)
+    }
+}
+
+impl<CS: Suite> Server<CS> {
+    /// every route clients run their exchanges against, with the server as their state, ready to
+    /// be served or merged into a bigger app
+    pub fn router(&self) -> Router {
+        let router = Router::new()
+            .route("/registration", get(ws_registration::<CS>))
+            .route("/authenticate", get(ws_authenticate::<CS>))
+            .route("/change_password", get(ws_change_password::<CS>))
+            .route("/delete", get(ws_delete::<CS>))
+            .route(&format!("/{SESSION_PATH}"), get(ws_session::<CS>))
+            .route(&format!("/{STORE_PATH}"), get(ws_store::<CS>))
+            .route(&format!("/{RETRIEVE_PATH}"), get(ws_retrieve::<CS>))
+            .route(&format!("/{INFO_PATH}"), get(info::<CS>))
+            .route("/logout", post(logout::<CS>))
+            .route(
+                &format!("/{ATTRIBUTES_PATH}"),
+                get(get_attributes::<CS>).put(put_attributes::<CS>),
+            )
+            .route(&format!("/{TAKEOUT_PATH}"), get(takeout::<CS>));
+        #[cfg(feature = "metrics")]
+        let router = router.route("/metrics", get(metrics::<CS>));
+        let router = match self.admin_token {
+            Some(_) => router.nest("/admin", self.user_admin_routes()),
+            None => router,
         };
+        router.with_state(self.clone())
+    }
+
+    /// routes for managing users, behind the admin token
+    fn user_admin_routes(&self) -> Router<Self> {
+        Router::new()
+            .route("/users", get(admin::list_users::<CS>))
+            .route("/users/:username", delete(admin::delete_user::<CS>))
+            .route(
+                "/users/:username/attributes",
+                get(admin::user_attributes::<CS>).put(admin::set_user_attributes::<CS>),
+            )
+            .route("/users/:username/takeout", get(admin::user_takeout::<CS>))
+            .route("/invites", post(admin::create_invite::<CS>))
+            .route_layer(middleware::from_fn_with_state(
+                self.clone(),
+                admin::require_token::<CS>,
+            ))
+    }
+
+    /// [`Server::router`] mounted under `prefix`, e.g. `"/auth"`
+    pub fn router_with_prefix(&self, prefix: &str) -> Router {
+        Router::new().nest(prefix, self.router())
+    }
+
+    /// [`Server::router`] with `layer` wrapped around every route, e.g. a
+    /// [`ServiceBuilder`](tower::ServiceBuilder) stack of middleware
+    pub fn router_with_layer<L>(&self, layer: L) -> Router
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.router().layer(layer)
+    }
+
+    /// routes for operators, kept apart from the public ones so they can be served somewhere
+    /// only reachable from inside the deployment
+    pub fn admin_router(&self) -> Router {
+        Router::new()
+            .route("/runtime", get(runtime::<CS>))
+            .route("/store", get(store_stats::<CS>))
+            .route("/maintenance", post(maintain::<CS>))
+            .with_state(self.clone())
+    }
 
-        let password_file_bytes = match self.store.get(state.username()) {
-            Ok(res) => {
-                if let Some(res) = res {
-                    res
-                } else {
-                    let err = ServerError::UserDoesNotExist;
-                    Self::close(ws, &err).await?;
-                    return Err(err);
+    /// serve on every listener until `shutdown` completes, or until any one of them fails in
+    /// which case the rest are stopped too
+    ///
+    /// every address is bound before anything is served, so a bad address fails straight away
+    /// with an error naming it
+    pub async fn serve_all(
+        self,
+        listeners: &[ListenerConfig],
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ServerError> {
+        let mut bound = Vec::with_capacity(listeners.len());
+        for listener in listeners {
+            let socket = match &listener.addr {
+                ListenAddr::Tcp(addr) => TcpListener::bind(addr).await.map(Bound::Tcp),
+                #[cfg(unix)]
+                ListenAddr::Unix(path) => UnixListener::bind(path).map(Bound::Unix),
+            };
+            let socket = socket.map_err(|err| ServerError::Bind(listener.addr.clone(), err))?;
+            #[cfg(feature = "server-tls")]
+            let socket = match (socket, &listener.tls) {
+                (Bound::Tcp(tcp), Some(tls)) => {
+                    tracing::info!(role = %listener.role, tls = true, "Listening on {}", listener.addr);
+                    bound.push((Bound::Tls(tcp, tls.load()?), listener));
+                    continue;
                 }
-            }
-            Err(err) => {
-                let err = err.into();
-                Self::close(ws, &err).await?;
-                return Err(err);
-            }
-        };
+                (socket, _) => socket,
+            };
+            tracing::info!(role = %listener.role, "Listening on {}", listener.addr);
+            bound.push((socket, listener));
+        }
 
-        let state = match state.step(password_file_bytes.to_vec()) {
-            Ok(res) => res,
-            Err(err) => {
-                Self::close(ws, &err).await?;
-                return Err(err);
-            }
-        };
+        let (stop, stopped) = watch::channel(false);
+        let mut tasks = JoinSet::new();
+        for (socket, listener) in bound {
+            let router = match listener.role {
+                ListenerRole::Public => self.router(),
+                ListenerRole::Admin => self.admin_router(),
+            };
+            let mut stopped = stopped.clone();
+            let stopping = async move {
+                let _ = stopped.wait_for(|stop| *stop).await;
+            };
+            match socket {
+                Bound::Tcp(tcp) => tasks.spawn(async move {
+                    axum::serve(
+                        tcp,
+                        router.into_make_service_with_connect_info::<SocketAddr>(),
+                    )
+                    .with_graceful_shutdown(stopping)
+                    .await
+                }),
+                #[cfg(feature = "server-tls")]
+                Bound::Tls(tcp, config) => {
+                    tasks.spawn(tls::serve_tls(tcp, config, router, stopping))
+                }
+                #[cfg(unix)]
+                Bound::Unix(unix) => tasks.spawn(listeners::serve_unix(
+                    unix,
+                    router,
+                    listener.allowed_uids.clone(),
+                    stopping,
+                )),
+            };
+        }
 
-        let data = state.to_data();
-        ws.write_frame(Frame::new(true, OpCode::Binary, None, data.into()))
-            .await?;
-        let frame = ws.read_frame().await?;
-        match frame.opcode {
-            OpCode::Binary => {}
-            OpCode::Close => {
-                return Err(ServerError::ClosedEarly);
-            }
-            _ => {
-                let err = frame.into();
-                Self::close(ws, &err).await?;
-                return Err(err);
+        let mut shutdown = std::pin::pin!(shutdown);
+        let mut stopping = false;
+        let mut result = Ok(());
+        loop {
+            tokio::select! {
+                () = &mut shutdown, if !stopping => {
+                    stopping = true;
+                    let _ = stop.send(true);
+                }
+                joined = tasks.join_next() => match joined {
+                    None => break,
+                    Some(Ok(Ok(()))) => {}
+                    Some(Ok(Err(err))) => {
+                        result = Err(err.into());
+                        stopping = true;
+                        let _ = stop.send(true);
+                    }
+                    Some(Err(err)) => {
+                        tracing::error!("Listener stopped unexpectedly: `{err}`");
+                        stopping = true;
+                        let _ = stop.send(true);
+                    }
+                },
             }
         }
+        let shutdown = self.shutdown().await;
+        result.and(shutdown)
+    }
+}
 
-        let data = frame.payload.to_vec();
-        let state = match state.step(data) {
-            Ok(res) => res,
-            Err(err) => {
-                Self::close(ws, &err).await?;
-                return Err(err);
-            }
-        };
-        let data = state.to_data();
-
-        ws.write_frame(Frame::new(true, OpCode::Binary, None, data.into()))
-            .await?;
-        let frame = ws.read_frame().await?;
-        match frame.opcode {
-            OpCode::Binary => {}
-            OpCode::Close => {
-                return Err(ServerError::ClosedEarly);
-            }
-            _ => {
-                let err = frame.into();
-                Self::close(ws, &err).await?;
-                return Err(err);
-            }
+impl<CS: Suite> Server<CS> {
+    /// wait for the exchanges that are running to finish, for up to the grace period, then flush
+    /// the database so nothing that was written is lost
+    ///
+    /// stop accepting connections before calling this, [`Server::serve_all`] calls it once its
+    /// listeners have stopped
+    pub async fn shutdown(&self) -> Result<(), ServerError> {
+        self.exchanges.close();
+        if timeout(self.shutdown_grace, self.exchanges.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                running = self.exchanges.len(),
+                "Grace period ran out, abandoning the exchanges still running"
+            );
         }
+        self.store.flush_async().await?;
+        tracing::info!("Flushed the database");
+        Ok(())
+    }
 
-        let data = frame.payload.to_vec();
-        let state = state.step(data);
+    /// name of one of the server's trees, scoped by the tree prefix when there is one
+    fn tree_name(&self, tree: &str) -> String {
+        match &self.tree_prefix {
+            Some(prefix) => format!("{prefix}_{tree}"),
+            None => tree.into(),
+        }
+    }
 
-        ws.write_frame(Frame::close(1000, b"done".as_slice()))
-            .await?;
+    /// where the password files are kept
+    fn users(&self) -> Result<Arc<dyn UserStore>, ServerError> {
+        match &self.user_store {
+            Some(store) => Ok(store.clone()),
+            None => Ok(Arc::new(SledStore::new(self.users_tree()?))),
+        }
+    }
 
-        Ok(state)
+    /// the session tokens handed out after logins
+    fn sessions(&self) -> Result<Sessions, ServerError> {
+        Ok(Sessions::new(
+            self.store.open_tree(self.tree_name(SESSIONS_TREE))?,
+            self.store.open_tree(self.tree_name(USER_SESSIONS_TREE))?,
+        ))
     }
-}
 
-/// hook for calling the registration endpoint
-pub async fn ws_registration(
-    ws: upgrade::IncomingUpgrade,
-    State(state): State<Server<'static>>,
-) -> impl IntoResponse {
-    let (response, fut) = ws.upgrade().unwrap();
-    tokio::task::spawn(async move {
-        if let Err(e) = state.registration(fut).await {
-            eprintln!("Error in websocket connection: `{e}`");
+    /// sled tree holding the password files, unless they're kept in a separate [`UserStore`]
+    fn users_tree(&self) -> Result<sled::Tree, ServerError> {
+        match &self.tree_prefix {
+            Some(_) => Ok(self.store.open_tree(self.tree_name(USERS_TREE))?),
+            None => Ok((*self.store).clone()),
         }
-    });
+    }
 
-    response
-}
+    /// run a read against the store, trying again a few times when it fails transiently
+    ///
+    /// only meant for reads, anything with side effects could end up applied more than once
+    async fn read_with_retry<T, F>(&self, read: impl Fn() -> F) -> Result<T, ServerError>
+    where
+        F: Future<Output = Result<T, ServerError>>,
+    {
+        let mut backoff = READ_BACKOFF;
+        for _ in 1..READ_ATTEMPTS {
+            match read().await {
+                Err(err) if err.is_transient() => {
+                    tracing::warn!("Transient error reading from the store, trying again: `{err}`");
+                    let jitter = rand::thread_rng().gen_range(0..=backoff.as_millis() as u64);
+                    tokio::time::sleep(backoff + Duration::from_millis(jitter)).await;
+                    backoff *= 2;
+                }
+                res => return res,
+            }
+        }
+        read().await
+    }
 
-/// hook for calling the authentication endpoint
-pub async fn ws_authenticate(
-    ws: upgrade::IncomingUpgrade,
-    State(state): State<Server<'static>>,
-) -> impl IntoResponse {
-    let (response, fut) = ws.upgrade().unwrap();
-    tokio::task::spawn(async move {
-        if let Err(e) = state.authenticate(fut).await {
-            eprintln!("Error in websocket connection: `{e}`");
+    /// the key `username`'s data is stored under
+    fn storage_key(&self, username: &[u8]) -> Result<StorageKey, ServerError> {
+        Ok(StorageKey::for_user(
+            &self.key_policy,
+            self.tenant.as_deref(),
+            username,
+        )?)
+    }
+
+    /// `key` as it should appear in logs, redacted unless usernames are logged
+    fn loggable(&self, key: &StorageKey) -> Cow<'_, str> {
+        if self.log_usernames {
+            Cow::Owned(String::from_utf8_lossy(key.as_bytes()).into_owned())
+        } else {
+            Cow::Borrowed(REDACTED)
         }
-    });
+    }
 
-    response
+    /// note on the current operation's span who it's for
+    fn record_user(&self, key: &StorageKey) {
+        Span::current().record("user", self.loggable(key).as_ref());
+    }
+
+    /// tell subscribers about `event`
+    fn publish(&self, event: ServerEvent) {
+        // only fails when nobody is subscribed
+        let _ = self.events.send(event);
+    }
+
+    /// tell subscribers and hooks about `event`
+    async fn emit(&self, event: ServerEvent) {
+        self.publish(event.clone());
+        if let Some(hooks) = &self.hooks {
+            hooks.enqueue(event).await;
+        }
+    }
 }
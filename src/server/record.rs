@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{Scheme, SuiteId};
+
+/// marks a record wrapped in the checked envelope
+const MAGIC: &[u8; 4] = b"TREC";
+/// envelope whose payload is a [`UserRecord`]
+const VERSION: u8 = 2;
+/// envelope whose payload is the bare password file
+const VERSION_PASSWORD_FILE: u8 = 1;
+/// bytes of the digest kept as the checksum, plenty for catching corruption
+const CHECKSUM_LEN: usize = 8;
+/// magic, version, and checksum
+const HEADER_LEN: usize = MAGIC.len() + 1 + CHECKSUM_LEN;
+
+#[derive(Debug, Error)]
+pub enum RecordError {
+    #[error("Record is truncated")]
+    Truncated,
+    #[error("Record does not match its checksum")]
+    Checksum,
+    #[error("Record has unsupported format version `{0}`")]
+    UnsupportedVersion(u8),
+    #[error("Record could not be decoded `{0}`")]
+    Malformed(bincode::Error),
+    #[error("Record was made with the `{0}` cipher suite, the server uses `{1}`")]
+    WrongSuite(SuiteId, SuiteId),
+}
+
+/// Everything stored about a user's password
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserRecord {
+    /// layout of the record, [`UserRecord::LEGACY`] for one read from a bare password file
+    pub version: u8,
+    /// the serialized `ServerRegistration`
+    pub password_file: Vec<u8>,
+    /// seconds since the unix epoch, 0 when the record predates this being kept
+    pub created_at: u64,
+    /// seconds since the unix epoch of the last successful login
+    pub last_login: Option<u64>,
+    /// the cipher suite that produced the password file
+    pub suite: SuiteId,
+}
+
+/// [`UserRecord`] as it was laid out before the suite was kept, always [`Scheme`]
+#[derive(Deserialize)]
+struct UserRecordV1 {
+    version: u8,
+    password_file: Vec<u8>,
+    created_at: u64,
+    last_login: Option<u64>,
+}
+
+impl From<UserRecordV1> for UserRecord {
+    fn from(value: UserRecordV1) -> Self {
+        Self {
+            version: value.version,
+            password_file: value.password_file,
+            created_at: value.created_at,
+            last_login: value.last_login,
+            suite: Scheme::ID,
+        }
+    }
+}
+
+impl UserRecord {
+    /// the layout written now
+    pub const CURRENT: u8 = 2;
+    /// the layout from before the suite was kept
+    const WITHOUT_SUITE: u8 = 1;
+    /// records stored as just the password file, with none of the metadata
+    pub const LEGACY: u8 = 0;
+
+    pub fn new(password_file: Vec<u8>, created_at: u64) -> Self {
+        Self {
+            version: Self::CURRENT,
+            password_file,
+            created_at,
+            last_login: None,
+            suite: Scheme::ID,
+        }
+    }
+
+    /// the record of a password file made with `suite` rather than [`Scheme`]
+    pub fn with_suite(mut self, suite: SuiteId) -> Self {
+        self.suite = suite;
+        self
+    }
+
+    fn legacy(password_file: &[u8]) -> Self {
+        Self {
+            version: Self::LEGACY,
+            password_file: password_file.to_vec(),
+            created_at: 0,
+            last_login: None,
+            suite: Scheme::ID,
+        }
+    }
+
+    /// the password file, as long as it was made with [`Scheme`]
+    pub fn checked_password_file(self) -> Result<Vec<u8>, RecordError> {
+        self.password_file_for(Scheme::ID)
+    }
+
+    /// the password file, as long as it was made with `suite`, the one the server runs
+    pub fn password_file_for(self, suite: SuiteId) -> Result<Vec<u8>, RecordError> {
+        if self.suite != suite {
+            return Err(RecordError::WrongSuite(self.suite, suite));
+        }
+        Ok(self.password_file)
+    }
+}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let digest = Sha256::digest(payload);
+    let mut checksum = [0; CHECKSUM_LEN];
+    checksum.copy_from_slice(&digest[..CHECKSUM_LEN]);
+    checksum
+}
+
+/// wrap a user's record in the envelope it's stored in, so corruption in the store is caught
+/// when the record is read instead of surfacing as a failed login
+pub fn seal(record: &UserRecord) -> Vec<u8> {
+    // a legacy record that's stored again is stored in the current layout
+    let payload = bincode::serialize(&UserRecord {
+        version: UserRecord::CURRENT,
+        ..record.clone()
+    })
+    .expect("serializing to memory can't fail");
+    let mut sealed = Vec::with_capacity(HEADER_LEN + payload.len());
+    sealed.extend_from_slice(MAGIC);
+    sealed.push(VERSION);
+    sealed.extend_from_slice(&checksum(&payload));
+    sealed.extend_from_slice(&payload);
+    sealed
+}
+
+/// the user's record in a stored one, checking it against its checksum
+///
+/// records stored as a bare password file, with or without the envelope, come back as a
+/// [`UserRecord::LEGACY`] record and are rewritten in the current layout the next time they're
+/// stored
+pub fn unseal(record: &[u8]) -> Result<UserRecord, RecordError> {
+    let Some(rest) = record.strip_prefix(MAGIC) else {
+        return Ok(UserRecord::legacy(record));
+    };
+    let Some((&version, rest)) = rest.split_first() else {
+        return Err(RecordError::Truncated);
+    };
+    if version != VERSION && version != VERSION_PASSWORD_FILE {
+        return Err(RecordError::UnsupportedVersion(version));
+    }
+    if rest.len() < CHECKSUM_LEN {
+        return Err(RecordError::Truncated);
+    }
+    let (stored, payload) = rest.split_at(CHECKSUM_LEN);
+    if checksum(payload) != stored {
+        return Err(RecordError::Checksum);
+    }
+    if version == VERSION_PASSWORD_FILE {
+        return Ok(UserRecord::legacy(payload));
+    }
+    // the record's own version is its first byte
+    match payload.first() {
+        Some(&UserRecord::CURRENT) => bincode::deserialize(payload).map_err(RecordError::Malformed),
+        Some(&UserRecord::WITHOUT_SUITE) => bincode::deserialize::<UserRecordV1>(payload)
+            .map(UserRecord::from)
+            .map_err(RecordError::Malformed),
+        Some(&version) => Err(RecordError::UnsupportedVersion(version)),
+        None => Err(RecordError::Truncated),
+    }
+}
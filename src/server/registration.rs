@@ -1,77 +1,99 @@
-use opaque_ke::{
-    RegistrationRequest, RegistrationUpload, ServerRegistration, ServerRegistrationStartResult,
-    ServerSetup,
-};
+use std::marker::PhantomData;
+
+use opaque_ke::ServerSetup;
 
-use crate::{Scheme, WithUsername};
+use crate::{
+    suite::Suite,
+    username::{self, DEFAULT_MAX_USERNAME_LEN},
+    wire, Scheme, WithUsername,
+};
 
 use super::error::ServerError;
 
 /// initial waiting state, given the first message from the client can move to the next state
 /// [`RegInitial`]
-pub struct RegWaiting<'a> {
-    server_setup: ServerSetup<Scheme<'a>>,
+pub struct RegWaiting<CS: Suite = Scheme> {
+    server_setup: CS::Setup,
+    max_username_len: usize,
 }
 
-impl<'a> RegWaiting<'a> {
-    pub fn step(self, initial_data: Vec<u8>) -> Result<RegInitial<'a>, ServerError> {
-        let data: WithUsername = bincode::deserialize(&initial_data)?;
-        let username = data.username;
-        let registration_request_bytes = data.data;
-        let registration_request = RegistrationRequest::deserialize(registration_request_bytes)?;
-        let server_registration_start_result = ServerRegistration::<Scheme>::start(
-            &self.server_setup,
-            registration_request,
-            username,
-        )?;
+impl RegWaiting {
+    pub fn new(server_setup: ServerSetup<Scheme>) -> Self {
+        Self::for_suite(server_setup)
+    }
+}
 
-        Ok(RegInitial::new(
-            username.into(),
-            server_registration_start_result,
-        ))
+impl<CS: Suite> RegWaiting<CS> {
+    /// the username is normalized the same way the client did it, see [`username::normalize`]
+    pub fn step(self, initial_data: Vec<u8>) -> Result<RegInitial<CS>, ServerError> {
+        let data: WithUsername = wire::decode(&initial_data)?;
+        let username = username::normalize_bytes(data.username, self.max_username_len)?;
+        let registration_response =
+            CS::server_registration_start(&self.server_setup, data.data, username.as_bytes())?;
+
+        Ok(
+            RegInitial::new(username.into_string().into_bytes(), registration_response)
+                .with_invite(data.invite.map(Vec::from)),
+        )
+    }
+
+    /// [`RegWaiting::new`] for servers running `CS`
+    pub fn for_suite(server_setup: CS::Setup) -> Self {
+        Self {
+            server_setup,
+            max_username_len: DEFAULT_MAX_USERNAME_LEN,
+        }
     }
 
-    pub fn new(server_setup: ServerSetup<Scheme<'a>>) -> Self {
-        Self { server_setup }
+    pub fn with_max_username_len(mut self, max_username_len: usize) -> Self {
+        self.max_username_len = max_username_len;
+        self
     }
 }
 
 /// the second state after receiving the first message, with the next message data moves to
 /// [`RegUpload`]
 /// Arguably poorly named
-pub struct RegInitial<'a> {
+pub struct RegInitial<CS: Suite = Scheme> {
     username: Vec<u8>,
-    server_registration_start_result: ServerRegistrationStartResult<Scheme<'a>>,
+    /// the serialized `RegistrationResponse`
+    registration_response: Vec<u8>,
+    invite: Option<Vec<u8>>,
+    suite: PhantomData<CS>,
 }
 
-impl<'a> RegInitial<'a> {
-    pub fn new(
-        username: Vec<u8>,
-        server_registration_start_result: ServerRegistrationStartResult<Scheme<'a>>,
-    ) -> Self {
+impl<CS: Suite> RegInitial<CS> {
+    pub fn new(username: Vec<u8>, registration_response: Vec<u8>) -> Self {
         Self {
             username,
-            server_registration_start_result,
+            registration_response,
+            invite: None,
+            suite: PhantomData,
         }
     }
 
+    pub fn with_invite(mut self, invite: Option<Vec<u8>>) -> Self {
+        self.invite = invite;
+        self
+    }
+
+    pub fn username(&self) -> &[u8] {
+        &self.username
+    }
+
+    /// invite the client registered with
+    pub fn invite(&self) -> Option<&[u8]> {
+        self.invite.as_deref()
+    }
+
     pub fn to_data(&self) -> Vec<u8> {
-        self.server_registration_start_result
-            .message
-            .serialize()
-            .as_slice()
-            .into()
+        self.registration_response.clone()
     }
 
     pub fn step(self, message_bytes: Vec<u8>) -> Result<RegUpload, ServerError> {
-        let registration_upload = RegistrationUpload::<Scheme>::deserialize(&message_bytes)?;
-        let password_file = ServerRegistration::finish(registration_upload);
-        let password_serialized = password_file.serialize();
-
-        Ok(RegUpload::new(
-            self.username,
-            password_serialized.as_slice().into(),
-        ))
+        let password_serialized = CS::server_registration_finish(&message_bytes)?;
+
+        Ok(RegUpload::new(self.username, password_serialized).with_invite(self.invite))
     }
 }
 
@@ -80,6 +102,7 @@ impl<'a> RegInitial<'a> {
 pub struct RegUpload {
     username: Vec<u8>,
     password_serialized: Vec<u8>,
+    invite: Option<Vec<u8>>,
 }
 
 impl RegUpload {
@@ -87,9 +110,20 @@ impl RegUpload {
         Self {
             username,
             password_serialized,
+            invite: None,
         }
     }
 
+    pub fn with_invite(mut self, invite: Option<Vec<u8>>) -> Self {
+        self.invite = invite;
+        self
+    }
+
+    /// invite the client registered with
+    pub fn invite(&self) -> Option<&[u8]> {
+        self.invite.as_deref()
+    }
+
     pub fn to_data(&self) -> (&[u8], &[u8]) {
         (&self.username, &self.password_serialized)
     }
@@ -0,0 +1,143 @@
+use std::{fmt::Display, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    concurrency::Operation, confirmation::ConfirmationPolicy, deletion::DeletionPolicy,
+    lockout::LockoutPolicy, shedding::LoadShedding,
+};
+use crate::{ksf::KsfParams, wire::Feature, Identifiers};
+
+/// Snapshot of how a running server is configured, for telling deployments apart when
+/// debugging them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuntimeInfo {
+    pub version: String,
+    pub frame_timeout: Duration,
+    /// how long a frame may keep arriving once its first bytes are in
+    pub frame_arrival_limit: Duration,
+    pub write_coalescing: Option<Duration>,
+    pub padding: bool,
+    pub budgets: Vec<(Operation, usize)>,
+    /// how many exchanges of any kind can run at once
+    pub max_handshakes: usize,
+    pub max_username_len: usize,
+    /// key stretching of the server's suite, at the costs clients run it with unless told
+    /// otherwise
+    pub ksf: KsfParams,
+    pub tenant: Option<String>,
+    pub trees: Vec<String>,
+    pub user_count: usize,
+    /// whether a bootstrap token is waiting to be used
+    pub bootstrap: bool,
+    /// whether connections have to arrive over TLS
+    pub require_tls: bool,
+    /// how many authentications a single user can have running at once
+    pub user_in_flight_limit: Option<usize>,
+    pub lockout: Option<LockoutPolicy>,
+    /// whether clients are told their account is locked
+    pub disclose_lockouts: bool,
+    pub load_shedding: Option<LoadShedding>,
+    /// optional protocol behaviour advertised to clients
+    pub features: Vec<Feature>,
+    pub deletion_policy: DeletionPolicy,
+    pub confirmation_policy: ConfirmationPolicy,
+    /// whether records that fail their checksum are moved out of the way
+    pub quarantine: bool,
+    /// whether usernames show up in logs
+    pub log_usernames: bool,
+    /// how long running exchanges get to finish on shutdown
+    pub shutdown_grace: Duration,
+    /// whether the `/admin` routes are served
+    pub admin_api: bool,
+    /// whether registering takes an invite
+    pub invite_only: bool,
+    /// how long invites can be used for
+    pub invite_ttl: Duration,
+    /// how long session tokens last
+    pub session_ttl: Duration,
+    /// largest blob a user can store, in bytes
+    pub max_blob_size: usize,
+    /// identities logins are bound to
+    pub identifiers: Identifiers,
+}
+
+impl Display for RuntimeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "tinap server {}", self.version)?;
+        writeln!(f, "  frame timeout: {:?}", self.frame_timeout)?;
+        writeln!(f, "  frame arrival limit: {:?}", self.frame_arrival_limit)?;
+        writeln!(f, "  shutdown grace: {:?}", self.shutdown_grace)?;
+        writeln!(f, "  session tokens last: {:?}", self.session_ttl)?;
+        writeln!(f, "  max blob size: {} bytes", self.max_blob_size)?;
+        match self.write_coalescing {
+            Some(interval) => writeln!(f, "  write coalescing: every {interval:?}")?,
+            None => writeln!(f, "  write coalescing: off")?,
+        }
+        writeln!(f, "  padding: {}", if self.padding { "on" } else { "off" })?;
+        writeln!(
+            f,
+            "  require tls: {}",
+            if self.require_tls { "yes" } else { "no" }
+        )?;
+        for (operation, limit) in &self.budgets {
+            writeln!(f, "  {operation} budget: {limit}")?;
+        }
+        writeln!(f, "  handshake budget: {}", self.max_handshakes)?;
+        if let Some(shedding) = &self.load_shedding {
+            writeln!(
+                f,
+                "  load shedding: above {} exchanges or {:?} latency, until {} exchanges",
+                shedding.high_water, shedding.latency_threshold, shedding.low_water
+            )?;
+        }
+        let features = self
+            .features
+            .iter()
+            .map(|feature| feature.name())
+            .collect::<Vec<_>>();
+        writeln!(f, "  features: {}", features.join(", "))?;
+        writeln!(f, "  account deletion: {}", self.deletion_policy)?;
+        writeln!(f, "  login confirmation: {}", self.confirmation_policy)?;
+        writeln!(
+            f,
+            "  quarantine corrupt records: {}",
+            if self.quarantine { "yes" } else { "no" }
+        )?;
+        writeln!(
+            f,
+            "  log usernames: {}",
+            if self.log_usernames { "yes" } else { "no" }
+        )?;
+        writeln!(f, "  max username length: {}", self.max_username_len)?;
+        if let Some(limit) = self.user_in_flight_limit {
+            writeln!(f, "  authentications per user: {limit}")?;
+        }
+        if let Some(lockout) = &self.lockout {
+            writeln!(
+                f,
+                "  lockout: after {} failed logins, for {:?} doubling up to {:?}",
+                lockout.threshold, lockout.window, lockout.max_window
+            )?;
+            if self.disclose_lockouts {
+                writeln!(f, "  lockouts are disclosed to clients")?;
+            }
+        }
+        writeln!(f, "  {}", self.ksf)?;
+        if let Some(tenant) = &self.tenant {
+            writeln!(f, "  tenant: {tenant}")?;
+        }
+        writeln!(f, "  trees: {}", self.trees.join(", "))?;
+        if self.invite_only {
+            writeln!(f, "  registration: invite only")?;
+        }
+        writeln!(f, "  invites last: {:?}", self.invite_ttl)?;
+        if self.admin_api {
+            writeln!(f, "  admin api: on")?;
+        }
+        if self.bootstrap {
+            writeln!(f, "  bootstrap: waiting for the admin registration")?;
+        }
+        write!(f, "  users: {}", self.user_count)
+    }
+}
@@ -0,0 +1,297 @@
+use std::{
+    fs::{rename, File},
+    io::{ErrorKind, Write},
+    path::Path,
+};
+
+use hmac::{Hmac, Mac};
+use opaque_ke::ServerSetup;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::error::ServerError;
+use crate::Scheme;
+
+/// environment variable holding a machine local secret, when set the setup file is protected by
+/// a MAC under it rather than a plain checksum
+pub const SETUP_KEY_ENV: &str = "TINAP_SETUP_KEY";
+
+const MAGIC: &[u8; 8] = b"TINAPSET";
+const VERSION: u16 = 1;
+/// magic, version, flags, and payload length
+const HEADER_LEN: usize = MAGIC.len() + 2 + 1 + 4;
+const TAG_LEN: usize = 32;
+const FLAG_KEYED: u8 = 1;
+
+#[derive(Debug, Error)]
+pub enum SetupError {
+    #[error("Server setup file is truncated")]
+    Truncated,
+    #[error("Server setup file is corrupted")]
+    Corrupted,
+    #[error("Server setup file has been tampered with")]
+    Tampered,
+    #[error("Server setup file is protected by a key, set `{SETUP_KEY_ENV}` to read it")]
+    MissingKey,
+    #[error("Server setup file has unsupported format version `{0}`")]
+    UnsupportedVersion(u16),
+    #[error("Server setup file is in the old unchecked format, run `tinap-admin migrate-setup`")]
+    Legacy,
+    #[error(
+        "Server setup file is not protected by the key in `{SETUP_KEY_ENV}`, run \
+         `tinap-admin migrate-setup`"
+    )]
+    Unkeyed,
+}
+
+/// the machine local secret, if there is one
+fn setup_key() -> Option<Vec<u8>> {
+    std::env::var_os(SETUP_KEY_ENV).map(|key| key.into_encoded_bytes())
+}
+
+fn tag(key: Option<&[u8]>, data: &[u8]) -> [u8; TAG_LEN] {
+    match key {
+        Some(key) => {
+            let mut mac =
+                Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+            mac.update(data);
+            mac.finalize().into_bytes().into()
+        }
+        None => Sha256::digest(data).into(),
+    }
+}
+
+/// wrap a server setup in the container format
+pub fn encode(setup: &ServerSetup<Scheme>, key: Option<&[u8]>) -> Result<Vec<u8>, ServerError> {
+    let payload = bincode::serialize(setup)?;
+    let mut data = Vec::with_capacity(HEADER_LEN + payload.len() + TAG_LEN);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&VERSION.to_be_bytes());
+    data.push(if key.is_some() { FLAG_KEYED } else { 0 });
+    data.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    data.extend_from_slice(&payload);
+    let tag = tag(key, &data);
+    data.extend_from_slice(&tag);
+    Ok(data)
+}
+
+/// unwrap a server setup from the container format
+///
+/// with a `key` the file has to be protected by it. Files written without one and the raw files
+/// from before the container existed are only read by [`migrate_setup`]
+pub fn decode(data: &[u8], key: Option<&[u8]>) -> Result<ServerSetup<Scheme>, ServerError> {
+    if !data.starts_with(MAGIC) {
+        if MAGIC.starts_with(data) {
+            return Err(SetupError::Truncated.into());
+        }
+        return match bincode::deserialize::<ServerSetup<Scheme>>(data) {
+            Ok(_) => Err(SetupError::Legacy.into()),
+            Err(_) => Err(SetupError::Corrupted.into()),
+        };
+    }
+    let (setup, keyed) = open_container(data, key)?;
+    if key.is_some() && !keyed {
+        return Err(SetupError::Unkeyed.into());
+    }
+    Ok(setup)
+}
+
+/// the setup in a container and whether it was protected by a key, checked against `key` or
+/// its checksum
+fn open_container(
+    data: &[u8],
+    key: Option<&[u8]>,
+) -> Result<(ServerSetup<Scheme>, bool), ServerError> {
+    if data.len() < HEADER_LEN {
+        return Err(SetupError::Truncated.into());
+    }
+
+    let (header, rest) = data.split_at(HEADER_LEN);
+    let version = u16::from_be_bytes([header[8], header[9]]);
+    if version != VERSION {
+        return Err(SetupError::UnsupportedVersion(version).into());
+    }
+    let keyed = header[10] & FLAG_KEYED != 0;
+    let len = u32::from_be_bytes([header[11], header[12], header[13], header[14]]) as usize;
+    if rest.len() < len + TAG_LEN {
+        return Err(SetupError::Truncated.into());
+    }
+    if rest.len() > len + TAG_LEN {
+        return Err(SetupError::Corrupted.into());
+    }
+
+    let (covered, stored_tag) = data.split_at(HEADER_LEN + len);
+    if keyed {
+        let key = key.ok_or(SetupError::MissingKey)?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(covered);
+        mac.verify_slice(stored_tag)
+            .map_err(|_| SetupError::Tampered)?;
+    } else if tag(None, covered) != stored_tag {
+        return Err(SetupError::Corrupted.into());
+    }
+
+    match bincode::deserialize(&covered[HEADER_LEN..]) {
+        Ok(setup) => Ok((setup, keyed)),
+        Err(_) => Err(SetupError::Corrupted.into()),
+    }
+}
+
+/// the setup in `data` when it has to be rewritten to be read with `key`, `None` when it
+/// already can be
+fn migrate(data: &[u8], key: Option<&[u8]>) -> Result<Option<ServerSetup<Scheme>>, ServerError> {
+    match decode(data, key) {
+        Ok(_) => Ok(None),
+        Err(ServerError::Setup(SetupError::Legacy)) => Ok(Some(
+            bincode::deserialize(data).map_err(|_| SetupError::Corrupted)?,
+        )),
+        Err(ServerError::Setup(SetupError::Unkeyed)) => Ok(Some(open_container(data, None)?.0)),
+        Err(err) => Err(err),
+    }
+}
+
+/// read the server setup at `path`, `None` when there is no file there
+pub fn read_setup(path: impl AsRef<Path>) -> Result<Option<ServerSetup<Scheme>>, ServerError> {
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let key = setup_key();
+    Ok(Some(decode(&data, key.as_deref())?))
+}
+
+/// rewrite the setup at `path` so [`read_setup`] accepts it, wrapping a file in the old raw
+/// format in the container and protecting an unkeyed one with the key in [`SETUP_KEY_ENV`].
+/// Returns whether the file had to be rewritten
+pub fn migrate_setup(path: impl AsRef<Path>) -> Result<bool, ServerError> {
+    let data = std::fs::read(&path)?;
+    let key = setup_key();
+    match migrate(&data, key.as_deref())? {
+        Some(setup) => {
+            write_setup(path, &setup)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// write the server setup to `path`, going through a temporary file so a crash can't leave a
+/// partially written setup behind
+pub fn write_setup(path: impl AsRef<Path>, setup: &ServerSetup<Scheme>) -> Result<(), ServerError> {
+    let path = path.as_ref();
+    let key = setup_key();
+    let data = encode(setup, key.as_deref())?;
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let mut file = File::create(&temp)?;
+    file.write_all(&data)?;
+    file.sync_all()?;
+    rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::OsRng;
+
+    use super::*;
+
+    const KEY: &[u8] = b"machine secret";
+
+    fn setup() -> ServerSetup<Scheme> {
+        ServerSetup::new(&mut OsRng)
+    }
+
+    fn same(a: &ServerSetup<Scheme>, b: &ServerSetup<Scheme>) -> bool {
+        bincode::serialize(a).unwrap() == bincode::serialize(b).unwrap()
+    }
+
+    #[test]
+    fn round_trips() {
+        let setup = setup();
+        for key in [None, Some(KEY)] {
+            let data = encode(&setup, key).unwrap();
+            assert!(same(&decode(&data, key).unwrap(), &setup));
+        }
+    }
+
+    #[test]
+    fn truncated_files_are_refused() {
+        for key in [None, Some(KEY)] {
+            let data = encode(&setup(), key).unwrap();
+            for len in 0..data.len() {
+                assert!(
+                    matches!(
+                        decode(&data[..len], key),
+                        Err(ServerError::Setup(SetupError::Truncated))
+                    ),
+                    "accepted a file cut to {len} bytes"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn flipped_bits_are_caught() {
+        for key in [None, Some(KEY)] {
+            let data = encode(&setup(), key).unwrap();
+            for byte in 0..data.len() {
+                for bit in 0..8 {
+                    let mut flipped = data.clone();
+                    flipped[byte] ^= 1 << bit;
+                    assert!(
+                        decode(&flipped, key).is_err(),
+                        "accepted a flip of bit {bit} in byte {byte}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keyed_file_needs_the_key() {
+        let data = encode(&setup(), Some(KEY)).unwrap();
+        assert!(matches!(
+            decode(&data, None),
+            Err(ServerError::Setup(SetupError::MissingKey))
+        ));
+        assert!(matches!(
+            decode(&data, Some(b"another secret")),
+            Err(ServerError::Setup(SetupError::Tampered))
+        ));
+    }
+
+    #[test]
+    fn unkeyed_file_is_refused_once_there_is_a_key() {
+        let data = encode(&setup(), None).unwrap();
+        assert!(matches!(
+            decode(&data, Some(KEY)),
+            Err(ServerError::Setup(SetupError::Unkeyed))
+        ));
+    }
+
+    #[test]
+    fn legacy_file_is_refused() {
+        let data = bincode::serialize(&setup()).unwrap();
+        for key in [None, Some(KEY)] {
+            assert!(matches!(
+                decode(&data, key),
+                Err(ServerError::Setup(SetupError::Legacy))
+            ));
+        }
+    }
+
+    #[test]
+    fn migration_keeps_the_setup() {
+        let setup = setup();
+        let legacy = bincode::serialize(&setup).unwrap();
+        let unkeyed = encode(&setup, None).unwrap();
+        for data in [legacy, unkeyed] {
+            let migrated = migrate(&data, Some(KEY)).unwrap().unwrap();
+            assert!(same(&migrated, &setup));
+        }
+        let keyed = encode(&setup, Some(KEY)).unwrap();
+        assert!(migrate(&keyed, Some(KEY)).unwrap().is_none());
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// weight of the newest sample in the moving average of exchange latency, out of 16
+const LATENCY_WEIGHT: u64 = 2;
+
+/// When to start and stop turning new connections away
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LoadShedding {
+    /// start shedding once this many exchanges are running
+    pub high_water: usize,
+    /// stop shedding once running exchanges drop to this many
+    pub low_water: usize,
+    /// start shedding once the average exchange takes longer than this
+    pub latency_threshold: Duration,
+}
+
+/// Current load as seen by the shedder
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LoadStatus {
+    pub in_flight: usize,
+    pub average_latency: Duration,
+    pub shedding: bool,
+}
+
+struct ShedderInner {
+    policy: LoadShedding,
+    in_flight: AtomicUsize,
+    /// moving average of how long exchanges take, in microseconds
+    average_latency: AtomicU64,
+    shedding: AtomicBool,
+}
+
+/// Rejects new connections straight away while the server is overloaded, rather than accepting
+/// them only for them to time out
+///
+/// Separate high and low water marks keep it from flapping between the two states.
+#[derive(Clone)]
+pub struct LoadShedder {
+    inner: Arc<ShedderInner>,
+}
+
+impl LoadShedder {
+    pub fn new(policy: LoadShedding) -> Self {
+        Self {
+            inner: Arc::new(ShedderInner {
+                policy,
+                in_flight: AtomicUsize::new(0),
+                average_latency: AtomicU64::new(0),
+                shedding: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    pub fn policy(&self) -> LoadShedding {
+        self.inner.policy
+    }
+
+    pub fn status(&self) -> LoadStatus {
+        LoadStatus {
+            in_flight: self.inner.in_flight.load(Ordering::Relaxed),
+            average_latency: Duration::from_micros(
+                self.inner.average_latency.load(Ordering::Relaxed),
+            ),
+            shedding: self.inner.shedding.load(Ordering::Relaxed),
+        }
+    }
+
+    /// let a new exchange in, `None` while shedding
+    pub fn admit(&self) -> Option<ShedGuard> {
+        let status = self.status();
+        let policy = self.inner.policy;
+        let overloaded = status.in_flight >= policy.high_water
+            || status.average_latency > policy.latency_threshold;
+        // nothing new gets admitted while shedding, so latency can't recover on its own
+        let recovered = status.in_flight <= policy.low_water;
+        let shedding = if status.shedding {
+            !recovered
+        } else {
+            overloaded
+        };
+        if shedding != status.shedding {
+            self.inner.shedding.store(shedding, Ordering::Relaxed);
+            if !shedding {
+                // samples from before the overload cleared up are no longer representative
+                self.inner.average_latency.store(0, Ordering::Relaxed);
+            }
+            tracing::warn!(
+                in_flight = status.in_flight,
+                "Load shedding {}",
+                if shedding { "started" } else { "stopped" },
+            );
+        }
+        if shedding {
+            return None;
+        }
+
+        self.inner.in_flight.fetch_add(1, Ordering::Relaxed);
+        Some(ShedGuard {
+            started: Instant::now(),
+            inner: self.inner.clone(),
+        })
+    }
+}
+
+/// An admitted exchange, its latency is recorded when dropped
+pub struct ShedGuard {
+    started: Instant,
+    inner: Arc<ShedderInner>,
+}
+
+impl Drop for ShedGuard {
+    fn drop(&mut self) {
+        let latency = self.started.elapsed().as_micros() as u64;
+        let _ = self.inner.average_latency.fetch_update(
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+            |average| Some((average * (16 - LATENCY_WEIGHT) + latency * LATENCY_WEIGHT) / 16),
+        );
+        self.inner.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+use boring_derive::From;
+use thiserror::Error;
+
+use crate::storage_key::StorageKey;
+
+/// what the [`UserStore`] methods hand back
+pub type StoreFuture<'f, T> = Pin<Box<dyn Future<Output = Result<T, StoreError>> + Send + 'f>>;
+
+#[derive(Debug, Error, From)]
+pub enum StoreError {
+    #[error("Error interacting with sled `{0}`")]
+    Sled(sled::Error),
+    /// the backend is down or overloaded, the same request may work later
+    #[from(skip)]
+    #[error("Storage backend unavailable `{0}`")]
+    Unavailable(String),
+    #[from(skip)]
+    #[error("Storage backend error `{0}`")]
+    Backend(String),
+}
+
+impl StoreError {
+    /// whether the same request could succeed if tried again
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Sled(err) => matches!(err, sled::Error::Io(_)),
+            Self::Unavailable(_) => true,
+            Self::Backend(_) => false,
+        }
+    }
+}
+
+/// Where the password files of registered users are kept
+///
+/// Everything else the server stores, like attributes and failed logins, stays in its sled
+/// database. Stores other than [`SledStore`] can't take part in the server's sled transactions,
+/// so writes touching both are then made in two steps and a crash in between leaves the first
+/// one behind:
+///
+/// - removing a user drops the password file first, the rest of the account and its session
+///   tokens can outlive it, though nothing about a user without a password file is used
+/// - a password change stores the new password file first, the old sessions and any request to
+///   register a new password can outlive it
+/// - registering with an invite uses the invite up first, it's lost if the user isn't stored
+/// - bootstrapping marks the server as bootstrapped and stores the password file before the
+///   admin attribute, leaving an admin without it
+pub trait UserStore: Send + Sync + 'static {
+    fn get<'f>(&'f self, key: &'f StorageKey) -> StoreFuture<'f, Option<Vec<u8>>>;
+
+    /// store a password file for a new user, `false` when the user already exists, in which
+    /// case nothing is written. The check and the write have to happen atomically
+    fn insert_if_absent<'f>(
+        &'f self,
+        key: &'f StorageKey,
+        password_file: &'f [u8],
+    ) -> StoreFuture<'f, bool>;
+
+    /// store a password file, replacing any that was there
+    fn insert<'f>(&'f self, key: &'f StorageKey, password_file: &'f [u8]) -> StoreFuture<'f, ()>;
+
+    /// `false` when there was nothing to remove
+    fn remove<'f>(&'f self, key: &'f StorageKey) -> StoreFuture<'f, bool>;
+
+    fn contains<'f>(&'f self, key: &'f StorageKey) -> StoreFuture<'f, bool>;
+
+    /// how many users are registered
+    fn count(&self) -> StoreFuture<'_, usize>;
+
+    /// the key of every registered user, in byte order
+    fn keys(&self) -> StoreFuture<'_, Vec<Vec<u8>>>;
+
+    /// the sled tree behind the store, when there is one, so the server can write to it in the
+    /// same transaction as its own trees
+    fn as_sled(&self) -> Option<&sled::Tree> {
+        None
+    }
+}
+
+/// Password files in a sled tree, what the server uses unless told otherwise
+#[derive(Debug, Clone)]
+pub struct SledStore {
+    tree: sled::Tree,
+}
+
+impl SledStore {
+    pub fn new(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+}
+
+impl UserStore for SledStore {
+    fn get<'f>(&'f self, key: &'f StorageKey) -> StoreFuture<'f, Option<Vec<u8>>> {
+        Box::pin(async move { Ok(self.tree.get(key)?.map(|file| file.to_vec())) })
+    }
+
+    fn insert_if_absent<'f>(
+        &'f self,
+        key: &'f StorageKey,
+        password_file: &'f [u8],
+    ) -> StoreFuture<'f, bool> {
+        Box::pin(async move {
+            Ok(self
+                .tree
+                .compare_and_swap(key, None as Option<&[u8]>, Some(password_file))?
+                .is_ok())
+        })
+    }
+
+    fn insert<'f>(&'f self, key: &'f StorageKey, password_file: &'f [u8]) -> StoreFuture<'f, ()> {
+        Box::pin(async move {
+            self.tree.insert(key, password_file)?;
+            Ok(())
+        })
+    }
+
+    fn remove<'f>(&'f self, key: &'f StorageKey) -> StoreFuture<'f, bool> {
+        Box::pin(async move { Ok(self.tree.remove(key)?.is_some()) })
+    }
+
+    fn contains<'f>(&'f self, key: &'f StorageKey) -> StoreFuture<'f, bool> {
+        Box::pin(async move { Ok(self.tree.contains_key(key)?) })
+    }
+
+    fn count(&self) -> StoreFuture<'_, usize> {
+        Box::pin(async move { Ok(self.tree.len()) })
+    }
+
+    fn keys(&self) -> StoreFuture<'_, Vec<Vec<u8>>> {
+        Box::pin(async move {
+            let mut keys = Vec::new();
+            for key in self.tree.iter().keys() {
+                keys.push(key?.to_vec());
+            }
+            Ok(keys)
+        })
+    }
+
+    fn as_sled(&self) -> Option<&sled::Tree> {
+        Some(&self.tree)
+    }
+}
+
+/// Password files kept in memory and lost when the server stops, for tests and trying things
+/// out
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    users: Arc<Mutex<HashMap<StorageKey, Vec<u8>>>>,
+}
+
+impl UserStore for MemoryStore {
+    fn get<'f>(&'f self, key: &'f StorageKey) -> StoreFuture<'f, Option<Vec<u8>>> {
+        Box::pin(async move { Ok(self.users.lock().unwrap().get(key).cloned()) })
+    }
+
+    fn insert_if_absent<'f>(
+        &'f self,
+        key: &'f StorageKey,
+        password_file: &'f [u8],
+    ) -> StoreFuture<'f, bool> {
+        Box::pin(async move {
+            let mut users = self.users.lock().unwrap();
+            if users.contains_key(key) {
+                return Ok(false);
+            }
+            users.insert(key.clone(), password_file.into());
+            Ok(true)
+        })
+    }
+
+    fn insert<'f>(&'f self, key: &'f StorageKey, password_file: &'f [u8]) -> StoreFuture<'f, ()> {
+        Box::pin(async move {
+            self.users
+                .lock()
+                .unwrap()
+                .insert(key.clone(), password_file.into());
+            Ok(())
+        })
+    }
+
+    fn remove<'f>(&'f self, key: &'f StorageKey) -> StoreFuture<'f, bool> {
+        Box::pin(async move { Ok(self.users.lock().unwrap().remove(key).is_some()) })
+    }
+
+    fn contains<'f>(&'f self, key: &'f StorageKey) -> StoreFuture<'f, bool> {
+        Box::pin(async move { Ok(self.users.lock().unwrap().contains_key(key)) })
+    }
+
+    fn count(&self) -> StoreFuture<'_, usize> {
+        Box::pin(async move { Ok(self.users.lock().unwrap().len()) })
+    }
+
+    fn keys(&self) -> StoreFuture<'_, Vec<Vec<u8>>> {
+        Box::pin(async move {
+            let mut keys = self
+                .users
+                .lock()
+                .unwrap()
+                .keys()
+                .map(|key| key.as_bytes().to_vec())
+                .collect::<Vec<_>>();
+            keys.sort();
+            Ok(keys)
+        })
+    }
+}
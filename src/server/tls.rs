@@ -0,0 +1,126 @@
+use std::{future::Future, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use axum::{extract::ConnectInfo, Extension, Router};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio_rustls::{rustls::ServerConfig as RustlsConfig, TlsAcceptor};
+
+use super::{error::ServerError, listeners::TlsTerminated};
+
+/// how long a client gets to finish the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificate chain and private key a listener terminates TLS with, both PEM files
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// the server's certificate followed by any intermediates
+    pub cert_chain: PathBuf,
+    pub private_key: PathBuf,
+}
+
+impl TlsConfig {
+    pub fn new(cert_chain: impl Into<PathBuf>, private_key: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_chain: cert_chain.into(),
+            private_key: private_key.into(),
+        }
+    }
+
+    /// read the certificate chain and key into a config [`serve_tls`] can use
+    pub fn load(&self) -> Result<Arc<RustlsConfig>, ServerError> {
+        let chain = std::fs::read(&self.cert_chain)?;
+        let certs = rustls_pemfile::certs(&mut &chain[..])
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| {
+                ServerError::Tls(format!(
+                    "unreadable certificate in `{}` {err}",
+                    self.cert_chain.display()
+                ))
+            })?;
+        if certs.is_empty() {
+            return Err(ServerError::Tls(format!(
+                "no certificates in `{}`",
+                self.cert_chain.display()
+            )));
+        }
+        let key = std::fs::read(&self.private_key)?;
+        let key = rustls_pemfile::private_key(&mut &key[..])
+            .map_err(|err| {
+                ServerError::Tls(format!(
+                    "unreadable private key in `{}` {err}",
+                    self.private_key.display()
+                ))
+            })?
+            .ok_or_else(|| {
+                ServerError::Tls(format!(
+                    "no private key in `{}`",
+                    self.private_key.display()
+                ))
+            })?;
+        let mut config = RustlsConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|err| ServerError::Tls(err.to_string()))?;
+        // websockets are upgraded from http/1.1, so don't offer anything else
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Arc::new(config))
+    }
+}
+
+/// serve `router` over TLS on `listener` until `shutdown` completes
+///
+/// handshakes run on each connection's own task, so a client that fails or stalls the handshake
+/// only loses its own connection
+pub async fn serve_tls(
+    listener: TcpListener,
+    config: Arc<RustlsConfig>,
+    router: Router,
+    shutdown: impl Future<Output = ()>,
+) -> std::io::Result<()> {
+    let acceptor = TlsAcceptor::from(config);
+    let mut shutdown = std::pin::pin!(shutdown);
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    tracing::warn!("Error accepting connection: `{err}`");
+                    continue;
+                }
+            },
+            () = &mut shutdown => return Ok(()),
+        };
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(
+            router
+                .clone()
+                .layer(Extension(ConnectInfo::<SocketAddr>(peer)))
+                .layer(Extension(TlsTerminated)),
+        );
+        tokio::task::spawn(async move {
+            let stream =
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(err)) => {
+                        tracing::warn!(%peer, "TLS handshake failed: `{err}`");
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::warn!(%peer, "TLS handshake timed out");
+                        return;
+                    }
+                };
+            let served = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await;
+            if let Err(err) = served {
+                tracing::warn!(%peer, "Error serving TLS connection: `{err}`");
+            }
+        });
+    }
+}
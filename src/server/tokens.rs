@@ -0,0 +1,276 @@
+use std::{fmt::Write, marker::PhantomData, sync::Arc, time::Duration};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use rand::{rngs::OsRng, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionalTree, UnabortableTransactionError},
+    Transactional,
+};
+
+use super::{clock::Clock, error::ServerError, Server};
+use crate::{storage_key::StorageKey, suite::Suite, Scheme};
+
+/// sled tree holding the session tokens handed out after logins
+pub(crate) const SESSIONS_TREE: &str = "sessions";
+/// sled tree indexing the session tokens by the user they were issued to
+pub(crate) const USER_SESSIONS_TREE: &str = "user_sessions";
+/// how long a session token is good for unless configured otherwise
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+/// random bytes in a session token
+const TOKEN_LEN: usize = 32;
+/// bytes of the digest a token is stored under
+const TOKEN_KEY_LEN: usize = 32;
+
+/// `len` random bytes as hex, for tokens that get handed around as text
+pub(crate) fn random_token(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill(&mut bytes[..]);
+    bytes
+        .iter()
+        .fold(String::with_capacity(len * 2), |mut token, byte| {
+            let _ = write!(token, "{byte:02x}");
+            token
+        })
+}
+
+/// What a session token stands for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SessionEntry {
+    user: Vec<u8>,
+    /// seconds since the unix epoch
+    expires_at: u64,
+}
+
+/// tokens are stored as their digest, so whoever can read the store can't use them
+fn token_key(token: &str) -> impl AsRef<[u8]> {
+    Sha256::digest(token.as_bytes())
+}
+
+/// key in the index of `user`'s tokens, the user is length prefixed so one user's keys are
+/// never a prefix of another's
+fn index_prefix(user: &[u8]) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(4 + user.len() + TOKEN_KEY_LEN);
+    prefix.extend_from_slice(&(user.len() as u32).to_be_bytes());
+    prefix.extend_from_slice(user);
+    prefix
+}
+
+fn index_key(user: &[u8], token_key: &[u8]) -> Vec<u8> {
+    let mut key = index_prefix(user);
+    key.extend_from_slice(token_key);
+    key
+}
+
+/// The session tokens handed out after logins, indexed by the user they were issued to so all of
+/// a user's tokens can be revoked at once
+#[derive(Clone)]
+pub(crate) struct Sessions {
+    tokens: sled::Tree,
+    by_user: sled::Tree,
+}
+
+impl Sessions {
+    pub(crate) fn new(tokens: sled::Tree, by_user: sled::Tree) -> Self {
+        Self { tokens, by_user }
+    }
+
+    /// a new token for `user` that lasts for `ttl`
+    pub(crate) fn issue(
+        &self,
+        user: &StorageKey,
+        now: u64,
+        ttl: Duration,
+    ) -> Result<String, ServerError> {
+        let token = random_token(TOKEN_LEN);
+        let key = token_key(&token);
+        let entry = bincode::serialize(&SessionEntry {
+            user: user.as_bytes().to_vec(),
+            expires_at: now.saturating_add(ttl.as_secs()),
+        })?;
+        let index = index_key(user.as_bytes(), key.as_ref());
+        (&self.tokens, &self.by_user).transaction(|(tokens, by_user)| {
+            tokens.insert(key.as_ref(), entry.as_slice())?;
+            by_user.insert(index.as_slice(), &[])?;
+            Ok::<_, ConflictableTransactionError<ServerError>>(())
+        })?;
+        Ok(token)
+    }
+
+    /// the key of the user `token` was issued to, `None` when it's unknown, revoked, or expired
+    pub(crate) fn lookup(&self, token: &str, now: u64) -> Result<Option<Vec<u8>>, ServerError> {
+        let key = token_key(token);
+        let Some(data) = self.tokens.get(&key)? else {
+            return Ok(None);
+        };
+        let entry: SessionEntry = bincode::deserialize(&data)?;
+        if entry.expires_at <= now {
+            self.forget(key.as_ref(), &data)?;
+            return Ok(None);
+        }
+        Ok(Some(entry.user))
+    }
+
+    /// forget `token`, `false` when it wasn't known
+    pub(crate) fn revoke(&self, token: &str) -> Result<bool, ServerError> {
+        let key = token_key(token);
+        match self.tokens.get(&key)? {
+            Some(data) => self.forget(key.as_ref(), &data),
+            None => Ok(false),
+        }
+    }
+
+    /// the trees the tokens and their index are kept in, for revoking tokens in the same
+    /// transaction as other writes, see [`UserTokens`]
+    pub(crate) fn trees(&self) -> (&sled::Tree, &sled::Tree) {
+        (&self.tokens, &self.by_user)
+    }
+
+    /// every token issued to `user` so far
+    pub(crate) fn of_user(&self, user: &StorageKey) -> Result<UserTokens, ServerError> {
+        let prefix = index_prefix(user.as_bytes());
+        let indexes = self
+            .by_user
+            .scan_prefix(&prefix)
+            .keys()
+            .collect::<Result<_, _>>()?;
+        Ok(UserTokens {
+            prefix_len: prefix.len(),
+            indexes,
+        })
+    }
+
+    /// when each of `user`'s tokens that are still good expires
+    pub(crate) fn expiries_of(&self, user: &StorageKey, now: u64) -> Result<Vec<u64>, ServerError> {
+        let prefix = index_prefix(user.as_bytes());
+        let mut expiries = Vec::new();
+        for index in self.by_user.scan_prefix(&prefix).keys() {
+            let index = index?;
+            let Some(data) = self.tokens.get(&index[prefix.len()..])? else {
+                continue;
+            };
+            let entry: SessionEntry = bincode::deserialize(&data)?;
+            if entry.expires_at > now {
+                expiries.push(entry.expires_at);
+            }
+        }
+        Ok(expiries)
+    }
+
+    /// drop every expired token, returns how many there were
+    pub(crate) fn sweep(&self, now: u64) -> Result<usize, ServerError> {
+        let mut swept = 0;
+        for entry in self.tokens.iter() {
+            let (key, data) = entry?;
+            let expired = bincode::deserialize::<SessionEntry>(&data)
+                .map_or(true, |entry| entry.expires_at <= now);
+            if expired && self.forget(&key, &data)? {
+                swept += 1;
+            }
+        }
+        Ok(swept)
+    }
+
+    /// drop the token stored under `key` along with its index entry, as long as it's still
+    /// `data`. Returns whether it was dropped
+    fn forget(&self, key: &[u8], data: &[u8]) -> Result<bool, ServerError> {
+        if self
+            .tokens
+            .compare_and_swap(key, Some(data), None as Option<&[u8]>)?
+            .is_err()
+        {
+            return Ok(false);
+        }
+        if let Ok(entry) = bincode::deserialize::<SessionEntry>(data) {
+            self.by_user.remove(index_key(&entry.user, key))?;
+        }
+        Ok(true)
+    }
+
+    /// sweep expired tokens every `interval` for as long as the runtime is alive
+    pub(crate) fn schedule_sweep(self, clock: Arc<dyn Clock>, interval: Duration) {
+        tokio::task::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                match self.sweep(clock.unix_secs()) {
+                    Ok(0) => {}
+                    Ok(swept) => tracing::debug!(swept, "Swept expired session tokens"),
+                    Err(err) => tracing::error!("Error sweeping session tokens: `{err}`"),
+                }
+            }
+        });
+    }
+}
+
+/// A user's session tokens, looked up ahead of a transaction that revokes them
+///
+/// sled transactions can't scan a tree, so tokens issued after the lookup aren't part of it.
+/// Those come from a login racing the change and are turned away by
+/// [`Server::validate_token`] once the user is gone
+pub(crate) struct UserTokens {
+    prefix_len: usize,
+    indexes: Vec<sled::IVec>,
+}
+
+impl UserTokens {
+    /// remove the tokens from inside a transaction over the trees of [`Sessions::trees`]
+    pub(crate) fn revoke(
+        &self,
+        tokens: &TransactionalTree,
+        by_user: &TransactionalTree,
+    ) -> Result<(), UnabortableTransactionError> {
+        for index in &self.indexes {
+            tokens.remove(&index[self.prefix_len..])?;
+            by_user.remove(index)?;
+        }
+        Ok(())
+    }
+}
+
+/// The user behind the bearer token of a request, see [`Server::validate_token`]
+///
+/// requests without a valid token are turned away with a 401 before reaching the handler. `CS`
+/// is the suite of the server in the request's state
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser<CS: Suite = Scheme> {
+    pub username: String,
+    /// the token the request came with
+    pub token: String,
+    suite: PhantomData<CS>,
+}
+
+#[async_trait]
+impl<S, CS: Suite> FromRequestParts<S> for AuthenticatedUser<CS>
+where
+    Server<CS>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let Some(token) = token else {
+            return Err((StatusCode::UNAUTHORIZED, "Missing session token").into_response());
+        };
+        match Server::<CS>::from_ref(state).validate_token(token).await {
+            Ok(Some(username)) => Ok(Self {
+                username,
+                token: token.into(),
+                suite: PhantomData,
+            }),
+            Ok(None) => Err((StatusCode::UNAUTHORIZED, "Invalid session token").into_response()),
+            Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// default upper bound on the length of a username in bytes
+pub const DEFAULT_MAX_USERNAME_LEN: usize = 64;
+/// separates the tenant from the username in a key, so it can't appear in either
+const TENANT_SEPARATOR: u8 = 0;
+
+#[derive(Debug, Error)]
+pub enum UsernameError {
+    #[error("Username is empty")]
+    Empty,
+    #[error("Username is `{0}` bytes, longer than the limit of `{1}`")]
+    TooLong(usize, usize),
+    #[error("Username or tenant contains a null byte")]
+    NullByte,
+    #[error("Username is not valid UTF-8")]
+    NotUtf8,
+    #[error("Username contains the control character `{0:?}`")]
+    ControlCharacter(char),
+}
+
+/// How usernames are checked before being turned into [`StorageKey`]s
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyPolicy {
+    pub max_len: usize,
+}
+
+impl Default for KeyPolicy {
+    fn default() -> Self {
+        Self {
+            max_len: DEFAULT_MAX_USERNAME_LEN,
+        }
+    }
+}
+
+/// The key a user's data is stored under
+///
+/// Every lookup of a user's data should go through [`StorageKey::for_user`], that way
+/// registration, authentication, and everything else can't end up deriving different keys for
+/// the same username. Usernames are normalized first, see
+/// [`normalize`](crate::username::normalize)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StorageKey(Vec<u8>);
+
+impl StorageKey {
+    pub fn for_user(
+        policy: &KeyPolicy,
+        tenant: Option<&str>,
+        username: &[u8],
+    ) -> Result<Self, UsernameError> {
+        // the separator is a control character, so normalizing already turns it away
+        let username = crate::username::normalize_bytes(username, policy.max_len)?;
+        let username = username.as_bytes();
+
+        match tenant {
+            Some(tenant) => {
+                if tenant.as_bytes().contains(&TENANT_SEPARATOR) {
+                    return Err(UsernameError::NullByte);
+                }
+                let mut key = Vec::with_capacity(tenant.len() + 1 + username.len());
+                key.extend_from_slice(tenant.as_bytes());
+                key.push(TENANT_SEPARATOR);
+                key.extend_from_slice(username);
+                Ok(Self(key))
+            }
+            None => Ok(Self(username.into())),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// the username a stored `key` was derived from, `None` when it belongs to a different tenant
+pub fn username_of<'k>(key: &'k [u8], tenant: Option<&str>) -> Option<&'k [u8]> {
+    match tenant {
+        Some(tenant) => key
+            .strip_prefix(tenant.as_bytes())?
+            .strip_prefix(&[TENANT_SEPARATOR]),
+        None if key.contains(&TENANT_SEPARATOR) => None,
+        None => Some(key),
+    }
+}
+
+impl AsRef<[u8]> for StorageKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
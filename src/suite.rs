@@ -0,0 +1,268 @@
+use opaque_ke::{
+    errors::ProtocolError, ksf::Ksf, ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+    ClientRegistrationFinishParameters, CredentialFinalization, CredentialRequest,
+    CredentialResponse, RegistrationRequest, RegistrationResponse, RegistrationUpload, ServerLogin,
+    ServerLoginStartParameters, ServerRegistration, ServerSetup,
+};
+use rand::rngs::OsRng;
+
+use crate::{ksf::KsfParams, Argon2, Identifiers, Scheme, SuiteId};
+
+/// Key stretching whose salt is derived from the username, see [`Argon2::salted_for`]
+pub trait SaltedKsf: Ksf + Default + Clone + Send + Sync + 'static {
+    /// the same costs, salted with a value derived from `username`
+    fn salted_for(self, username: &[u8]) -> Self;
+
+    /// the costs this stretches passwords with
+    fn params(&self) -> KsfParams;
+}
+
+impl SaltedKsf for Argon2 {
+    fn salted_for(self, username: &[u8]) -> Self {
+        Argon2::salted_for(self, username)
+    }
+
+    fn params(&self) -> KsfParams {
+        KsfParams::Argon2(Argon2::params(self))
+    }
+}
+
+#[cfg(feature = "scrypt")]
+impl SaltedKsf for crate::Scrypt {
+    fn salted_for(self, username: &[u8]) -> Self {
+        crate::Scrypt::salted_for(self, username)
+    }
+
+    fn params(&self) -> KsfParams {
+        crate::Scrypt::params(self)
+    }
+}
+
+/// What the client ends a registration with, see [`Suite::client_registration_finish`]
+pub struct RegistrationFinish {
+    /// the upload for the server
+    pub message: Vec<u8>,
+    pub export_key: Vec<u8>,
+    pub server_public_key: Vec<u8>,
+}
+
+/// What the client ends a login with, see [`Suite::client_login_finish`]
+pub struct LoginFinish {
+    /// the finalization for the server
+    pub message: Vec<u8>,
+    pub session_key: Vec<u8>,
+    pub export_key: Vec<u8>,
+    pub server_public_key: Vec<u8>,
+}
+
+/// A cipher suite the [`Server`](crate::server::Server) and [`Client`](crate::client::Client)
+/// can run the OPAQUE exchanges with, [`Scheme`] unless they're told otherwise
+///
+/// `opaque_ke` puts bounds on every `CipherSuite` that code generic over one has to repeat, so
+/// the exchanges go through this instead and pass every message as bytes. It's implemented for
+/// each suite the crate ships
+pub trait Suite: std::fmt::Debug + Clone + Copy + Send + Sync + 'static {
+    /// recorded with every password file the suite makes
+    const ID: SuiteId;
+    /// how the client stretches passwords
+    type Ksf: SaltedKsf;
+    /// the server's long term keys, a `ServerSetup`
+    type Setup: Clone + Send + Sync + 'static;
+    /// the first message of a login, a `CredentialRequest`
+    type CredentialRequest: Send;
+    /// the server waiting on the client to finish a login, a `ServerLogin`
+    type ServerLogin: Send;
+    /// the client waiting on the server's registration response, a `ClientRegistration`
+    type ClientRegistration: Send;
+    /// the client waiting on the server's login response, a `ClientLogin`
+    type ClientLogin: Send;
+
+    /// long term keys for a new server
+    fn new_setup() -> Self::Setup;
+
+    /// the server's response to a registration `request` from `username`
+    fn server_registration_start(
+        setup: &Self::Setup,
+        request: &[u8],
+        username: &[u8],
+    ) -> Result<Vec<u8>, ProtocolError>;
+
+    /// the password file the client's registration `upload` amounts to
+    fn server_registration_finish(upload: &[u8]) -> Result<Vec<u8>, ProtocolError>;
+
+    fn credential_request(request: &[u8]) -> Result<Self::CredentialRequest, ProtocolError>;
+
+    /// the server's response to `username` logging in, run against a dummy password file when
+    /// `password_file` is `None`
+    fn server_login_start(
+        setup: &Self::Setup,
+        password_file: Option<&[u8]>,
+        request: Self::CredentialRequest,
+        username: &[u8],
+        identifiers: &Identifiers,
+    ) -> Result<(Self::ServerLogin, Vec<u8>), ProtocolError>;
+
+    /// the session key once the client's `finalization` checks out
+    fn server_login_finish(
+        state: Self::ServerLogin,
+        finalization: &[u8],
+    ) -> Result<Vec<u8>, ProtocolError>;
+
+    /// start registering `password`, along with the request for the server
+    fn client_registration_start(
+        password: &[u8],
+    ) -> Result<(Self::ClientRegistration, Vec<u8>), ProtocolError>;
+
+    fn client_registration_finish(
+        state: Self::ClientRegistration,
+        password: &[u8],
+        response: &[u8],
+        identifiers: &Identifiers,
+        ksf: &Self::Ksf,
+    ) -> Result<RegistrationFinish, ProtocolError>;
+
+    /// start logging in with `password`, along with the request for the server
+    fn client_login_start(password: &[u8]) -> Result<(Self::ClientLogin, Vec<u8>), ProtocolError>;
+
+    /// fails with `InvalidLoginError` when `password` doesn't open the server's `response`
+    fn client_login_finish(
+        state: Self::ClientLogin,
+        password: &[u8],
+        response: &[u8],
+        identifiers: &Identifiers,
+        ksf: &Self::Ksf,
+    ) -> Result<LoginFinish, ProtocolError>;
+}
+
+macro_rules! impl_suite {
+    ($suite:ty, $ksf:ty) => {
+        impl Suite for $suite {
+            const ID: SuiteId = <$suite>::ID;
+            type Ksf = $ksf;
+            type Setup = ServerSetup<$suite>;
+            type CredentialRequest = CredentialRequest<$suite>;
+            type ServerLogin = ServerLogin<$suite>;
+            type ClientRegistration = ClientRegistration<$suite>;
+            type ClientLogin = ClientLogin<$suite>;
+
+            fn new_setup() -> Self::Setup {
+                ServerSetup::new(&mut OsRng)
+            }
+
+            fn server_registration_start(
+                setup: &Self::Setup,
+                request: &[u8],
+                username: &[u8],
+            ) -> Result<Vec<u8>, ProtocolError> {
+                let request = RegistrationRequest::deserialize(request)?;
+                let result = ServerRegistration::<$suite>::start(setup, request, username)?;
+                Ok(result.message.serialize().to_vec())
+            }
+
+            fn server_registration_finish(upload: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+                let upload = RegistrationUpload::<$suite>::deserialize(upload)?;
+                Ok(ServerRegistration::finish(upload).serialize().to_vec())
+            }
+
+            fn credential_request(
+                request: &[u8],
+            ) -> Result<Self::CredentialRequest, ProtocolError> {
+                CredentialRequest::deserialize(request)
+            }
+
+            fn server_login_start(
+                setup: &Self::Setup,
+                password_file: Option<&[u8]>,
+                request: Self::CredentialRequest,
+                username: &[u8],
+                identifiers: &Identifiers,
+            ) -> Result<(Self::ServerLogin, Vec<u8>), ProtocolError> {
+                let password_file = password_file
+                    .map(ServerRegistration::<$suite>::deserialize)
+                    .transpose()?;
+                let result = ServerLogin::start(
+                    &mut OsRng,
+                    setup,
+                    password_file,
+                    request,
+                    username,
+                    ServerLoginStartParameters {
+                        context: None,
+                        identifiers: identifiers.as_opaque(),
+                    },
+                )?;
+                Ok((result.state, result.message.serialize().to_vec()))
+            }
+
+            fn server_login_finish(
+                state: Self::ServerLogin,
+                finalization: &[u8],
+            ) -> Result<Vec<u8>, ProtocolError> {
+                let finalization = CredentialFinalization::deserialize(finalization)?;
+                Ok(state.finish(finalization)?.session_key.to_vec())
+            }
+
+            fn client_registration_start(
+                password: &[u8],
+            ) -> Result<(Self::ClientRegistration, Vec<u8>), ProtocolError> {
+                let result = ClientRegistration::<$suite>::start(&mut OsRng, password)?;
+                Ok((result.state, result.message.serialize().to_vec()))
+            }
+
+            fn client_registration_finish(
+                state: Self::ClientRegistration,
+                password: &[u8],
+                response: &[u8],
+                identifiers: &Identifiers,
+                ksf: &Self::Ksf,
+            ) -> Result<RegistrationFinish, ProtocolError> {
+                let response = RegistrationResponse::deserialize(response)?;
+                let result = state.finish(
+                    &mut OsRng,
+                    password,
+                    response,
+                    ClientRegistrationFinishParameters::new(identifiers.as_opaque(), Some(ksf)),
+                )?;
+                Ok(RegistrationFinish {
+                    message: result.message.serialize().to_vec(),
+                    export_key: result.export_key.to_vec(),
+                    server_public_key: result.server_s_pk.serialize().to_vec(),
+                })
+            }
+
+            fn client_login_start(
+                password: &[u8],
+            ) -> Result<(Self::ClientLogin, Vec<u8>), ProtocolError> {
+                let result = ClientLogin::<$suite>::start(&mut OsRng, password)?;
+                Ok((result.state, result.message.serialize().to_vec()))
+            }
+
+            fn client_login_finish(
+                state: Self::ClientLogin,
+                password: &[u8],
+                response: &[u8],
+                identifiers: &Identifiers,
+                ksf: &Self::Ksf,
+            ) -> Result<LoginFinish, ProtocolError> {
+                let response = CredentialResponse::deserialize(response)?;
+                let result = state.finish(
+                    password,
+                    response,
+                    ClientLoginFinishParameters::new(None, identifiers.as_opaque(), Some(ksf)),
+                )?;
+                Ok(LoginFinish {
+                    message: result.message.serialize().to_vec(),
+                    session_key: result.session_key.to_vec(),
+                    export_key: result.export_key.to_vec(),
+                    server_public_key: result.server_s_pk.serialize().to_vec(),
+                })
+            }
+        }
+    };
+}
+
+impl_suite!(Scheme, Argon2);
+#[cfg(feature = "p256")]
+impl_suite!(crate::P256Scheme, Argon2);
+#[cfg(feature = "scrypt")]
+impl_suite!(crate::SchemeScrypt, crate::Scrypt);
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+use crate::attributes::Attributes;
+
+/// Why an authentication of an existing user didn't succeed
+///
+/// Clients only ever see a uniform failure, this is the server side record of what actually went
+/// wrong so the account owner and support can find out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthFailure {
+    /// seconds since the unix epoch
+    pub at: u64,
+    pub reason: String,
+}
+
+/// A session token that is still good, the token itself is only stored as a digest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionSummary {
+    /// seconds since the unix epoch
+    pub expires_at: u64,
+}
+
+/// Everything the server holds about one user, apart from the password file
+///
+/// The password file is left out on purpose, it's of no use to its owner and sensitive to hand
+/// out. Anything new the server starts keeping per user belongs in here as well.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TakeoutDocument {
+    pub username: String,
+    /// seconds since the unix epoch, 0 for accounts from before this was kept
+    pub created_at: u64,
+    /// seconds since the unix epoch
+    pub last_login: Option<u64>,
+    pub attributes: Attributes,
+    pub auth_failures: Vec<AuthFailure>,
+    /// seconds until the account unlocks, `None` when it isn't locked
+    pub locked_for: Option<u64>,
+    /// whether the user has to register a new password before logging in again
+    pub must_reregister: bool,
+    pub sessions: Vec<SessionSummary>,
+    /// the blob the user stored, still encrypted the way their client sent it
+    pub blob: Option<Vec<u8>>,
+}
@@ -0,0 +1,64 @@
+use std::fmt::Display;
+
+use unicode_normalization::UnicodeNormalization;
+
+pub use crate::storage_key::{UsernameError, DEFAULT_MAX_USERNAME_LEN};
+
+/// lowercasing can shrink a character to a third of its bytes, anything over this many times
+/// the limit can't fit however it normalizes, and is turned away without the work
+const MAX_SHRINK: usize = 3;
+
+/// A username in the one form both sides use for it, see [`normalize`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Username(String);
+
+impl Username {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl Display for Username {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// `username` trimmed, lowercased, and NFC normalized, so every way of typing the same name
+/// ends up as the same account. Limited to [`DEFAULT_MAX_USERNAME_LEN`] bytes
+pub fn normalize(username: &str) -> Result<Username, UsernameError> {
+    normalize_with_limit(username, DEFAULT_MAX_USERNAME_LEN)
+}
+
+/// [`normalize`] with a limit of `max_len` bytes, which applies to the normalized name
+pub fn normalize_with_limit(username: &str, max_len: usize) -> Result<Username, UsernameError> {
+    let trimmed = username.trim();
+    if trimmed.len() > max_len.saturating_mul(MAX_SHRINK) {
+        return Err(UsernameError::TooLong(trimmed.len(), max_len));
+    }
+    let normalized = trimmed.to_lowercase().nfc().collect::<String>();
+    if normalized.is_empty() {
+        return Err(UsernameError::Empty);
+    }
+    if let Some(control) = normalized.chars().find(|c| c.is_control()) {
+        return Err(UsernameError::ControlCharacter(control));
+    }
+    if normalized.len() > max_len {
+        return Err(UsernameError::TooLong(normalized.len(), max_len));
+    }
+    Ok(Username(normalized))
+}
+
+/// [`normalize_with_limit`] for a username as it came off the wire
+pub fn normalize_bytes(username: &[u8], max_len: usize) -> Result<Username, UsernameError> {
+    let username = std::str::from_utf8(username).map_err(|_| UsernameError::NotUtf8)?;
+    normalize_with_limit(username, max_len)
+}
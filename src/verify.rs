@@ -0,0 +1,68 @@
+use boring_derive::From;
+use opaque_ke::{errors::ProtocolError, ServerSetup};
+use thiserror::Error;
+
+use crate::{
+    client::{authenticate::AuthenticateInitialize, error::ClientError},
+    server::{autheticate::AuthWaiting, error::ServerError, record},
+    Scheme,
+};
+
+#[derive(Debug, Error, From)]
+pub enum VerifyError {
+    #[error("Client side of the verification failed `{0}`")]
+    Client(ClientError),
+    #[error("Server side of the verification failed `{0}`")]
+    Server(ServerError),
+}
+
+/// Check whether a stored password file belongs to `username` and `password` without any
+/// networking or database access
+///
+/// Runs the same client and server login state machines as a real authentication, passing the
+/// messages between them in memory. Returns `Ok(false)` when the credentials don't match the
+/// record (including records created under a different `server_setup`) and an error when the
+/// record itself can't be used, including when it fails its checksum.
+///
+/// Records registered before salts were derived from usernames are checked with the legacy salt
+/// too. This runs the key stretching function, so expect it to be deliberately slow.
+pub fn verify_record(
+    server_setup: &ServerSetup<Scheme>,
+    username: &str,
+    password: &str,
+    record: &[u8],
+) -> Result<bool, VerifyError> {
+    if verify_with(server_setup, username, password, record, false)? {
+        return Ok(true);
+    }
+    verify_with(server_setup, username, password, record, true)
+}
+
+fn verify_with(
+    server_setup: &ServerSetup<Scheme>,
+    username: &str,
+    password: &str,
+    record: &[u8],
+    legacy_salt: bool,
+) -> Result<bool, VerifyError> {
+    let client = AuthenticateInitialize::new(username.into(), password.into())?
+        .with_legacy_salt(legacy_salt);
+    let server = AuthWaiting::new(server_setup.clone()).step(client.to_data())?;
+    let password_file = record::unseal(record)
+        .and_then(record::UserRecord::checked_password_file)
+        .map_err(ServerError::CorruptRecord)?;
+    let server = server.step(Some(password_file))?;
+
+    let client = match client.step(server.to_data()) {
+        Ok(res) => res,
+        Err(ClientError::ProtocolError(ProtocolError::InvalidLoginError)) => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+    let server = match server.step(client.to_data()) {
+        Ok(res) => res,
+        Err(ServerError::ProtocolError(ProtocolError::InvalidLoginError)) => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok(client.step(server.to_data()).to_data())
+}
@@ -0,0 +1,363 @@
+use std::fmt::Display;
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+/// close code telling the client the server couldn't handle the request right now and it should
+/// try again later
+pub const TRY_AGAIN_LATER: u16 = 1013;
+/// close code for messages that were malformed or arrived out of order
+pub const INVALID_MESSAGE: u16 = 4000;
+/// close code for requests that were understood but refused
+pub const REJECTED: u16 = 4001;
+/// header a client sends the bootstrap token in when registering
+pub const BOOTSTRAP_HEADER: &str = "x-tinap-bootstrap-token";
+/// header the server lists the [`Feature`]s it supports in when accepting a connection
+pub const FEATURES_HEADER: &str = "x-tinap-features";
+/// path of the endpoint the server describes itself on, see [`ServerInfo`]
+pub const INFO_PATH: &str = "info";
+/// path of the endpoint that runs any number of operations, one after another, over a single
+/// connection
+///
+/// every operation starts with a frame naming the [`Operation`] and runs as it would on its own
+/// endpoint, except that it ends with a text frame carrying what would otherwise be the close
+/// frame's payload, see [`session_end`]. The connection stays open for the next operation until
+/// either side closes it
+pub const SESSION_PATH: &str = "ws";
+/// path of the endpoint that logs in and then stores the single blob sent after the login,
+/// replacing whatever was stored for the user before
+pub const STORE_PATH: &str = "store";
+/// path of the endpoint that logs in and then sends back the user's blob
+pub const RETRIEVE_PATH: &str = "retrieve";
+/// path of the plain http endpoint a logged in user reads and replaces their attributes on,
+/// with the session token as bearer token
+pub const ATTRIBUTES_PATH: &str = "attributes";
+/// path of the plain http endpoint a logged in user downloads everything stored about them
+/// from, see [`TakeoutDocument`](crate::takeout::TakeoutDocument)
+pub const TAKEOUT_PATH: &str = "takeout";
+/// reason an exchange that went through is closed with, after a login followed by the session
+/// token as its detail
+pub const DONE: &str = "done";
+/// reason a retrieval is closed with when the user has nothing stored
+pub const NO_BLOB: &str = "no_blob";
+/// most bytes a close reason can take, control frames carry at most 125 bytes and the status
+/// code takes two of them
+pub const MAX_CLOSE_REASON: usize = 123;
+/// most bytes a websocket message can take unless configured otherwise, anything larger is
+/// refused while it's still being read
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+/// most bytes a protocol message can take, far more than any of them needs
+pub const MAX_PAYLOAD_SIZE: usize = 64 * 1024;
+
+/// decode a protocol message the way [`bincode::serialize`] encoded it, but refusing anything that
+/// claims to be longer than [`MAX_PAYLOAD_SIZE`] instead of trying to allocate for it
+pub fn decode<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<T, bincode::Error> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_PAYLOAD_SIZE as u64)
+        .deserialize(data)
+}
+
+/// Broad categories of failure, the close code of every error on either side comes from its
+/// category so both sides agree on what a code means
+///
+/// Application specific codes are allocated from 4000 upwards, new categories take the next free
+/// code rather than reusing one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ErrorKind {
+    /// the exchange ended without anything going wrong on this side
+    Closed,
+    /// the connection itself failed
+    Transport,
+    /// a message was malformed or arrived out of order
+    Invalid,
+    /// the request was understood but refused
+    Rejected,
+    /// the request couldn't be handled right now but could be later
+    Unavailable,
+    /// something went wrong on this side that the peer can't do anything about
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn close_code(self) -> u16 {
+        match self {
+            Self::Closed => 1000,
+            Self::Transport => 1002,
+            Self::Invalid => INVALID_MESSAGE,
+            Self::Rejected => REJECTED,
+            Self::Unavailable => TRY_AGAIN_LATER,
+            Self::Internal => 1011,
+        }
+    }
+
+    /// the category a close code was sent for, `None` for codes neither side sends
+    pub fn from_close_code(code: u16) -> Option<Self> {
+        match code {
+            1000 => Some(Self::Closed),
+            1002 => Some(Self::Transport),
+            INVALID_MESSAGE => Some(Self::Invalid),
+            REJECTED => Some(Self::Rejected),
+            TRY_AGAIN_LATER => Some(Self::Unavailable),
+            1011 => Some(Self::Internal),
+            _ => None,
+        }
+    }
+}
+
+/// The operations a client can start on the server
+///
+/// In a session each operation starts with a frame carrying the bincode encoded operation, see
+/// [`SESSION_PATH`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Operation {
+    Registration,
+    Authenticate,
+    ChangePassword,
+    Delete,
+}
+
+impl Operation {
+    /// endpoint the operation has of its own, outside of a session
+    pub fn path(self) -> &'static str {
+        match self {
+            Self::Registration => "registration",
+            Self::Authenticate => "authenticate",
+            Self::ChangePassword => "change_password",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Registration => write!(f, "registration"),
+            Self::Authenticate => write!(f, "authenticate"),
+            Self::ChangePassword => write!(f, "change password"),
+            Self::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+/// Optional protocol behaviour a server may or may not support
+///
+/// Servers list what they support in the [`FEATURES_HEADER`] of every connection they accept,
+/// so clients can check before trying something the server doesn't do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Feature {
+    /// messages are padded to a fixed size
+    Padding,
+    /// credentials can be checked without logging in
+    VerifyOnly,
+    /// passwords can be changed in place
+    ChangePassword,
+    /// users can delete their own account
+    Deletion,
+    /// operations can run one after another over a single connection, see [`SESSION_PATH`]
+    Session,
+    /// users can keep a blob on the server, see [`STORE_PATH`] and [`RETRIEVE_PATH`]
+    Blobs,
+    /// users can read and replace their attributes with a session token, see [`ATTRIBUTES_PATH`]
+    Attributes,
+}
+
+impl Feature {
+    pub const ALL: [Self; 7] = [
+        Self::Padding,
+        Self::VerifyOnly,
+        Self::ChangePassword,
+        Self::Deletion,
+        Self::Session,
+        Self::Blobs,
+        Self::Attributes,
+    ];
+
+    /// name of the feature on the wire
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Padding => "padding",
+            Self::VerifyOnly => "verify-only",
+            Self::ChangePassword => "change-password",
+            Self::Deletion => "delete",
+            Self::Session => "session",
+            Self::Blobs => "blobs",
+            Self::Attributes => "attributes",
+        }
+    }
+
+    /// the feature with the given name, `None` for features this side doesn't know about
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|feature| feature.name() == name)
+    }
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What the server says about itself on [`INFO_PATH`], nothing in it needs credentials
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub version: String,
+    pub features: Vec<Feature>,
+    /// the server's clock in seconds since the unix epoch, for spotting clock skew
+    pub server_time: u64,
+    /// whether connections that didn't come over TLS are refused
+    #[serde(default)]
+    pub require_tls: bool,
+}
+
+/// the features listed in a [`FEATURES_HEADER`] value, skipping unknown ones
+pub fn parse_features(value: &str) -> Vec<Feature> {
+    value
+        .split(',')
+        .filter_map(|name| Feature::from_name(name.trim()))
+        .collect()
+}
+
+/// Stable identifiers the server closes failed exchanges with, meant to be matched on rather
+/// than shown to users
+pub mod kind {
+    pub const CLOSED_EARLY: &str = "closed_early";
+    pub const USER_ALREADY_EXISTS: &str = "user_already_exists";
+    pub const USER_DOES_NOT_EXIST: &str = "user_does_not_exist";
+    pub const CLIENT_UNRESPONSIVE: &str = "client_unresponsive";
+    pub const TOO_MANY_ATTEMPTS: &str = "too_many_attempts";
+    pub const PROTOCOL: &str = "protocol";
+    pub const WEBSOCKET: &str = "websocket";
+    pub const IO: &str = "io";
+    pub const HTTP: &str = "http";
+    pub const UNEXPECTED_FRAME: &str = "unexpected_frame";
+    pub const SERIALIZATION: &str = "serialization";
+    pub const DATABASE: &str = "database";
+    pub const DATABASE_UNAVAILABLE: &str = "database_unavailable";
+    pub const ATTRIBUTES: &str = "attributes";
+    pub const PADDING: &str = "padding";
+    pub const INVALID_USERNAME: &str = "invalid_username";
+    pub const SETUP: &str = "setup";
+    pub const REREGISTRATION_REQUIRED: &str = "reregistration_required";
+    pub const INSTANCE_MISMATCH: &str = "instance_mismatch";
+    pub const NOT_AUTHENTICATED: &str = "not_authenticated";
+    pub const USERNAME_MISMATCH: &str = "username_mismatch";
+    /// followed by the seconds until the account unlocks, when the server knows
+    pub const ACCOUNT_LOCKED: &str = "account_locked";
+    pub const BUSY: &str = "busy";
+    pub const UNSUPPORTED: &str = "unsupported";
+    pub const TLS: &str = "tls";
+    pub const INVALID_INVITE: &str = "invalid_invite";
+    pub const BOOTSTRAP_UNAVAILABLE: &str = "bootstrap_unavailable";
+    pub const BLOB_TOO_LARGE: &str = "blob_too_large";
+    pub const PAYLOAD_TOO_LARGE: &str = "payload_too_large";
+    pub const OUT_OF_SEQUENCE: &str = "out_of_sequence";
+}
+
+/// A close frame's status code and reason, as either side sends them and reads them back
+///
+/// On the wire the reason stays the `kind: detail` text [`close_reason`] builds, it has to fit in
+/// [`MAX_CLOSE_REASON`] bytes and stays readable for peers that know nothing of this type
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CloseReason {
+    pub code: u16,
+    /// category the code was sent for, `None` for codes neither side sends
+    pub kind: Option<ErrorKind>,
+    /// stable identifier from [`kind`](mod@kind), or whatever a foreign peer sent
+    pub message: String,
+    pub detail: Option<String>,
+}
+
+impl CloseReason {
+    pub fn new(kind: ErrorKind, message: &str, detail: Option<&str>) -> Self {
+        Self {
+            code: kind.close_code(),
+            kind: Some(kind),
+            message: message.into(),
+            detail: detail.map(Into::into),
+        }
+    }
+
+    /// decode the payload of a close frame, which starts with the status code
+    pub fn from_payload(payload: &[u8]) -> Self {
+        let (code, reason) = match payload.split_first_chunk::<2>() {
+            Some((code, reason)) => (u16::from_be_bytes(*code), reason),
+            // a close without a payload has no status, which RFC 6455 reports as 1005
+            None => (1005, &[][..]),
+        };
+        let reason = String::from_utf8_lossy(reason);
+        let (message, detail) = match reason.split_once(": ") {
+            Some((message, detail)) => (message.into(), Some(detail.into())),
+            None => (reason.into_owned(), None),
+        };
+        Self {
+            code,
+            kind: ErrorKind::from_close_code(code),
+            message,
+            detail,
+        }
+    }
+
+    /// the reason to send after the status code
+    pub fn to_payload(&self) -> Vec<u8> {
+        close_reason(&self.message, self.detail.as_deref())
+    }
+
+    /// whether the exchange ended the way it was supposed to
+    pub fn is_normal(&self) -> bool {
+        self.code == 1000
+    }
+
+    /// the reason as it was sent, `kind: detail`
+    pub fn text(&self) -> String {
+        match &self.detail {
+            Some(detail) => format!("{}: {detail}", self.message),
+            None => self.message.clone(),
+        }
+    }
+}
+
+impl Display for CloseReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.text(), self.code)
+    }
+}
+
+/// payload of a close reason, `kind` optionally followed by a short detail
+///
+/// anything that isn't printable ascii is dropped from the detail and the whole reason is cut
+/// off at [`MAX_CLOSE_REASON`] bytes, so the close frame is always valid
+pub fn close_reason(kind: &str, detail: Option<&str>) -> Vec<u8> {
+    let mut reason: Vec<u8> = kind.bytes().take(MAX_CLOSE_REASON).collect();
+    if let Some(detail) = detail {
+        reason.extend_from_slice(b": ");
+        reason.extend(
+            detail
+                .bytes()
+                .filter(|byte| byte.is_ascii_graphic() || *byte == b' '),
+        );
+        reason.truncate(MAX_CLOSE_REASON);
+    }
+    reason
+}
+
+/// payload of the text frame ending an operation in a session, see [`SESSION_PATH`]
+///
+/// text frames have to be valid UTF-8, so the status code goes first written out in its four
+/// digits rather than as the two bytes a close frame starts with
+pub fn session_end(code: u16, reason: &[u8]) -> Vec<u8> {
+    let mut payload = format!("{code:04}").into_bytes();
+    payload.extend_from_slice(reason);
+    payload
+}
+
+/// the close frame payload a [`session_end`] text stands in for, `None` when it doesn't start
+/// with a status code
+pub fn session_close(text: &[u8]) -> Option<Vec<u8>> {
+    let (digits, reason) = text.split_first_chunk::<4>()?;
+    let code: u16 = std::str::from_utf8(digits).ok()?.parse().ok()?;
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason);
+    Some(payload)
+}
@@ -0,0 +1,370 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{pair, s, server};
+use opaque_ke::ServerSetup;
+use rand::rngs::OsRng;
+use tinap::{
+    client::error::ClientError,
+    derive_key,
+    loopback::loopback_pair,
+    outcome::{DeleteOutcome, RegistrationOutcome},
+    server::{events::ServerEvent, record, Server},
+    Identifiers, Scheme,
+};
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn registered_user_logs_in() {
+    let (server, client) = pair();
+    assert_eq!(
+        client
+            .register_user(s("alice"), s("hunter2"))
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+
+    let confirm = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .expect("login was refused");
+    assert!(!confirm.session_key().is_empty());
+    assert!(client.verify(s("alice"), s("hunter2")).await.unwrap());
+    assert_eq!(server.user_count().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn wrong_password_is_refused() {
+    let (server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let mut events = server.subscribe();
+
+    assert!(client
+        .authenticate(s("alice"), s("hunter3"))
+        .await
+        .unwrap()
+        .is_none());
+    assert!(!client.verify(s("alice"), s("hunter3")).await.unwrap());
+    // the server finishes its side after the client has its answer
+    for _ in 0..2 {
+        loop {
+            let event = timeout(Duration::from_secs(5), events.recv()).await;
+            if let ServerEvent::AuthenticationFailed { .. } = event.unwrap().unwrap() {
+                break;
+            }
+        }
+    }
+    // the owner can find out why, the client only ever sees the uniform failure
+    assert_eq!(server.auth_failures(b"alice").unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn verifying_is_not_a_login() {
+    let store = sled::Config::new().temporary(true).open().unwrap();
+    let server = Server::new(ServerSetup::<Scheme>::new(&mut OsRng), store.clone());
+    let client = loopback_pair(&server);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let mut events = server.subscribe();
+
+    assert!(client.verify(s("alice"), s("hunter2")).await.unwrap());
+    // the server finishes its side after the client has its answer
+    let event = timeout(Duration::from_secs(5), events.recv()).await;
+    assert!(
+        matches!(event.unwrap().unwrap(), ServerEvent::Verified { .. }),
+        "a check was reported as something else"
+    );
+
+    assert!(store.open_tree("sessions").unwrap().is_empty());
+    let stored = store.get(b"alice").unwrap().expect("alice wasn't stored");
+    assert_eq!(record::unseal(&stored).unwrap().last_login, None);
+}
+
+#[tokio::test]
+async fn unknown_user_fails_like_a_wrong_password() {
+    let (server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    assert!(client
+        .authenticate(s("bob"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        client.delete_user(s("bob"), s("hunter2")).await.unwrap(),
+        client.delete_user(s("alice"), s("wrong")).await.unwrap()
+    );
+    // nothing is recorded against a user that doesn't exist
+    assert!(server.auth_failures(b"bob").unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn registering_a_taken_name_keeps_the_first_account() {
+    let (_server, client) = pair();
+    assert_eq!(
+        client
+            .register_user(s("alice"), s("hunter2"))
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+    assert_eq!(
+        client.register_user(s("alice"), s("other")).await.unwrap(),
+        RegistrationOutcome::AlreadyExists
+    );
+
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+    assert!(client
+        .authenticate(s("alice"), s("other"))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn concurrent_registrations_create_one_account() {
+    let (server, client) = pair();
+    let outcomes = tokio::join!(
+        client.register_user(s("alice"), s("first")),
+        client.register_user(s("alice"), s("second")),
+        client.register_user(s("alice"), s("third")),
+        client.register_user(s("alice"), s("fourth")),
+    );
+    let created = [outcomes.0, outcomes.1, outcomes.2, outcomes.3]
+        .into_iter()
+        .filter(|outcome| *outcome.as_ref().unwrap() == RegistrationOutcome::Created)
+        .count();
+    assert_eq!(created, 1);
+    assert_eq!(server.user_count().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn deleted_user_is_gone() {
+    let (server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        client.delete_user(s("alice"), s("wrong")).await.unwrap(),
+        DeleteOutcome::NotAuthenticated
+    );
+    assert_eq!(
+        client.delete_user(s("alice"), s("hunter2")).await.unwrap(),
+        DeleteOutcome::Deleted
+    );
+    assert_eq!(server.user_count().await.unwrap(), 0);
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        client.delete_user(s("alice"), s("hunter2")).await.unwrap(),
+        DeleteOutcome::NotAuthenticated
+    );
+}
+
+#[tokio::test]
+async fn events_follow_an_account_from_registration_to_deletion() {
+    let (server, client) = pair();
+    let mut events = server.subscribe();
+    let mut seen = Vec::new();
+
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        client.delete_user(s("alice"), s("hunter2")).await.unwrap(),
+        DeleteOutcome::Deleted
+    );
+    // the server finishes its side after the client has its answer
+    loop {
+        let event = timeout(Duration::from_secs(5), events.recv()).await;
+        let event = event.unwrap().unwrap();
+        let deleted = matches!(event, ServerEvent::Deleted { .. });
+        seen.push(event);
+        if deleted {
+            break;
+        }
+    }
+
+    assert!(
+        matches!(
+            &seen[..],
+            [
+                ServerEvent::Registered { .. },
+                ServerEvent::Authenticated {
+                    unconfirmed: false,
+                    ..
+                },
+                // deleting starts with a login of its own
+                ServerEvent::Authenticated { .. },
+                ServerEvent::Deleted {
+                    unconfirmed: false,
+                    ..
+                },
+            ]
+        ),
+        "{seen:?}"
+    );
+    assert!(events.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn usernames_are_normalized() {
+    let (server, client) = pair();
+    client
+        .register_user(s("  Alice "), s("hunter2"))
+        .await
+        .unwrap();
+
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        client.register_user(s("ALICE"), s("other")).await.unwrap(),
+        RegistrationOutcome::AlreadyExists
+    );
+    assert_eq!(server.list_users(0, 10).await.unwrap(), vec![s("alice")]);
+
+    let err = client
+        .register_user(s("al\u{7}ice"), s("hunter2"))
+        .await
+        .expect_err("a control character was let through");
+    assert!(matches!(err.inner(), ClientError::Username(_)), "{err:?}");
+}
+
+#[tokio::test]
+async fn registration_hands_back_the_login_export_key() {
+    let (_server, client) = pair();
+    let registered = client
+        .register_confirmed(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .expect("username was taken");
+    let confirm = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .expect("login was refused");
+
+    assert_eq!(registered.export_key(), confirm.export_key());
+    assert_eq!(registered.server_public_key(), confirm.server_public_key());
+    assert!(client
+        .register_confirmed(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn pinned_server_key_is_enforced() {
+    let (server, client) = pair();
+    let registered = client
+        .register_confirmed(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .expect("username was taken");
+    let pinned =
+        loopback_pair(&server).with_server_public_key(registered.server_public_key().into());
+    assert!(pinned
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+
+    // the same account on a server with other keys
+    let (_other, client) = pair();
+    let other = client
+        .register_confirmed(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .expect("username was taken");
+    let pinned = client.with_server_public_key(registered.server_public_key().into());
+    assert_ne!(other.server_public_key(), registered.server_public_key());
+    let err = pinned
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .expect_err("login went through with the wrong server");
+    assert!(
+        matches!(err.inner(), ClientError::ServerKeyMismatch),
+        "{err:?}"
+    );
+}
+
+#[tokio::test]
+async fn identifiers_have_to_match() {
+    let identifiers = Identifiers {
+        client: None,
+        server: Some(b"auth.example.com".to_vec()),
+    };
+    let server = server().with_identifiers(identifiers.clone());
+    let client = loopback_pair(&server).with_identifiers(identifiers);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+    assert!(loopback_pair(&server)
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn derived_keys_follow_the_session_key() {
+    let (_server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let confirm = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .expect("login was refused");
+
+    let mac = confirm.derive_key(b"mac", 32).unwrap();
+    assert_eq!(mac, derive_key(confirm.session_key(), b"mac", 32).unwrap());
+    assert_ne!(mac, confirm.derive_key(b"storage", 32).unwrap());
+    assert!(confirm.derive_key(b"mac", 255 * 64 + 1).is_err());
+
+    // every login gets its own session key
+    let again = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .expect("login was refused");
+    assert_ne!(mac, again.derive_key(b"mac", 32).unwrap());
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{call, s, server};
+use tinap::{loopback::loopback_pair, server::admin::UserPage};
+
+const ADMIN_TOKEN: &str = "admin secret";
+
+#[tokio::test]
+async fn admin_lists_users_a_page_at_a_time() {
+    let server = server().with_admin_token(s(ADMIN_TOKEN));
+    let client = loopback_pair(&server);
+    for username in ["carol", "alice", "bob"] {
+        client
+            .register_user(s(username), s("hunter2"))
+            .await
+            .unwrap();
+    }
+
+    let (status, body) = call(
+        server.router(),
+        Method::GET,
+        "/admin/users",
+        Some(ADMIN_TOKEN),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let page: UserPage = serde_json::from_str(&body).unwrap();
+    assert_eq!(page.total, 3);
+    assert_eq!(page.users, vec![s("alice"), s("bob"), s("carol")]);
+
+    let (status, body) = call(
+        server.router(),
+        Method::GET,
+        "/admin/users?offset=1&limit=1",
+        Some(ADMIN_TOKEN),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let page: UserPage = serde_json::from_str(&body).unwrap();
+    assert_eq!((page.total, page.offset), (3, 1));
+    assert_eq!(page.users, vec![s("bob")]);
+}
+
+#[tokio::test]
+async fn admin_force_deletes_a_user() {
+    let server = server().with_admin_token(s(ADMIN_TOKEN));
+    let client = loopback_pair(&server);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    let (status, _) = call(
+        server.router(),
+        Method::DELETE,
+        "/admin/users/alice",
+        Some(ADMIN_TOKEN),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_none());
+
+    let (status, _) = call(
+        server.router(),
+        Method::DELETE,
+        "/admin/users/alice",
+        Some(ADMIN_TOKEN),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn admin_routes_need_the_token() {
+    let server = server().with_admin_token(s(ADMIN_TOKEN));
+    loopback_pair(&server)
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    for token in [None, Some("wrong")] {
+        let (status, _) = call(server.router(), Method::GET, "/admin/users", token, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = call(
+            server.router(),
+            Method::DELETE,
+            "/admin/users/alice",
+            token,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    assert_eq!(server.user_count().await.unwrap(), 1);
+
+    // without a token configured the routes aren't there at all
+    let (status, _) = call(
+        common::server().router(),
+        Method::GET,
+        "/admin/users",
+        Some(ADMIN_TOKEN),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
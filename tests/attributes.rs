@@ -0,0 +1,342 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use axum::http::{Method, StatusCode};
+use common::{call, login, pair, s};
+use opaque_ke::ServerSetup;
+use rand::rngs::OsRng;
+use tinap::{
+    attributes::{AttributeError, Attributes, MAX_ATTRIBUTES, MAX_KEY_LEN, MAX_VALUE_LEN},
+    client::error::ClientError,
+    loopback::loopback_pair,
+    server::{
+        backup::{export_users, import_users},
+        Server, ATTRIBUTES_TREE,
+    },
+    wire::kind,
+    Scheme,
+};
+
+const ADMIN_TOKEN: &str = "admin secret";
+
+fn attributes(entries: &[(&str, &str)]) -> Attributes {
+    let mut attributes = Attributes::new();
+    for (key, value) in entries {
+        attributes.insert(s(key), s(value)).unwrap();
+    }
+    attributes
+}
+
+/// `count` distinct attributes, each within the limits
+fn many(count: usize) -> BTreeMap<String, String> {
+    (0..count)
+        .map(|i| (format!("key{i}"), s("value")))
+        .collect()
+}
+
+#[tokio::test]
+async fn user_reads_and_replaces_their_attributes() {
+    let (_server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let token = login(&client, "alice", "hunter2").await;
+
+    assert!(client.get_attributes(&token).await.unwrap().is_empty());
+
+    let first = attributes(&[("locale", "en"), ("display_name", "Alice")]);
+    client.set_attributes(&token, &first).await.unwrap();
+    assert_eq!(client.get_attributes(&token).await.unwrap(), first);
+
+    // setting replaces the whole map, anything left out is gone
+    let second = attributes(&[("locale", "de")]);
+    client.set_attributes(&token, &second).await.unwrap();
+    let stored = client.get_attributes(&token).await.unwrap();
+    assert_eq!(stored, second);
+    assert_eq!(stored.get("display_name"), None);
+}
+
+#[tokio::test]
+async fn attributes_are_kept_per_user() {
+    let (server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    client.register_user(s("bob"), s("hunter3")).await.unwrap();
+    let alice = login(&client, "alice", "hunter2").await;
+    let bob = login(&client, "bob", "hunter3").await;
+
+    client
+        .set_attributes(&alice, &attributes(&[("locale", "en")]))
+        .await
+        .unwrap();
+
+    assert!(client.get_attributes(&bob).await.unwrap().is_empty());
+    assert!(server.attributes(b"bob").unwrap().is_empty());
+    assert_eq!(
+        server.attributes(b"alice").unwrap().get("locale"),
+        Some("en")
+    );
+}
+
+#[tokio::test]
+async fn unauthenticated_access_is_refused() {
+    let (server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    server
+        .set_attributes(b"alice", &attributes(&[("locale", "en")]))
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        client.get_attributes("not a token").await,
+        Err(ClientError::NotAuthenticated)
+    ));
+    assert!(matches!(
+        client
+            .set_attributes("not a token", &attributes(&[("locale", "de")]))
+            .await,
+        Err(ClientError::NotAuthenticated)
+    ));
+
+    let (status, _) = call(server.router(), Method::GET, "/attributes", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = call(
+        server.router(),
+        Method::PUT,
+        "/attributes",
+        None,
+        Some(s(r#"{"locale":"de"}"#)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // a revoked token is as good as none
+    let token = login(&client, "alice", "hunter2").await;
+    assert!(server.revoke_token(&token).unwrap());
+    assert!(matches!(
+        client.get_attributes(&token).await,
+        Err(ClientError::NotAuthenticated)
+    ));
+    assert_eq!(
+        server.attributes(b"alice").unwrap().get("locale"),
+        Some("en")
+    );
+}
+
+#[test]
+fn limits_are_checked_on_insert() {
+    let mut attributes = Attributes::new();
+    assert!(matches!(
+        attributes.insert(s(""), s("value")),
+        Err(AttributeError::EmptyKey)
+    ));
+    assert!(matches!(
+        attributes.insert("k".repeat(MAX_KEY_LEN + 1), s("value")),
+        Err(AttributeError::KeyTooLong(_))
+    ));
+    assert!(matches!(
+        attributes.insert(s("key"), "v".repeat(MAX_VALUE_LEN + 1)),
+        Err(AttributeError::ValueTooLong(_))
+    ));
+    assert!(attributes.is_empty());
+
+    attributes
+        .insert("k".repeat(MAX_KEY_LEN), "v".repeat(MAX_VALUE_LEN))
+        .unwrap();
+    for (key, value) in many(MAX_ATTRIBUTES - 1) {
+        attributes.insert(key, value).unwrap();
+    }
+    assert_eq!(attributes.len(), MAX_ATTRIBUTES);
+    assert!(matches!(
+        attributes.insert(s("one more"), s("value")),
+        Err(AttributeError::TooMany(_))
+    ));
+    // replacing a value doesn't count as another attribute
+    attributes.insert(s("key0"), s("replaced")).unwrap();
+    assert_eq!(attributes.get("key0"), Some("replaced"));
+}
+
+#[test]
+fn limits_are_checked_on_deserialize() {
+    let fits = serde_json::to_string(&many(MAX_ATTRIBUTES)).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Attributes>(&fits).unwrap().len(),
+        MAX_ATTRIBUTES
+    );
+
+    let too_many = serde_json::to_string(&many(MAX_ATTRIBUTES + 1)).unwrap();
+    assert!(serde_json::from_str::<Attributes>(&too_many).is_err());
+    let empty_key = r#"{"":"value"}"#;
+    assert!(serde_json::from_str::<Attributes>(empty_key).is_err());
+    let long_value = format!(r#"{{"key":"{}"}}"#, "v".repeat(MAX_VALUE_LEN + 1));
+    assert!(serde_json::from_str::<Attributes>(&long_value).is_err());
+}
+
+#[tokio::test]
+async fn server_refuses_attributes_over_the_limits() {
+    let (server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let token = login(&client, "alice", "hunter2").await;
+    client
+        .set_attributes(&token, &attributes(&[("locale", "en")]))
+        .await
+        .unwrap();
+
+    let too_many = serde_json::to_string(&many(MAX_ATTRIBUTES + 1)).unwrap();
+    let (status, body) = call(
+        server.router(),
+        Method::PUT,
+        "/attributes",
+        Some(&token),
+        Some(too_many),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body, kind::ATTRIBUTES);
+
+    let long_key = format!(r#"{{"{}":"value"}}"#, "k".repeat(MAX_KEY_LEN + 1));
+    let (status, _) = call(
+        server.router(),
+        Method::PUT,
+        "/attributes",
+        Some(&token),
+        Some(long_key),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // a refused update leaves the stored attributes alone
+    assert_eq!(
+        client.get_attributes(&token).await.unwrap(),
+        attributes(&[("locale", "en")])
+    );
+}
+
+#[tokio::test]
+async fn admin_reads_and_replaces_attributes() {
+    let server = common::server().with_admin_token(s(ADMIN_TOKEN));
+    let client = loopback_pair(&server);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    let (status, body) = call(
+        server.router(),
+        Method::PUT,
+        "/admin/users/alice/attributes",
+        Some(ADMIN_TOKEN),
+        Some(s(r#"{"plan":"pro"}"#)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT, "{body}");
+    let (status, body) = call(
+        server.router(),
+        Method::GET,
+        "/admin/users/alice/attributes",
+        Some(ADMIN_TOKEN),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        serde_json::from_str::<Attributes>(&body).unwrap(),
+        attributes(&[("plan", "pro")])
+    );
+
+    // the user sees what the admin set
+    let token = login(&client, "alice", "hunter2").await;
+    assert_eq!(
+        client.get_attributes(&token).await.unwrap(),
+        attributes(&[("plan", "pro")])
+    );
+
+    let (status, _) = call(
+        server.router(),
+        Method::GET,
+        "/admin/users/nobody/attributes",
+        Some(ADMIN_TOKEN),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(
+        server.router(),
+        Method::PUT,
+        "/admin/users/alice/attributes",
+        Some(ADMIN_TOKEN),
+        Some(serde_json::to_string(&many(MAX_ATTRIBUTES + 1)).unwrap()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // a session token is no admin token
+    let (status, _) = call(
+        server.router(),
+        Method::GET,
+        "/admin/users/alice/attributes",
+        Some(&token),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn deleting_the_user_purges_attributes() {
+    let (server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    server
+        .set_attributes(b"alice", &attributes(&[("locale", "en")]))
+        .await
+        .unwrap();
+
+    server.delete_user(b"alice").await.unwrap();
+    client.register_user(s("alice"), s("other")).await.unwrap();
+    assert!(server.attributes(b"alice").unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn attributes_survive_export_and_import() {
+    let store = sled::Config::new().temporary(true).open().unwrap();
+    let server = Server::new(ServerSetup::<Scheme>::new(&mut OsRng), store.clone());
+    let client = loopback_pair(&server);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    client.register_user(s("bob"), s("hunter3")).await.unwrap();
+    let stored = attributes(&[("locale", "en"), ("plan", "pro")]);
+    server.set_attributes(b"alice", &stored).await.unwrap();
+
+    let backup = export_users(&store, &store.open_tree(ATTRIBUTES_TREE).unwrap()).unwrap();
+    let json = serde_json::to_string(&backup).unwrap();
+    let backup = serde_json::from_str(&json).unwrap();
+
+    let restored = sled::Config::new().temporary(true).open().unwrap();
+    let report = import_users(
+        &restored,
+        &restored.open_tree(ATTRIBUTES_TREE).unwrap(),
+        &backup,
+        false,
+    )
+    .unwrap();
+    assert_eq!(report.added, 2);
+
+    let server = Server::new(ServerSetup::<Scheme>::new(&mut OsRng), restored);
+    assert_eq!(server.attributes(b"alice").unwrap(), stored);
+    assert!(server.attributes(b"bob").unwrap().is_empty());
+}
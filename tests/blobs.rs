@@ -0,0 +1,85 @@
+mod common;
+
+use common::{pair, s};
+use tinap::client::error::ClientError;
+
+#[tokio::test]
+async fn blob_round_trips() {
+    let (_server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        client
+            .retrieve_blob(s("alice"), s("hunter2"))
+            .await
+            .unwrap(),
+        None
+    );
+    client
+        .store_blob(s("alice"), s("hunter2"), b"recovery codes")
+        .await
+        .unwrap();
+    assert_eq!(
+        client
+            .retrieve_blob(s("alice"), s("hunter2"))
+            .await
+            .unwrap()
+            .as_deref(),
+        Some(&b"recovery codes"[..])
+    );
+
+    // storing again replaces it
+    client
+        .store_blob(s("alice"), s("hunter2"), b"new codes")
+        .await
+        .unwrap();
+    assert_eq!(
+        client
+            .retrieve_blob(s("alice"), s("hunter2"))
+            .await
+            .unwrap()
+            .as_deref(),
+        Some(&b"new codes"[..])
+    );
+}
+
+#[tokio::test]
+async fn blobs_need_the_password() {
+    let (_server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    client
+        .store_blob(s("alice"), s("hunter2"), b"recovery codes")
+        .await
+        .unwrap();
+
+    let err = client
+        .retrieve_blob(s("alice"), s("wrong"))
+        .await
+        .expect_err("blob was handed out without the password");
+    assert!(
+        matches!(err.inner(), ClientError::NotAuthenticated),
+        "{err:?}"
+    );
+    let err = client
+        .store_blob(s("alice"), s("wrong"), b"overwritten")
+        .await
+        .expect_err("blob was replaced without the password");
+    assert!(
+        matches!(err.inner(), ClientError::NotAuthenticated),
+        "{err:?}"
+    );
+    assert_eq!(
+        client
+            .retrieve_blob(s("alice"), s("hunter2"))
+            .await
+            .unwrap()
+            .as_deref(),
+        Some(&b"recovery codes"[..])
+    );
+}
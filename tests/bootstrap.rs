@@ -0,0 +1,96 @@
+mod common;
+
+use common::{s, server};
+use tinap::{
+    client::error::ClientError, loopback::loopback_pair, outcome::RegistrationOutcome,
+    server::bootstrap::ADMIN_ATTRIBUTE, wire::kind,
+};
+
+const TOKEN: &str = "let me in";
+
+#[tokio::test]
+async fn bootstrap_creates_an_admin() {
+    let server = server().with_bootstrap_token(s(TOKEN)).await.unwrap();
+    let client = loopback_pair(&server).with_bootstrap_token(Some(s(TOKEN)));
+
+    assert_eq!(
+        client.register_user(s("root"), s("hunter2")).await.unwrap(),
+        RegistrationOutcome::Created
+    );
+
+    let attributes = server.attributes(b"root").unwrap();
+    assert_eq!(attributes.get(ADMIN_ATTRIBUTE), Some("true"));
+    assert!(client
+        .authenticate(s("root"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn bootstrap_only_works_once() {
+    let server = server().with_bootstrap_token(s(TOKEN)).await.unwrap();
+    let client = loopback_pair(&server).with_bootstrap_token(Some(s(TOKEN)));
+    assert_eq!(
+        client.register_user(s("root"), s("hunter2")).await.unwrap(),
+        RegistrationOutcome::Created
+    );
+
+    assert!(client
+        .register_user(s("second"), s("hunter2"))
+        .await
+        .is_err());
+    assert_eq!(server.user_count().await.unwrap(), 1);
+    assert_eq!(
+        server.attributes(b"second").unwrap().get(ADMIN_ATTRIBUTE),
+        None
+    );
+}
+
+#[tokio::test]
+async fn bootstrap_token_is_ignored_when_users_exist() {
+    let server = server();
+    let client = loopback_pair(&server);
+    assert_eq!(
+        client
+            .register_user(s("alice"), s("hunter2"))
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+
+    // users already exist, so the token is ignored
+    let server = server.with_bootstrap_token(s(TOKEN)).await.unwrap();
+    let client = loopback_pair(&server).with_bootstrap_token(Some(s(TOKEN)));
+    assert!(client.register_user(s("root"), s("hunter2")).await.is_err());
+    assert_eq!(
+        server.attributes(b"root").unwrap().get(ADMIN_ATTRIBUTE),
+        None
+    );
+}
+
+#[tokio::test]
+async fn bootstrap_is_refused_once_users_exist() {
+    let server = server().with_bootstrap_token(s(TOKEN)).await.unwrap();
+    // a plain registration sneaks in after the token was accepted
+    let plain = loopback_pair(&server);
+    assert_eq!(
+        plain.register_user(s("alice"), s("hunter2")).await.unwrap(),
+        RegistrationOutcome::Created
+    );
+
+    let client = loopback_pair(&server).with_bootstrap_token(Some(s(TOKEN)));
+    let err = client
+        .register_user(s("root"), s("hunter2"))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err.inner(), ClientError::Rejected(_, reason) if reason == kind::BOOTSTRAP_UNAVAILABLE),
+        "{err:?}"
+    );
+    assert_eq!(server.user_count().await.unwrap(), 1);
+    assert_eq!(
+        server.attributes(b"root").unwrap().get(ADMIN_ATTRIBUTE),
+        None
+    );
+}
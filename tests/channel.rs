@@ -0,0 +1,83 @@
+mod common;
+
+use axum::Router;
+use common::s;
+use fastwebsockets::{Frame, OpCode};
+use tinap::{
+    client::Client,
+    server::{autheticate::AuthConfirm, ws_authenticate_with, AppSocket},
+};
+use tokio::{net::TcpListener, sync::mpsc};
+
+/// a server with an endpoint that opens every frame after a login, reporting how that went to
+/// `opened` and answering the ones that check out
+async fn listen(opened: mpsc::UnboundedSender<Result<Vec<u8>, String>>) -> Client {
+    let server = common::server();
+    let handler = move |confirm: AuthConfirm, mut ws: AppSocket| async move {
+        let mut channel = confirm.channel();
+        while let Ok(frame) = ws.read_frame().await {
+            if frame.opcode == OpCode::Close {
+                break;
+            }
+            match channel.open(&frame.payload) {
+                Ok(message) => {
+                    let reply = channel.seal(&[b"got ", message.as_slice()].concat());
+                    let _ = opened.send(Ok(message));
+                    if ws.write_frame(Frame::binary(reply.into())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    let _ = opened.send(Err(err.to_string()));
+                }
+            }
+        }
+    };
+    let router = Router::new()
+        .route("/echo", ws_authenticate_with(handler))
+        .with_state(server.clone())
+        .merge(server.router());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    let client = Client::new(s("127.0.0.1"), port);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    client
+}
+
+#[tokio::test]
+async fn sealed_frames_interoperate_and_tampering_is_refused() {
+    let (opened, mut server_side) = mpsc::unbounded_channel();
+    let client = listen(opened).await;
+    let (confirm, mut ws) = client
+        .authenticate_then("echo", s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .expect("login was refused");
+    let mut channel = confirm.channel();
+
+    let first = channel.seal(b"hello");
+    ws.write_frame(Frame::binary(first.clone().into()))
+        .await
+        .unwrap();
+    assert_eq!(server_side.recv().await.unwrap().unwrap(), b"hello");
+    let reply = ws.read_frame().await.unwrap();
+    assert_eq!(channel.open(&reply.payload).unwrap(), b"got hello");
+
+    let mut tampered = channel.seal(b"transfer 10");
+    // the last byte of the ciphertext, right before the tag
+    let at = tampered.len() - 17;
+    tampered[at] ^= 1;
+    ws.write_frame(Frame::binary(tampered.into()))
+        .await
+        .unwrap();
+    let refused = server_side.recv().await.unwrap().unwrap_err();
+    assert!(refused.contains("does not check out"), "{refused}");
+
+    ws.write_frame(Frame::binary(first.into())).await.unwrap();
+    let refused = server_side.recv().await.unwrap().unwrap_err();
+    assert!(refused.contains("already opened"), "{refused}");
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use std::{
+    io::{BufRead, BufReader},
+    path::Path,
+    process::{Command, Stdio},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use common::{s, temp_dir};
+use tinap::{
+    client::Client,
+    ksf::{Argon2Params, MIN_MEMORY_KIB},
+    loopback::loopback_pair,
+    outcome::RegistrationOutcome,
+    server::{config::ServerConfig, instance::MismatchPolicy, Server},
+};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+/// set for the child process of [`acknowledged_registrations_survive_a_kill`], to the directory
+/// its server keeps the setup and database in
+const CHILD_DIR: &str = "TINAP_COALESCING_CHILD_DIR";
+/// what the child prints for every registration it was told went through
+const ACKNOWLEDGED: &str = "acknowledged ";
+/// how long the child's writes wait for each other, long enough that it's always holding
+/// inserts that haven't been flushed yet
+const INTERVAL: Duration = Duration::from_millis(200);
+
+fn open(dir: &Path) -> Server {
+    let config = ServerConfig {
+        setup_path: dir.join("setup"),
+        db_path: dir.join("db"),
+        ..ServerConfig::default()
+    };
+    Server::initialize_from(config, "test", MismatchPolicy::Refuse).unwrap()
+}
+
+/// `server`'s loopback client, stretching passwords cheaply so the store is what's measured
+fn client(server: &Server) -> Client {
+    let ksf = Argon2Params {
+        memory_kib: MIN_MEMORY_KIB,
+        iterations: 1,
+        parallelism: 1,
+    }
+    .to_ksf()
+    .unwrap();
+    loopback_pair(server).with_ksf(ksf)
+}
+
+/// registers `concurrency` users at a time until the process is killed, printing every one the
+/// server acknowledged
+#[tokio::test]
+#[ignore = "only runs as the child of acknowledged_registrations_survive_a_kill"]
+async fn register_until_killed() {
+    let Ok(dir) = std::env::var(CHILD_DIR) else {
+        return;
+    };
+    let server = open(Path::new(&dir)).with_write_coalescing(INTERVAL);
+    let client = Arc::new(client(&server));
+    let permits = Arc::new(Semaphore::new(20));
+    for i in 0.. {
+        let permit = permits.clone().acquire_owned().await.unwrap();
+        let client = client.clone();
+        tokio::spawn(async move {
+            let username = format!("user{i}");
+            let outcome = client.register_user(username.clone(), s("hunter2")).await;
+            if let Ok(RegistrationOutcome::Created) = outcome {
+                println!("{ACKNOWLEDGED}{username}");
+            }
+            drop(permit);
+        });
+    }
+}
+
+#[tokio::test]
+async fn acknowledged_registrations_survive_a_kill() {
+    let dir = temp_dir("coalescing-kill");
+    let mut child = Command::new(std::env::current_exe().unwrap())
+        .args([
+            "register_until_killed",
+            "--exact",
+            "--ignored",
+            "--nocapture",
+        ])
+        .env(CHILD_DIR, &dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut acknowledged = Vec::new();
+    while acknowledged.len() < 50 {
+        let line = lines.next().expect("child stopped registering").unwrap();
+        if let Some(username) = line.strip_prefix(ACKNOWLEDGED) {
+            acknowledged.push(username.to_string());
+        }
+    }
+    // SIGKILL, whatever was inserted since the last flush is never written out
+    child.kill().unwrap();
+    child.wait().unwrap();
+    // anything printed before it died was acknowledged before it died too
+    for line in lines {
+        if let Some(username) = line.unwrap().strip_prefix(ACKNOWLEDGED) {
+            acknowledged.push(username.to_string());
+        }
+    }
+
+    let server = open(&dir);
+    assert!(server.user_count().await.unwrap() >= acknowledged.len());
+    let client = client(&server);
+    for username in acknowledged {
+        assert!(
+            client
+                .authenticate(username.clone(), s("hunter2"))
+                .await
+                .unwrap()
+                .is_some(),
+            "{username} was acknowledged but lost"
+        );
+    }
+}
+
+/// registrations per second with 100 running at once against `server`
+async fn throughput(server: &Server) -> f64 {
+    let client = Arc::new(client(server));
+    let started = Instant::now();
+    let mut registrations = JoinSet::new();
+    for i in 0..100 {
+        let client = client.clone();
+        registrations.spawn(async move {
+            client
+                .register_user(format!("user{i}"), s("hunter2"))
+                .await
+                .unwrap()
+        });
+    }
+    while let Some(outcome) = registrations.join_next().await {
+        assert_eq!(outcome.unwrap(), RegistrationOutcome::Created);
+    }
+    100.0 / started.elapsed().as_secs_f64()
+}
+
+/// run with `cargo test --release --test coalescing -- --ignored --nocapture throughput`
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "a benchmark, only meaningful in release builds on a real disk"]
+async fn throughput_at_100_concurrent_registrations() {
+    // nothing waits on the disk without coalescing, this is as fast as it gets
+    let unflushed = throughput(&open(&temp_dir("coalescing-unflushed"))).await;
+    println!("no durability promise: {unflushed:.0} registrations/s");
+    for interval in [
+        Duration::ZERO,
+        Duration::from_millis(5),
+        Duration::from_millis(20),
+    ] {
+        let server = open(&temp_dir("coalescing-bench")).with_write_coalescing(interval);
+        let coalesced = throughput(&server).await;
+        println!("durable, flushing every {interval:?}: {coalesced:.0} registrations/s");
+    }
+}
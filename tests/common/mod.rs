@@ -0,0 +1,193 @@
+#![allow(dead_code)]
+
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use axum::{
+    http::{header::AUTHORIZATION, Method, Request, StatusCode},
+    Router,
+};
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Bytes, client::conn::http1::handshake, server::conn::http1};
+use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+use opaque_ke::ServerSetup;
+use rand::rngs::OsRng;
+use tinap::{
+    client::Client,
+    loopback::loopback_pair,
+    server::Server,
+    wire::{session_close, CloseReason},
+    Scheme,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// tells apart the directories handed out within one test binary
+static NEXT_DIR: AtomicU32 = AtomicU32::new(0);
+
+/// an empty directory no other test uses
+pub fn temp_dir(name: &str) -> PathBuf {
+    let id = NEXT_DIR.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!("tinap-{name}-{}-{id}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("Failed to create test directory");
+    dir
+}
+
+/// a server with a fresh setup over a temporary store
+pub fn server() -> Server {
+    let store = sled::Config::new()
+        .temporary(true)
+        .open()
+        .expect("Failed to open temporary store");
+    Server::new(ServerSetup::<Scheme>::new(&mut OsRng), store)
+}
+
+/// a server and a client wired to it
+pub fn pair() -> (Server, Client) {
+    let server = server();
+    let client = loopback_pair(&server);
+    (server, client)
+}
+
+/// log `username` in and hand back the session token the server issued
+pub async fn login(client: &Client, username: &str, password: &str) -> String {
+    let confirm = client
+        .authenticate(s(username), s(password))
+        .await
+        .unwrap()
+        .expect("login was refused");
+    confirm
+        .session_token()
+        .expect("no session token")
+        .to_string()
+}
+
+/// send a request to `router` served over an in memory stream, giving back the status and the
+/// body
+pub async fn call(
+    router: Router,
+    method: Method,
+    uri: &str,
+    token: Option<&str>,
+    body: Option<String>,
+) -> (StatusCode, String) {
+    let (stream, served) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(
+        http1::Builder::new()
+            .serve_connection(TokioIo::new(served), TowerToHyperService::new(router)),
+    );
+    let (mut sender, conn) = handshake(TokioIo::new(stream)).await.unwrap();
+    tokio::spawn(conn);
+
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Host", "loopback");
+    let request = match token {
+        Some(token) => request.header(AUTHORIZATION, format!("Bearer {token}")),
+        None => request,
+    };
+    let request = match body {
+        Some(body) => request
+            .header("content-type", "application/json")
+            .body(Full::new(Bytes::from(body))),
+        None => request.body(Full::new(Bytes::new())),
+    };
+    let response = sender.send_request(request.unwrap()).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+/// `server` listening on a local port, for tests that talk to it without a [`Client`]
+pub async fn listen(server: &Server) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let router = server.router();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    port
+}
+
+/// a websocket to the endpoint at `path`, upgraded by hand
+pub async fn upgraded(port: u16, path: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let request = format!(
+        "GET /{path} HTTP/1.1\r\n\
+         Host: 127.0.0.1:{port}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).await.unwrap();
+        response.push(byte[0]);
+    }
+    assert!(response.starts_with(b"HTTP/1.1 101"), "upgrade was refused");
+    stream
+}
+
+/// write a single masked frame with `opcode`, the mask is all zeroes so the payload goes out as
+/// it is
+pub async fn send_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8]) {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(0x80 | len as u8),
+        len => {
+            let len = u16::try_from(len).expect("only frames up to 64 KiB are supported");
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&len.to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&[0; 4]);
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame).await.unwrap();
+}
+
+/// the reason the server ends the exchange with, from its close frame or from the text frame
+/// standing in for it where the connection stays open, see
+/// [`SESSION_PATH`](tinap::wire::SESSION_PATH)
+pub async fn read_reason(stream: &mut TcpStream) -> CloseReason {
+    loop {
+        let (opcode, payload) = read_frame(stream).await;
+        match opcode {
+            0x1 => return CloseReason::from_payload(&session_close(&payload).unwrap()),
+            0x8 => return CloseReason::from_payload(&payload),
+            _ => {}
+        }
+    }
+}
+
+/// the opcode and payload of the next frame the server sends, which are never masked
+pub async fn read_frame(stream: &mut (impl AsyncRead + Unpin)) -> (u8, Vec<u8>) {
+    let mut header = [0; 2];
+    stream.read_exact(&mut header).await.unwrap();
+    let len = match header[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).await.unwrap();
+            u16::from_be_bytes(len) as usize
+        }
+        127 => {
+            let mut len = [0; 8];
+            stream.read_exact(&mut len).await.unwrap();
+            u64::from_be_bytes(len) as usize
+        }
+        len => len as usize,
+    };
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).await.unwrap();
+    (header[0] & 0x0f, payload)
+}
+
+pub fn s(value: &str) -> String {
+    value.to_string()
+}
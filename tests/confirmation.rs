@@ -0,0 +1,82 @@
+mod common;
+
+use common::s;
+use opaque_ke::ServerSetup;
+use rand::rngs::OsRng;
+use tinap::{
+    client::authenticate::{AuthenticateInitialize, AuthenticateWaiting},
+    loopback::loopback_pair,
+    server::{
+        autheticate::{AuthWaiting, AuthWithCreds},
+        record, Server,
+    },
+    Scheme,
+};
+
+/// a server's setup and the password file it stored for alice
+async fn registered() -> (ServerSetup<Scheme>, Vec<u8>) {
+    let setup = ServerSetup::<Scheme>::new(&mut OsRng);
+    let store = sled::Config::new().temporary(true).open().unwrap();
+    let server = Server::new(setup.clone(), store.clone());
+    loopback_pair(&server)
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let stored = store.get(b"alice").unwrap().expect("alice wasn't stored");
+    let password_file = record::unseal(&stored)
+        .and_then(record::UserRecord::checked_password_file)
+        .unwrap();
+    (setup, password_file)
+}
+
+/// run a login of alice up to the client's finalization, without anything in between
+fn login(
+    setup: &ServerSetup<Scheme>,
+    password_file: &[u8],
+) -> (AuthenticateWaiting, AuthWithCreds) {
+    let client = AuthenticateInitialize::new(s("alice"), s("hunter2")).unwrap();
+    let server = AuthWaiting::new(setup.clone())
+        .step(client.to_data())
+        .unwrap()
+        .step(Some(password_file.to_vec()))
+        .unwrap();
+    let client = client.step(server.to_data()).unwrap();
+    (client, server)
+}
+
+#[tokio::test]
+async fn only_a_confirming_report_authenticates() {
+    let (setup, password_file) = registered().await;
+
+    let (client, server) = login(&setup, &password_file);
+    let server = server.step(client.to_data()).unwrap();
+    assert!(server.step(vec![1]).authenticated());
+
+    for report in [vec![0], vec![], vec![1, 1], vec![2]] {
+        let (client, server) = login(&setup, &password_file);
+        let server = server.step(client.to_data()).unwrap();
+        assert!(
+            !server.step(report.clone()).authenticated(),
+            "{report:?} was taken as a confirmation"
+        );
+    }
+}
+
+#[tokio::test]
+async fn forged_finalization_is_refused() {
+    let (setup, password_file) = registered().await;
+    let (client, server) = login(&setup, &password_file);
+
+    let mut forged = client.to_data();
+    forged[0] ^= 1;
+    assert!(server.step(forged).is_err());
+}
+
+#[tokio::test]
+async fn replayed_finalization_is_refused() {
+    let (setup, password_file) = registered().await;
+    let (first, _) = login(&setup, &password_file);
+    let (_, second) = login(&setup, &password_file);
+
+    assert!(second.step(first.to_data()).is_err());
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{call, listen, s, server};
+use tinap::{
+    client::{error::ClientError, Client},
+    loopback::loopback_pair,
+    outcome::DeleteOutcome,
+    server::{deletion::DeletionPolicy, Server},
+    wire::Feature,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const ADMIN_TOKEN: &str = "admin secret";
+
+/// a server with `policy` that alice and bob are registered with, and a client that knows
+/// what the server offers from registering them
+async fn registered(policy: DeletionPolicy) -> (Server, Client) {
+    let server = server()
+        .with_admin_token(s(ADMIN_TOKEN))
+        .with_deletion_policy(policy);
+    let client = loopback_pair(&server);
+    for username in ["alice", "bob"] {
+        client
+            .register_user(s(username), s("hunter2"))
+            .await
+            .unwrap();
+    }
+    (server, client)
+}
+
+/// the status the admin API answers removing `username` with
+async fn admin_delete(server: &Server, username: &str) -> StatusCode {
+    let uri = format!("/admin/users/{username}");
+    call(
+        server.router(),
+        Method::DELETE,
+        &uri,
+        Some(ADMIN_TOKEN),
+        None,
+    )
+    .await
+    .0
+}
+
+/// the status line a websocket upgrade of the delete endpoint gets
+async fn delete_upgrade(server: &Server) -> String {
+    let port = listen(server).await;
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let request = format!(
+        "GET /delete HTTP/1.1\r\n\
+         Host: 127.0.0.1:{port}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = [0; 12];
+    stream.read_exact(&mut response).await.unwrap();
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+async fn logs_in(client: &Client, username: &str) -> bool {
+    client
+        .authenticate(s(username), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some()
+}
+
+fn refused_by_client(result: Result<DeleteOutcome, ClientError>) {
+    let err = result.expect_err("deletion went through");
+    assert!(
+        matches!(
+            err.inner(),
+            ClientError::FeatureUnsupported(Feature::Deletion)
+        ),
+        "{err:?}"
+    );
+}
+
+#[tokio::test]
+async fn self_service_lets_users_and_admins_delete() {
+    let (server, client) = registered(DeletionPolicy::SelfService).await;
+    assert!(server.features().contains(&Feature::Deletion));
+    assert_eq!(delete_upgrade(&server).await, "HTTP/1.1 101");
+
+    assert_eq!(
+        client.delete_user(s("alice"), s("hunter2")).await.unwrap(),
+        DeleteOutcome::Deleted
+    );
+    assert!(!logs_in(&client, "alice").await);
+    assert_eq!(admin_delete(&server, "bob").await, StatusCode::NO_CONTENT);
+    assert!(!logs_in(&client, "bob").await);
+}
+
+#[tokio::test]
+async fn admin_only_leaves_deletion_to_the_admin_api() {
+    let (server, client) = registered(DeletionPolicy::AdminOnly).await;
+    assert!(!server.features().contains(&Feature::Deletion));
+    assert_eq!(delete_upgrade(&server).await, "HTTP/1.1 403");
+
+    refused_by_client(client.delete_user(s("alice"), s("hunter2")).await);
+    assert!(logs_in(&client, "alice").await);
+    assert_eq!(admin_delete(&server, "alice").await, StatusCode::NO_CONTENT);
+    assert!(!logs_in(&client, "alice").await);
+}
+
+#[tokio::test]
+async fn disabled_leaves_deletion_to_the_offline_tool() {
+    let (server, client) = registered(DeletionPolicy::Disabled).await;
+    assert!(!server.features().contains(&Feature::Deletion));
+    assert_eq!(delete_upgrade(&server).await, "HTTP/1.1 403");
+
+    refused_by_client(client.delete_user(s("alice"), s("hunter2")).await);
+    assert_eq!(admin_delete(&server, "alice").await, StatusCode::FORBIDDEN);
+    assert!(logs_in(&client, "alice").await);
+    // what `tinap-admin` does, straight on the store
+    server.delete_user(b"alice").await.unwrap();
+    assert!(!logs_in(&client, "alice").await);
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::{listen, pair, read_reason, s, send_frame, server, upgraded};
+use tinap::{
+    client::error::ClientError,
+    outcome::{DeleteOutcome, RegistrationOutcome},
+    username::UsernameError,
+    wire::kind,
+    AuthenticateRequest, WithUsername,
+};
+
+const BINARY: u8 = 0x2;
+const BLANK: [&str; 3] = ["", " ", "\t\n "];
+
+#[tokio::test]
+async fn blank_usernames_never_leave_the_client() {
+    let (server, client) = pair();
+    let mut events = server.subscribe();
+    for username in BLANK {
+        let errors = [
+            client.register_user(s(username), s("hunter2")).await.err(),
+            client.authenticate(s(username), s("hunter2")).await.err(),
+            client.delete_user(s(username), s("hunter2")).await.err(),
+        ];
+        for err in errors {
+            let err = err.expect("blank username went through");
+            assert!(
+                matches!(err.inner(), ClientError::Username(UsernameError::Empty)),
+                "{username:?}: {err:?}"
+            );
+        }
+    }
+    assert!(events.try_recv().is_err(), "the server heard of it");
+}
+
+#[tokio::test]
+async fn empty_passwords_never_leave_the_client() {
+    let (server, client) = pair();
+    let mut events = server.subscribe();
+    let errors = [
+        client.register_user(s("alice"), s("")).await.err(),
+        client.authenticate(s("alice"), s("")).await.err(),
+        client.delete_user(s("alice"), s("")).await.err(),
+    ];
+    for err in errors {
+        let err = err.expect("empty password went through");
+        assert!(matches!(err.inner(), ClientError::EmptyPassword), "{err:?}");
+    }
+    assert!(events.try_recv().is_err(), "the server heard of it");
+}
+
+#[tokio::test]
+async fn short_and_blank_passwords_are_taken_as_given() {
+    let (_server, client) = pair();
+    for (username, password) in [("a", " "), ("b", "x"), ("c", "\t")] {
+        assert_eq!(
+            client
+                .register_user(s(username), s(password))
+                .await
+                .unwrap(),
+            RegistrationOutcome::Created
+        );
+        assert!(client
+            .authenticate(s(username), s(password))
+            .await
+            .unwrap()
+            .is_some());
+        // blank passwords aren't trimmed, so another amount of blank is another password
+        assert!(client
+            .authenticate(s(username), format!("{password} "))
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            client.delete_user(s(username), s(password)).await.unwrap(),
+            DeleteOutcome::Deleted
+        );
+    }
+}
+
+/// the first message of `path` for `username`, the rest of it never gets looked at
+fn first_message(path: &str, username: &[u8]) -> Vec<u8> {
+    match path {
+        "authenticate" => bincode::serialize(&AuthenticateRequest {
+            username,
+            data: &[0; 32],
+            verify_only: false,
+        }),
+        _ => bincode::serialize(&WithUsername {
+            username,
+            data: &[0; 32],
+            invite: None,
+        }),
+    }
+    .unwrap()
+}
+
+#[tokio::test]
+async fn server_refuses_blank_usernames() {
+    let server = server();
+    let port = listen(&server).await;
+    for path in ["registration", "authenticate"] {
+        for username in BLANK {
+            let mut stream = upgraded(port, path).await;
+            send_frame(
+                &mut stream,
+                BINARY,
+                &first_message(path, username.as_bytes()),
+            )
+            .await;
+            let reason = read_reason(&mut stream).await;
+            assert_eq!(
+                reason.message,
+                kind::INVALID_USERNAME,
+                "{path} {username:?}: {reason}"
+            );
+        }
+    }
+    assert_eq!(server.user_count().await.unwrap(), 0);
+}
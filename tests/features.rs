@@ -0,0 +1,64 @@
+use std::{collections::BTreeSet, process::Command};
+
+/// crates that only the networked client and the server need
+const NETWORKING: &[&str] = &[
+    "tokio",
+    "hyper",
+    "hyper-util",
+    "axum",
+    "fastwebsockets",
+    "sled",
+    "tower",
+];
+
+/// names of the crates built into this one with `features`
+fn dependencies(features: &[&str]) -> BTreeSet<String> {
+    let mut command = Command::new(env!("CARGO"));
+    command
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args([
+            "tree", "--edges", "normal", "--prefix", "none", "--format", "{p}",
+        ])
+        .arg("--no-default-features");
+    if !features.is_empty() {
+        command.args(["--features", &features.join(",")]);
+    }
+    let output = command.output().expect("Failed to run cargo tree");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(Into::into)
+        .collect()
+}
+
+#[test]
+fn the_core_builds_without_any_networking() {
+    for features in [&[][..], &["p256"], &["scrypt"]] {
+        let dependencies = dependencies(features);
+        assert!(dependencies.contains("opaque-ke"), "{dependencies:?}");
+        let networking = NETWORKING
+            .iter()
+            .filter(|name| dependencies.contains(**name))
+            .collect::<Vec<_>>();
+        assert!(
+            networking.is_empty(),
+            "{features:?} pulls in {networking:?}"
+        );
+    }
+}
+
+#[test]
+fn the_networking_comes_with_the_client_and_the_server() {
+    for features in ["client", "server"] {
+        let dependencies = dependencies(&[features]);
+        assert!(dependencies.contains("tokio"), "{features} left out tokio");
+    }
+    assert!(dependencies(&["server"]).contains("sled"));
+    assert!(!dependencies(&["client"]).contains("sled"));
+}
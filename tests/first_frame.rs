@@ -0,0 +1,106 @@
+mod common;
+
+use axum::http::Method;
+use common::{call, listen, read_reason, send_frame, server, upgraded};
+use tinap::wire::{kind, INVALID_MESSAGE};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const WEBSOCKET_PATHS: [&str; 7] = [
+    "registration",
+    "authenticate",
+    "change_password",
+    "delete",
+    "store",
+    "retrieve",
+    "ws",
+];
+
+const TEXT: u8 = 0x1;
+
+/// open `path` and send a text frame where the exchange's first binary frame belongs
+async fn text_first(path: &str) {
+    let server = server();
+    let port = listen(&server).await;
+    let mut stream = upgraded(port, path).await;
+    send_frame(&mut stream, TEXT, b"hello").await;
+
+    let reason = read_reason(&mut stream).await;
+    assert_eq!(reason.code, INVALID_MESSAGE, "{path}: {reason}");
+    assert_eq!(reason.message, kind::UNEXPECTED_FRAME, "{path}: {reason}");
+}
+
+#[tokio::test]
+async fn registration_refuses_text_first() {
+    text_first("registration").await;
+}
+
+#[tokio::test]
+async fn authenticate_refuses_text_first() {
+    text_first("authenticate").await;
+}
+
+#[tokio::test]
+async fn change_password_refuses_text_first() {
+    text_first("change_password").await;
+}
+
+#[tokio::test]
+async fn delete_refuses_text_first() {
+    text_first("delete").await;
+}
+
+#[tokio::test]
+async fn store_refuses_text_first() {
+    text_first("store").await;
+}
+
+#[tokio::test]
+async fn retrieve_refuses_text_first() {
+    text_first("retrieve").await;
+}
+
+#[tokio::test]
+async fn session_refuses_text_first() {
+    text_first("ws").await;
+}
+
+#[tokio::test]
+async fn plain_gets_are_refused_with_a_client_error() {
+    let server = server();
+    for path in WEBSOCKET_PATHS {
+        let (status, body) = call(
+            server.router(),
+            Method::GET,
+            &format!("/{path}"),
+            None,
+            None,
+        )
+        .await;
+        assert!(status.is_client_error(), "{path}: {status} {body}");
+    }
+}
+
+#[tokio::test]
+async fn unfinished_upgrades_are_refused_with_a_client_error() {
+    let server = server();
+    let port = listen(&server).await;
+    for path in WEBSOCKET_PATHS {
+        // asks for an upgrade without the key it needs
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let request = format!(
+            "GET /{path} HTTP/1.1\r\n\
+             Host: 127.0.0.1:{port}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade, close\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n"
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 4"), "{path}: {response}");
+    }
+}
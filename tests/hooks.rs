@@ -0,0 +1,134 @@
+mod common;
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::{s, server};
+use tinap::{
+    ksf::{Argon2Params, MIN_MEMORY_KIB},
+    loopback::loopback_pair,
+    server::{
+        events::ServerEvent,
+        hooks::{Hook, OverflowPolicy},
+    },
+    Argon2,
+};
+use tokio::time::timeout;
+
+/// quick to hash, for tests that log in over and over
+fn cheap() -> Argon2 {
+    Argon2Params {
+        memory_kib: MIN_MEMORY_KIB,
+        iterations: 1,
+        parallelism: 1,
+    }
+    .to_ksf()
+    .unwrap()
+}
+
+/// a hook that takes far longer than any exchange
+struct Slow;
+
+impl Hook for Slow {
+    fn name(&self) -> &str {
+        "slow"
+    }
+
+    fn call(&self, _event: ServerEvent) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep(Duration::from_secs(3600)))
+    }
+}
+
+/// a hook that panics on every event
+struct Panicking;
+
+impl Hook for Panicking {
+    fn name(&self) -> &str {
+        "panicking"
+    }
+
+    fn call(&self, _event: ServerEvent) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async { panic!("hook failed") })
+    }
+}
+
+/// a hook counting the events it was called with
+#[derive(Clone, Default)]
+struct Counting(Arc<AtomicUsize>);
+
+impl Hook for Counting {
+    fn name(&self) -> &str {
+        "counting"
+    }
+
+    fn call(&self, _event: ServerEvent) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async {})
+    }
+}
+
+#[tokio::test]
+async fn slow_hooks_drop_events_instead_of_holding_up_exchanges() {
+    for policy in [
+        OverflowPolicy::DropNewest,
+        OverflowPolicy::DropOldest,
+        OverflowPolicy::Block(Duration::from_millis(10)),
+    ] {
+        let server =
+            server().with_hooks(vec![Arc::new(Slow)], 2, policy, Duration::from_secs(3600));
+        let client = loopback_pair(&server).with_ksf(cheap());
+
+        for i in 0..8 {
+            let username = format!("user{i}");
+            let registered = client.register_user(username.clone(), s("hunter2"));
+            timeout(Duration::from_secs(10), registered)
+                .await
+                .expect("registration waited on the hook")
+                .unwrap();
+            let authenticated = client.authenticate(username, s("hunter2"));
+            assert!(timeout(Duration::from_secs(10), authenticated)
+                .await
+                .expect("login waited on the hook")
+                .unwrap()
+                .is_some());
+        }
+
+        let (depth, dropped) = server.hook_queue_stats().unwrap();
+        assert!(depth <= 2, "{policy:?} queued {depth} events");
+        assert!(dropped > 0, "{policy:?} dropped nothing");
+    }
+}
+
+#[tokio::test]
+async fn panicking_hooks_leave_the_others_running() {
+    let counting = Counting::default();
+    let server = server().with_hooks(
+        vec![Arc::new(Panicking), Arc::new(counting.clone())],
+        16,
+        OverflowPolicy::DropNewest,
+        Duration::from_secs(5),
+    );
+    let client = loopback_pair(&server);
+
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    client.register_user(s("bob"), s("hunter2")).await.unwrap();
+
+    timeout(Duration::from_secs(5), async {
+        while counting.0.load(Ordering::SeqCst) < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("events stopped reaching the hooks");
+    assert_eq!(server.hook_queue_stats(), Some((0, 0)));
+}
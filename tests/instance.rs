@@ -0,0 +1,95 @@
+mod common;
+
+use std::path::Path;
+
+use common::temp_dir;
+use tinap::server::{config::ServerConfig, error::ServerError, instance::MismatchPolicy, Server};
+
+fn open(setup: &Path, db: &Path, policy: MismatchPolicy) -> Result<Server, ServerError> {
+    let config = ServerConfig {
+        setup_path: setup.to_path_buf(),
+        db_path: db.to_path_buf(),
+        ..ServerConfig::default()
+    };
+    Server::initialize_from(config, "test", policy)
+}
+
+#[test]
+fn reopening_the_same_pair_works() {
+    let dir = temp_dir("instance-same");
+    let (setup, db) = (dir.join("setup"), dir.join("db"));
+    drop(open(&setup, &db, MismatchPolicy::Refuse).unwrap());
+
+    assert!(open(&setup, &db, MismatchPolicy::Refuse).is_ok());
+}
+
+#[test]
+fn mismatched_pair_is_refused() {
+    let dir = temp_dir("instance-mismatch");
+    drop(
+        open(
+            &dir.join("setup_a"),
+            &dir.join("db_a"),
+            MismatchPolicy::Refuse,
+        )
+        .unwrap(),
+    );
+    drop(
+        open(
+            &dir.join("setup_b"),
+            &dir.join("db_b"),
+            MismatchPolicy::Refuse,
+        )
+        .unwrap(),
+    );
+
+    let mixed = open(
+        &dir.join("setup_a"),
+        &dir.join("db_b"),
+        MismatchPolicy::Refuse,
+    );
+    assert!(matches!(mixed, Err(ServerError::InstanceMismatch(_, _))));
+}
+
+#[test]
+fn mismatched_pair_starts_when_overridden() {
+    let dir = temp_dir("instance-override");
+    drop(
+        open(
+            &dir.join("setup_a"),
+            &dir.join("db_a"),
+            MismatchPolicy::Refuse,
+        )
+        .unwrap(),
+    );
+    drop(
+        open(
+            &dir.join("setup_b"),
+            &dir.join("db_b"),
+            MismatchPolicy::Refuse,
+        )
+        .unwrap(),
+    );
+
+    assert!(open(
+        &dir.join("setup_a"),
+        &dir.join("db_b"),
+        MismatchPolicy::Warn
+    )
+    .is_ok());
+}
+
+#[test]
+fn refused_start_leaves_no_setup_behind() {
+    let dir = temp_dir("instance-missing-setup");
+    let db = dir.join("db");
+    drop(open(&dir.join("setup"), &db, MismatchPolicy::Refuse).unwrap());
+    let replacement = dir.join("replacement");
+
+    let first = open(&replacement, &db, MismatchPolicy::Refuse);
+    assert!(matches!(first, Err(ServerError::InstanceMismatch(_, _))));
+    assert!(!replacement.exists());
+    // the refusal holds on the next start too
+    let second = open(&replacement, &db, MismatchPolicy::Refuse);
+    assert!(matches!(second, Err(ServerError::InstanceMismatch(_, _))));
+}
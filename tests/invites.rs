@@ -0,0 +1,208 @@
+mod common;
+
+use std::{
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use axum::http::{Method, StatusCode};
+use common::{call, s, server};
+use tinap::{
+    client::{error::ClientError, Client},
+    clock::MockClock,
+    loopback::loopback_pair,
+    outcome::RegistrationOutcome,
+    server::{admin::NewInvite, store::MemoryStore, Server},
+    wire::kind,
+};
+use tokio::task::JoinSet;
+
+const ADMIN_TOKEN: &str = "admin secret";
+
+/// whether `result` is the server turning down the invite
+fn refused(result: &Result<RegistrationOutcome, ClientError>) -> bool {
+    matches!(
+        result.as_ref().map_err(ClientError::inner),
+        Err(ClientError::Rejected(_, reason)) if reason == kind::INVALID_INVITE
+    )
+}
+
+/// `server` only letting invited users register, with `invite` ready to use
+fn invite_only(server: Server) -> (Server, Client, String) {
+    let server = server.with_invite_only(true);
+    let client = loopback_pair(&server);
+    let invite = server.create_invite().unwrap();
+    (server, client, invite)
+}
+
+#[tokio::test]
+async fn invites_only_work_once() {
+    let (server, client, invite) = invite_only(server());
+
+    assert_eq!(
+        client
+            .register_with_invite(s("alice"), s("hunter2"), invite.clone())
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+    assert!(refused(
+        &client
+            .register_with_invite(s("bob"), s("hunter2"), invite)
+            .await
+    ));
+    assert_eq!(server.user_count().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn registering_needs_a_known_invite() {
+    let (server, client, _) = invite_only(server());
+
+    let err = client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .expect_err("registered without an invite");
+    assert!(
+        matches!(err.inner(), ClientError::Rejected(_, reason) if reason == kind::INVALID_INVITE),
+        "{err:?}"
+    );
+    assert!(refused(
+        &client
+            .register_with_invite(s("alice"), s("hunter2"), s("not an invite"))
+            .await
+    ));
+    assert_eq!(server.user_count().await.unwrap(), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn racing_registrations_only_get_one_use_out_of_an_invite() {
+    for server in [server(), server().with_user_store(MemoryStore::default())] {
+        let (server, client, invite) = invite_only(server);
+        let client = Arc::new(client);
+
+        let mut registrations = JoinSet::new();
+        for i in 0..8 {
+            let (client, invite) = (client.clone(), invite.clone());
+            registrations.spawn(async move {
+                client
+                    .register_with_invite(format!("user{i}"), s("hunter2"), invite)
+                    .await
+            });
+        }
+        let mut created = 0;
+        while let Some(result) = registrations.join_next().await {
+            let result = result.unwrap();
+            if matches!(result, Ok(RegistrationOutcome::Created)) {
+                created += 1;
+            } else {
+                assert!(refused(&result), "{result:?}");
+            }
+        }
+        assert_eq!(created, 1);
+        assert_eq!(server.user_count().await.unwrap(), 1);
+    }
+}
+
+#[tokio::test]
+async fn taken_usernames_leave_the_invite_unused() {
+    for server in [server(), server().with_user_store(MemoryStore::default())] {
+        let (server, client, invite) = invite_only(server);
+        let first = server.create_invite().unwrap();
+        client
+            .register_with_invite(s("alice"), s("hunter2"), first)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client
+                .register_with_invite(s("alice"), s("hunter3"), invite.clone())
+                .await
+                .unwrap(),
+            RegistrationOutcome::AlreadyExists
+        );
+        assert_eq!(
+            client
+                .register_with_invite(s("bob"), s("hunter2"), invite)
+                .await
+                .unwrap(),
+            RegistrationOutcome::Created
+        );
+    }
+}
+
+#[tokio::test]
+async fn admins_can_mint_invites() {
+    let server = server()
+        .with_invite_only(true)
+        .with_admin_token(s(ADMIN_TOKEN));
+    let client = loopback_pair(&server);
+
+    let (status, body) = call(
+        server.router(),
+        Method::POST,
+        "/admin/invites",
+        Some(ADMIN_TOKEN),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let NewInvite { invite } = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        client
+            .register_with_invite(s("alice"), s("hunter2"), invite)
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+
+    let (status, _) = call(server.router(), Method::POST, "/admin/invites", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn open_servers_ignore_invites() {
+    let client = loopback_pair(&server());
+    assert_eq!(
+        client
+            .register_with_invite(s("alice"), s("hunter2"), s("not an invite"))
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+    assert_eq!(
+        client.register_user(s("bob"), s("hunter2")).await.unwrap(),
+        RegistrationOutcome::Created
+    );
+}
+
+#[tokio::test]
+async fn invites_run_out_with_their_ttl() {
+    let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000));
+    let server = server()
+        .with_clock(clock.clone())
+        .with_invite_only(true)
+        .with_invite_ttl(Duration::from_secs(60));
+    let client = loopback_pair(&server);
+    let stale = server.create_invite().unwrap();
+
+    clock.advance(Duration::from_secs(60));
+    let err = client
+        .register_with_invite(s("alice"), s("hunter2"), stale)
+        .await
+        .expect_err("registered with an expired invite");
+    assert!(
+        matches!(err.inner(), ClientError::Rejected(_, reason) if reason == kind::INVALID_INVITE),
+        "{err:?}"
+    );
+    assert_eq!(server.user_count().await.unwrap(), 0);
+
+    let fresh = server.create_invite().unwrap();
+    clock.advance(Duration::from_secs(59));
+    assert_eq!(
+        client
+            .register_with_invite(s("alice"), s("hunter2"), fresh)
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+}
@@ -0,0 +1,154 @@
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use common::{pair, s, server};
+use tinap::{
+    client::executor::{Inline, KsfExecutor, SpawnBlocking},
+    ksf::{Argon2Params, ParamsError, Strictness, MIN_MEMORY_KIB},
+    loopback::loopback_pair,
+    server::Server,
+    Argon2,
+};
+
+/// parameters within the recommended range that are still quick to hash
+const CHEAP: Argon2Params = Argon2Params {
+    memory_kib: MIN_MEMORY_KIB,
+    iterations: 1,
+    parallelism: 1,
+};
+
+/// costly enough that running it in place visibly stalls the runtime
+const SLOW: Argon2Params = Argon2Params {
+    iterations: 4,
+    ..CHEAP
+};
+
+#[tokio::test]
+async fn logins_need_the_registered_costs() {
+    let (_server, client) = pair();
+    let client = client.with_ksf(CHEAP.to_ksf().unwrap());
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+    let costlier = client.with_ksf(Argon2::default());
+    assert!(costlier
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn inline_executor_runs_the_exchanges() {
+    let (_server, client) = pair();
+    let client = client
+        .with_ksf(CHEAP.to_ksf().unwrap())
+        .with_ksf_executor(Inline);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+    assert!(client
+        .change_password(s("alice"), s("hunter2"), s("hunter3"))
+        .await
+        .unwrap());
+}
+
+/// the longest the runtime went without running a task ticking every few milliseconds while a
+/// user registered and deleted themselves, with key stretching run through `executor`, and how
+/// long that took
+async fn longest_stall(
+    server: &Server,
+    executor: impl KsfExecutor + 'static,
+) -> (Duration, Duration) {
+    let client = loopback_pair(server)
+        .with_ksf(SLOW.to_ksf().unwrap())
+        .with_ksf_executor(executor);
+    let longest = Arc::new(Mutex::new(Duration::ZERO));
+    let ticker = tokio::spawn({
+        let longest = longest.clone();
+        async move {
+            let mut last = Instant::now();
+            loop {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                let gap = last.elapsed();
+                let mut longest = longest.lock().unwrap();
+                *longest = (*longest).max(gap);
+                last = Instant::now();
+            }
+        }
+    });
+    // let the ticker get going
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    let started = Instant::now();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    client.delete_user(s("alice"), s("hunter2")).await.unwrap();
+    let took = started.elapsed();
+    ticker.abort();
+    let longest = *longest.lock().unwrap();
+    (longest, took)
+}
+
+/// a single threaded runtime, where a stall in place holds up every other task
+#[tokio::test(flavor = "current_thread")]
+async fn key_stretching_leaves_the_runtime_free() {
+    let server = server();
+
+    let (inline, inline_took) = longest_stall(&server, Inline).await;
+    let (blocking, blocking_took) = longest_stall(&server, SpawnBlocking).await;
+    println!(
+        "longest stall in place {inline:?} of {inline_took:?}, \
+         on a blocking thread {blocking:?} of {blocking_took:?}"
+    );
+
+    // each of the two hashes stalls everything when run in place
+    assert!(inline >= inline_took / 4, "stalled for {inline:?}");
+    assert!(blocking < inline / 4, "stalled for {blocking:?}");
+}
+
+#[test]
+fn unsound_params_are_caught() {
+    assert!(CHEAP.validate().is_ok());
+    assert!(Argon2Params::default().validate().is_ok());
+
+    let weak = Argon2Params {
+        memory_kib: MIN_MEMORY_KIB - 1,
+        ..CHEAP
+    };
+    assert!(matches!(weak.validate(), Err(ParamsError::MemoryTooLow(_))));
+    assert!(matches!(weak.check(Strictness::Warn), Ok(Some(_))));
+    assert!(weak.check(Strictness::Refuse).is_err());
+    // still usable when the caller chooses to go ahead
+    assert!(weak.to_ksf().is_ok());
+
+    let no_lanes = Argon2Params {
+        parallelism: 0,
+        ..CHEAP
+    };
+    assert!(matches!(
+        no_lanes.validate(),
+        Err(ParamsError::Parallelism(0))
+    ));
+    assert!(Argon2::with_params(0, 0, 0).is_err());
+}
@@ -0,0 +1,407 @@
+mod common;
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use opaque_ke::ServerSetup;
+use rand::rngs::OsRng;
+
+use common::{listen, read_frame, read_reason, s, send_frame, server, upgraded};
+use tinap::{
+    client::{
+        authenticate::AuthenticateInitialize, error::ClientError, retry::RetryPolicy, Client,
+    },
+    clock::MockClock,
+    ksf::{Argon2Params, MIN_MEMORY_KIB},
+    loopback::loopback_pair,
+    server::{events::ServerEvent, lockout::LockoutPolicy, Server},
+    wire::{kind, INVALID_MESSAGE, REJECTED},
+    Argon2, Scheme,
+};
+use tokio::{sync::broadcast::Receiver, time::timeout};
+
+const BINARY: u8 = 0x2;
+
+/// quick to hash, for tests that log in over and over
+fn cheap() -> Argon2 {
+    Argon2Params {
+        memory_kib: MIN_MEMORY_KIB,
+        iterations: 1,
+        parallelism: 1,
+    }
+    .to_ksf()
+    .unwrap()
+}
+
+#[tokio::test]
+async fn repeated_failures_lock_the_account() {
+    let server = server().with_lockout(LockoutPolicy {
+        threshold: 2,
+        window: Duration::from_secs(60),
+        max_window: Duration::from_secs(60),
+    });
+    let client = loopback_pair(&server).with_ksf(cheap());
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    client.register_user(s("bob"), s("hunter3")).await.unwrap();
+
+    for _ in 0..2 {
+        assert!(client
+            .authenticate(s("alice"), s("wrong"))
+            .await
+            .unwrap()
+            .is_none());
+    }
+    // locked, which looks the same as a wrong password
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_none());
+    assert!(client
+        .authenticate(s("bob"), s("hunter3"))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+/// wait for the server to be done with an exchange that `matches`
+async fn wait_for(events: &mut Receiver<ServerEvent>, matches: impl Fn(&ServerEvent) -> bool) {
+    loop {
+        let event = timeout(Duration::from_secs(5), events.recv()).await;
+        if matches(&event.unwrap().unwrap()) {
+            return;
+        }
+    }
+}
+
+/// a lockout that kicks in after two wrong passwords, on `clock`
+fn locking(clock: &MockClock) -> Server {
+    server()
+        .with_lockout(LockoutPolicy {
+            threshold: 2,
+            window: Duration::from_secs(30),
+            max_window: Duration::from_secs(30),
+        })
+        .with_clock(clock.clone())
+}
+
+/// log alice in with `password`, waiting for the server to be done with the attempt
+async fn attempt(client: &Client, events: &mut Receiver<ServerEvent>, password: &str) -> bool {
+    let authenticated = client
+        .authenticate(s("alice"), s(password))
+        .await
+        .unwrap()
+        .is_some();
+    wait_for(events, |event| {
+        matches!(
+            event,
+            ServerEvent::Authenticated { .. } | ServerEvent::AuthenticationFailed { .. }
+        )
+    })
+    .await;
+    authenticated
+}
+
+#[tokio::test]
+async fn a_successful_login_starts_the_count_over() {
+    let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000));
+    let server = locking(&clock);
+    let client = loopback_pair(&server).with_ksf(cheap());
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let mut events = server.subscribe();
+
+    assert!(!attempt(&client, &mut events, "wrong").await);
+    assert!(attempt(&client, &mut events, "hunter2").await);
+    assert!(!attempt(&client, &mut events, "wrong").await);
+    // a single failure since the last success isn't enough to lock
+    assert!(attempt(&client, &mut events, "hunter2").await);
+}
+
+#[tokio::test]
+async fn locks_run_out_with_their_window() {
+    let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000));
+    let server = locking(&clock);
+    let client = loopback_pair(&server).with_ksf(cheap());
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let mut events = server.subscribe();
+
+    assert!(!attempt(&client, &mut events, "wrong").await);
+    assert!(!attempt(&client, &mut events, "wrong").await);
+    clock.advance(Duration::from_secs(29));
+    assert!(!attempt(&client, &mut events, "hunter2").await);
+    let failures = server.auth_failures(b"alice").unwrap();
+    assert_eq!(failures.last().unwrap().reason, "Account is locked");
+
+    // trying while locked didn't push the lock any further out
+    clock.advance(Duration::from_secs(1));
+    assert!(attempt(&client, &mut events, "hunter2").await);
+}
+
+#[tokio::test]
+async fn failure_reasons_tell_wrong_passwords_locks_and_corrupt_records_apart() {
+    let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000));
+    let store = sled::Config::new().temporary(true).open().unwrap();
+    let server = Server::new(ServerSetup::<Scheme>::new(&mut OsRng), store.clone())
+        .with_lockout(LockoutPolicy {
+            threshold: 2,
+            window: Duration::from_secs(30),
+            max_window: Duration::from_secs(30),
+        })
+        .with_clock(clock.clone());
+    let client = loopback_pair(&server).with_ksf(cheap());
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let mut events = server.subscribe();
+
+    assert!(!attempt(&client, &mut events, "wrong").await);
+    assert!(!attempt(&client, &mut events, "wrong").await);
+    assert!(!attempt(&client, &mut events, "hunter2").await);
+    clock.advance(Duration::from_secs(30));
+    let mut record = store.get(b"alice").unwrap().unwrap().to_vec();
+    *record.last_mut().unwrap() ^= 1;
+    store.insert(b"alice", record).unwrap();
+    let err = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .expect_err("logged in with a corrupt record");
+    assert!(
+        matches!(err.inner(), ClientError::ServerFailed(_)),
+        "{err:?}"
+    );
+    wait_for(&mut events, |event| {
+        matches!(event, ServerEvent::AuthenticationFailed { .. })
+    })
+    .await;
+
+    let reasons: Vec<_> = server
+        .auth_failures(b"alice")
+        .unwrap()
+        .into_iter()
+        .map(|failure| failure.reason)
+        .collect();
+    let [wrong, again, locked, corrupt] = &reasons[..] else {
+        panic!("expected four failures, got {reasons:?}");
+    };
+    assert_eq!(wrong, again);
+    assert_eq!(locked, "Account is locked");
+    assert_ne!(wrong, locked);
+    assert_ne!(wrong, corrupt);
+    assert_ne!(locked, corrupt);
+}
+
+#[tokio::test]
+async fn dropped_logins_do_not_count_as_guesses() {
+    let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000));
+    let server = locking(&clock);
+    let client = loopback_pair(&server).with_ksf(cheap());
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let mut events = server.subscribe();
+
+    let port = listen(&server).await;
+    for _ in 0..3 {
+        let login = AuthenticateInitialize::new(s("alice"), s("hunter2")).unwrap();
+        let mut stream = upgraded(port, "authenticate").await;
+        send_frame(&mut stream, BINARY, &login.to_data()).await;
+        read_frame(&mut stream).await;
+        // gone without finishing the login
+        drop(stream);
+        wait_for(&mut events, |event| {
+            matches!(event, ServerEvent::AuthenticationFailed { .. })
+        })
+        .await;
+    }
+    assert!(attempt(&client, &mut events, "hunter2").await);
+}
+
+#[tokio::test]
+async fn disclosed_lockouts_are_waited_out_by_the_client() {
+    let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000));
+    let server = server()
+        .with_lockout(LockoutPolicy {
+            threshold: 2,
+            window: Duration::from_secs(5),
+            max_window: Duration::from_secs(5),
+        })
+        .with_lockout_disclosure()
+        .with_clock(clock.clone());
+    let client = loopback_pair(&server)
+        .with_ksf(cheap())
+        .with_clock(clock.clone());
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let mut events = server.subscribe();
+    for _ in 0..2 {
+        assert!(client
+            .authenticate(s("alice"), s("wrong"))
+            .await
+            .unwrap()
+            .is_none());
+        wait_for(&mut events, |event| {
+            matches!(event, ServerEvent::AuthenticationFailed { .. })
+        })
+        .await;
+    }
+
+    let locked = |err: ClientError| match err.inner() {
+        ClientError::AccountLocked { retry_after } => *retry_after,
+        other => panic!("expected a lockout, got {other:?}"),
+    };
+    let err = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap_err();
+    assert_eq!(locked(err), Some(Duration::from_secs(5)));
+    wait_for(&mut events, |event| {
+        matches!(event, ServerEvent::ExchangeFailed { kind, .. } if *kind == kind::ACCOUNT_LOCKED)
+    })
+    .await;
+
+    // the client knows better than to ask again while the lock lasts
+    clock.advance(Duration::from_secs(2));
+    let err = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap_err();
+    assert_eq!(locked(err), Some(Duration::from_secs(3)));
+    assert!(timeout(Duration::from_millis(200), events.recv())
+        .await
+        .is_err());
+    // other clients don't share what this one remembers
+    let other = loopback_pair(&server).with_ksf(cheap());
+    let err = other
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap_err();
+    assert_eq!(locked(err), Some(Duration::from_secs(3)));
+
+    clock.advance(Duration::from_secs(3));
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn concurrent_logins_of_one_user_are_turned_away() {
+    let server = server().with_user_in_flight_limit(1);
+    let client = loopback_pair(&server).with_ksf(cheap());
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    client.register_user(s("bob"), s("hunter3")).await.unwrap();
+
+    // a login of alice that stops after the server's response
+    let port = listen(&server).await;
+    let mut held = upgraded(port, "authenticate").await;
+    let request = AuthenticateInitialize::new(s("alice"), s("hunter2")).unwrap();
+    send_frame(&mut held, BINARY, &request.to_data()).await;
+    let (opcode, _) = read_frame(&mut held).await;
+    assert_eq!(opcode, BINARY);
+
+    let err = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .expect_err("a second login of the same user went through");
+    assert!(
+        matches!(err.inner(), ClientError::Rejected(REJECTED, _)),
+        "{err:?}"
+    );
+    assert!(client
+        .authenticate(s("bob"), s("hunter3"))
+        .await
+        .unwrap()
+        .is_some());
+
+    // the guard goes once the server notices the held login is gone
+    drop(held);
+    for _ in 0..20 {
+        if server.in_flight_users() == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn handshakes_over_the_limit_are_turned_away() {
+    let server = server().with_handshake_limit(1);
+    let client = loopback_pair(&server).with_ksf(cheap());
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    let port = listen(&server).await;
+    let held = upgraded(port, "authenticate").await;
+    let err = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .expect_err("a handshake over the limit went through");
+    assert!(err.is_unreachable(), "{err:?}");
+    assert_eq!(server.handshake_budget().in_use(), 1);
+
+    let retrying = client.with_retry(RetryPolicy {
+        max_attempts: 2,
+        base_delay: Duration::from_millis(10),
+        jitter: Duration::ZERO,
+    });
+    let err = retrying
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .expect_err("a handshake over the limit went through");
+    assert!(
+        matches!(err.inner(), ClientError::RetriesExhausted(2, _)),
+        "{err:?}"
+    );
+
+    // once the held connection is gone its permit goes back
+    drop(held);
+    let retrying = retrying.with_retry(RetryPolicy {
+        max_attempts: 10,
+        base_delay: Duration::from_millis(50),
+        jitter: Duration::ZERO,
+    });
+    assert!(retrying
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn oversized_length_claim_is_refused() {
+    let server = server();
+    let port = listen(&server).await;
+    let mut stream = upgraded(port, "authenticate").await;
+
+    // a username claiming to be far larger than anything the server would allocate for
+    let mut claim = (u64::MAX / 2).to_le_bytes().to_vec();
+    claim.extend_from_slice(b"alice");
+    send_frame(&mut stream, BINARY, &claim).await;
+
+    let reason = read_reason(&mut stream).await;
+    assert_eq!(reason.code, INVALID_MESSAGE, "{reason}");
+    assert_eq!(reason.message, kind::SERIALIZATION, "{reason}");
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use std::{
+    net::{SocketAddr, TcpListener as StdListener},
+    time::Duration,
+};
+
+use common::server;
+use tinap::server::{error::ServerError, listeners::ListenerConfig};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+};
+
+/// a local address nothing is listening on right now
+fn free_addr() -> SocketAddr {
+    StdListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// the status line a GET of `path` on `addr` is answered with, once it's been bound
+async fn status(addr: SocketAddr, path: &str) -> String {
+    let mut stream = None;
+    for _ in 0..100 {
+        if let Ok(connected) = TcpStream::connect(addr).await {
+            stream = Some(connected);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let mut stream = stream.unwrap_or_else(|| panic!("{addr} was never bound"));
+    let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8_lossy(&response);
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+#[tokio::test]
+async fn public_and_admin_routes_stay_on_their_own_listeners() {
+    let (public, admin) = (free_addr(), free_addr());
+    let listeners = [ListenerConfig::public(public), ListenerConfig::admin(admin)];
+    let (stop, stopped) = oneshot::channel::<()>();
+    let serving = tokio::spawn(async move {
+        server()
+            .serve_all(&listeners, async {
+                let _ = stopped.await;
+            })
+            .await
+    });
+
+    assert_eq!(status(public, "/info").await, "HTTP/1.1 200 OK");
+    assert_eq!(status(public, "/runtime").await, "HTTP/1.1 404 Not Found");
+    assert_eq!(status(admin, "/runtime").await, "HTTP/1.1 200 OK");
+    assert_eq!(status(admin, "/info").await, "HTTP/1.1 404 Not Found");
+
+    stop.send(()).unwrap();
+    serving.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn bind_failures_name_the_address() {
+    let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let taken = taken.local_addr().unwrap();
+    let listeners = [
+        ListenerConfig::public(free_addr()),
+        ListenerConfig::admin(taken),
+    ];
+
+    let err = server()
+        .serve_all(&listeners, std::future::pending())
+        .await
+        .expect_err("bound an address that was taken");
+    assert!(
+        matches!(&err, ServerError::Bind(addr, _) if addr == &taken.into()),
+        "{err:?}"
+    );
+    assert!(err.to_string().contains(&taken.to_string()), "{err}");
+}
+
+/// peer uid checks, which read `SO_PEERCRED`
+#[cfg(target_os = "linux")]
+mod unix {
+    use std::{os::unix::fs::MetadataExt, path::Path, time::Duration};
+
+    use tinap::server::listeners::{ListenAddr, ListenerConfig};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixStream,
+        sync::oneshot,
+    };
+
+    use super::common::{server, temp_dir};
+
+    /// the status line the admin listener on a Unix socket answers with, as whoever runs the
+    /// tests, when it only lets `allowed_uids` in
+    async fn admin_over_unix(name: &str, allowed_uids: Vec<u32>) -> String {
+        let path = temp_dir(name).join("admin.sock");
+        let listeners = [
+            ListenerConfig::admin(ListenAddr::Unix(path.clone())).with_allowed_uids(allowed_uids)
+        ];
+        let (stop, stopped) = oneshot::channel::<()>();
+        let serving = tokio::spawn(async move {
+            server()
+                .serve_all(&listeners, async {
+                    let _ = stopped.await;
+                })
+                .await
+        });
+
+        let mut stream = connect(&path).await;
+        stream
+            .write_all(b"GET /runtime HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        stop.send(()).unwrap();
+        serving.await.unwrap().unwrap();
+        let response = String::from_utf8_lossy(&response);
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    /// a connection to the socket at `path`, once it's been bound
+    async fn connect(path: &Path) -> UnixStream {
+        for _ in 0..100 {
+            if let Ok(stream) = UnixStream::connect(path).await {
+                return stream;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{} was never bound", path.display());
+    }
+
+    /// the uid the tests run as, the owner of anything they create
+    fn own_uid() -> u32 {
+        std::fs::metadata(temp_dir("listeners-uid")).unwrap().uid()
+    }
+
+    #[tokio::test]
+    async fn allowed_uids_are_served() {
+        let status = admin_over_unix("listeners-allowed", vec![own_uid()]).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+    }
+
+    #[tokio::test]
+    async fn other_uids_are_refused() {
+        let status = admin_over_unix("listeners-refused", vec![own_uid().wrapping_add(1)]).await;
+        assert_eq!(status, "HTTP/1.1 403 Forbidden");
+    }
+}
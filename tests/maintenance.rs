@@ -0,0 +1,117 @@
+//! Store maintenance run over and over while users come and go
+//!
+//! the run over thousands of users is ignored by default, run it with
+//! `cargo test --release --test maintenance -- --ignored thousands`
+mod common;
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use axum::http::{Method, StatusCode};
+use common::{call, login, s};
+use opaque_ke::ServerSetup;
+use rand::rngs::OsRng;
+use tinap::{
+    ksf::{Argon2Params, MIN_MEMORY_KIB},
+    loopback::loopback_pair,
+    outcome::DeleteOutcome,
+    server::{
+        maintenance::{MaintenanceReport, StoreStats},
+        Server,
+    },
+    Argon2, Scheme,
+};
+use tokio::task::JoinSet;
+
+const WORKERS: usize = 4;
+
+/// quick to hash, the store is what's under test
+fn cheap() -> Argon2 {
+    Argon2Params {
+        memory_kib: MIN_MEMORY_KIB,
+        iterations: 1,
+        parallelism: 1,
+    }
+    .to_ksf()
+    .unwrap()
+}
+
+/// run maintenance through the admin routes for as long as `churn` users per worker are being
+/// registered and deleted again, checking a steady user can log in throughout
+async fn maintain_during_churn(churn: usize) {
+    let dir = common::temp_dir("maintenance");
+    let store = sled::open(&dir).unwrap();
+    let server = Server::new(ServerSetup::<Scheme>::new(&mut OsRng), store);
+    let client = Arc::new(loopback_pair(&server).with_ksf(cheap()));
+    client
+        .register_user(s("steady"), s("hunter2"))
+        .await
+        .unwrap();
+
+    let churning = Arc::new(AtomicBool::new(true));
+    let mut workers = JoinSet::new();
+    for worker in 0..WORKERS {
+        let client = client.clone();
+        workers.spawn(async move {
+            for i in 0..churn {
+                let username = format!("user{worker}-{i}");
+                client
+                    .register_user(username.clone(), s("hunter2"))
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    client.delete_user(username, s("hunter2")).await.unwrap(),
+                    DeleteOutcome::Deleted
+                );
+            }
+        });
+    }
+    let watcher = tokio::spawn({
+        let churning = churning.clone();
+        async move {
+            while workers.join_next().await.transpose().unwrap().is_some() {}
+            churning.store(false, Ordering::SeqCst);
+        }
+    });
+
+    let mut runs = 0;
+    while churning.load(Ordering::SeqCst) {
+        let (status, body) = call(
+            server.admin_router(),
+            Method::POST,
+            "/maintenance",
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let report: MaintenanceReport = serde_json::from_str(&body).unwrap();
+        assert!(report.size_after > 0);
+        runs += 1;
+        // logins keep working while maintenance runs
+        login(&client, "steady", "hunter2").await;
+    }
+    watcher.await.unwrap();
+    assert!(runs > 0);
+
+    let (status, body) = call(server.admin_router(), Method::GET, "/store", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let stats: StoreStats = serde_json::from_str(&body).unwrap();
+    assert!(stats.size_on_disk > 0);
+    assert!(stats.last_maintenance.is_some());
+    assert_eq!(server.user_count().await.unwrap(), 1);
+    login(&client, "steady", "hunter2").await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn maintenance_runs_alongside_churn() {
+    maintain_during_churn(10).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+#[ignore = "takes minutes outside of release builds, see the module docs"]
+async fn maintenance_runs_alongside_thousands_of_users() {
+    maintain_during_churn(1000).await;
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use axum::http::{Method, StatusCode};
+use common::{call, listen, pair, s, server, upgraded};
+use tinap::{client::Client, server::shedding::LoadShedding};
+
+#[tokio::test]
+async fn exchanges_are_counted() {
+    let (server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .expect("login was refused");
+    assert!(client
+        .authenticate(s("alice"), s("wrong"))
+        .await
+        .unwrap()
+        .is_none());
+
+    // the server counts an exchange once its side is done, which can be after the client's
+    let counted = "handshake_duration_seconds_count{operation=\"authenticate\"} 2";
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut rendered = server.metrics().render();
+    while !rendered.contains(counted) && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        rendered = server.metrics().render();
+    }
+    assert!(rendered.contains("registrations_total 1"), "{rendered}");
+    assert!(rendered.contains("auth_success_total 1"), "{rendered}");
+    assert!(rendered.contains("auth_failure_total{"), "{rendered}");
+    assert!(rendered.contains(counted), "{rendered}");
+}
+
+#[tokio::test]
+async fn checks_are_counted_apart_from_logins() {
+    let (server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    assert!(client.verify(s("alice"), s("hunter2")).await.unwrap());
+    assert!(!client.verify(s("alice"), s("wrong")).await.unwrap());
+
+    let counted = "handshake_duration_seconds_count{operation=\"verify\"} 2";
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut rendered = server.metrics().render();
+    while !rendered.contains(counted) && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        rendered = server.metrics().render();
+    }
+    assert!(rendered.contains(counted), "{rendered}");
+    assert!(rendered.contains("verify_success_total 1"), "{rendered}");
+    assert!(rendered.contains("verify_failure_total{"), "{rendered}");
+    assert!(rendered.contains("auth_success_total 0"), "{rendered}");
+    assert!(!rendered.contains("auth_failure_total{"), "{rendered}");
+    assert!(
+        !rendered.contains("handshake_duration_seconds_count{operation=\"authenticate\"}"),
+        "{rendered}"
+    );
+}
+
+#[tokio::test]
+async fn load_shedding_shows_up_in_the_scrape() {
+    let server = server().with_load_shedding(LoadShedding {
+        high_water: 1,
+        low_water: 0,
+        latency_threshold: Duration::from_secs(3600),
+    });
+    let port = listen(&server).await;
+
+    let (status, rendered) = call(server.router(), Method::GET, "/metrics", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(rendered.contains("load_shedding 0"), "{rendered}");
+
+    let _stalled = upgraded(port, "registration").await;
+    assert!(Client::new(s("127.0.0.1"), port)
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .is_err());
+    let (_, rendered) = call(server.router(), Method::GET, "/metrics", None, None).await;
+    assert!(rendered.contains("load_shedding 1"), "{rendered}");
+    assert!(
+        rendered.contains("load_shedding_average_latency_seconds"),
+        "{rendered}"
+    );
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use common::s;
+use opaque_ke::ServerSetup;
+use rand::rngs::OsRng;
+use tinap::{
+    client::{error::ClientError, Client},
+    ksf::{Argon2Params, MIN_MEMORY_KIB},
+    loopback::loopback_pair,
+    outcome::RegistrationOutcome,
+    server::{record::unseal, Server},
+    Argon2, P256Scheme, Scheme, SuiteId,
+};
+
+fn store() -> sled::Db {
+    sled::Config::new()
+        .temporary(true)
+        .open()
+        .expect("Failed to open temporary store")
+}
+
+/// quick to hash, the suite is what's under test
+fn cheap() -> Argon2 {
+    Argon2Params {
+        memory_kib: MIN_MEMORY_KIB,
+        iterations: 1,
+        parallelism: 1,
+    }
+    .to_ksf()
+    .unwrap()
+}
+
+/// a P-256 server over `store` and a client wired to it
+fn p256_pair(store: sled::Db) -> (Server<P256Scheme>, Client<P256Scheme>) {
+    let server = Server::for_suite(ServerSetup::<P256Scheme>::new(&mut OsRng), store);
+    let client = loopback_pair(&server).with_ksf(cheap());
+    (server, client)
+}
+
+#[tokio::test]
+async fn p256_registers_and_logs_in() {
+    let store = store();
+    let (_server, client) = p256_pair(store.clone());
+    assert_eq!(
+        client
+            .register_user(s("alice"), s("hunter2"))
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+
+    let confirm = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .expect("login was refused");
+    assert!(!confirm.session_key().is_empty());
+    assert!(client
+        .authenticate(s("alice"), s("hunter3"))
+        .await
+        .unwrap()
+        .is_none());
+
+    // the record says which suite made it
+    let record = unseal(&store.get(b"alice").unwrap().unwrap()).unwrap();
+    assert_eq!(record.suite, SuiteId::P256);
+}
+
+#[tokio::test]
+async fn p256_changes_password() {
+    let (_server, client) = p256_pair(store());
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    assert!(client
+        .change_password(s("alice"), s("hunter2"), s("correct horse"))
+        .await
+        .unwrap());
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_none());
+    assert!(client
+        .authenticate(s("alice"), s("correct horse"))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn records_are_refused_by_the_other_suite() {
+    let store = store();
+    let ristretto = Server::new(ServerSetup::<Scheme>::new(&mut OsRng), store.clone());
+    loopback_pair(&ristretto)
+        .with_ksf(cheap())
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let (p256, client) = p256_pair(store);
+    client.register_user(s("bob"), s("hunter2")).await.unwrap();
+
+    let err = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .expect_err("a record from another suite was used");
+    assert!(
+        matches!(err.inner(), ClientError::ServerFailed(_)),
+        "{err:?}"
+    );
+    let err = loopback_pair(&ristretto)
+        .with_ksf(cheap())
+        .authenticate(s("bob"), s("hunter2"))
+        .await
+        .expect_err("a record from another suite was used");
+    assert!(
+        matches!(err.inner(), ClientError::ServerFailed(_)),
+        "{err:?}"
+    );
+    // both records are intact, they just belong to the other suite
+    assert_eq!(p256.corrupt_record_count(), 0);
+    assert_eq!(ristretto.corrupt_record_count(), 0);
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::{s, server};
+use tinap::{client::Client, loopback::loopback_pair, outcome::RegistrationOutcome};
+
+/// a padding server and a client asking for padding
+fn padded_pair() -> Client {
+    let server = server().with_padding(true);
+    loopback_pair(&server).with_padding(true)
+}
+
+#[tokio::test]
+async fn padded_registration_and_login_work() {
+    let client = padded_pair();
+    let outcome = client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    assert_eq!(outcome, RegistrationOutcome::Created);
+
+    let confirm = client.authenticate(s("alice"), s("hunter2")).await.unwrap();
+    assert!(confirm.is_some_and(|confirm| confirm.session_token().is_some()));
+    let refused = client.authenticate(s("alice"), s("hunter3")).await.unwrap();
+    assert!(refused.is_none());
+}
+
+#[tokio::test]
+async fn padded_duplicate_registration_is_told_apart() {
+    let client = padded_pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let outcome = client.register_user(s("alice"), s("other")).await.unwrap();
+    assert_eq!(outcome, RegistrationOutcome::AlreadyExists);
+}
+
+#[tokio::test]
+async fn padding_has_to_be_agreed_on() {
+    let server = server().with_padding(true);
+    let client = loopback_pair(&server);
+    assert!(client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .is_err());
+}
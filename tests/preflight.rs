@@ -0,0 +1,108 @@
+mod common;
+
+use std::{
+    net::TcpListener as StdListener,
+    time::{Duration, SystemTime},
+};
+
+use axum::Router;
+use common::{listen, s, server};
+use tinap::{
+    client::{
+        preflight::{CheckStatus, Layer},
+        Client,
+    },
+    clock::MockClock,
+};
+use tokio::net::TcpListener;
+
+/// a local port nothing is listening on
+fn dead_port() -> u16 {
+    let listener = StdListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+#[tokio::test]
+async fn a_running_server_passes_every_check() {
+    let server = server();
+    let port = listen(&server).await;
+    // two minutes behind the server
+    let clock = MockClock::new(SystemTime::now() - Duration::from_secs(120));
+
+    let report = Client::new(s("127.0.0.1"), port)
+        .with_clock(clock)
+        .preflight()
+        .await;
+    assert!(report.passed(), "{report}");
+    let target = &report.targets[0];
+    let layers = target
+        .checks
+        .iter()
+        .map(|check| check.layer)
+        .collect::<Vec<_>>();
+    assert_eq!(layers, [Layer::Dns, Layer::Tcp, Layer::Tls, Layer::Info]);
+    assert!(matches!(target.checks[2].status, CheckStatus::Skipped(_)));
+    assert!(target.connect_latency.is_some());
+    assert!(target.round_trip.is_some());
+    assert_eq!(
+        target.info.as_ref().unwrap().version,
+        env!("CARGO_PKG_VERSION")
+    );
+    let skew = target.clock_skew.unwrap();
+    assert!((119..=122).contains(&skew), "skew of {skew}s");
+}
+
+#[tokio::test]
+async fn a_dead_port_fails_at_tcp() {
+    let report = Client::new(s("127.0.0.1"), dead_port()).preflight().await;
+    assert!(!report.passed());
+    let target = &report.targets[0];
+    assert_eq!(target.failed_layer(), Some(Layer::Tcp));
+    // the checks stop at the failure
+    assert_eq!(target.checks.len(), 2);
+    assert!(target.info.is_none());
+    assert!(target.clock_skew.is_none());
+}
+
+#[tokio::test]
+async fn requiring_tls_without_it_fails_at_tls() {
+    let server = server();
+    let port = listen(&server).await;
+
+    let report = Client::new(s("127.0.0.1"), port)
+        .with_require_tls(true)
+        .preflight()
+        .await;
+    assert_eq!(report.targets[0].failed_layer(), Some(Layer::Tls));
+}
+
+#[tokio::test]
+async fn something_other_than_tinap_fails_at_info() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move { axum::serve(listener, Router::new()).await });
+
+    let report = Client::new(s("127.0.0.1"), port).preflight().await;
+    let target = &report.targets[0];
+    assert_eq!(target.failed_layer(), Some(Layer::Info));
+    assert!(
+        matches!(&target.checks[3].status, CheckStatus::Failed(reason) if reason.contains("info endpoint")),
+        "{target}"
+    );
+}
+
+#[tokio::test]
+async fn every_target_gets_its_own_report() {
+    let server = server();
+    let port = listen(&server).await;
+    let dead = dead_port();
+
+    let report = Client::with_targets(vec![(s("127.0.0.1"), dead), (s("127.0.0.1"), port)])
+        .preflight()
+        .await;
+    assert!(!report.passed());
+    assert_eq!(report.targets.len(), 2);
+    assert_eq!(report.targets[0].target, format!("127.0.0.1:{dead}"));
+    assert_eq!(report.targets[0].failed_layer(), Some(Layer::Tcp));
+    assert!(report.targets[1].passed(), "{report}");
+}
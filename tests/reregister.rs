@@ -0,0 +1,108 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{login, s, server};
+use tinap::{client::error::ClientError, loopback::loopback_pair, server::lockout::LockoutPolicy};
+
+#[tokio::test]
+async fn flagged_logins_do_not_lock_the_account() {
+    let server = server().with_lockout(LockoutPolicy {
+        threshold: 2,
+        window: Duration::from_secs(60),
+        max_window: Duration::from_secs(60),
+    });
+    let client = loopback_pair(&server);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    server.set_must_reregister(b"alice", true).await.unwrap();
+
+    // more correct logins than it takes failures to lock the account
+    for _ in 0..3 {
+        let err = client
+            .authenticate(s("alice"), s("hunter2"))
+            .await
+            .expect_err("logged in while flagged");
+        assert!(
+            matches!(err.inner(), ClientError::PasswordChangeRequired),
+            "{err:?}"
+        );
+    }
+
+    assert!(client
+        .change_password(s("alice"), s("hunter2"), s("hunter3"))
+        .await
+        .unwrap());
+    assert!(!server.must_reregister(b"alice").unwrap());
+    assert!(server.auth_failures(b"alice").unwrap().is_empty());
+    assert!(client
+        .authenticate(s("alice"), s("hunter3"))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn flagged_users_only_get_a_session_after_a_new_password() {
+    let server = server();
+    let client = loopback_pair(&server);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    server.set_must_reregister(b"alice", true).await.unwrap();
+
+    let err = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .expect_err("logged in while flagged");
+    assert!(
+        matches!(err.inner(), ClientError::PasswordChangeRequired),
+        "{err:?}"
+    );
+    // only the right password hears about the flag
+    assert!(client
+        .authenticate(s("alice"), s("wrong"))
+        .await
+        .unwrap()
+        .is_none());
+    assert!(server.takeout(b"alice").await.unwrap().sessions.is_empty());
+
+    // a failed change leaves the flag in place
+    assert!(!client
+        .change_password(s("alice"), s("wrong"), s("hunter3"))
+        .await
+        .unwrap());
+    assert!(server.must_reregister(b"alice").unwrap());
+
+    assert!(client
+        .change_password(s("alice"), s("hunter2"), s("hunter3"))
+        .await
+        .unwrap());
+    assert!(!server.must_reregister(b"alice").unwrap());
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_none());
+    login(&client, "alice", "hunter3").await;
+    assert_eq!(server.takeout(b"alice").await.unwrap().sessions.len(), 1);
+}
+
+#[tokio::test]
+async fn clearing_the_flag_lets_the_old_password_back_in() {
+    let server = server();
+    let client = loopback_pair(&server);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    server.set_must_reregister(b"alice", true).await.unwrap();
+    assert!(client.authenticate(s("alice"), s("hunter2")).await.is_err());
+
+    server.set_must_reregister(b"alice", false).await.unwrap();
+    login(&client, "alice", "hunter2").await;
+    assert_eq!(server.takeout(b"alice").await.unwrap().sessions.len(), 1);
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use common::s;
+use opaque_ke::ServerSetup;
+use rand::rngs::OsRng;
+use tinap::{
+    client::{error::ClientError, Client},
+    ksf::{Argon2Params, KsfParams},
+    loopback::loopback_pair,
+    outcome::RegistrationOutcome,
+    server::Server,
+    Scheme, SchemeScrypt, Scrypt,
+};
+
+fn store() -> sled::Db {
+    sled::Config::new()
+        .temporary(true)
+        .open()
+        .expect("Failed to open temporary store")
+}
+
+/// a scrypt server over `store` and a client wired to it, with costs low enough for tests
+fn scrypt_pair(store: sled::Db) -> (Server<SchemeScrypt>, Client<SchemeScrypt>) {
+    let server = Server::for_suite(ServerSetup::<SchemeScrypt>::new(&mut OsRng), store);
+    let ksf = Scrypt::with_params(10, 8, 1).unwrap();
+    let client = loopback_pair(&server).with_ksf(ksf);
+    (server, client)
+}
+
+#[tokio::test]
+async fn scrypt_registers_and_logs_in() {
+    let (_server, client) = scrypt_pair(store());
+    assert_eq!(
+        client
+            .register_user(s("alice"), s("hunter2"))
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+
+    let confirm = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .expect("login was refused");
+    assert!(!confirm.session_key().is_empty());
+    assert!(client
+        .authenticate(s("alice"), s("hunter3"))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn scrypt_changes_password() {
+    let (_server, client) = scrypt_pair(store());
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    assert!(client
+        .change_password(s("alice"), s("hunter2"), s("correct horse"))
+        .await
+        .unwrap());
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_none());
+    assert!(client
+        .authenticate(s("alice"), s("correct horse"))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn argon2_record_is_refused_under_scrypt() {
+    let store = store();
+    let argon2 = Server::new(ServerSetup::<Scheme>::new(&mut OsRng), store.clone());
+    loopback_pair(&argon2)
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    let (scrypt, client) = scrypt_pair(store);
+    let err = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .expect_err("a record from another suite was used");
+    assert!(
+        matches!(err.inner(), ClientError::ServerFailed(_)),
+        "{err:?}"
+    );
+    // the record is intact, it just belongs to the other suite
+    assert_eq!(scrypt.corrupt_record_count(), 0);
+    assert!(loopback_pair(&argon2)
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn runtime_info_reports_the_suites_key_stretching() {
+    let (server, _) = scrypt_pair(store());
+    let info = server.runtime_info().await.unwrap();
+    assert_eq!(info.ksf, Scrypt::default().params());
+    assert!(info.to_string().contains("scrypt: 2^17"), "{info}");
+
+    let server = Server::new(ServerSetup::<Scheme>::new(&mut OsRng), store());
+    let info = server.runtime_info().await.unwrap();
+    assert_eq!(info.ksf, KsfParams::Argon2(Argon2Params::default()));
+}
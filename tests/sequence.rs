@@ -0,0 +1,45 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{pair, s};
+use tinap::{outcome::DeleteOutcome, server::events::ServerEvent, wire::kind};
+use tokio::time::timeout;
+
+/// every operation runs to the end with both drivers holding each other to the declared tables
+#[tokio::test]
+async fn drivers_follow_the_declared_sequences() {
+    let (server, client) = pair();
+    let mut events = server.subscribe();
+
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+    assert!(client
+        .change_password(s("alice"), s("hunter2"), s("hunter3"))
+        .await
+        .unwrap());
+    assert_eq!(
+        client.delete_user(s("alice"), s("hunter3")).await.unwrap(),
+        DeleteOutcome::Deleted
+    );
+
+    // the server is done with the last exchange once it reports the deletion
+    loop {
+        let event = timeout(Duration::from_secs(5), events.recv()).await;
+        match event.unwrap().unwrap() {
+            ServerEvent::ExchangeFailed { kind, error, .. } => {
+                assert_ne!(kind, kind::OUT_OF_SEQUENCE, "{error}");
+                panic!("exchange failed with `{error}`");
+            }
+            ServerEvent::Deleted { .. } => break,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use common::{login, pair, s, server};
+use opaque_ke::ServerSetup;
+use rand::rngs::OsRng;
+use tinap::{
+    clock::MockClock, loopback::loopback_pair, outcome::RegistrationOutcome, server::Server, Scheme,
+};
+
+#[tokio::test]
+async fn token_names_the_user_until_revoked() {
+    let (server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let token = login(&client, "alice", "hunter2").await;
+
+    assert_eq!(
+        server.validate_token(&token).await.unwrap(),
+        Some(s("alice"))
+    );
+    assert!(server.revoke_token(&token).unwrap());
+    assert_eq!(server.validate_token(&token).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn tokens_run_out_with_their_ttl() {
+    let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000));
+    let server = server()
+        .with_clock(clock.clone())
+        .with_session_ttl(Duration::from_secs(60));
+    let client = loopback_pair(&server);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let token = login(&client, "alice", "hunter2").await;
+
+    clock.advance(Duration::from_secs(59));
+    assert_eq!(
+        server.validate_token(&token).await.unwrap(),
+        Some(s("alice"))
+    );
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(server.validate_token(&token).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn changing_the_password_revokes_tokens() {
+    let (server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    let first = login(&client, "alice", "hunter2").await;
+    let second = login(&client, "alice", "hunter2").await;
+
+    assert!(client
+        .change_password(s("alice"), s("hunter2"), s("correct horse"))
+        .await
+        .unwrap());
+
+    assert_eq!(server.validate_token(&first).await.unwrap(), None);
+    assert_eq!(server.validate_token(&second).await.unwrap(), None);
+    let fresh = login(&client, "alice", "correct horse").await;
+    assert_eq!(
+        server.validate_token(&fresh).await.unwrap(),
+        Some(s("alice"))
+    );
+}
+
+#[tokio::test]
+async fn deleting_the_user_revokes_tokens() {
+    let (server, client) = pair();
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    client.register_user(s("bob"), s("hunter3")).await.unwrap();
+    let alice = login(&client, "alice", "hunter2").await;
+    let bob = login(&client, "bob", "hunter3").await;
+
+    server.delete_user(b"alice").await.unwrap();
+    assert_eq!(server.validate_token(&alice).await.unwrap(), None);
+    assert_eq!(server.validate_token(&bob).await.unwrap(), Some(s("bob")));
+
+    // a new account under the same name doesn't inherit the old one's sessions
+    assert_eq!(
+        client.register_user(s("alice"), s("other")).await.unwrap(),
+        RegistrationOutcome::Created
+    );
+    assert_eq!(server.validate_token(&alice).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn revoked_tokens_leave_nothing_behind() {
+    let store = sled::Config::new().temporary(true).open().unwrap();
+    let server = Server::new(ServerSetup::<Scheme>::new(&mut OsRng), store.clone());
+    let client = loopback_pair(&server);
+    for (username, password) in [("alice", "hunter2"), ("bob", "hunter3")] {
+        client
+            .register_user(s(username), s(password))
+            .await
+            .unwrap();
+        login(&client, username, password).await;
+    }
+    let tokens = store.open_tree("sessions").unwrap();
+    let by_user = store.open_tree("user_sessions").unwrap();
+
+    assert!(client
+        .change_password(s("alice"), s("hunter2"), s("correct horse"))
+        .await
+        .unwrap());
+    assert_eq!((tokens.len(), by_user.len()), (1, 1));
+    login(&client, "alice", "correct horse").await;
+    server.delete_user(b"alice").await.unwrap();
+    assert_eq!((tokens.len(), by_user.len()), (1, 1));
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use common::{listen, s, server, upgraded};
+use fastwebsockets::WebSocketError;
+use tinap::{
+    client::{error::ClientError, Client},
+    ksf::{Argon2Params, MIN_MEMORY_KIB},
+    server::shedding::{LoadShedder, LoadShedding},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task::JoinSet,
+};
+
+/// a policy that only sheds on the number of running exchanges
+fn by_count(high_water: usize, low_water: usize) -> LoadShedding {
+    LoadShedding {
+        high_water,
+        low_water,
+        latency_threshold: Duration::from_secs(3600),
+    }
+}
+
+/// the response head the server answers a registration upgrade with, lower cased
+async fn upgrade_response(port: u16) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let request = format!(
+        "GET /registration HTTP/1.1\r\n\
+         Host: 127.0.0.1:{port}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).await.unwrap();
+        response.push(byte[0]);
+    }
+    String::from_utf8(response).unwrap().to_lowercase()
+}
+
+#[test]
+fn shedding_lasts_until_the_low_water_mark() {
+    let shedder = LoadShedder::new(by_count(3, 1));
+    let mut admitted = (0..3)
+        .map(|_| shedder.admit().expect("shed below the high water mark"))
+        .collect::<Vec<_>>();
+    assert!(shedder.admit().is_none());
+    assert!(shedder.status().shedding);
+
+    // back under the high water mark but not down to the low one
+    admitted.pop();
+    assert!(shedder.admit().is_none());
+    admitted.pop();
+    assert!(shedder.admit().is_some());
+    assert!(!shedder.status().shedding);
+}
+
+#[tokio::test]
+async fn slow_exchanges_start_shedding() {
+    let shedder = LoadShedder::new(LoadShedding {
+        high_water: 100,
+        low_water: 0,
+        latency_threshold: Duration::from_millis(1),
+    });
+    let slow = shedder.admit().unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    drop(slow);
+    assert!(shedder.status().average_latency > Duration::from_millis(1));
+
+    assert!(shedder.admit().is_none());
+    // nothing is running, so the next one gets in and the stale average is forgotten
+    assert!(shedder.admit().is_some());
+    assert_eq!(shedder.status().average_latency, Duration::ZERO);
+}
+
+#[tokio::test]
+async fn overloaded_servers_turn_upgrades_away_straight_away() {
+    let server = server().with_load_shedding(by_count(1, 0));
+    let port = listen(&server).await;
+    // holds on to its exchange without sending anything
+    let stalled = upgraded(port, "registration").await;
+
+    let response = upgrade_response(port).await;
+    assert!(response.starts_with("http/1.1 503"), "{response}");
+    assert!(response.contains("retry-after: 1\r\n"), "{response}");
+    assert!(server.load_status().unwrap().shedding);
+    let err = Client::new(s("127.0.0.1"), port)
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .expect_err("registered while load was being shed");
+    assert!(
+        matches!(
+            err.inner(),
+            ClientError::Websocket(WebSocketError::InvalidStatusCode(503))
+        ),
+        "{err:?}"
+    );
+    assert!(err.is_unreachable());
+
+    drop(stalled);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.load_status().unwrap().in_flight > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    Client::new(s("127.0.0.1"), port)
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    assert!(!server.load_status().unwrap().shedding);
+}
+
+/// the `percentile`th of `latencies`
+fn percentile(latencies: &mut [Duration], percentile: usize) -> Duration {
+    latencies.sort();
+    latencies[(latencies.len() - 1) * percentile / 100]
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn accepted_exchanges_stay_fast_while_shedding() {
+    let server = server().with_load_shedding(by_count(4, 2));
+    let port = listen(&server).await;
+    let ksf = Argon2Params {
+        memory_kib: MIN_MEMORY_KIB,
+        iterations: 1,
+        parallelism: 1,
+    }
+    .to_ksf()
+    .unwrap();
+    let client = Arc::new(Client::new(s("127.0.0.1"), port).with_ksf(ksf));
+
+    // bursts of four times as many registrations as are let in at once
+    let (mut accepted, mut shed) = (Vec::new(), Vec::new());
+    for burst in 0..8 {
+        let mut registrations = JoinSet::new();
+        for i in 0..16 {
+            let client = client.clone();
+            registrations.spawn(async move {
+                let started = Instant::now();
+                let username = format!("user{burst}-{i}");
+                let result = client.register_user(username, s("hunter2")).await;
+                (result, started.elapsed())
+            });
+        }
+        while let Some(joined) = registrations.join_next().await {
+            match joined.unwrap() {
+                (Ok(_), took) => accepted.push(took),
+                (Err(err), took) if err.is_unreachable() => shed.push(took),
+                (Err(err), _) => panic!("registration failed `{err:?}`"),
+            }
+        }
+    }
+
+    assert!(!accepted.is_empty());
+    assert!(!shed.is_empty(), "nothing was shed");
+    let accepted_p99 = percentile(&mut accepted, 99);
+    let shed_p50 = percentile(&mut shed, 50);
+    println!(
+        "{} accepted with p99 {accepted_p99:?}, {} shed with p50 {shed_p50:?}",
+        accepted.len(),
+        shed.len()
+    );
+    // with at most 4 running at once nothing queues up behind the rest
+    assert!(
+        accepted_p99 < Duration::from_secs(5),
+        "p99 {accepted_p99:?}"
+    );
+    assert!(shed_p50 < accepted_p99, "shed p50 {shed_p50:?}");
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{listen, s, server, upgraded};
+use tinap::{
+    client::{retry::RetryPolicy, Client},
+    server::listeners::ListenerConfig,
+};
+use tokio::{net::TcpListener, sync::oneshot};
+
+#[tokio::test]
+async fn serving_stops_cleanly_on_shutdown() {
+    let server = server();
+    let addr = {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    };
+    let (stop, stopped) = oneshot::channel::<()>();
+    let serving = tokio::spawn({
+        let server = server.clone();
+        async move {
+            let listeners = [ListenerConfig::public(addr)];
+            server
+                .serve_all(&listeners, async {
+                    let _ = stopped.await;
+                })
+                .await
+        }
+    });
+
+    let client = Client::new(s("127.0.0.1"), addr.port()).with_retry(RetryPolicy {
+        max_attempts: 10,
+        base_delay: Duration::from_millis(20),
+        jitter: Duration::ZERO,
+    });
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    stop.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(10), serving)
+        .await
+        .expect("server didn't stop")
+        .unwrap()
+        .unwrap();
+
+    // nothing is listening anymore, but everything written was kept
+    let client = Client::new(s("127.0.0.1"), addr.port());
+    assert!(client.authenticate(s("alice"), s("hunter2")).await.is_err());
+    assert_eq!(server.user_count().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn shutdown_waits_out_the_grace_period() {
+    const GRACE: Duration = Duration::from_millis(300);
+    let server = server().with_shutdown_grace(GRACE);
+    let port = listen(&server).await;
+    // an exchange that never finishes on its own
+    let _held = upgraded(port, "authenticate").await;
+
+    let started = Instant::now();
+    server.shutdown().await.unwrap();
+    assert!(started.elapsed() >= GRACE);
+}
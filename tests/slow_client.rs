@@ -0,0 +1,58 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{listen, s, server, upgraded};
+use tinap::{loopback::loopback_pair, outcome::RegistrationOutcome};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const FRAME_TIMEOUT: Duration = Duration::from_secs(10);
+const ARRIVAL_LIMIT: Duration = Duration::from_millis(300);
+
+#[tokio::test]
+async fn stalled_frame_is_reaped_and_its_permit_released() {
+    let server = server()
+        .with_frame_timeout(FRAME_TIMEOUT)
+        .with_frame_arrival_limit(ARRIVAL_LIMIT)
+        .with_handshake_limit(1);
+    let port = listen(&server).await;
+    let mut stream = upgraded(port, "registration").await;
+
+    // a masked binary frame announcing 1000 bytes, of which only a few ever arrive
+    let started = Instant::now();
+    stream
+        .write_all(&[0x82, 0x80 | 126, 0x03, 0xe8])
+        .await
+        .unwrap();
+    stream.write_all(&[1, 2, 3, 4, 5, 6, 7, 8]).await.unwrap();
+
+    let mut rest = Vec::new();
+    tokio::time::timeout(FRAME_TIMEOUT / 2, stream.read_to_end(&mut rest))
+        .await
+        .expect("stalled connection was not reaped in time")
+        .unwrap();
+    assert!(started.elapsed() >= ARRIVAL_LIMIT);
+
+    // the only handshake permit is free again
+    let client = loopback_pair(&server);
+    let outcome = client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    assert_eq!(outcome, RegistrationOutcome::Created);
+}
+
+#[tokio::test]
+async fn waiting_before_a_frame_is_not_cut_short() {
+    let server = server()
+        .with_frame_timeout(FRAME_TIMEOUT)
+        .with_frame_arrival_limit(ARRIVAL_LIMIT);
+    let port = listen(&server).await;
+    let mut stream = upgraded(port, "registration").await;
+
+    // idling longer than the arrival limit is fine as long as no frame has started
+    tokio::time::sleep(ARRIVAL_LIMIT * 2).await;
+    let mut byte = [0];
+    let read = tokio::time::timeout(ARRIVAL_LIMIT, stream.read(&mut byte)).await;
+    assert!(read.is_err(), "connection was closed while idle");
+}
@@ -0,0 +1,193 @@
+//! A long running mix of well behaved and misbehaving clients, checking nothing the server
+//! keeps around grows with the traffic
+//!
+//! ignored by default, run it with `cargo test --test soak -- --ignored soak`. It runs for
+//! `TINAP_SOAK_SECS` seconds, 60 unless set
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{listen, s, send_frame, server, upgraded};
+use tinap::{
+    client::Client,
+    ksf::{Argon2Params, MIN_MEMORY_KIB},
+    server::{lockout::LockoutPolicy, Server},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task::JoinSet,
+};
+
+const FRAME_TIMEOUT: Duration = Duration::from_millis(500);
+const USERS: usize = 8;
+/// clients of each kind per round
+const ROUND: usize = 4;
+/// how far a sample may end up above the first one before it counts as a leak
+const FD_TOLERANCE: usize = 16;
+
+/// what the server holds on to, sampled once it's gone idle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sample {
+    exchanges: usize,
+    in_flight_users: usize,
+    permits: usize,
+    /// open file descriptors of the whole test process, `None` off Linux
+    fds: Option<usize>,
+}
+
+fn sample(server: &Server) -> Sample {
+    Sample {
+        exchanges: server.running_exchanges(),
+        in_flight_users: server.in_flight_users(),
+        permits: server
+            .budget_usage()
+            .iter()
+            .map(|(_, _, in_use)| in_use)
+            .sum::<usize>()
+            + server.handshake_budget().in_use(),
+        fds: std::fs::read_dir("/proc/self/fd")
+            .ok()
+            .map(|fds| fds.count()),
+    }
+}
+
+/// a client of the server on `port`, stretching passwords cheaply enough to stay well within
+/// the frame timeout
+fn client(port: u16) -> Client {
+    let ksf = Argon2Params {
+        memory_kib: MIN_MEMORY_KIB,
+        iterations: 1,
+        parallelism: 1,
+    }
+    .to_ksf()
+    .unwrap();
+    Client::new(s("127.0.0.1"), port).with_ksf(ksf)
+}
+
+/// wait for the server to close `stream`, which it has to do on its own well within a few
+/// frame timeouts
+async fn closed_by_server(mut stream: TcpStream) {
+    let mut rest = Vec::new();
+    tokio::time::timeout(FRAME_TIMEOUT * 4, stream.read_to_end(&mut rest))
+        .await
+        .expect("server held on to a misbehaving connection")
+        .ok();
+}
+
+/// one round of every kind of client, both well behaved and not
+fn round(port: u16, clients: &mut JoinSet<()>) {
+    for i in 0..ROUND {
+        let user = format!("user{}", i % USERS);
+        clients.spawn(async move {
+            let client = client(port);
+            let _ = client.authenticate(user, s("hunter2")).await;
+        });
+        let user = format!("user{}", (i + 1) % USERS);
+        clients.spawn(async move {
+            let client = client(port);
+            let _ = client.authenticate(user, s("wrong")).await;
+        });
+        // upgraded, then never a word
+        clients.spawn(async move {
+            closed_by_server(upgraded(port, "authenticate").await).await;
+        });
+        // gone halfway through a frame
+        clients.spawn(async move {
+            let mut stream = upgraded(port, "registration").await;
+            stream.write_all(&[0x82, 0x80 | 100, 0, 0]).await.unwrap();
+        });
+        // something that isn't a registration at all
+        clients.spawn(async move {
+            let mut stream = upgraded(port, "registration").await;
+            send_frame(&mut stream, 0x2, &[0xff; 64]).await;
+            closed_by_server(stream).await;
+        });
+        // a frame far beyond what any message can be
+        clients.spawn(async move {
+            let mut stream = upgraded(port, "authenticate").await;
+            let mut header = vec![0x82, 0x80 | 127];
+            header.extend_from_slice(&(64u64 << 20).to_be_bytes());
+            header.extend_from_slice(&[0; 4]);
+            let _ = stream.write_all(&header).await;
+            closed_by_server(stream).await;
+        });
+        // an upgrade that's never finished asking for
+        clients.spawn(async move {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            stream
+                .write_all(b"GET /authenticate HTTP/1.1\r\nHost: 127.0.0.1\r\n")
+                .await
+                .unwrap();
+            tokio::time::sleep(FRAME_TIMEOUT).await;
+        });
+    }
+}
+
+/// the sample once the server has nothing running anymore
+async fn settled(server: &Server) -> Sample {
+    let started = Instant::now();
+    loop {
+        let sample = sample(server);
+        if sample.exchanges == 0 && sample.permits == 0 && sample.in_flight_users == 0 {
+            return sample;
+        }
+        assert!(
+            started.elapsed() < FRAME_TIMEOUT * 10,
+            "server never went idle: {sample:?}"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "runs for a minute, see the module docs"]
+async fn soak() {
+    let duration = std::env::var("TINAP_SOAK_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map_or(Duration::from_secs(60), Duration::from_secs);
+    let server = server()
+        .with_frame_timeout(FRAME_TIMEOUT)
+        .with_frame_arrival_limit(FRAME_TIMEOUT / 2)
+        .with_user_in_flight_limit(2)
+        .with_lockout(LockoutPolicy {
+            threshold: 3,
+            window: Duration::from_secs(1),
+            max_window: Duration::from_secs(2),
+        });
+    let port = listen(&server).await;
+    let client = client(port);
+    for i in 0..USERS {
+        client
+            .register_user(format!("user{i}"), s("hunter2"))
+            .await
+            .unwrap();
+    }
+
+    let mut samples = Vec::new();
+    let started = Instant::now();
+    while started.elapsed() < duration {
+        let mut clients = JoinSet::new();
+        round(port, &mut clients);
+        while let Some(client) = clients.join_next().await {
+            client.unwrap();
+        }
+        samples.push(settled(&server).await);
+    }
+
+    let first = samples[0];
+    for (at, sample) in samples.iter().enumerate() {
+        if let (Some(first), Some(fds)) = (first.fds, sample.fds) {
+            assert!(
+                fds <= first + FD_TOLERANCE,
+                "descriptors went from {first} to {fds} by round {at}"
+            );
+        }
+    }
+    println!(
+        "{} rounds, samples went from {first:?} to {:?}",
+        samples.len(),
+        samples.last().unwrap()
+    );
+}
@@ -0,0 +1,306 @@
+mod common;
+
+use common::{login, pair, s, temp_dir};
+use opaque_ke::ServerSetup;
+use rand::rngs::OsRng;
+use tinap::{
+    attributes::Attributes,
+    client::error::ClientError,
+    loopback::loopback_pair,
+    outcome::RegistrationOutcome,
+    server::{
+        backup::{export_users, import_users},
+        bootstrap::ADMIN_ATTRIBUTE,
+        error::ServerError,
+        migrate::migrate_store,
+        record::{self, UserRecord},
+        setup_file::{read_setup, write_setup},
+        Server, ATTRIBUTES_TREE,
+    },
+    storage_key::{username_of, KeyPolicy, StorageKey},
+    verify::verify_record,
+    wire::REJECTED,
+    Scheme,
+};
+
+fn store() -> sled::Db {
+    sled::Config::new()
+        .temporary(true)
+        .open()
+        .expect("Failed to open temporary store")
+}
+
+/// a server over `store` with `setup` that alice registered with, the users are kept in the
+/// store's default tree
+async fn with_alice(setup: &ServerSetup<Scheme>, store: &sled::Db) -> Server {
+    let server = Server::new(setup.clone(), store.clone());
+    loopback_pair(&server)
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    server
+}
+
+async fn logs_in(server: &Server, password: &str) -> bool {
+    loopback_pair(server)
+        .authenticate(s("alice"), s(password))
+        .await
+        .unwrap()
+        .is_some()
+}
+
+#[tokio::test]
+async fn stored_record_verifies_offline() {
+    let setup = ServerSetup::<Scheme>::new(&mut OsRng);
+    let store = store();
+    with_alice(&setup, &store).await;
+    let stored = store.get(b"alice").unwrap().unwrap();
+
+    assert!(verify_record(&setup, "alice", "hunter2", &stored).unwrap());
+    assert!(!verify_record(&setup, "alice", "hunter3", &stored).unwrap());
+    let other = ServerSetup::<Scheme>::new(&mut OsRng);
+    assert!(!verify_record(&other, "alice", "hunter2", &stored).unwrap());
+
+    let mut corrupted = stored.to_vec();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert!(verify_record(&setup, "alice", "hunter2", &corrupted).is_err());
+}
+
+#[tokio::test]
+async fn legacy_record_is_migrated_and_still_logs_in() {
+    let setup = ServerSetup::<Scheme>::new(&mut OsRng);
+    let store = store();
+    let server = with_alice(&setup, &store).await;
+    loopback_pair(&server)
+        .register_user(s("bob"), s("hunter3"))
+        .await
+        .unwrap();
+
+    // stored the way records were before they had an envelope
+    let stored = store.get(b"alice").unwrap().unwrap();
+    let password_file = record::unseal(&stored)
+        .and_then(UserRecord::checked_password_file)
+        .unwrap();
+    store.insert(b"alice", password_file).unwrap();
+
+    let report = migrate_store(&store).unwrap();
+    assert_eq!((report.migrated, report.skipped, report.failed), (1, 1, 0));
+    let stored = store.get(b"alice").unwrap().unwrap();
+    assert_eq!(
+        record::unseal(&stored).unwrap().version,
+        UserRecord::CURRENT
+    );
+    assert!(logs_in(&server, "hunter2").await);
+
+    // running it again finds nothing left to do
+    let report = migrate_store(&store).unwrap();
+    assert_eq!((report.migrated, report.skipped), (0, 2));
+}
+
+#[tokio::test]
+async fn imported_users_log_in_with_the_same_setup() {
+    let setup = ServerSetup::<Scheme>::new(&mut OsRng);
+    let original = store();
+    with_alice(&setup, &original).await;
+    let attributes = original.open_tree(ATTRIBUTES_TREE).unwrap();
+    let backup = export_users(&original, &attributes).unwrap();
+
+    let restored = store();
+    let attributes = restored.open_tree(ATTRIBUTES_TREE).unwrap();
+    let report = import_users(&restored, &attributes, &backup, false).unwrap();
+    assert_eq!((report.added, report.replaced), (1, 0));
+    assert!(import_users(&restored, &attributes, &backup, false).is_err());
+    let report = import_users(&restored, &attributes, &backup, true).unwrap();
+    assert_eq!((report.added, report.replaced), (0, 1));
+
+    let server = Server::new(setup, restored.clone());
+    assert!(logs_in(&server, "hunter2").await);
+    // the records are useless without the setup they were registered under
+    let server = Server::new(ServerSetup::<Scheme>::new(&mut OsRng), restored);
+    assert!(!logs_in(&server, "hunter2").await);
+}
+
+#[tokio::test]
+async fn setup_file_round_trips_and_refuses_tampering() {
+    let setup = ServerSetup::<Scheme>::new(&mut OsRng);
+    let store = store();
+    with_alice(&setup, &store).await;
+    let path = temp_dir("setup").join("server_setup");
+
+    assert!(read_setup(&path).unwrap().is_none());
+    write_setup(&path, &setup).unwrap();
+    let read = read_setup(&path).unwrap().expect("setup wasn't written");
+    assert!(logs_in(&Server::new(read, store), "hunter2").await);
+
+    let mut data = std::fs::read(&path).unwrap();
+    let middle = data.len() / 2;
+    data[middle] ^= 1;
+    std::fs::write(&path, data).unwrap();
+    assert!(matches!(read_setup(&path), Err(ServerError::Setup(_))));
+}
+
+#[tokio::test]
+async fn tenants_sharing_a_store_stay_apart() {
+    let setup = ServerSetup::<Scheme>::new(&mut OsRng);
+    let store = store();
+    let first = Server::new(setup.clone(), store.clone()).with_tenant(s("first"));
+    let second = Server::new(setup, store).with_tenant(s("second"));
+    loopback_pair(&first)
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    assert_eq!(second.user_count().await.unwrap(), 0);
+    assert!(!logs_in(&second, "hunter2").await);
+    assert_eq!(
+        loopback_pair(&second)
+            .register_user(s("alice"), s("other"))
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+    assert!(logs_in(&first, "hunter2").await);
+    assert!(logs_in(&second, "other").await);
+    assert_eq!(first.list_users(0, 10).await.unwrap(), vec![s("alice")]);
+}
+
+#[test]
+fn storage_keys_round_trip_per_tenant() {
+    let policy = KeyPolicy::default();
+    let key = StorageKey::for_user(&policy, Some("first"), b" Alice").unwrap();
+    assert_eq!(
+        username_of(key.as_bytes(), Some("first")),
+        Some(&b"alice"[..])
+    );
+    assert_eq!(username_of(key.as_bytes(), Some("second")), None);
+    assert_eq!(username_of(key.as_bytes(), None), None);
+
+    let key = StorageKey::for_user(&policy, None, b"alice").unwrap();
+    assert_eq!(key.as_bytes(), b"alice");
+    assert!(StorageKey::for_user(&policy, Some("fir\0st"), b"alice").is_err());
+    assert!(StorageKey::for_user(&policy, None, b"").is_err());
+}
+
+#[tokio::test]
+async fn server_enforces_its_username_limit() {
+    let (server, _) = pair();
+    let server = server.with_key_policy(KeyPolicy { max_len: 8 });
+    let client = loopback_pair(&server);
+
+    let err = client
+        .register_user(s("a-rather-long-name"), s("hunter2"))
+        .await
+        .expect_err("a username over the limit was taken");
+    assert!(
+        matches!(err.inner(), ClientError::Rejected(REJECTED, _)),
+        "{err:?}"
+    );
+    assert_eq!(
+        client
+            .register_user(s("alice"), s("hunter2"))
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+}
+
+#[tokio::test]
+async fn interrupted_deletion_leaves_the_account_whole() {
+    let setup = ServerSetup::<Scheme>::new(&mut OsRng);
+    let store = store();
+    let server = with_alice(&setup, &store).await;
+    let mut attributes = Attributes::new();
+    attributes.insert(s("team"), s("red")).unwrap();
+    server.set_attributes(b"alice", &attributes).await.unwrap();
+    let token = login(&loopback_pair(&server), "alice", "hunter2").await;
+
+    let crashing = Server::new(setup.clone(), store.clone()).with_crash_before_commit();
+    assert!(crashing.delete_user(b"alice").await.is_err());
+
+    assert!(logs_in(&server, "hunter2").await);
+    assert_eq!(server.attributes(b"alice").unwrap(), attributes);
+    assert_eq!(
+        server.validate_token(&token).await.unwrap(),
+        Some(s("alice"))
+    );
+}
+
+#[tokio::test]
+async fn interrupted_password_change_keeps_the_old_password() {
+    let setup = ServerSetup::<Scheme>::new(&mut OsRng);
+    let store = store();
+    let server = with_alice(&setup, &store).await;
+    let token = login(&loopback_pair(&server), "alice", "hunter2").await;
+
+    let crashing = Server::new(setup.clone(), store.clone()).with_crash_before_commit();
+    let changed = loopback_pair(&crashing)
+        .change_password(s("alice"), s("hunter2"), s("hunter3"))
+        .await;
+    assert!(!matches!(changed, Ok(true)), "{changed:?}");
+
+    assert!(logs_in(&server, "hunter2").await);
+    assert!(!logs_in(&server, "hunter3").await);
+    assert_eq!(
+        server.validate_token(&token).await.unwrap(),
+        Some(s("alice"))
+    );
+}
+
+#[tokio::test]
+async fn interrupted_invited_registration_keeps_the_invite() {
+    let setup = ServerSetup::<Scheme>::new(&mut OsRng);
+    let store = store();
+    let server = Server::new(setup.clone(), store.clone()).with_invite_only(true);
+    let invite = server.create_invite().unwrap();
+
+    let crashing = Server::new(setup.clone(), store.clone())
+        .with_invite_only(true)
+        .with_crash_before_commit();
+    assert!(loopback_pair(&crashing)
+        .register_with_invite(s("alice"), s("hunter2"), invite.clone())
+        .await
+        .is_err());
+    assert_eq!(server.user_count().await.unwrap(), 0);
+
+    assert_eq!(
+        loopback_pair(&server)
+            .register_with_invite(s("alice"), s("hunter2"), invite)
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+}
+
+#[tokio::test]
+async fn interrupted_bootstrap_can_be_tried_again() {
+    let setup = ServerSetup::<Scheme>::new(&mut OsRng);
+    let store = store();
+    let crashing = Server::new(setup.clone(), store.clone())
+        .with_crash_before_commit()
+        .with_bootstrap_token(s("let me in"))
+        .await
+        .unwrap();
+    assert!(loopback_pair(&crashing)
+        .with_bootstrap_token(Some(s("let me in")))
+        .register_user(s("root"), s("hunter2"))
+        .await
+        .is_err());
+    assert_eq!(crashing.user_count().await.unwrap(), 0);
+
+    let server = Server::new(setup, store)
+        .with_bootstrap_token(s("let me in"))
+        .await
+        .unwrap();
+    assert_eq!(
+        loopback_pair(&server)
+            .with_bootstrap_token(Some(s("let me in")))
+            .register_user(s("root"), s("hunter2"))
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+    assert_eq!(
+        server.attributes(b"root").unwrap().get(ADMIN_ATTRIBUTE),
+        Some("true")
+    );
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use common::{s, server};
+use tinap::{
+    client::error::ClientError,
+    loopback::loopback_pair,
+    server::store::{MemoryStore, StoreError, StoreFuture, UserStore},
+    storage_key::StorageKey,
+};
+
+/// a [`MemoryStore`] whose reads of password files fail while it still has failures to give
+#[derive(Clone, Default)]
+struct FlakyStore {
+    inner: MemoryStore,
+    failures: Arc<AtomicUsize>,
+}
+
+impl FlakyStore {
+    /// fail the next `failures` reads
+    fn fail(&self, failures: usize) {
+        self.failures.store(failures, Ordering::SeqCst);
+    }
+}
+
+impl UserStore for FlakyStore {
+    fn get<'f>(&'f self, key: &'f StorageKey) -> StoreFuture<'f, Option<Vec<u8>>> {
+        let failing = self
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                left.checked_sub(1)
+            })
+            .is_ok();
+        if failing {
+            return Box::pin(async { Err(StoreError::Unavailable("flaky".into())) });
+        }
+        self.inner.get(key)
+    }
+
+    fn insert_if_absent<'f>(
+        &'f self,
+        key: &'f StorageKey,
+        password_file: &'f [u8],
+    ) -> StoreFuture<'f, bool> {
+        self.inner.insert_if_absent(key, password_file)
+    }
+
+    fn insert<'f>(&'f self, key: &'f StorageKey, password_file: &'f [u8]) -> StoreFuture<'f, ()> {
+        self.inner.insert(key, password_file)
+    }
+
+    fn remove<'f>(&'f self, key: &'f StorageKey) -> StoreFuture<'f, bool> {
+        self.inner.remove(key)
+    }
+
+    fn contains<'f>(&'f self, key: &'f StorageKey) -> StoreFuture<'f, bool> {
+        self.inner.contains(key)
+    }
+
+    fn count(&self) -> StoreFuture<'_, usize> {
+        self.inner.count()
+    }
+
+    fn keys(&self) -> StoreFuture<'_, Vec<Vec<u8>>> {
+        self.inner.keys()
+    }
+}
+
+#[tokio::test]
+async fn logins_get_past_a_failed_first_read() {
+    let store = FlakyStore::default();
+    let server = server().with_user_store(store.clone());
+    let client = loopback_pair(&server);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    store.fail(1);
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+    // the login did run into the failure
+    assert_eq!(store.failures.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn a_store_that_stays_down_asks_the_client_to_try_again() {
+    let store = FlakyStore::default();
+    let server = server().with_user_store(store.clone());
+    let client = loopback_pair(&server);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    store.fail(usize::MAX);
+    let err = client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .expect_err("logged in without a password file");
+    assert!(
+        matches!(err.inner(), ClientError::TryAgainLater(_)),
+        "{err:?}"
+    );
+    // not a guess at the password
+    assert!(server.auth_failures(b"alice").unwrap().is_empty());
+
+    store.fail(0);
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+}
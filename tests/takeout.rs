@@ -0,0 +1,111 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::http::{Method, StatusCode};
+use common::{call, login, s, server};
+use tinap::{
+    attributes::Attributes,
+    client::error::ClientError,
+    loopback::loopback_pair,
+    server::{events::ServerEvent, lockout::LockoutPolicy},
+    takeout::TakeoutDocument,
+};
+
+const ADMIN_TOKEN: &str = "admin secret";
+
+#[tokio::test]
+async fn every_tree_about_the_user_ends_up_in_the_takeout() {
+    let server = server()
+        .with_lockout(LockoutPolicy {
+            threshold: 1,
+            window: Duration::from_secs(60),
+            max_window: Duration::from_secs(60),
+        })
+        .with_admin_token(s(ADMIN_TOKEN));
+    let client = loopback_pair(&server);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    client
+        .store_blob(s("alice"), s("hunter2"), b"secret notes")
+        .await
+        .unwrap();
+    let token = login(&client, "alice", "hunter2").await;
+    let mut attributes = Attributes::new();
+    attributes.insert(s("locale"), s("en")).unwrap();
+    client.set_attributes(&token, &attributes).await.unwrap();
+    // a wrong password, which also locks the account
+    assert!(client
+        .authenticate(s("alice"), s("wrong"))
+        .await
+        .unwrap()
+        .is_none());
+    server.set_must_reregister(b"alice", true).await.unwrap();
+    let mut events = server.subscribe();
+
+    let document = client.takeout(&token).await.unwrap();
+    assert_eq!(document.username, "alice");
+    assert!(document.created_at > 0);
+    assert!(document.last_login.is_some());
+    assert_eq!(document.attributes, attributes);
+    assert_eq!(document.auth_failures.len(), 1);
+    assert!(document.locked_for.is_some());
+    assert!(document.must_reregister);
+    // the store and the login each handed out a token
+    assert_eq!(document.sessions.len(), 2);
+    let blob = document.blob.expect("blob was left out");
+    assert!(!blob.is_empty());
+    assert!(!blob.windows(6).any(|window| window == b"secret"));
+    assert!(matches!(
+        events.recv().await.unwrap(),
+        ServerEvent::Exported { admin: false, .. }
+    ));
+
+    // support gets the same document
+    let (status, body) = call(
+        server.router(),
+        Method::GET,
+        "/admin/users/alice/takeout",
+        Some(ADMIN_TOKEN),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let exported: TakeoutDocument = serde_json::from_str(&body).unwrap();
+    assert_eq!(exported.username, "alice");
+    assert_eq!(exported.sessions.len(), 2);
+    assert!(matches!(
+        events.recv().await.unwrap(),
+        ServerEvent::Exported { admin: true, .. }
+    ));
+}
+
+#[tokio::test]
+async fn sessions_only_take_out_their_own_user() {
+    let server = server();
+    let client = loopback_pair(&server);
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    client.register_user(s("bob"), s("hunter3")).await.unwrap();
+    client
+        .store_blob(s("alice"), s("hunter2"), b"secret notes")
+        .await
+        .unwrap();
+    let token = login(&client, "bob", "hunter3").await;
+
+    let document = client.takeout(&token).await.unwrap();
+    assert_eq!(document.username, "bob");
+    assert!(document.blob.is_none());
+    assert_eq!(document.sessions.len(), 1);
+
+    assert!(matches!(
+        client.takeout("not a token").await,
+        Err(ClientError::NotAuthenticated)
+    ));
+    let (status, _) = call(server.router(), Method::GET, "/takeout", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
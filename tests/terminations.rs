@@ -0,0 +1,202 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{listen, read_frame, s, server};
+use tinap::{
+    client::{error::ClientError, Client},
+    loopback::loopback_pair,
+    server::Server,
+    wire::DONE,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+};
+
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+/// messages the server sends in a registration
+const REGISTRATION_MESSAGES: usize = 1;
+/// messages the server sends in a login, deletions start with one
+const LOGIN_MESSAGES: usize = 2;
+
+/// How the scripted server ends an exchange once it has sent its last message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ending {
+    /// a close arrives along with the message, before the client has answered it
+    EarlyClose,
+    /// the connection drops straight after the message, the server never hears back
+    Eof,
+    /// the server hears back and finishes, but its close is lost and the connection drops
+    NoClose,
+}
+
+/// a server frame as it goes over the wire, unmasked
+fn encode(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// a port serving the exchanges of the server on `server_port`, in each of which the server
+/// sends `messages` messages, ended the way `ending` says rather than how the server ends them
+async fn scripted(server_port: u16, messages: usize, ending: Ending) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (client, _) = listener.accept().await.unwrap();
+            let upstream = TcpStream::connect(("127.0.0.1", server_port))
+                .await
+                .unwrap();
+            tokio::spawn(relay(client, upstream, messages, ending));
+        }
+    });
+    port
+}
+
+/// pass one connection through to the server, stepping in after the server's last message
+async fn relay(client: TcpStream, upstream: TcpStream, messages: usize, ending: Ending) {
+    let (mut client_read, mut client_write) = client.into_split();
+    let (mut upstream_read, mut upstream_write) = upstream.into_split();
+    let requests = tokio::spawn(async move {
+        let _ = tokio::io::copy(&mut client_read, &mut upstream_write).await;
+    });
+
+    forward_upgrade(&mut upstream_read, &mut client_write).await;
+    let mut sent = 0;
+    loop {
+        let (opcode, payload) = read_frame(&mut upstream_read).await;
+        if opcode == CLOSE && ending == Ending::NoClose {
+            break;
+        }
+        let mut frame = encode(opcode, &payload);
+        if opcode == BINARY {
+            sent += 1;
+        }
+        if sent == messages && ending != Ending::NoClose {
+            // nothing the client says from here on reaches the server
+            requests.abort();
+            if ending == Ending::EarlyClose {
+                let done = [&1000u16.to_be_bytes(), DONE.as_bytes()].concat();
+                frame.extend(encode(CLOSE, &done));
+            }
+            client_write.write_all(&frame).await.unwrap();
+            break;
+        }
+        client_write.write_all(&frame).await.unwrap();
+        if opcode == CLOSE {
+            break;
+        }
+    }
+    requests.abort();
+}
+
+/// hand the server's answer to the upgrade on to the client as is
+async fn forward_upgrade(upstream: &mut OwnedReadHalf, client: &mut OwnedWriteHalf) {
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        upstream.read_exact(&mut byte).await.unwrap();
+        response.push(byte[0]);
+    }
+    client.write_all(&response).await.unwrap();
+}
+
+/// a client reaching `server` through a scripted server, ending exchanges in which the server
+/// sends `messages` messages with `ending`
+async fn client_ending(server: &Server, messages: usize, ending: Ending) -> Client {
+    let port = scripted(listen(server).await, messages, ending).await;
+    Client::new(s("127.0.0.1"), port).with_read_timeout(Duration::from_secs(5))
+}
+
+#[tokio::test]
+async fn logins_settle_however_the_server_ends_them() {
+    let server = server();
+    loopback_pair(&server)
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    for ending in [Ending::EarlyClose, Ending::Eof, Ending::NoClose] {
+        let confirm = client_ending(&server, LOGIN_MESSAGES, ending)
+            .await
+            .authenticate(s("alice"), s("hunter2"))
+            .await
+            .unwrap_or_else(|err| panic!("{ending:?} failed the login `{err:?}`"));
+        assert!(confirm.is_some(), "{ending:?} refused the login");
+    }
+}
+
+#[tokio::test]
+async fn registrations_cut_off_before_the_close_are_unknown() {
+    let server = server();
+
+    // the upload never reached the server
+    let err = client_ending(&server, REGISTRATION_MESSAGES, Ending::Eof)
+        .await
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .expect_err("a registration the server never finished succeeded");
+    assert!(
+        matches!(err.inner(), ClientError::OutcomeUnknown),
+        "{err:?}"
+    );
+    assert_eq!(server.user_count().await.unwrap(), 0);
+
+    // the server stored the upload but couldn't say so
+    let err = client_ending(&server, REGISTRATION_MESSAGES, Ending::NoClose)
+        .await
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .expect_err("a registration without a verdict succeeded");
+    assert!(
+        matches!(err.inner(), ClientError::OutcomeUnknown),
+        "{err:?}"
+    );
+    assert_eq!(server.user_count().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn deletions_cut_off_before_the_close_are_unknown() {
+    let server = server();
+    loopback_pair(&server)
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    // the confirmation never reached the server
+    let err = client_ending(&server, LOGIN_MESSAGES, Ending::Eof)
+        .await
+        .delete_user(s("alice"), s("hunter2"))
+        .await
+        .expect_err("a deletion the server never finished succeeded");
+    assert!(
+        matches!(err.inner(), ClientError::OutcomeUnknown),
+        "{err:?}"
+    );
+    assert_eq!(server.user_count().await.unwrap(), 1);
+
+    // the server deleted the user but couldn't say so
+    let err = client_ending(&server, LOGIN_MESSAGES, Ending::NoClose)
+        .await
+        .delete_user(s("alice"), s("hunter2"))
+        .await
+        .expect_err("a deletion without a verdict succeeded");
+    assert!(
+        matches!(err.inner(), ClientError::OutcomeUnknown),
+        "{err:?}"
+    );
+    assert_eq!(server.user_count().await.unwrap(), 0);
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{s, server};
+use tinap::{
+    client::timings::Phase,
+    ksf::{Argon2Params, MIN_MEMORY_KIB},
+    loopback::loopback_pair,
+    outcome::RegistrationOutcome,
+    Argon2,
+};
+
+use Phase::{Connect, Local, RoundTrip};
+
+/// slow enough for the key stretching to stand out from everything else
+fn slow() -> Argon2 {
+    Argon2Params {
+        memory_kib: MIN_MEMORY_KIB,
+        iterations: 4,
+        parallelism: 1,
+    }
+    .to_ksf()
+    .unwrap()
+}
+
+/// the phases of `timings` without how long they took
+fn phases(timings: &[(Phase, Duration)]) -> Vec<Phase> {
+    timings.iter().map(|(phase, _)| *phase).collect()
+}
+
+#[tokio::test]
+async fn registrations_time_every_phase_in_order() {
+    let server = server();
+    let client = loopback_pair(&server).with_ksf(slow());
+
+    let started = Instant::now();
+    let (outcome, timings) = client.register_user_timed(s("alice"), s("hunter2")).await;
+    let elapsed = started.elapsed();
+    assert_eq!(outcome.unwrap(), RegistrationOutcome::Created);
+
+    assert_eq!(
+        phases(timings.phases()),
+        [Connect, Local, RoundTrip, Local, RoundTrip, Local]
+    );
+    assert!(timings.phases().iter().all(|(_, took)| !took.is_zero()));
+    assert!(timings.total() <= elapsed);
+    // the key stretching happens between the server's answer and the upload
+    let stretching = timings.phases()[3].1;
+    assert!(timings
+        .phases()
+        .iter()
+        .enumerate()
+        .all(|(i, (_, took))| i == 3 || *took < stretching));
+    assert_eq!(
+        timings.total_of(Connect) + timings.total_of(Local) + timings.total_of(RoundTrip),
+        timings.total()
+    );
+}
+
+#[tokio::test]
+async fn logins_time_every_phase_in_order() {
+    let server = server();
+    let client = loopback_pair(&server).with_ksf(slow());
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+
+    let (confirm, timings) = client.authenticate_timed(s("alice"), s("hunter2")).await;
+    assert!(confirm.unwrap().is_some());
+    assert_eq!(
+        phases(timings.phases()),
+        [Connect, Local, RoundTrip, Local, RoundTrip, Local, RoundTrip, Local]
+    );
+    let stretching = timings.phases()[3].1;
+    assert!(timings.total_of(Local) >= stretching);
+    assert!(timings.total_of(RoundTrip) < stretching);
+
+    // a refused password is timed up to where the client gives up
+    let (confirm, timings) = client.authenticate_timed(s("alice"), s("hunter3")).await;
+    assert!(confirm.unwrap().is_none());
+    assert_eq!(phases(timings.phases()), [Connect, Local, RoundTrip, Local]);
+}
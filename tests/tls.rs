@@ -0,0 +1,168 @@
+mod common;
+
+use std::{future::pending, time::Duration};
+
+use common::{s, server, temp_dir};
+use tinap::{
+    client::{error::ClientError, retry::RetryPolicy, Client},
+    outcome::RegistrationOutcome,
+    server::{
+        listeners::ListenerConfig,
+        tls::{serve_tls, TlsConfig},
+        Server,
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// a self-signed certificate for `name`, written out where a [`TlsConfig`] can read it, along
+/// with the certificate as PEM for clients to trust
+fn certificate(name: &str) -> (TlsConfig, String) {
+    let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+    let dir = temp_dir("tls");
+    let config = TlsConfig::new(dir.join("cert.pem"), dir.join("key.pem"));
+    let pem = generated.cert.pem();
+    std::fs::write(&config.cert_chain, &pem).unwrap();
+    std::fs::write(&config.private_key, generated.key_pair.serialize_pem()).unwrap();
+    (config, pem)
+}
+
+/// `server` terminating TLS with `config` on a local port
+async fn listen_tls(server: &Server, config: &TlsConfig) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (config, router) = (config.load().unwrap(), server.router());
+    tokio::spawn(serve_tls(listener, config, router, pending()));
+    port
+}
+
+#[tokio::test]
+async fn logs_in_over_tls_with_a_self_signed_certificate() {
+    let (config, pem) = certificate("localhost");
+    let server = server();
+    let port = listen_tls(&server, &config).await;
+    let client = Client::new_tls(s("localhost"), port)
+        .with_root_certificates(pem.as_bytes())
+        .unwrap();
+
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn terminating_tls_satisfies_require_tls() {
+    let (config, pem) = certificate("localhost");
+    // no proxy is trusted, the server's own TLS is the only way in
+    let server = server().with_require_tls(vec![]);
+    let port = listen_tls(&server, &config).await;
+    let client = Client::new_tls(s("localhost"), port)
+        .with_root_certificates(pem.as_bytes())
+        .unwrap()
+        .with_require_tls(true);
+
+    client
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .unwrap();
+    assert!(client
+        .authenticate(s("alice"), s("hunter2"))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn untrusted_certificates_are_a_tls_error() {
+    let (config, _) = certificate("localhost");
+    let server = server();
+    let port = listen_tls(&server, &config).await;
+
+    let err = Client::new_tls(s("localhost"), port)
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .expect_err("trusted a self-signed certificate");
+    assert!(matches!(err.inner(), ClientError::Tls(_)), "{err:?}");
+    assert_eq!(server.user_count().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn certificates_have_to_name_the_server() {
+    let (config, pem) = certificate("example.com");
+    let server = server();
+    let port = listen_tls(&server, &config).await;
+
+    // trusted, but for another name than the one connected to
+    let err = Client::new_tls(s("localhost"), port)
+        .with_root_certificates(pem.as_bytes())
+        .unwrap()
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .expect_err("accepted a certificate for another name");
+    assert!(matches!(err.inner(), ClientError::Tls(_)), "{err:?}");
+}
+
+#[tokio::test]
+async fn failed_handshakes_leave_the_listener_serving() {
+    let (config, pem) = certificate("localhost");
+    let server = server();
+    let port = listen_tls(&server, &config).await;
+
+    // plain http where a handshake belongs, then a client that goes away halfway through one
+    let mut plain = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    plain
+        .write_all(b"GET /info HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut rest = Vec::new();
+    let _ = plain.read_to_end(&mut rest).await;
+    let mut hello = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    hello.write_all(&[0x16, 0x03, 0x01, 0x00]).await.unwrap();
+    drop(hello);
+
+    let client = Client::new_tls(s("localhost"), port)
+        .with_root_certificates(pem.as_bytes())
+        .unwrap();
+    assert_eq!(
+        client
+            .register_user(s("alice"), s("hunter2"))
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+}
+
+#[tokio::test]
+async fn listeners_configured_with_tls_terminate_it() {
+    let (config, pem) = certificate("localhost");
+    let addr = {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    };
+    let listeners = [ListenerConfig::public(addr).with_tls(config)];
+    tokio::spawn(async move { server().serve_all(&listeners, pending()).await });
+
+    let client = Client::new_tls(s("localhost"), addr.port())
+        .with_root_certificates(pem.as_bytes())
+        .unwrap()
+        .with_retry(RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(20),
+            jitter: Duration::ZERO,
+        });
+    assert_eq!(
+        client
+            .register_user(s("alice"), s("hunter2"))
+            .await
+            .unwrap(),
+        RegistrationOutcome::Created
+    );
+}
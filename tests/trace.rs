@@ -0,0 +1,179 @@
+mod common;
+
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use common::{listen, s, server};
+use tinap::client::{error::ClientError, Client};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+const PASSWORD: &str = "correct horse battery staple";
+
+/// Bytes shared between the test and whatever writes them
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Capture {
+    fn bytes(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// copy `from` into `to`, keeping a copy of everything that went through
+async fn relay(
+    mut from: impl AsyncReadExt + Unpin,
+    mut to: impl AsyncWriteExt + Unpin,
+    seen: Capture,
+) {
+    let mut buf = [0; 4096];
+    while let Ok(read @ 1..) = from.read(&mut buf).await {
+        seen.0.lock().unwrap().extend_from_slice(&buf[..read]);
+        if to.write_all(&buf[..read]).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// a port forwarding to `port`, recording what each side sent
+async fn recording_proxy(port: u16) -> (u16, Capture, Capture) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = listener.local_addr().unwrap().port();
+    let (upstream, downstream) = (Capture::default(), Capture::default());
+    let (sent, received) = (upstream.clone(), downstream.clone());
+    tokio::spawn(async move {
+        while let Ok((client, _)) = listener.accept().await {
+            let server = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            let (client_read, client_write) = client.into_split();
+            let (server_read, server_write) = server.into_split();
+            tokio::spawn(relay(client_read, server_write, sent.clone()));
+            tokio::spawn(relay(server_read, client_write, received.clone()));
+        }
+    });
+    (proxy, upstream, downstream)
+}
+
+/// the payloads of the binary websocket frames in `stream`, unmasked, skipping the HTTP
+/// upgrade in front of them
+fn payloads(stream: &[u8]) -> Vec<Vec<u8>> {
+    let mut payloads = Vec::new();
+    let mut rest = stream;
+    // every connection of the operation starts with an upgrade of its own
+    while let Some(at) = rest.windows(4).position(|window| window == b"\r\n\r\n") {
+        rest = &rest[at + 4..];
+        while rest.len() >= 2 && !rest.starts_with(b"GET ") && !rest.starts_with(b"HTTP/") {
+            let binary = rest[0] & 0x0f == 0x2;
+            let masked = rest[1] & 0x80 != 0;
+            let (len, mut at) = match rest[1] & 0x7f {
+                126 => (u16::from_be_bytes([rest[2], rest[3]]) as usize, 4),
+                127 => (
+                    u64::from_be_bytes(rest[2..10].try_into().unwrap()) as usize,
+                    10,
+                ),
+                len => (len as usize, 2),
+            };
+            let mask = masked.then(|| {
+                at += 4;
+                [rest[at - 4], rest[at - 3], rest[at - 2], rest[at - 1]]
+            });
+            let Some(payload) = rest.get(at..at + len) else {
+                break;
+            };
+            if binary {
+                payloads.push(match mask {
+                    Some(mask) => payload
+                        .iter()
+                        .enumerate()
+                        .map(|(i, byte)| byte ^ mask[i % 4])
+                        .collect(),
+                    None => payload.to_vec(),
+                });
+            }
+            rest = &rest[at + len..];
+        }
+    }
+    payloads
+}
+
+/// every way `bytes` could plausibly end up written down
+fn renderings(bytes: &[u8]) -> Vec<String> {
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    let debug = format!("{bytes:?}");
+    vec![
+        hex.to_uppercase(),
+        hex,
+        debug.trim_matches(['[', ']']).to_string(),
+        String::from_utf8_lossy(bytes).into_owned(),
+    ]
+}
+
+#[tokio::test]
+async fn secrets_stay_out_of_a_failing_traced_login() {
+    let logs = Capture::default();
+    let writer = logs.clone();
+    let _logging = tracing::subscriber::set_default(
+        tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || writer.clone())
+            .finish(),
+    );
+    let server = server();
+    let (proxy, sent, received) = recording_proxy(listen(&server).await).await;
+    let client = Client::new(s("127.0.0.1"), proxy).with_trace(true);
+    client.register_user(s("alice"), s(PASSWORD)).await.unwrap();
+    server.set_must_reregister(b"alice", true).await.unwrap();
+
+    // fails after every password carrying step went through
+    let err = client
+        .authenticate(s("alice"), s(PASSWORD))
+        .await
+        .expect_err("logged in while flagged");
+    assert!(
+        matches!(err.inner(), ClientError::PasswordChangeRequired),
+        "{err:?}"
+    );
+    assert!(!err.context().is_empty());
+
+    let mut written = format!("{err}\n{err:?}\n");
+    for entry in err.context() {
+        written.push_str(&format!("{entry}\n{entry:?}\n"));
+    }
+    let logged = String::from_utf8_lossy(&logs.bytes()).into_owned();
+    assert!(logged.contains("bytes"), "the trace wasn't logged");
+    written.push_str(&logged);
+    assert!(!written.contains(PASSWORD), "{written}");
+    let messages = payloads(&sent.bytes())
+        .into_iter()
+        .chain(payloads(&received.bytes()))
+        .filter(|payload| payload.len() >= 32)
+        .collect::<Vec<_>>();
+    assert!(messages.len() >= 4, "only saw {} messages", messages.len());
+    for message in messages {
+        for chunk in message.chunks_exact(8) {
+            for rendering in renderings(chunk) {
+                assert!(
+                    !written.contains(&rendering),
+                    "{rendering:?} of an exchanged message was written down:\n{written}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use axum::http::{Method, StatusCode};
+use common::{call, s, server};
+use tinap::{
+    client::{error::ClientError, Client},
+    server::Server,
+    wire::ServerInfo,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// `server` on a local port, served with the peer's address so proxies can be told apart
+async fn listen_with_peers(server: &Server) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let service = server
+        .router()
+        .into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, service).await });
+    port
+}
+
+/// the status line the server answers a registration upgrade with, sent with `proto` as the
+/// forwarded protocol
+async fn upgrade_status(port: u16, proto: Option<&str>) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let forwarded = proto
+        .map(|proto| format!("X-Forwarded-Proto: {proto}\r\n"))
+        .unwrap_or_default();
+    let request = format!(
+        "GET /registration HTTP/1.1\r\n\
+         Host: 127.0.0.1:{port}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Sec-WebSocket-Version: 13\r\n\
+         {forwarded}\r\n"
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).await.unwrap();
+        response.push(byte[0]);
+    }
+    String::from_utf8(response).unwrap().trim_end().to_string()
+}
+
+#[tokio::test]
+async fn trusted_proxies_reporting_https_are_let_in() {
+    let server = server().with_require_tls(vec![LOCALHOST]);
+    let port = listen_with_peers(&server).await;
+
+    assert!(upgrade_status(port, Some("https"))
+        .await
+        .starts_with("HTTP/1.1 101"));
+}
+
+#[tokio::test]
+async fn plain_connections_are_refused() {
+    let server = server().with_require_tls(vec![LOCALHOST]);
+    let port = listen_with_peers(&server).await;
+
+    assert!(upgrade_status(port, None).await.starts_with("HTTP/1.1 403"));
+    assert!(upgrade_status(port, Some("http"))
+        .await
+        .starts_with("HTTP/1.1 403"));
+}
+
+#[tokio::test]
+async fn https_from_untrusted_peers_is_refused() {
+    let server = server().with_require_tls(vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))]);
+    let port = listen_with_peers(&server).await;
+
+    assert!(upgrade_status(port, Some("https"))
+        .await
+        .starts_with("HTTP/1.1 403"));
+
+    // nothing to go on when the peer's address isn't known
+    let port = common::listen(&server).await;
+    assert!(upgrade_status(port, Some("https"))
+        .await
+        .starts_with("HTTP/1.1 403"));
+}
+
+#[tokio::test]
+async fn clients_requiring_tls_refuse_plain_connections() {
+    let server = server();
+    let port = common::listen(&server).await;
+
+    let err = Client::new(s("127.0.0.1"), port)
+        .with_require_tls(true)
+        .register_user(s("alice"), s("hunter2"))
+        .await
+        .expect_err("registered over a plain connection");
+    assert!(
+        matches!(err.inner(), ClientError::InsecureTransport(_)),
+        "{err:?}"
+    );
+    assert_eq!(server.user_count().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn info_says_whether_tls_is_required() {
+    for (server, required) in [(server(), false), (server().with_require_tls(vec![]), true)] {
+        let (status, body) = call(server.router(), Method::GET, "/info", None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let info: ServerInfo = serde_json::from_str(&body).unwrap();
+        assert_eq!(info.require_tls, required);
+    }
+}